@@ -33,6 +33,8 @@
             rust-bin.stable.latest.default
             pkg-config
             openssl
+            fontconfig
+            freetype
           ];
 
           shellHook = ''
@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// The arguments after the name of a script in the command, exposed to scripts as `ARGS`, so one
+/// script can serve many pallets or features. `key=value` arguments are looked up by their key,
+/// the others by their position among themselves.
+///
+/// ```rhai
+/// // /benchbot bench pallet_balances pallet_staking steps=50
+/// for pallet in ARGS { print(pallet); }
+/// let steps = if ARGS.contains("steps") { parse_int(ARGS.steps) } else { 20 };
+/// let first = ARGS[0];
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    positional: Vec<String>,
+    named: BTreeMap<String, String>,
+}
+
+impl Args {
+    /// Split `args` into `key=value` and positional arguments. Keys are made of letters, digits,
+    /// `_` and `-`, and don't start with `-`, so flags like `--features=runtime-benchmarks` stay
+    /// positional.
+    pub fn parse(args: &[String]) -> Self {
+        let mut parsed = Args::default();
+        for arg in args {
+            match arg.split_once('=') {
+                Some((key, value)) if is_key(key) => {
+                    parsed.named.insert(key.to_string(), value.to_string());
+                }
+                _ => parsed.positional.push(arg.clone()),
+            }
+        }
+        parsed
+    }
+
+    /// The positional argument at `index`, `()` if there are fewer
+    pub fn get_index(&mut self, index: rhai::INT) -> rhai::Dynamic {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.positional.get(index))
+            .map_or(rhai::Dynamic::UNIT, |arg| arg.as_str().into())
+    }
+
+    /// The value of `key=value`, `()` if it wasn't given
+    pub fn get_named(&mut self, key: &str) -> rhai::Dynamic {
+        self.named
+            .get(key)
+            .map_or(rhai::Dynamic::UNIT, |value| value.as_str().into())
+    }
+
+    /// Whether `key=value` was given
+    pub fn contains(&mut self, key: &str) -> bool {
+        self.named.contains_key(key)
+    }
+
+    /// Number of positional arguments
+    pub fn len(&mut self) -> rhai::INT {
+        self.positional.len() as rhai::INT
+    }
+
+    pub fn is_empty(&mut self) -> bool {
+        self.positional.is_empty()
+    }
+
+    /// The positional arguments, for functions that take an array
+    pub fn get_positional(&mut self) -> rhai::Array {
+        self.positional
+            .iter()
+            .map(|arg| arg.as_str().into())
+            .collect()
+    }
+
+    /// The `key=value` arguments
+    pub fn get_named_map(&mut self) -> rhai::Map {
+        self.named
+            .iter()
+            .map(|(key, value)| (key.as_str().into(), value.as_str().into()))
+            .collect()
+    }
+}
+
+impl IntoIterator for Args {
+    type Item = String;
+    type IntoIter = std::vec::IntoIter<String>;
+
+    /// Over the positional arguments
+    fn into_iter(self) -> Self::IntoIter {
+        self.positional.into_iter()
+    }
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('-')
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+
+    fn parse(args: &[&str]) -> Args {
+        Args::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn named_and_positional_arguments_are_told_apart() {
+        let args = parse(&["pallet_balances", "steps=50", "pallet_staking", "repeat=20"]);
+        assert_eq!(
+            args.positional,
+            strings(&["pallet_balances", "pallet_staking"])
+        );
+        assert_eq!(args.named.len(), 2);
+        assert_eq!(args.named["steps"], "50");
+        assert_eq!(args.named["repeat"], "20");
+    }
+
+    #[test]
+    fn values_may_contain_equal_signs() {
+        let args = parse(&["filter=a=b", "empty="]);
+        assert!(args.positional.is_empty());
+        assert_eq!(args.named["filter"], "a=b");
+        assert_eq!(args.named["empty"], "");
+    }
+
+    #[test]
+    fn flags_and_odd_keys_stay_positional() {
+        let args = parse(&[
+            "--features=runtime-benchmarks",
+            "-p=1",
+            "=x",
+            "a.b=c",
+            "x y=z",
+        ]);
+        assert_eq!(
+            args.positional,
+            strings(&[
+                "--features=runtime-benchmarks",
+                "-p=1",
+                "=x",
+                "a.b=c",
+                "x y=z"
+            ])
+        );
+        assert!(args.named.is_empty());
+
+        let args = parse(&["pallet-name=x", "max_steps=3"]);
+        assert!(args.positional.is_empty());
+        assert_eq!(args.named["pallet-name"], "x");
+        assert_eq!(args.named["max_steps"], "3");
+    }
+
+    #[test]
+    fn the_last_of_repeated_keys_wins() {
+        let args = parse(&["steps=1", "steps=2"]);
+        assert_eq!(args.named["steps"], "2");
+    }
+
+    #[test]
+    fn scripts_see_the_arguments() {
+        let mut engine = rhai::Engine::new();
+        engine
+            .register_type_with_name::<Args>("Args")
+            .register_indexer_get(Args::get_index)
+            .register_indexer_get(Args::get_named)
+            .register_fn("contains", Args::contains)
+            .register_fn("len", Args::len)
+            .register_fn("is_empty", Args::is_empty)
+            .register_get("positional", Args::get_positional)
+            .register_get("named", Args::get_named_map)
+            .register_iterator::<Args>();
+        let mut scope = rhai::Scope::new();
+        scope.push(
+            "ARGS",
+            parse(&["pallet_balances", "steps=50", "pallet_staking"]),
+        );
+
+        let mut eval = |script: &str| {
+            engine
+                .eval_with_scope::<rhai::Dynamic>(&mut scope, script)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(eval("ARGS[0]"), "pallet_balances");
+        assert_eq!(eval("ARGS[1]"), "pallet_staking");
+        assert_eq!(eval("ARGS[2] == ()"), "true");
+        assert_eq!(eval("ARGS[-1] == ()"), "true");
+        assert_eq!(eval("ARGS.steps"), "50");
+        assert_eq!(eval(r#"ARGS["steps"]"#), "50");
+        assert_eq!(eval("ARGS.repeat == ()"), "true");
+        assert_eq!(eval(r#"ARGS.contains("steps")"#), "true");
+        assert_eq!(eval(r#"ARGS.contains("repeat")"#), "false");
+        assert_eq!(eval("ARGS.len()"), "2");
+        assert_eq!(eval("ARGS.is_empty()"), "false");
+        assert_eq!(
+            eval(r#"let all = ""; for arg in ARGS { all += arg + " "; } all"#),
+            "pallet_balances pallet_staking "
+        );
+        assert_eq!(eval("ARGS.positional.len()"), "2");
+        assert_eq!(eval("ARGS.named.steps"), "50");
+    }
+}
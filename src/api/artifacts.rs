@@ -0,0 +1,172 @@
+//! Files scripts hand over to whoever triggered them, like profiles, through
+//! `upload_artifact(path)`. The result comment links to them.
+//!
+//! Repositories whose artifacts may be sensitive can have them encrypted to the certificates of
+//! their recipients. Encrypted artifacts are stored as CMS enveloped data (`<name>.p7m`), which
+//! recipients decrypt with their private key:
+//!
+//! ```sh
+//! openssl cms -decrypt -inform DER -in profile.json.p7m -inkey key.pem -out profile.json
+//! ```
+
+use openssl::cms::{CMSOptions, CmsContentInfo};
+use openssl::error::ErrorStack;
+use openssl::stack::Stack;
+use openssl::symm::Cipher;
+use openssl::x509::X509;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read recipient certificates {0:?}: {1}")]
+    ReadRecipients(PathBuf, std::io::Error),
+    #[error("Invalid recipient certificates {0:?}: {1}")]
+    InvalidRecipients(PathBuf, ErrorStack),
+    #[error("No artifact {0:?} in the repository")]
+    NotFound(String),
+    #[error("Invalid artifact name {0:?}, only letters, digits, `.`, `-` and `_` are allowed")]
+    InvalidName(String),
+    #[error("Failed to store artifact: {0}")]
+    Store(#[from] std::io::Error),
+    #[error("Failed to encrypt artifact: {0}")]
+    Encrypt(#[from] ErrorStack),
+}
+
+/// Read the PEM certificates (one or more per file) to encrypt artifacts to
+pub fn load_recipients<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<X509>, Error> {
+    let mut recipients = vec![];
+    for path in paths {
+        let path = path.as_ref();
+        let pem =
+            std::fs::read(path).map_err(|err| Error::ReadRecipients(path.to_path_buf(), err))?;
+        let certs = X509::stack_from_pem(&pem)
+            .map_err(|err| Error::InvalidRecipients(path.to_path_buf(), err))?;
+        recipients.extend(certs);
+    }
+    Ok(recipients)
+}
+
+/// An uploaded artifact
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Artifact {
+    pub name: String,
+    pub url: String,
+    pub encrypted: bool,
+}
+
+/// Where the artifacts of a job go. Clones share the list of uploaded artifacts.
+#[derive(Clone, Debug)]
+pub struct Artifacts {
+    dir: PathBuf,
+    /// URL `dir` is served at
+    url: String,
+    /// Encrypt artifacts to these, if any
+    recipients: Vec<X509>,
+    uploaded: Arc<Mutex<Vec<Artifact>>>,
+}
+
+impl Artifacts {
+    pub fn new<P: Into<PathBuf>, U: Into<String>>(dir: P, url: U, recipients: Vec<X509>) -> Self {
+        Artifacts {
+            dir: dir.into(),
+            url: url.into(),
+            recipients,
+            uploaded: Default::default(),
+        }
+    }
+
+    /// Store the file at `path` within `root` (the checkout) as `name`, encrypted if there are
+    /// recipients
+    pub fn upload(&self, root: &Path, path: &str, name: Option<&str>) -> Result<Artifact, Error> {
+        // Don't let scripts hand out files from outside the checkout
+        let source = root
+            .join(path)
+            .canonicalize()
+            .ok()
+            .filter(|source| {
+                root.canonicalize()
+                    .is_ok_and(|root| source.starts_with(root))
+            })
+            .filter(|source| source.is_file())
+            .ok_or_else(|| Error::NotFound(path.to_string()))?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => source
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        self.store(&source, &name)
+    }
+
+    /// Store the file at `source`, which the job made itself (like a chart), as `name`
+    pub(crate) fn store(&self, source: &Path, name: &str) -> Result<Artifact, Error> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+        if !valid {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+
+        std::fs::create_dir_all(&self.dir)?;
+        let encrypted = !self.recipients.is_empty();
+        let name = if encrypted {
+            let mut recipients = Stack::new()?;
+            for cert in &self.recipients {
+                recipients.push(cert.clone())?;
+            }
+            let data = std::fs::read(source)?;
+            let cms = CmsContentInfo::encrypt(
+                &recipients,
+                &data,
+                Cipher::aes_256_cbc(),
+                CMSOptions::BINARY,
+            )?;
+            let name = format!("{name}.p7m");
+            std::fs::write(self.dir.join(&name), cms.to_der()?)?;
+            name
+        } else {
+            std::fs::copy(source, self.dir.join(name))?;
+            name.to_string()
+        };
+
+        let artifact = Artifact {
+            url: format!("{}/{name}", self.url.trim_end_matches('/')),
+            name,
+            encrypted,
+        };
+        tracing::info!("Uploaded artifact {}", artifact.url);
+        if let Ok(mut uploaded) = self.uploaded.lock() {
+            uploaded.retain(|uploaded| uploaded.name != artifact.name);
+            uploaded.push(artifact.clone());
+        }
+        Ok(artifact)
+    }
+
+    pub(crate) fn uploaded(&self) -> Vec<Artifact> {
+        self.uploaded
+            .lock()
+            .map(|uploaded| uploaded.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Markdown section linking the artifacts of a job
+pub fn render(artifacts: &[Artifact]) -> String {
+    let mut section = String::from("### Artifacts\n\n");
+    for artifact in artifacts {
+        section.push_str(&format!("- [{}]({})\n", artifact.name, artifact.url));
+    }
+    if artifacts.iter().any(|artifact| artifact.encrypted) {
+        section.push_str(
+            "\n<sub>`.p7m` artifacts are encrypted to the configured recipients, decrypt them \
+             with `openssl cms -decrypt -inform DER -in <artifact> -inkey <key>`.</sub>\n",
+        );
+    }
+    section
+}
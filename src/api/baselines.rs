@@ -0,0 +1,84 @@
+//! Runs saved under a name to compare against instead of the moving base branch, exposed to
+//! scripts as `BASELINES`:
+//!
+//! ```rhai
+//! BASELINES.save("release-1.9", "4f2c1a9");
+//! let base = BASELINES.get("release-1.9");
+//! print(base["ref-time"]);
+//! ```
+
+use super::results::History;
+
+#[derive(Clone, Debug, Default)]
+pub struct Baselines {
+    history: Option<History>,
+}
+
+impl Baselines {
+    pub fn new(history: Option<History>) -> Self {
+        Baselines { history }
+    }
+
+    fn history(&self) -> Result<&History, Box<rhai::EvalAltResult>> {
+        Ok(self
+            .history
+            .as_ref()
+            .ok_or("There are no earlier results on this runner")?)
+    }
+
+    /// Names of the baselines saved for the repository
+    pub fn names(&mut self) -> Result<rhai::Array, Box<rhai::EvalAltResult>> {
+        let history = self.history()?;
+        let baselines = history
+            .store
+            .baselines(&history.repo)
+            .map_err(|e| e.to_string())?;
+        Ok(baselines
+            .into_iter()
+            .map(|baseline| baseline.name.into())
+            .collect())
+    }
+
+    /// The metrics of the baseline `name` by name, `()` if there's no such baseline
+    pub fn get(&mut self, name: &str) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
+        let history = self.history()?;
+        let baseline = history
+            .store
+            .baseline(&history.repo, name)
+            .map_err(|e| e.to_string())?;
+        Ok(match baseline {
+            Some((_, run)) => {
+                let mut metrics = rhai::Map::new();
+                for metric in run.metrics {
+                    metrics.insert(metric.name.into(), rhai::Dynamic::from_float(metric.value));
+                }
+                metrics.into()
+            }
+            None => rhai::Dynamic::UNIT,
+        })
+    }
+
+    /// The commit the baseline `name` was saved from, `()` if there's no such baseline
+    pub fn commit(&mut self, name: &str) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
+        let history = self.history()?;
+        let baseline = history
+            .store
+            .baseline(&history.repo, name)
+            .map_err(|e| e.to_string())?;
+        Ok(baseline
+            .and_then(|(baseline, _)| baseline.commit)
+            .map(rhai::Dynamic::from)
+            .unwrap_or(rhai::Dynamic::UNIT))
+    }
+
+    /// Save the results last recorded on `commit` as the baseline `name`
+    pub fn save(&mut self, name: &str, commit: &str) -> Result<(), Box<rhai::EvalAltResult>> {
+        let history = self.history()?;
+        history
+            .store
+            .save_baseline(&history.repo, name, commit)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("No results were recorded on {commit} to save as {name}"))?;
+        Ok(())
+    }
+}
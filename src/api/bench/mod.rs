@@ -0,0 +1,3 @@
+//! Helpers for scripts measuring things themselves
+
+pub mod stats;
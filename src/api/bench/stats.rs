@@ -0,0 +1,482 @@
+//! Statistics of repeated runs, so scripts don't each calculate their own in rhai. `run_n`
+//! calls a function a number of times, taking what it returns as the sample (or how long it
+//! took in seconds, if it returns nothing), and rejects outliers:
+//!
+//! ```rhai
+//! let after = run_n(10, || {
+//!     let result = cargo "bench -p my-crate -- --exact transfer";
+//!     parse_json(result.stdout).nanoseconds
+//! });
+//! print(`${after}`); // 1234.50 ± 12.25 (n = 9, 1 outlier)
+//! let comparison = after.compare(stats(before));
+//! if comparison.significant { print(`Changed by ${format_percent(comparison.change)}`); }
+//! ```
+//!
+//! Outliers are samples outside of 1.5 times the interquartile range from the quartiles
+//! (Tukey's fences), once there are at least 4 samples. Comparisons use Welch's t-test.
+
+use rhai::plugin::*;
+
+/// Significance level of comparisons, unless given
+pub const ALPHA: f64 = 0.05;
+
+/// Summary of samples, without the outliers
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// In the order they were taken
+    pub samples: Vec<f64>,
+    pub outliers: Vec<f64>,
+    pub mean: f64,
+    pub median: f64,
+    /// Of the samples (not the population), 0 for a single one
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    pub fn new(samples: Vec<f64>) -> Result<Self, Box<EvalAltResult>> {
+        if samples.is_empty() {
+            return Err("No samples to calculate statistics of".into());
+        }
+        if samples.iter().any(|sample| !sample.is_finite()) {
+            return Err("Samples must be finite numbers".into());
+        }
+        let (samples, outliers) = if samples.len() >= 4 {
+            let sorted = sorted(&samples);
+            let (q1, q3) = (quantile(&sorted, 0.25), quantile(&sorted, 0.75));
+            let fence = 1.5 * (q3 - q1);
+            samples
+                .into_iter()
+                .partition(|sample| (q1 - fence..=q3 + fence).contains(sample))
+        } else {
+            (samples, vec![])
+        };
+        let sorted = sorted(&samples);
+        let count = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / count;
+        let stddev = if samples.len() > 1 {
+            let squares = samples.iter().map(|sample| (sample - mean).powi(2));
+            (squares.sum::<f64>() / (count - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+        Ok(Stats {
+            mean,
+            median: quantile(&sorted, 0.5),
+            stddev,
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            samples,
+            outliers,
+        })
+    }
+
+    /// Whether these samples differ from `baseline` at significance level `alpha`, with
+    /// Welch's t-test
+    pub fn compare(&self, baseline: &Stats, alpha: f64) -> Result<Comparison, Box<EvalAltResult>> {
+        if self.samples.len() < 2 || baseline.samples.len() < 2 {
+            return Err("Comparing takes at least 2 samples (without outliers) of each".into());
+        }
+        let variance = |stats: &Stats| stats.stddev.powi(2) / stats.samples.len() as f64;
+        let (a, b) = (variance(self), variance(baseline));
+        let difference = self.mean - baseline.mean;
+        let (t, p_value) = if a + b == 0.0 {
+            // Every sample the same
+            if difference == 0.0 {
+                (0.0, 1.0)
+            } else {
+                (difference.signum() * f64::INFINITY, 0.0)
+            }
+        } else {
+            let t = difference / (a + b).sqrt();
+            let freedom = (a + b).powi(2)
+                / (a.powi(2) / (self.samples.len() - 1) as f64
+                    + b.powi(2) / (baseline.samples.len() - 1) as f64);
+            // Two-sided, from the CDF of Student's t-distribution
+            (
+                t,
+                incomplete_beta(freedom / 2.0, 0.5, freedom / (freedom + t * t)),
+            )
+        };
+        Ok(Comparison {
+            difference,
+            change: if baseline.mean == 0.0 {
+                0.0
+            } else {
+                difference / baseline.mean * 100.0
+            },
+            t,
+            p_value,
+            significant: p_value < alpha,
+        })
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2} ± {:.2} (n = {}",
+            self.mean,
+            self.stddev,
+            self.samples.len()
+        )?;
+        match self.outliers.len() {
+            0 => write!(f, ")"),
+            1 => write!(f, ", 1 outlier)"),
+            outliers => write!(f, ", {outliers} outliers)"),
+        }
+    }
+}
+
+/// How samples compare to a baseline
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    /// Of the means
+    pub difference: f64,
+    /// Relative to the mean of the baseline, in percent
+    pub change: f64,
+    pub t: f64,
+    /// Chance of a difference at least this large if there's none
+    pub p_value: f64,
+    pub significant: bool,
+}
+
+impl Comparison {
+    fn into_map(self) -> rhai::Map {
+        let mut map = rhai::Map::new();
+        map.insert("difference".into(), self.difference.into());
+        map.insert("change".into(), self.change.into());
+        map.insert("t".into(), self.t.into());
+        map.insert("p_value".into(), self.p_value.into());
+        map.insert("significant".into(), self.significant.into());
+        map
+    }
+}
+
+fn sorted(samples: &[f64]) -> Vec<f64> {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted
+}
+
+/// Interpolated between the closest samples
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let (below, above) = (position.floor() as usize, position.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (position - below as f64)
+}
+
+/// The regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // The continued fraction converges quickly on this side only
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction of the incomplete beta function, by the modified Lentz's method
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let clamp = |value: f64| if value.abs() < TINY { TINY } else { value };
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut fraction = d;
+    for m in 1..300 {
+        let m = m as f64;
+        let even = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / clamp(1.0 + even * d);
+        c = clamp(1.0 + even / c);
+        fraction *= d * c;
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / clamp(1.0 + odd * d);
+        c = clamp(1.0 + odd / c);
+        let step = d * c;
+        fraction *= step;
+        if (step - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    fraction
+}
+
+/// Logarithm of the gamma function, by the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |sum, (i, c)| {
+            sum + c / (x + 1.0 + i as f64)
+        });
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// A sample given by a script
+fn sample(value: Dynamic) -> Result<f64, Box<EvalAltResult>> {
+    if let Some(value) = value.clone().try_cast::<rhai::FLOAT>() {
+        Ok(value)
+    } else if let Some(value) = value.clone().try_cast::<rhai::INT>() {
+        Ok(value as f64)
+    } else {
+        Err(format!("Samples must be numbers, got {}", value.type_name()).into())
+    }
+}
+
+#[export_module]
+pub mod functions {
+    use super::Stats;
+    use rhai::{Array, FnPtr, Map, FLOAT, INT};
+
+    /// Call `f` `times` times, taking what it returns as a sample, or how long it took in
+    /// seconds if it returns `()`
+    #[rhai_fn(return_raw)]
+    pub fn run_n(
+        context: NativeCallContext,
+        times: INT,
+        f: FnPtr,
+    ) -> Result<Stats, Box<EvalAltResult>> {
+        let mut samples = vec![];
+        for _ in 0..times {
+            let started = std::time::Instant::now();
+            let value: Dynamic = f.call_raw(&context, None, [])?;
+            samples.push(if value.is::<()>() {
+                started.elapsed().as_secs_f64()
+            } else {
+                super::sample(value)?
+            });
+        }
+        Stats::new(samples)
+    }
+
+    /// Statistics of samples taken before, like the results of the base branch
+    #[rhai_fn(return_raw)]
+    pub fn stats(samples: Array) -> Result<Stats, Box<EvalAltResult>> {
+        Stats::new(
+            samples
+                .into_iter()
+                .map(super::sample)
+                .collect::<Result<_, _>>()?,
+        )
+    }
+
+    #[rhai_fn(get = "mean", pure)]
+    pub fn mean(stats: &mut Stats) -> FLOAT {
+        stats.mean
+    }
+
+    #[rhai_fn(get = "median", pure)]
+    pub fn median(stats: &mut Stats) -> FLOAT {
+        stats.median
+    }
+
+    #[rhai_fn(get = "stddev", pure)]
+    pub fn stddev(stats: &mut Stats) -> FLOAT {
+        stats.stddev
+    }
+
+    #[rhai_fn(get = "min", pure)]
+    pub fn min(stats: &mut Stats) -> FLOAT {
+        stats.min
+    }
+
+    #[rhai_fn(get = "max", pure)]
+    pub fn max(stats: &mut Stats) -> FLOAT {
+        stats.max
+    }
+
+    /// Number of samples, without the outliers
+    #[rhai_fn(get = "count", pure)]
+    pub fn count(stats: &mut Stats) -> INT {
+        stats.samples.len() as INT
+    }
+
+    #[rhai_fn(get = "samples", pure)]
+    pub fn samples(stats: &mut Stats) -> Array {
+        stats
+            .samples
+            .iter()
+            .map(|sample| (*sample).into())
+            .collect()
+    }
+
+    #[rhai_fn(get = "outliers", pure)]
+    pub fn outliers(stats: &mut Stats) -> Array {
+        stats
+            .outliers
+            .iter()
+            .map(|sample| (*sample).into())
+            .collect()
+    }
+
+    /// Compare against `baseline`, giving `#{difference, change, t, p_value, significant}`
+    /// with `change` in percent
+    #[rhai_fn(return_raw)]
+    pub fn compare(stats: &mut Stats, baseline: Stats) -> Result<Map, Box<EvalAltResult>> {
+        Ok(stats.compare(&baseline, super::ALPHA)?.into_map())
+    }
+
+    #[rhai_fn(name = "compare", return_raw)]
+    pub fn compare_samples(stats: &mut Stats, baseline: Array) -> Result<Map, Box<EvalAltResult>> {
+        compare(stats, self::stats(baseline)?)
+    }
+
+    /// Compare at significance level `alpha`, like 0.01
+    #[rhai_fn(name = "compare", return_raw)]
+    pub fn compare_at(
+        stats: &mut Stats,
+        baseline: Array,
+        alpha: FLOAT,
+    ) -> Result<Map, Box<EvalAltResult>> {
+        Ok(stats.compare(&self::stats(baseline)?, alpha)?.into_map())
+    }
+
+    #[rhai_fn(name = "to_string", name = "to_debug", pure)]
+    pub fn to_string(stats: &mut Stats) -> String {
+        stats.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} isn't within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn special_functions_match_known_values() {
+        // ln Γ(1) = ln 0! and ln Γ(1/2) = ln √π
+        assert_close(ln_gamma(1.0), 0.0, 1e-10);
+        assert_close(ln_gamma(0.5), 0.5723649429247001, 1e-10);
+        assert_close(ln_gamma(10.0), 362880f64.ln(), 1e-10);
+        assert_close(ln_gamma(3.7), 1.428_072_326_665_388, 1e-10);
+        // I_x(1, 1) = x, I_x(a, 1) = x^a and I_x(2, 3) = P(Binomial(4, x) >= 2)
+        assert_close(incomplete_beta(1.0, 1.0, 0.3), 0.3, 1e-10);
+        assert_close(incomplete_beta(3.0, 1.0, 0.5), 0.125, 1e-10);
+        assert_close(incomplete_beta(2.0, 3.0, 0.4), 0.5248, 1e-10);
+        assert_close(incomplete_beta(2.5, 0.5, 0.3), 0.018_927_124_071_945_65, 1e-10);
+        assert_close(incomplete_beta(4.0, 4.0, 0.5), 0.5, 1e-10);
+        assert_eq!(incomplete_beta(2.0, 3.0, 0.0), 0.0);
+        assert_eq!(incomplete_beta(2.0, 3.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn p_values_match_the_t_table() {
+        // Two-sided critical values of Student's t-distribution at 0.05, with `degrees` degrees
+        // of freedom
+        for (degrees, critical) in [(1.0, 12.706205), (4.0, 2.776445), (10.0, 2.228139)] {
+            let x = degrees / (degrees + critical * critical);
+            assert_close(incomplete_beta(degrees / 2.0, 0.5, x), 0.05, 1e-5);
+        }
+        // At 0.01
+        assert_close(
+            incomplete_beta(2.5, 0.5, 5.0 / (5.0 + 4.032143f64.powi(2))),
+            0.01,
+            1e-5,
+        );
+    }
+
+    #[test]
+    fn welch_test_matches_reference_values() {
+        let after = Stats::new(vec![10.1, 10.4, 9.8, 10.0, 10.2]).unwrap();
+        let before = Stats::new(vec![11.0, 12.1, 10.6, 11.7]).unwrap();
+        let comparison = after.compare(&before, ALPHA).unwrap();
+        assert_close(comparison.t, -3.547_378_273_803_02, 1e-9);
+        assert_close(comparison.p_value, 0.029341551433148374, 1e-9);
+        assert!(comparison.significant);
+        assert!(!after.compare(&before, 0.01).unwrap().significant);
+        assert_close(comparison.difference, 10.1 - 11.35, 1e-9);
+        assert_close(comparison.change, (10.1 - 11.35) / 11.35 * 100.0, 1e-9);
+
+        // Equal variances and sizes, so 4 degrees of freedom, right at the critical value
+        let before = Stats::new(vec![3.2666, 4.2666, 5.2666]).unwrap();
+        let comparison = Stats::new(vec![1.0, 2.0, 3.0])
+            .unwrap()
+            .compare(&before, ALPHA)
+            .unwrap();
+        assert_close(comparison.p_value, 0.050022434052325, 1e-9);
+    }
+
+    #[test]
+    fn outliers_are_outside_of_tukeys_fences() {
+        // Quartiles 10.25 and 12.75, so the fences are at 6.5 and 16.5, keeping the order
+        let stats = Stats::new(vec![12.0, 10.0, 100.0, 13.0, 11.0, 8.0]).unwrap();
+        assert_eq!(stats.samples, vec![12.0, 10.0, 13.0, 11.0, 8.0]);
+        assert_eq!(stats.outliers, vec![100.0]);
+
+        // Quartiles 11 and 13, so the fences are at 8 and 16
+        let stats = Stats::new(vec![10.0, 11.0, 12.0, 13.0, 100.0]).unwrap();
+        assert_eq!(stats.samples, vec![10.0, 11.0, 12.0, 13.0]);
+        assert_eq!(stats.outliers, vec![100.0]);
+        assert_eq!(stats.mean, 11.5);
+        assert_eq!(stats.median, 11.5);
+        assert_close(stats.stddev, (5.0f64 / 3.0).sqrt(), 1e-12);
+        assert_eq!((stats.min, stats.max), (10.0, 13.0));
+        assert_eq!(stats.to_string(), "11.50 ± 1.29 (n = 4, 1 outlier)");
+
+        // Too few to tell
+        let stats = Stats::new(vec![1.0, 2.0, 100.0]).unwrap();
+        assert!(stats.outliers.is_empty());
+    }
+
+    #[test]
+    fn quantiles_are_interpolated() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(quantile(&sorted, 0.0), 1.0);
+        assert_eq!(quantile(&sorted, 0.25), 1.75);
+        assert_eq!(quantile(&sorted, 0.5), 2.5);
+        assert_eq!(quantile(&sorted, 1.0), 4.0);
+        assert_eq!(quantile(&[7.0], 0.75), 7.0);
+    }
+
+    #[test]
+    fn single_and_equal_samples() {
+        let single = Stats::new(vec![5.0]).unwrap();
+        assert_eq!((single.mean, single.median, single.stddev), (5.0, 5.0, 0.0));
+        assert_eq!(single.to_string(), "5.00 ± 0.00 (n = 1)");
+        assert!(single.compare(&single, ALPHA).is_err());
+
+        let equal = Stats::new(vec![3.0; 5]).unwrap();
+        assert_eq!(equal.samples.len(), 5);
+        assert!(equal.outliers.is_empty());
+        let same = equal
+            .compare(&Stats::new(vec![3.0; 3]).unwrap(), ALPHA)
+            .unwrap();
+        assert_eq!((same.t, same.p_value, same.significant), (0.0, 1.0, false));
+        let lower = equal
+            .compare(&Stats::new(vec![4.0; 3]).unwrap(), ALPHA)
+            .unwrap();
+        assert_eq!((lower.t, lower.p_value), (f64::NEG_INFINITY, 0.0));
+        assert!(lower.significant);
+
+        assert!(Stats::new(vec![]).is_err());
+        assert!(Stats::new(vec![1.0, f64::NAN]).is_err());
+    }
+}
@@ -0,0 +1,574 @@
+pub mod runtime;
+pub mod sccache;
+pub mod workspace;
+
+use crate::secrets::Redactor;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the output of cargo goes, which can be too much to keep in memory (like hundreds of MB
+/// of benchmark output)
+#[derive(Clone, Debug, Default)]
+pub struct Output {
+    /// Directory to write all of it to, as `cargo-<id>.stdout` and `cargo-<id>.stderr`
+    pub log_dir: Option<PathBuf>,
+    /// Bytes of each stream scripts get, half from its start and half from its end
+    pub limit: Option<usize>,
+}
+
+pub struct Run {
+    /// `cargo` unless set otherwise with [`Run::program`]
+    program: String,
+    args: Vec<String>,
+    dir: PathBuf,
+    envs: Vec<(String, String)>,
+    redactor: Option<Redactor>,
+    output: Output,
+    cores: Option<Vec<usize>>,
+    sccache: sccache::Sccache,
+    accounting: super::usage::Accounting,
+    quota: super::quota::Quota,
+}
+
+impl Run {
+    pub fn new<S: ToString, A: AsRef<[S]>, P: AsRef<Path>>(args: A, dir: P) -> Self {
+        let args = args.as_ref().iter().map(|arg| arg.to_string()).collect();
+        let dir = dir.as_ref().into();
+        Run {
+            program: "cargo".to_string(),
+            args,
+            dir,
+            envs: vec![],
+            redactor: None,
+            output: Output::default(),
+            cores: None,
+            sccache: Default::default(),
+            accounting: Default::default(),
+            quota: Default::default(),
+        }
+    }
+
+    /// Run `program` instead of cargo, like a binary cargo built, the same way
+    pub fn program<S: Into<String>>(mut self, program: S) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Set these environment variables, cargo doesn't inherit any
+    pub fn envs(mut self, envs: &[(String, String)]) -> Self {
+        self.envs.extend_from_slice(envs);
+        self
+    }
+
+    /// Mask secrets in the captured output, before the script gets to see it
+    pub fn redactor(mut self, redactor: &Redactor) -> Self {
+        self.redactor = Some(redactor.clone());
+        self
+    }
+
+    /// Write the output to files and only keep part of it, all of it is kept in memory otherwise
+    pub fn output(mut self, output: &Output) -> Self {
+        self.output = output.clone();
+        self
+    }
+
+    /// Run on the cores pinned to right now, if any
+    pub fn pinning(mut self, pinning: &super::pinning::Pinning) -> Self {
+        self.cores = pinning.cores();
+        self
+    }
+
+    /// Build through sccache if the job does, counting its hits and misses
+    pub fn sccache(mut self, sccache: &sccache::Sccache) -> Self {
+        self.envs.extend(sccache.envs());
+        self.sccache = sccache.clone();
+        self
+    }
+
+    /// Count what cargo used towards the usage of the job
+    pub fn accounting(mut self, accounting: &super::usage::Accounting) -> Self {
+        self.accounting = accounting.clone();
+        self
+    }
+
+    /// Run within the limits of the job, if it has any
+    pub fn quota(mut self, quota: &super::quota::Quota) -> Self {
+        self.envs.extend(quota.envs());
+        self.quota = quota.clone();
+        self
+    }
+
+    /// Run with the toolchain selected right now, if any
+    pub fn toolchain(mut self, toolchain: &super::toolchain::Toolchain) -> Self {
+        self.envs.extend(toolchain.envs());
+        self
+    }
+
+    pub fn run(self) -> CargoResult {
+        tracing::info!(
+            "Running {} in {:?} with args {:?}",
+            self.program,
+            self.dir,
+            self.args
+        );
+        self.capture(|_| {})
+    }
+
+    /// Run cargo with `--message-format=json` and parse the artifacts and diagnostics it
+    /// reports. Artifacts are timed by when cargo reported them.
+    pub fn run_json(mut self) -> CargoResult {
+        // Before the arguments cargo passes on, like the ones of `cargo bench -- <filter>`
+        let at = self
+            .args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(self.args.len());
+        self.args.insert(at, "--message-format=json".to_string());
+        tracing::info!("Running cargo in {:?} with args {:?}", self.dir, self.args);
+        let started = std::time::Instant::now();
+        let mut artifacts = vec![];
+        let mut diagnostics = vec![];
+        let mut last_finished = 0.0;
+        // Parsed as cargo goes, to tell when each artifact was done
+        let mut result = self.capture(|line| match serde_json::from_str(line) {
+            Ok(Message::CompilerArtifact {
+                package_id,
+                target,
+                fresh,
+                filenames,
+            }) => {
+                let finished = started.elapsed().as_secs_f64();
+                artifacts.push(Artifact {
+                    package_id,
+                    name: target.name,
+                    kind: target.kind,
+                    fresh,
+                    filenames,
+                    finished,
+                    duration: finished - last_finished,
+                });
+                last_finished = finished;
+            }
+            Ok(Message::CompilerMessage {
+                package_id,
+                message,
+            }) => diagnostics.push(message.into_diagnostic(package_id)),
+            Ok(Message::Other) | Err(_) => {}
+        });
+        result.artifacts = artifacts;
+        result.diagnostics = diagnostics;
+        result
+    }
+
+    /// Run cargo, passing each (redacted) line of stdout to `on_stdout` as it comes
+    fn capture(&self, mut on_stdout: impl FnMut(&str)) -> CargoResult {
+        let cache_before = if self.sccache.is_enabled() {
+            self.sccache.stats(&self.envs)
+        } else {
+            None
+        };
+        let started = std::time::Instant::now();
+        let mut command = std::process::Command::new(&self.program);
+        command
+            .env_clear()
+            .envs(self.envs.iter().cloned())
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .args(&self.args);
+        if let Some(cores) = &self.cores {
+            super::pinning::pin(&mut command, cores);
+        }
+        if let Err(e) = self.quota.prepare(&mut command) {
+            return CargoResult::failed(&self.program, e);
+        }
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => return CargoResult::failed(&self.program, e),
+        };
+        let watch = self.quota.watch(&child);
+        let id = uuid::Uuid::new_v4();
+        let log = |stream: &str| {
+            let dir = self.output.log_dir.as_ref()?;
+            Some(dir.join(format!("cargo-{id}.{stream}")))
+        };
+        let mut stdout = Capture::new(log("stdout"), self.output.limit);
+        let mut stderr = Capture::new(log("stderr"), self.output.limit);
+        let stderr_pipe = child.stderr.take();
+        let redactor = self.redactor.clone();
+        let stderr = std::thread::spawn(move || {
+            if let Some(pipe) = stderr_pipe {
+                read_lines(pipe, redactor.as_ref(), |line| stderr.write(line));
+            }
+            stderr
+        });
+        if let Some(pipe) = child.stdout.take() {
+            read_lines(pipe, self.redactor.as_ref(), |line| {
+                on_stdout(line);
+                stdout.write(line);
+            });
+        }
+        let (exit_code, signal, usage) = match super::usage::wait(&mut child) {
+            Ok((status, usage)) => {
+                if let Some(usage) = usage {
+                    self.accounting.record(usage);
+                }
+                (status.code(), crate::platform::signal(&status), usage)
+            }
+            Err(_) => (Some(-1), None, None),
+        };
+        drop(watch);
+        self.quota.check();
+        let duration = started.elapsed();
+        let (stdout, stdout_path, stdout_truncated) = stdout.finish();
+        let (stderr, stderr_path, stderr_truncated) = match stderr.join() {
+            Ok(stderr) => stderr.finish(),
+            Err(_) => Default::default(),
+        };
+        CargoResult {
+            exit_code,
+            signal,
+            duration,
+            stdout,
+            stderr,
+            stdout_path,
+            stderr_path,
+            truncated: stdout_truncated || stderr_truncated,
+            sccache: self.sccache.since(cache_before, &self.envs),
+            usage,
+            ..Default::default()
+        }
+    }
+}
+
+/// Pass the lines of `reader` to `f`, with their line break and secrets masked
+fn read_lines(reader: impl std::io::Read, redactor: Option<&Redactor>, mut f: impl FnMut(&str)) {
+    for line in std::io::BufReader::new(reader).split(b'\n') {
+        let line = match line {
+            Ok(line) => String::from_utf8_lossy(&line).into_owned(),
+            Err(_) => break,
+        };
+        let mut line = match redactor {
+            Some(redactor) => redactor.redact(&line),
+            None => line,
+        };
+        line.push('\n');
+        f(&line);
+    }
+}
+
+/// A stream of cargo's output, written to a file while only its start and end are kept
+struct Capture {
+    file: Option<(PathBuf, std::io::BufWriter<std::fs::File>)>,
+    limit: Option<usize>,
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    /// Bytes dropped between the head and the tail
+    dropped: u64,
+}
+
+impl Capture {
+    fn new(path: Option<PathBuf>, limit: Option<usize>) -> Self {
+        let file = path.and_then(|path| {
+            let file = std::fs::create_dir_all(path.parent().unwrap_or(&path))
+                .and_then(|()| std::fs::File::create(&path));
+            match file {
+                Ok(file) => Some((path, std::io::BufWriter::new(file))),
+                Err(err) => {
+                    tracing::warn!("Failed to create {path:?} for the output of cargo: {err}");
+                    None
+                }
+            }
+        });
+        Capture {
+            file,
+            limit,
+            head: vec![],
+            tail: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    fn write(&mut self, output: &str) {
+        if let Some((path, file)) = &mut self.file {
+            if let Err(err) = file.write_all(output.as_bytes()) {
+                tracing::warn!("Failed to write the output of cargo to {path:?}: {err}");
+                self.file = None;
+            }
+        }
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => {
+                self.head.extend_from_slice(output.as_bytes());
+                return;
+            }
+        };
+        let room = (limit - limit / 2).saturating_sub(self.head.len());
+        let (head, tail) = output.as_bytes().split_at(room.min(output.len()));
+        self.head.extend_from_slice(head);
+        self.tail.extend(tail);
+        let excess = self.tail.len().saturating_sub(limit / 2);
+        self.tail.drain(..excess);
+        self.dropped += excess as u64;
+    }
+
+    /// What's kept of the output, where all of it is and whether anything was dropped
+    fn finish(mut self) -> (String, Option<PathBuf>, bool) {
+        let path = self
+            .file
+            .take()
+            .and_then(|(path, mut file)| match file.flush() {
+                Ok(()) => Some(path),
+                Err(err) => {
+                    tracing::warn!("Failed to write the output of cargo to {path:?}: {err}");
+                    None
+                }
+            });
+        let mut output = String::from_utf8_lossy(&self.head).into_owned();
+        if self.dropped > 0 {
+            let full = match &path {
+                Some(path) => format!(", see {}", path.display()),
+                None => String::new(),
+            };
+            output.push_str(&format!(
+                "\n[... {} bytes truncated{full} ...]\n",
+                self.dropped
+            ));
+        }
+        output.push_str(&String::from_utf8_lossy(self.tail.make_contiguous()));
+        (output, path, self.dropped > 0)
+    }
+}
+
+/// Messages of `cargo --message-format=json`, see
+/// <https://doc.rust-lang.org/cargo/reference/external-tools.html#json-messages>
+#[derive(Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+// Named after the reasons cargo gives
+#[allow(clippy::enum_variant_names)]
+enum Message {
+    CompilerArtifact {
+        package_id: String,
+        target: Target,
+        fresh: bool,
+        filenames: Vec<String>,
+    },
+    CompilerMessage {
+        package_id: String,
+        message: CompilerMessage,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    level: String,
+    message: String,
+    code: Option<Code>,
+    #[serde(default)]
+    spans: Vec<Span>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Code {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: u64,
+    is_primary: bool,
+}
+
+impl CompilerMessage {
+    fn into_diagnostic(self, package_id: String) -> Diagnostic {
+        let span = self.spans.into_iter().find(|span| span.is_primary);
+        Diagnostic {
+            package_id,
+            level: self.level,
+            message: self.message,
+            code: self.code.map(|code| code.code),
+            file: span.as_ref().map(|span| span.file_name.clone()),
+            line: span.map(|span| span.line_start),
+            rendered: self.rendered,
+        }
+    }
+}
+
+/// A crate target built by cargo, exposed to scripts as a map
+#[derive(Clone, Debug, Serialize)]
+pub struct Artifact {
+    pub package_id: String,
+    /// Name of the target, like the crate name for libraries
+    pub name: String,
+    /// Kinds of the target, like `lib` or `bin`
+    pub kind: Vec<String>,
+    /// Up to date, not built again
+    pub fresh: bool,
+    pub filenames: Vec<String>,
+    /// Seconds since cargo started, when the artifact was done
+    pub finished: f64,
+    /// Seconds since the previous artifact was done, how long it took to build when cargo
+    /// builds one at a time (`-j 1`)
+    pub duration: f64,
+}
+
+/// A warning or error of the compiler, exposed to scripts as a map
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostic {
+    pub package_id: String,
+    /// `error`, `warning`, `note`, ...
+    pub level: String,
+    pub message: String,
+    /// Like `E0308` or `unused_variables`
+    pub code: Option<String>,
+    /// Where the diagnostic points to
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    /// As the compiler would print it
+    pub rendered: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CargoResult {
+    pub exit_code: Option<i32>, // remove `pub` after mocking
+    /// Signal cargo was killed by, like `SIGKILL` (9) by the OOM killer, instead of exiting
+    pub signal: Option<i32>,
+    /// How long cargo ran, until all of its output was read
+    pub duration: std::time::Duration,
+    pub stdout: String,
+    pub stderr: String,
+    /// Only parsed by [`Run::run_json`]
+    pub artifacts: Vec<Artifact>,
+    /// Only parsed by [`Run::run_json`]
+    pub diagnostics: Vec<Diagnostic>,
+    /// All of stdout, if written to a file
+    pub stdout_path: Option<PathBuf>,
+    /// All of stderr, if written to a file
+    pub stderr_path: Option<PathBuf>,
+    /// Part of the output was left out of `stdout` or `stderr`, see [`Output::limit`]
+    pub truncated: bool,
+    /// Hits and misses of sccache while cargo ran, if it built through it
+    pub sccache: Option<sccache::CacheStats>,
+    /// CPU time, peak memory and disk I/O of cargo and what it ran
+    pub usage: Option<super::usage::Usage>,
+}
+
+impl CargoResult {
+    fn failed(program: &str, e: std::io::Error) -> Self {
+        CargoResult {
+            exit_code: Some(-1),
+            stderr: format!("Error executing {}: {}", program, e),
+            ..Default::default()
+        }
+    }
+
+    // The &mut self is required by
+    // [rhai](https://rhai.rs/book/rust/custom.html#first-parameter-must-be-mut).
+    #[allow(clippy::wrong_self_convention)]
+    pub fn is_ok(&mut self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// The code cargo exited with, `()` if it was killed
+    pub fn get_exit_code(&mut self) -> rhai::Dynamic {
+        match self.exit_code {
+            Some(code) => (code as rhai::INT).into(),
+            None => rhai::Dynamic::UNIT,
+        }
+    }
+
+    /// The signal cargo was killed by, `()` if it exited
+    pub fn get_signal(&mut self) -> rhai::Dynamic {
+        match self.signal {
+            Some(signal) => (signal as rhai::INT).into(),
+            None => rhai::Dynamic::UNIT,
+        }
+    }
+
+    pub fn get_duration_ms(&mut self) -> rhai::INT {
+        self.duration.as_millis() as rhai::INT
+    }
+
+    pub fn get_stderr(&mut self) -> String {
+        self.stderr.clone()
+    }
+
+    pub fn get_stdout(&mut self) -> String {
+        self.stdout.clone()
+    }
+
+    pub fn get_stdout_path(&mut self) -> rhai::Dynamic {
+        path_or_unit(&self.stdout_path)
+    }
+
+    pub fn get_stderr_path(&mut self) -> rhai::Dynamic {
+        path_or_unit(&self.stderr_path)
+    }
+
+    pub fn get_truncated(&mut self) -> bool {
+        self.truncated
+    }
+
+    /// `#{ cpu_s, user_s, system_s, max_rss, read_bytes, written_bytes }` of cargo and what it
+    /// ran, `()` if it couldn't be told
+    pub fn get_usage(&mut self) -> rhai::Dynamic {
+        match self.usage {
+            Some(usage) => usage.to_dynamic(),
+            None => rhai::Dynamic::UNIT,
+        }
+    }
+
+    /// `#{ hits, misses, hit_rate }` of sccache, `()` if cargo didn't build through it
+    pub fn get_sccache(&mut self) -> rhai::Dynamic {
+        match self.sccache {
+            Some(stats) => stats.to_dynamic(),
+            None => rhai::Dynamic::UNIT,
+        }
+    }
+
+    pub fn get_artifacts(&mut self) -> rhai::Array {
+        self.artifacts
+            .iter()
+            .filter_map(|artifact| rhai::serde::to_dynamic(artifact).ok())
+            .collect()
+    }
+
+    pub fn get_diagnostics(&mut self) -> rhai::Array {
+        self.diagnostics
+            .iter()
+            .filter_map(|diagnostic| rhai::serde::to_dynamic(diagnostic).ok())
+            .collect()
+    }
+
+    /// Seconds it took to build each target that wasn't fresh by name, adding up the ones of the
+    /// same name (like build scripts). Only meaningful with `-j 1`, see [`Artifact::duration`].
+    pub fn get_timings(&mut self) -> rhai::Map {
+        let mut timings = rhai::Map::new();
+        for artifact in self.artifacts.iter().filter(|artifact| !artifact.fresh) {
+            let total = timings
+                .entry(artifact.name.as_str().into())
+                .or_insert_with(|| rhai::Dynamic::from_float(0.0));
+            *total = rhai::Dynamic::from_float(total.as_float().unwrap_or(0.0) + artifact.duration);
+        }
+        timings
+    }
+}
+
+fn path_or_unit(path: &Option<PathBuf>) -> rhai::Dynamic {
+    match path {
+        Some(path) => path.to_string_lossy().into_owned().into(),
+        None => rhai::Dynamic::UNIT,
+    }
+}
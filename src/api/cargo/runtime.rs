@@ -0,0 +1,139 @@
+//! Building the wasm runtime of a Substrate chain like srtool does, so the size and hash of the
+//! blob only change when the runtime does, and tracking them catches size regressions:
+//!
+//! ```rhai
+//! let runtime = build_runtime("kitchensink-runtime");
+//! OUTPUT.set("runtime_hash", runtime.hash);
+//! ```
+//!
+//! The size of the blob is recorded like `binary_size` records it.
+//!
+//! The build is `--locked` and in the `production` profile unless given another one. Paths are
+//! remapped to `/build` and incremental compilation is off, so the blob doesn't depend on where
+//! the checkout is or what was built in it before.
+
+use super::CargoResult;
+use std::path::{Path, PathBuf};
+
+/// Profile runtimes are released with
+pub const DEFAULT_PROFILE: &str = "production";
+
+/// Where the paths of the checkout appear to be in the blob, like in the container of srtool
+const BUILD_DIR: &str = "/build";
+
+/// Arguments to cargo to build the runtime `package` in `profile`
+pub fn args(package: &str, profile: &str) -> Vec<String> {
+    ["build", "--locked", "--profile", profile, "-p", package]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+/// Environment of a deterministic build in the checkout `dir`
+pub fn envs(dir: &Path) -> Vec<(String, String)> {
+    vec![
+        ("CARGO_INCREMENTAL".to_string(), "0".to_string()),
+        ("WASM_BUILD_NO_COLOR".to_string(), "1".to_string()),
+        (
+            "WASM_BUILD_RUSTFLAGS".to_string(),
+            format!("--remap-path-prefix={}={BUILD_DIR}", dir.display()),
+        ),
+    ]
+}
+
+/// The blob `wasm-builder` left for `package` in `target`, compressed if it could be
+fn find(target: &Path, package: &str, profile: &str) -> Option<PathBuf> {
+    let profile_dir = match profile {
+        "dev" | "test" => "debug",
+        "release" | "bench" => "release",
+        profile => profile,
+    };
+    let dir = target.join(profile_dir).join("wbuild").join(package);
+    let name = package.replace('-', "_");
+    ["compact.compressed.wasm", "compact.wasm", "wasm"]
+        .iter()
+        .map(|extension| dir.join(format!("{name}.{extension}")))
+        .find(|path| path.is_file())
+}
+
+/// A runtime built by `build_runtime`
+#[derive(Clone, Debug)]
+pub struct RuntimeBuild {
+    pub cargo: CargoResult,
+    /// Of the blob, None if the build failed
+    pub wasm: Option<PathBuf>,
+    /// Of the blob, in bytes
+    pub size: u64,
+    /// SHA-256 of the blob, in hex
+    pub hash: String,
+}
+
+impl RuntimeBuild {
+    /// The runtime `package` cargo built in `profile` into `target`. Fails if cargo succeeded but
+    /// there's no blob, like when `package` isn't a runtime.
+    pub fn new(
+        mut cargo: CargoResult,
+        target: &Path,
+        package: &str,
+        profile: &str,
+    ) -> Result<Self, String> {
+        if !cargo.is_ok() {
+            return Ok(RuntimeBuild {
+                cargo,
+                wasm: None,
+                size: 0,
+                hash: String::new(),
+            });
+        }
+        let wasm = find(target, package, profile).ok_or_else(|| {
+            format!("Built {package}, but it left no wasm blob in {target:?}, is it a runtime?")
+        })?;
+        let blob = std::fs::read(&wasm).map_err(|e| format!("Failed to read {wasm:?}: {e}"))?;
+        let hash = openssl::sha::sha256(&blob)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        Ok(RuntimeBuild {
+            cargo,
+            wasm: Some(wasm),
+            size: blob.len() as u64,
+            hash,
+        })
+    }
+
+    pub fn get_success(&mut self) -> bool {
+        self.wasm.is_some()
+    }
+
+    /// What cargo did, named so since `cargo` is a keyword of scripts
+    pub fn get_result(&mut self) -> CargoResult {
+        self.cargo.clone()
+    }
+
+    /// `()` if the build failed
+    pub fn get_path(&mut self) -> rhai::Dynamic {
+        match &self.wasm {
+            Some(wasm) => wasm.to_string_lossy().into_owned().into(),
+            None => rhai::Dynamic::UNIT,
+        }
+    }
+
+    /// `()` if the build failed
+    pub fn get_size(&mut self) -> rhai::Dynamic {
+        match self.wasm {
+            Some(_) => rhai::Dynamic::from(
+                super::super::units::Quantity::new(self.size as f64, "B")
+                    .expect("B is a known unit"),
+            ),
+            None => rhai::Dynamic::UNIT,
+        }
+    }
+
+    /// `()` if the build failed
+    pub fn get_hash(&mut self) -> rhai::Dynamic {
+        match self.wasm {
+            Some(_) => self.hash.clone().into(),
+            None => rhai::Dynamic::UNIT,
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Building through [sccache](https://github.com/mozilla/sccache), optionally with a cache
+//! shared by all machines. Runs of cargo count the hits and misses of sccache while they build
+//! (from the difference in `sccache --show-stats` before and after), so jobs can report how much
+//! the cache saved them.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(
+        "Invalid sccache endpoint {0:?}, expected redis://, rediss://, s3://<bucket>, \
+         gs://<bucket>, http:// or https://"
+    )]
+    Endpoint(String),
+}
+
+/// Remote storage of the cache, local to the machine if none
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Redis(String),
+    S3 {
+        bucket: String,
+        prefix: Option<String>,
+    },
+    Gcs {
+        bucket: String,
+        prefix: Option<String>,
+    },
+    Webdav(String),
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bucket = |rest: &str| match rest.split_once('/') {
+            Some((bucket, prefix)) if !prefix.is_empty() => {
+                (bucket.to_string(), Some(prefix.to_string()))
+            }
+            Some((bucket, _)) => (bucket.to_string(), None),
+            None => (rest.to_string(), None),
+        };
+        match s.split_once("://") {
+            Some(("redis" | "rediss", _)) => Ok(Endpoint::Redis(s.to_string())),
+            Some(("s3", rest)) if !rest.is_empty() => {
+                let (bucket, prefix) = bucket(rest);
+                Ok(Endpoint::S3 { bucket, prefix })
+            }
+            Some(("gs", rest)) if !rest.is_empty() => {
+                let (bucket, prefix) = bucket(rest);
+                Ok(Endpoint::Gcs { bucket, prefix })
+            }
+            Some(("http" | "https", _)) => Ok(Endpoint::Webdav(s.to_string())),
+            _ => Err(Error::Endpoint(s.to_string())),
+        }
+    }
+}
+
+/// Where sccache keeps the cache and how it gets in
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub endpoint: Option<Endpoint>,
+    /// `<access key ID>:<secret access key>` for S3, the path of the service account key for
+    /// GCS and the token for WebDAV. Redis takes them in the URL.
+    pub credentials: Option<String>,
+}
+
+impl Config {
+    /// Environment sccache reads the configuration from
+    fn envs(&self) -> Vec<(String, String)> {
+        let mut envs = vec![];
+        let mut set = |name: &str, value: &str| envs.push((name.to_string(), value.to_string()));
+        let credentials = self.credentials.as_deref();
+        match &self.endpoint {
+            None => {}
+            Some(Endpoint::Redis(url)) => set("SCCACHE_REDIS", url),
+            Some(Endpoint::S3 { bucket, prefix }) => {
+                set("SCCACHE_BUCKET", bucket);
+                if let Some(prefix) = prefix {
+                    set("SCCACHE_S3_KEY_PREFIX", prefix);
+                }
+                if let Some((id, secret)) = credentials.and_then(|c| c.split_once(':')) {
+                    set("AWS_ACCESS_KEY_ID", id);
+                    set("AWS_SECRET_ACCESS_KEY", secret);
+                }
+            }
+            Some(Endpoint::Gcs { bucket, prefix }) => {
+                set("SCCACHE_GCS_BUCKET", bucket);
+                if let Some(prefix) = prefix {
+                    set("SCCACHE_GCS_KEY_PREFIX", prefix);
+                }
+                if let Some(key_path) = credentials {
+                    set("SCCACHE_GCS_KEY_PATH", key_path);
+                    set("SCCACHE_GCS_RW_MODE", "READ_WRITE");
+                }
+            }
+            Some(Endpoint::Webdav(url)) => {
+                set("SCCACHE_WEBDAV_ENDPOINT", url);
+                if let Some(token) = credentials {
+                    set("SCCACHE_WEBDAV_TOKEN", token);
+                }
+            }
+        }
+        envs
+    }
+}
+
+/// Hits and misses of the cache
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Share of the compilations that were hits, none if nothing was compiled
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        (total > 0).then(|| self.hits as f64 / total as f64)
+    }
+
+    pub fn add(&mut self, other: CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+    }
+
+    /// Render as a short footer for a result comment
+    pub fn render(&self) -> String {
+        let rate = match self.hit_rate() {
+            Some(rate) => format!(" ({:.1}% hit rate)", rate * 100.0),
+            None => String::new(),
+        };
+        format!(
+            "<sub>sccache: {} hits, {} misses{rate}</sub>",
+            self.hits, self.misses
+        )
+    }
+
+    pub(crate) fn to_dynamic(self) -> rhai::Dynamic {
+        let mut map = rhai::Map::new();
+        map.insert("hits".into(), (self.hits as rhai::INT).into());
+        map.insert("misses".into(), (self.misses as rhai::INT).into());
+        map.insert(
+            "hit_rate".into(),
+            match self.hit_rate() {
+                Some(rate) => rate.into(),
+                None => rhai::Dynamic::UNIT,
+            },
+        );
+        rhai::Dynamic::from_map(map)
+    }
+}
+
+/// Whether the cargo runs of a job build through sccache, and the hits and misses they had so
+/// far. Builds without sccache unless created with [`Sccache::new`].
+#[derive(Clone, Debug, Default)]
+pub struct Sccache {
+    config: Option<Config>,
+    total: Arc<Mutex<CacheStats>>,
+}
+
+impl Sccache {
+    pub fn new(config: Config) -> Self {
+        Sccache {
+            config: Some(config),
+            total: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Environment of cargo to build through sccache, nothing if it doesn't
+    pub fn envs(&self) -> Vec<(String, String)> {
+        match &self.config {
+            Some(config) => {
+                let wrapper = executable().to_string_lossy().into_owned();
+                let mut envs = vec![("RUSTC_WRAPPER".to_string(), wrapper)];
+                envs.extend(config.envs());
+                envs
+            }
+            None => vec![],
+        }
+    }
+
+    /// What sccache counted so far, none if it can't tell
+    pub(crate) fn stats(&self, envs: &[(String, String)]) -> Option<CacheStats> {
+        let output = std::process::Command::new(executable())
+            .args(["--show-stats", "--stats-format", "json"])
+            .envs(envs.iter().cloned())
+            .output();
+        let output = match output {
+            Ok(output) if output.status.success() => output.stdout,
+            Ok(output) => {
+                tracing::warn!(
+                    "Failed to get the stats of sccache: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                return None;
+            }
+            Err(err) => {
+                tracing::warn!("Failed to run sccache: {err}");
+                return None;
+            }
+        };
+        let stats: serde_json::Value = serde_json::from_slice(&output).ok()?;
+        // Counts are per language, like `{"counts": {"Rust": 12}}`, or totals in old versions
+        let count = |key: &str| match &stats["stats"][key] {
+            serde_json::Value::Number(count) => count.as_u64(),
+            value => value["counts"]
+                .as_object()
+                .map(|counts| counts.values().filter_map(|count| count.as_u64()).sum()),
+        };
+        Some(CacheStats {
+            hits: count("cache_hits")?,
+            misses: count("cache_misses")?,
+        })
+    }
+
+    /// Count what happened between `before` and now towards the total of the job
+    pub(crate) fn since(
+        &self,
+        before: Option<CacheStats>,
+        envs: &[(String, String)],
+    ) -> Option<CacheStats> {
+        let (before, after) = (before?, self.stats(envs)?);
+        // The server restarted in between, it counts from zero again
+        let since = if after.hits < before.hits || after.misses < before.misses {
+            after
+        } else {
+            CacheStats {
+                hits: after.hits - before.hits,
+                misses: after.misses - before.misses,
+            }
+        };
+        self.total
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .add(since);
+        Some(since)
+    }
+
+    /// Hits and misses of the job so far, none if it doesn't build through sccache
+    pub fn total(&self) -> Option<CacheStats> {
+        self.is_enabled()
+            .then(|| *self.total.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Where sccache is on the `PATH` of the bot, since cargo runs without one
+fn executable() -> PathBuf {
+    std::env::var_os("PATH")
+        .and_then(|path| {
+            std::env::split_paths(&path)
+                .map(|dir| dir.join("sccache"))
+                .find(|candidate| candidate.is_file())
+        })
+        .unwrap_or_else(|| PathBuf::from("sccache"))
+}
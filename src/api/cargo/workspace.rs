@@ -0,0 +1,162 @@
+//! The crates of a cargo workspace, so scripts in huge monorepos can only build or benchmark the
+//! ones a pull request touches. `affected_crates()` maps the files the pull request changes to
+//! the members they belong to, adding the members depending on those:
+//!
+//! ```rhai
+//! for krate in affected_crates() {
+//!     cargo `bench -p ${krate}`;
+//! }
+//! ```
+//!
+//! Outside of pull requests (or with `run-local`) the changed files aren't known and all members
+//! are affected, unless the script passes them, like `affected_crates(REPO.diff("master"))`.
+
+use super::Run;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("cargo metadata failed: {0}")]
+    Metadata(String),
+    #[error("Failed to parse the output of cargo metadata: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+/// Files changing which affects every member, like the versions of all dependencies
+const SHARED_FILES: [&str; 2] = ["Cargo.toml", "Cargo.lock"];
+
+/// A member of the workspace
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub name: String,
+    /// Directory of its manifest, relative to the root of the workspace (empty for the root
+    /// package)
+    pub dir: PathBuf,
+    /// Names of the members it depends on by path
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Workspace {
+    pub members: Vec<Member>,
+}
+
+impl Workspace {
+    /// Ask cargo about the workspace in `dir`
+    pub fn load<P: AsRef<Path>>(dir: P, envs: &[(String, String)]) -> Result<Self, Error> {
+        let mut result = Run::new(["metadata", "--no-deps", "--format-version", "1"], dir)
+            .envs(envs)
+            .run();
+        if !result.is_ok() {
+            return Err(Error::Metadata(result.stderr));
+        }
+        Ok(Self::parse(&result.stdout)?)
+    }
+
+    /// Read the output of `cargo metadata --no-deps --format-version 1`
+    fn parse(metadata: &str) -> Result<Self, serde_json::Error> {
+        let metadata: Metadata = serde_json::from_str(metadata)?;
+        let dir_of = |manifest: &Path| {
+            let dir = manifest.parent().unwrap_or(manifest);
+            dir.strip_prefix(&metadata.workspace_root)
+                .unwrap_or(dir)
+                .to_path_buf()
+        };
+        let members: Vec<_> = metadata
+            .packages
+            .iter()
+            .filter(|package| metadata.workspace_members.contains(&package.id))
+            .collect();
+        let names: HashMap<_, _> = members
+            .iter()
+            .map(|package| (dir_of(&package.manifest_path), package.name.clone()))
+            .collect();
+        let members = members
+            .iter()
+            .map(|package| Member {
+                name: package.name.clone(),
+                dir: dir_of(&package.manifest_path),
+                dependencies: package
+                    .dependencies
+                    .iter()
+                    .filter_map(|dependency| {
+                        let dir = dependency.path.as_ref()?;
+                        let dir = dir.strip_prefix(&metadata.workspace_root).unwrap_or(dir);
+                        names.get(dir).cloned()
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(Workspace { members })
+    }
+
+    /// Names of the members containing any of `changed_files` (paths relative to the root of the
+    /// workspace) and of the members depending on those, all of them if a file shared by all of
+    /// them changed or `changed_files` isn't known
+    pub fn affected<S: AsRef<str>>(&self, changed_files: Option<&[S]>) -> Vec<String> {
+        let changed_files = match changed_files {
+            Some(changed_files) => changed_files,
+            None => return self.members.iter().map(|m| m.name.clone()).collect(),
+        };
+        if changed_files
+            .iter()
+            .any(|file| SHARED_FILES.contains(&file.as_ref()))
+        {
+            return self.members.iter().map(|m| m.name.clone()).collect();
+        }
+        let mut affected: BTreeSet<String> = changed_files
+            .iter()
+            .filter_map(|file| self.member_of(Path::new(file.as_ref())))
+            .map(|member| member.name.clone())
+            .collect();
+        // Until no more members depend on affected ones
+        loop {
+            let dependents: Vec<_> = self
+                .members
+                .iter()
+                .filter(|member| !affected.contains(&member.name))
+                .filter(|member| member.dependencies.iter().any(|d| affected.contains(d)))
+                .map(|member| member.name.clone())
+                .collect();
+            if dependents.is_empty() {
+                break;
+            }
+            affected.extend(dependents);
+        }
+        affected.into_iter().collect()
+    }
+
+    /// The member with the innermost directory containing `file`
+    fn member_of(&self, file: &Path) -> Option<&Member> {
+        self.members
+            .iter()
+            .filter(|member| file.starts_with(&member.dir))
+            .max_by_key(|member| member.dir.components().count())
+    }
+}
+
+/// What's used of `cargo metadata`, see
+/// <https://doc.rust-lang.org/cargo/commands/cargo-metadata.html#json-format>
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    workspace_members: Vec<String>,
+    workspace_root: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    id: String,
+    name: String,
+    manifest_path: PathBuf,
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Deserialize)]
+struct Dependency {
+    /// Only for path dependencies
+    path: Option<PathBuf>,
+}
@@ -0,0 +1,259 @@
+//! Line charts of metrics over runs, like "the runtime of the last 30 nightly runs", rendered to
+//! PNG or SVG and uploaded as artifacts so result comments can embed them:
+//!
+//! ```rhai
+//! let c = chart("Block import");
+//! c.line("import", RESULTS.history("import", "master", 30));
+//! c.set_unit("s");
+//! let url = upload_chart(c, "import.svg");
+//! ISSUE.comment(`![Block import](${url})`);
+//! ```
+
+use super::units::Quantity;
+use plotters::prelude::*;
+use rhai::plugin::*;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Unknown chart format {0:?}, expected a name ending in .png or .svg")]
+    UnknownFormat(String),
+    #[error("The chart has no values to draw")]
+    Empty,
+    #[error("Failed to render the chart: {0}")]
+    Render(String),
+}
+
+/// Colors of the lines, in the order they're added
+const PALETTE: [RGBColor; 6] = [
+    RGBColor(0x1f, 0x77, 0xb4),
+    RGBColor(0xff, 0x7f, 0x0e),
+    RGBColor(0x2c, 0xa0, 0x2c),
+    RGBColor(0xd6, 0x27, 0x28),
+    RGBColor(0x94, 0x67, 0xbd),
+    RGBColor(0x8c, 0x56, 0x4b),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Svg,
+}
+
+impl Format {
+    /// The format of a file named `name`, by its extension
+    pub fn of(name: &str) -> Result<Self, Error> {
+        let extension = Path::new(name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => Ok(Format::Png),
+            Some("svg") => Ok(Format::Svg),
+            _ => Err(Error::UnknownFormat(name.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Series {
+    name: String,
+    values: Vec<f64>,
+}
+
+/// A line chart, one line per series, with the runs along the x axis, oldest first
+#[derive(Clone, Debug)]
+pub struct Chart {
+    title: String,
+    /// Of the y axis
+    unit: Option<String>,
+    series: Vec<Series>,
+    /// Of the runs along the x axis, like commits or dates; their index otherwise
+    labels: Vec<String>,
+    width: u32,
+    height: u32,
+}
+
+impl Chart {
+    pub fn new<S: Into<String>>(title: S) -> Self {
+        Chart {
+            title: title.into(),
+            unit: None,
+            series: vec![],
+            labels: vec![],
+            width: 800,
+            height: 400,
+        }
+    }
+
+    pub fn line<S: Into<String>>(&mut self, name: S, values: Vec<f64>) {
+        self.series.push(Series {
+            name: name.into(),
+            values,
+        });
+    }
+
+    /// Render to the file at `path`, as PNG or SVG depending on its extension
+    pub fn render(&self, path: &Path) -> Result<(), Error> {
+        match Format::of(&path.to_string_lossy())? {
+            Format::Png => self.draw(BitMapBackend::new(path, (self.width, self.height))),
+            Format::Svg => self.draw(SVGBackend::new(path, (self.width, self.height))),
+        }
+    }
+
+    fn draw<DB: DrawingBackend>(&self, backend: DB) -> Result<(), Error> {
+        let runs = self
+            .series
+            .iter()
+            .map(|series| series.values.len())
+            .max()
+            .unwrap_or_default();
+        let values = || {
+            self.series
+                .iter()
+                .flat_map(|series| series.values.iter().copied())
+                .filter(|value| value.is_finite())
+        };
+        let (min, max) = match (values().reduce(f64::min), values().reduce(f64::max)) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Err(Error::Empty),
+        };
+        // Some room above and below the lines, and a range to draw even if they're flat
+        let margin = match (max - min) * 0.1 {
+            margin if margin > 0.0 => margin,
+            _ => min.abs().max(1.0) * 0.1,
+        };
+
+        let root = backend.into_drawing_area();
+        let render = |err: DrawingAreaErrorKind<DB::ErrorType>| Error::Render(err.to_string());
+        root.fill(&WHITE).map_err(render)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(&self.title, ("sans-serif", 22))
+            .margin(12)
+            .x_label_area_size(30)
+            .y_label_area_size(90)
+            .build_cartesian_2d(0..runs.max(2) - 1, (min - margin)..(max + margin))
+            .map_err(render)?;
+        let labels = &self.labels;
+        let unit = self.unit.as_deref().unwrap_or_default();
+        chart
+            .configure_mesh()
+            .x_labels(runs.min(10))
+            .x_label_formatter(&|run| match labels.get(*run) {
+                Some(label) => label.clone(),
+                None => (run + 1).to_string(),
+            })
+            // Quantities like durations scale to the unit that suits them best
+            .y_label_formatter(&|value| match Quantity::new(*value, unit) {
+                Ok(quantity) => quantity.to_string(),
+                Err(_) => format!("{value:.*} {unit}", precision(margin)),
+            })
+            .draw()
+            .map_err(render)?;
+        for (index, series) in self.series.iter().enumerate() {
+            let color = PALETTE[index % PALETTE.len()];
+            let points = || {
+                series
+                    .values
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .filter(|(_, value)| value.is_finite())
+            };
+            chart
+                .draw_series(LineSeries::new(points(), color.stroke_width(2)))
+                .map_err(render)?
+                .label(&series.name)
+                .legend(move |(x, y)| {
+                    PathElement::new([(x, y), (x + 16, y)], color.stroke_width(2))
+                });
+            chart
+                .draw_series(points().map(|point| Circle::new(point, 3, color.filled())))
+                .map_err(render)?;
+        }
+        if self.series.len() > 1 {
+            chart
+                .configure_series_labels()
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()
+                .map_err(render)?;
+        }
+        root.present().map_err(render)
+    }
+}
+
+/// Decimals to tell the ticks of an axis apart with, going by the room around its values
+fn precision(margin: f64) -> usize {
+    (-margin.log10().floor()).clamp(0.0, 6.0) as usize
+}
+
+/// A value of a series: a number, or a quantity in the base unit of its kind
+fn value(value: Dynamic) -> Result<f64, Box<EvalAltResult>> {
+    if value.is::<Quantity>() {
+        Ok(value.cast::<Quantity>().value)
+    } else if let Ok(int) = value.as_int() {
+        Ok(int as f64)
+    } else if let Ok(float) = value.as_float() {
+        Ok(float)
+    } else {
+        Err(format!(
+            "Can't chart {}, only numbers and quantities",
+            value.type_name()
+        )
+        .into())
+    }
+}
+
+#[export_module]
+pub mod functions {
+    use super::Chart;
+    use rhai::{Array, INT};
+
+    /// An empty line chart titled `title`
+    pub fn chart(title: &str) -> Chart {
+        Chart::new(title)
+    }
+
+    /// Add a line named `name` through `values`, oldest first. Quantities are drawn in the base
+    /// unit of their kind, which becomes the unit of the chart unless it has one.
+    #[rhai_fn(global, return_raw)]
+    pub fn line(chart: &mut Chart, name: &str, values: Array) -> Result<(), Box<EvalAltResult>> {
+        if chart.unit.is_none() {
+            chart.unit = values
+                .iter()
+                .find(|value| value.is::<super::Quantity>())
+                .map(|value| value.clone_cast::<super::Quantity>().unit().to_string());
+        }
+        let values = values
+            .into_iter()
+            .map(super::value)
+            .collect::<Result<_, _>>()?;
+        chart.line(name, values);
+        Ok(())
+    }
+
+    /// Unit of the y axis
+    #[rhai_fn(global)]
+    pub fn set_unit(chart: &mut Chart, unit: &str) {
+        chart.unit = Some(unit.to_string());
+    }
+
+    /// Labels of the runs along the x axis, like their dates or commits
+    #[rhai_fn(global)]
+    pub fn set_labels(chart: &mut Chart, labels: Array) {
+        chart.labels = labels.into_iter().map(|label| label.to_string()).collect();
+    }
+
+    /// Size of the image in pixels, 800 by 400 by default
+    #[rhai_fn(global)]
+    pub fn set_size(chart: &mut Chart, width: INT, height: INT) {
+        chart.width = width.clamp(100, 4000) as u32;
+        chart.height = height.clamp(100, 4000) as u32;
+    }
+
+    #[rhai_fn(global, name = "to_string", name = "to_debug", pure)]
+    pub fn to_string(chart: &mut Chart) -> String {
+        format!("chart({:?})", chart.title)
+    }
+}
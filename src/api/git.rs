@@ -5,6 +5,7 @@ use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
+use tracing::Instrument;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -44,6 +45,122 @@ pub enum Error {
     CurrentBranchInvalidUTF8,
     #[error("Remote URL contains invalid UTF-8")]
     RemoteInvalidUTF8,
+    #[error("{0}")]
+    Forge(#[from] crate::forge::Error),
+    #[error("`git {args}` failed: {stderr}")]
+    GitCommand { args: String, stderr: String },
+    #[error("Not allowed: {0}")]
+    PrPolicy(String),
+    #[error("Failed to sign commit: {0}")]
+    Signing(String),
+    #[error("Failed to push {branch}: {reason}")]
+    PushRejected {
+        branch: String,
+        reason: PushRejection,
+    },
+}
+
+/// Why a push failed, handed to scripts as the `kind` of the error they catch
+#[derive(Debug)]
+pub enum PushRejection {
+    /// The branch moved on the remote, and the push would lose its commits
+    NonFastForward,
+    /// The branch moved on the remote since it was last fetched or pushed
+    StaleLease,
+    /// Rebasing the commit onto the remote branch conflicted
+    Conflict(String),
+    /// The credentials were rejected
+    Auth(String),
+    /// Rejected for another reason, like a protected branch
+    Remote(String),
+}
+
+impl PushRejection {
+    fn kind(&self) -> &'static str {
+        match self {
+            PushRejection::NonFastForward => "non-fast-forward",
+            PushRejection::StaleLease => "stale-lease",
+            PushRejection::Conflict(_) => "conflict",
+            PushRejection::Auth(_) => "auth",
+            PushRejection::Remote(_) => "rejected",
+        }
+    }
+}
+
+impl std::fmt::Display for PushRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PushRejection::NonFastForward => {
+                write!(f, "the remote branch has commits that aren't here")
+            }
+            PushRejection::StaleLease => {
+                write!(f, "the remote branch changed since it was last fetched")
+            }
+            PushRejection::Conflict(commit) => {
+                write!(f, "{commit} conflicts with the remote branch")
+            }
+            PushRejection::Auth(message) => write!(f, "authentication failed: {message}"),
+            PushRejection::Remote(message) => write!(f, "rejected by the remote: {message}"),
+        }
+    }
+}
+
+/// How `push` handles a branch that moved on the remote
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PushMode {
+    /// Fail, the default
+    FastForward,
+    /// Replay the local commits on top of it first
+    Rebase,
+    /// Overwrite it, unless it changed since it was last fetched or pushed
+    ForceWithLease,
+}
+
+impl std::str::FromStr for PushMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast-forward" => Ok(PushMode::FastForward),
+            "rebase" => Ok(PushMode::Rebase),
+            "force-with-lease" => Ok(PushMode::ForceWithLease),
+            _ => Err(format!(
+                "Unknown push mode `{s}`, expected `fast-forward`, `rebase` or `force-with-lease`"
+            )),
+        }
+    }
+}
+
+/// Times pushing is attempted when the remote can't be reached
+const PUSH_ATTEMPTS: u32 = 3;
+
+/// Whether `err` is about reaching the remote rather than what was pushed
+fn is_transient(err: &git2::Error) -> bool {
+    matches!(
+        err.class(),
+        git2::ErrorClass::Net
+            | git2::ErrorClass::Http
+            | git2::ErrorClass::Ssh
+            | git2::ErrorClass::Os
+    ) && err.code() != git2::ErrorCode::Auth
+}
+
+/// Push failures as an error scripts can catch and tell apart:
+/// `#{ kind: "non-fast-forward", branch: "...", message: "..." }`
+fn push_error(err: Error) -> Box<rhai::EvalAltResult> {
+    match &err {
+        Error::PushRejected { branch, reason } => {
+            let mut map = rhai::Map::new();
+            map.insert("kind".into(), reason.kind().into());
+            map.insert("branch".into(), branch.clone().into());
+            map.insert("message".into(), err.to_string().into());
+            Box::new(rhai::EvalAltResult::ErrorRuntime(
+                map.into(),
+                rhai::Position::NONE,
+            ))
+        }
+        _ => format!("{err}").into(),
+    }
 }
 
 impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, git2::Repository>>> for Error {
@@ -52,19 +169,391 @@ impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, git2::Repository>>> f
     }
 }
 
+/// How to authenticate to remotes when cloning and fetching
+#[derive(Clone, Default)]
+pub enum Credentials {
+    /// Only public repositories can be cloned
+    #[default]
+    None,
+    /// Github App installation access token, used as the password of `x-access-token`
+    Token(String),
+    /// SSH key, which requires cloning over SSH
+    SshKey {
+        private_key: PathBuf,
+        passphrase: Option<String>,
+    },
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::None => write!(f, "None"),
+            Credentials::Token(_) => write!(f, "Token(..)"),
+            Credentials::SshKey { private_key, .. } => write!(f, "SshKey({:?})", private_key),
+        }
+    }
+}
+
+impl Credentials {
+    /// The URL to clone an `https://` URL from with these credentials
+    pub fn url(&self, url: &str) -> String {
+        match self {
+            Credentials::SshKey { .. } => match url::Url::parse(url) {
+                Ok(parsed) if parsed.scheme() == "https" => match parsed.host_str() {
+                    Some(host) => format!("git@{}:{}", host, parsed.path().trim_start_matches('/')),
+                    None => url.to_string(),
+                },
+                _ => url.to_string(),
+            },
+            _ => url.to_string(),
+        }
+    }
+
+    /// Callbacks providing the credentials to libgit2
+    pub(crate) fn remote_callbacks(&self) -> git2::RemoteCallbacks<'_> {
+        let mut callbacks = git2::RemoteCallbacks::new();
+        // libgit2 keeps asking for as long as the credentials are rejected
+        let mut attempted = false;
+        match self {
+            Credentials::None => {}
+            Credentials::Token(token) => {
+                callbacks.credentials(move |_url, _username_from_url, _allowed_types| {
+                    if std::mem::replace(&mut attempted, true) {
+                        return Err(git2::Error::from_str("Access token was rejected"));
+                    }
+                    git2::Cred::userpass_plaintext("x-access-token", token)
+                });
+            }
+            Credentials::SshKey {
+                private_key,
+                passphrase,
+            } => {
+                callbacks.credentials(move |_url, username_from_url, _allowed_types| {
+                    if std::mem::replace(&mut attempted, true) {
+                        return Err(git2::Error::from_str("SSH key was rejected"));
+                    }
+                    git2::Cred::ssh_key(
+                        username_from_url.unwrap_or("git"),
+                        None,
+                        private_key,
+                        passphrase.as_deref(),
+                    )
+                });
+            }
+        }
+        callbacks
+    }
+
+    pub(crate) fn fetch_options(&self) -> git2::FetchOptions<'_> {
+        let mut options = git2::FetchOptions::new();
+        options.remote_callbacks(self.remote_callbacks());
+        options
+    }
+
+    /// A `git` command that authenticates with these credentials. Tokens are passed through the
+    /// environment so they don't show up in the process list. SSH key passphrases aren't
+    /// supported here.
+    fn git_command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new("git");
+        command.env("GIT_TERMINAL_PROMPT", "0");
+        match self {
+            Credentials::None => {}
+            Credentials::Token(token) => {
+                command
+                    .args(["-c", "credential.helper="])
+                    .args([
+                        "-c",
+                        "credential.helper=!f() { echo username=x-access-token; \
+                         echo \"password=$CIS_GIT_TOKEN\"; }; f",
+                    ])
+                    .env("CIS_GIT_TOKEN", token);
+            }
+            Credentials::SshKey { private_key, .. } => {
+                command.env(
+                    "GIT_SSH_COMMAND",
+                    format!(
+                        "ssh -i {} -o IdentitiesOnly=yes -o BatchMode=yes",
+                        shell_words::quote(&private_key.to_string_lossy())
+                    ),
+                );
+            }
+        }
+        command
+    }
+}
+
+/// What pull requests scripts may open with `REPO.create_pr`
+#[derive(Clone, Debug, Default)]
+pub struct PrPolicy {
+    /// Branches pull requests may go into, any if empty. `*` matches any characters.
+    pub bases: Vec<String>,
+    /// Name of the branches the bot opens pull requests from, with `{repo}`, `{issue}` and
+    /// `{name}` filled in, like `benchbot/{repo}/{issue}-{name}`. Scripts get it from
+    /// `REPO.pr_branch(name)`, and can only open pull requests from branches like it, which are
+    /// force pushed.
+    pub branch_template: Option<String>,
+    /// Age of the last commit after which branches like `branch_template` that no pull request
+    /// is open from are deleted, checked whenever a pull request is opened
+    pub stale_after: Option<std::time::Duration>,
+}
+
+impl PrPolicy {
+    fn allows_base(&self, base: &str) -> bool {
+        self.bases.is_empty()
+            || self
+                .bases
+                .iter()
+                .any(|pattern| crate::suites::matches_segment(pattern.as_bytes(), base.as_bytes()))
+    }
+
+    /// The branch named `name` of the issue `issue` of the repository `repo`
+    fn branch(&self, repo: &str, issue: Option<u64>, name: &str) -> Result<String, Error> {
+        let template = match &self.branch_template {
+            Some(template) => template,
+            None => return Ok(name.to_string()),
+        };
+        let issue = match issue {
+            Some(issue) => issue.to_string(),
+            None if template.contains("{issue}") => {
+                return Err(Error::PrPolicy(
+                    "the branch name template needs an issue, and the job has none".to_string(),
+                ))
+            }
+            None => String::new(),
+        };
+        Ok(template
+            .replace("{repo}", repo)
+            .replace("{issue}", &issue)
+            .replace("{name}", name))
+    }
+
+    /// Whether `branch` is one of the bot's, always if there's no template
+    fn is_bot_branch(&self, branch: &str) -> bool {
+        match &self.branch_template {
+            Some(template) => {
+                let pattern = ["{repo}", "{issue}", "{name}"]
+                    .iter()
+                    .fold(template.clone(), |pattern, placeholder| {
+                        pattern.replace(placeholder, "*")
+                    });
+                crate::suites::matches_segment(pattern.as_bytes(), branch.as_bytes())
+            }
+            None => true,
+        }
+    }
+
+    /// What the names of all branches of the bot start with, if they're told apart at all
+    pub fn bot_branch_prefix(&self) -> Option<&str> {
+        let template = self.branch_template.as_deref()?;
+        let prefix = &template[..template.find('{').unwrap_or(template.len())];
+        (!prefix.is_empty()).then_some(prefix)
+    }
+}
+
+/// Who the commits scripts make with `commit` are by, and how they're signed
+#[derive(Clone, Debug, Default)]
+pub struct CommitConfig {
+    /// A placeholder if not set
+    pub author: Option<Identity>,
+    /// The author if not set
+    pub committer: Option<Identity>,
+    pub signing_key: Option<SigningKey>,
+}
+
+/// Name and email of an author or committer, parsed from `Name <email>`
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl std::str::FromStr for Identity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, email) = s
+            .trim()
+            .strip_suffix('>')
+            .and_then(|s| s.split_once('<'))
+            .ok_or_else(|| format!("Expected `Name <email>`, got `{s}`"))?;
+        Ok(Identity {
+            name: name.trim().to_string(),
+            email: email.trim().to_string(),
+        })
+    }
+}
+
+/// Key to sign commits with, for repositories that require signed commits
+#[derive(Clone, Debug)]
+pub enum SigningKey {
+    /// ID or fingerprint of a key in the GPG keyring of the user running the bot
+    Gpg(String),
+    /// Path to an SSH private key, for repositories that accept SSH signatures
+    Ssh(PathBuf),
+}
+
+impl SigningKey {
+    /// Sign the raw `commit` like `git commit -S` does, returning the armored signature
+    fn sign(&self, commit: &str) -> Result<String, Error> {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let mut command = match self {
+            SigningKey::Gpg(key) => {
+                let mut command = Command::new("gpg");
+                command.args(["--batch", "--detach-sign", "--armor", "--local-user", key]);
+                command
+            }
+            SigningKey::Ssh(key) => {
+                let mut command = Command::new("ssh-keygen");
+                command.args(["-Y", "sign", "-n", "git", "-f"]).arg(key);
+                command
+            }
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Signing(format!("{e}")))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(commit.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::Signing(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        String::from_utf8(output.stdout).map_err(|e| Error::Signing(format!("{e}")))
+    }
+}
+
+/// Run the `git` command line in `dir`, for what libgit2 doesn't support
+pub(crate) fn run_git<P: AsRef<Path>, S: AsRef<std::ffi::OsStr>>(
+    dir: P,
+    args: &[S],
+    credentials: &Credentials,
+) -> Result<(), Error> {
+    let output = credentials
+        .git_command()
+        .args(args)
+        .current_dir(dir)
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::GitCommand {
+            args: args
+                .iter()
+                .map(|arg| arg.as_ref().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" "),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+}
+
+/// Whether the repository is a shallow clone. libgit2 only looks at the repository itself, but
+/// for worktrees that's recorded in the repository they were added to.
+fn is_shallow(repo: &git2::Repository) -> bool {
+    let common_dir = || {
+        let common_dir = std::fs::read_to_string(repo.path().join("commondir")).ok()?;
+        Some(repo.path().join(common_dir.trim()))
+    };
+    repo.is_shallow()
+        || (repo.is_worktree() && common_dir().is_some_and(|dir| dir.join("shallow").exists()))
+}
+
+/// Whether the repository is a shallow or partial clone
+pub(crate) fn is_reduced(repo: &git2::Repository) -> bool {
+    is_shallow(repo)
+        || repo
+            .config()
+            .and_then(|config| config.get_bool("remote.origin.promisor"))
+            .unwrap_or(false)
+}
+
+/// Fetch the full history of the repository at `dir` if it's a shallow clone
+pub(crate) fn unshallow<P: AsRef<Path>>(dir: P, credentials: &Credentials) -> Result<(), Error> {
+    let dir = dir.as_ref();
+    if is_shallow(&git2::Repository::open(dir)?) {
+        tracing::info!("Fetching the full history in {:?}", dir);
+        run_git(dir, &["fetch", "--unshallow", "origin"], credentials)?;
+    }
+    Ok(())
+}
+
+/// Build the `git` module, exposing `git::clone(repo, head)` and `git::open(repo)` to scripts.
+pub fn module(git: Git) -> rhai::Module {
+    let mut module = rhai::Module::new();
+    let clone_git = git.clone();
+    module.set_native_fn(
+        "clone",
+        move |repo: rhai::ImmutableString, head: rhai::ImmutableString| {
+            Git::clone(&mut clone_git.clone(), repo.to_string(), head)
+        },
+    );
+    module.set_native_fn("open", move |repo: rhai::ImmutableString| {
+        git.clone().open(repo)
+    });
+    module
+}
+
+/// Deprecated as a scope constant (`Git`) in favour of the `git` module
 #[derive(Clone, Debug)]
 pub struct Git {
     /// Path to the repository owning the script
-    // TODO: Crate initializer so these don't need `pub`
     #[allow(unused)]
-    pub(crate) path: std::path::PathBuf,
+    path: std::path::PathBuf,
     /// Root containing the repositories
-    pub(crate) root: std::path::PathBuf,
-    pub(crate) github_client: Arc<Mutex<octocrab::Octocrab>>,
+    root: std::path::PathBuf,
+    github_client: Arc<Mutex<octocrab::Octocrab>>,
+    credentials: Credentials,
+    commits: CommitConfig,
     //pub(crate) tokio_handle: tokio::runtime::Handle,
 }
 
 impl Git {
+    pub(crate) fn new<P: AsRef<Path>, R: AsRef<Path>>(
+        path: P,
+        root: R,
+        github_client: Arc<Mutex<octocrab::Octocrab>>,
+        credentials: Credentials,
+    ) -> Self {
+        Git {
+            path: path.as_ref().into(),
+            root: root.as_ref().into(),
+            github_client,
+            credentials,
+            commits: Default::default(),
+        }
+    }
+
+    /// Who commits in the repositories scripts clone are by and how they're signed
+    pub(crate) fn with_commits(mut self, commits: CommitConfig) -> Self {
+        self.commits = commits;
+        self
+    }
+
+    /// Open a repository that was previously cloned with `clone`, without fetching anything.
+    pub fn open<S: AsRef<str>>(&mut self, repo: S) -> Result<LocalRepo, Box<rhai::EvalAltResult>> {
+        let repo = repo.as_ref();
+        let (repo_owner, repo_name) = split_repo_name(repo)?;
+        let dir = self.repo_dir(format!("https://github.com/{}", repo));
+        let local_repo = git2::Repository::open(&dir)
+            .map_err(|_| format!("Repository {repo} has not been cloned yet"))?;
+        Ok(LocalRepo::new(
+            dir,
+            repo_owner,
+            repo_name,
+            local_repo,
+            self.github_client.clone(),
+        )
+        .with_credentials(self.credentials.clone())
+        .with_commits(self.commits.clone()))
+    }
+
     // To make the common case both easy and efficient this function both clones and
     // fetches/checksout a ref.
     pub fn clone<S: AsRef<str>>(
@@ -73,12 +562,7 @@ impl Git {
         head: S,
     ) -> Result<LocalRepo, Box<rhai::EvalAltResult>> {
         let url = format!("https://github.com/{}", repo);
-        let (repo_owner, repo_name) = repo.split_at(
-            repo.find('/')
-                .ok_or(format!("Invalid Github Repository name (`owner/repo`)"))?,
-        );
-        let mut repo_name = String::from(repo_name);
-        repo_name.remove(0); // Remove the '/'
+        let (repo_owner, repo_name) = split_repo_name(&repo)?;
         let dir = self.repo_dir(&url);
         let repo = match std::fs::metadata(&dir) {
             Ok(metadata) if metadata.is_dir() => {
@@ -88,15 +572,16 @@ impl Git {
                 // Path doesn't exist
                 let mut checkout = CheckoutBuilder::new();
                 checkout.remove_untracked(true).remove_ignored(true).force();
-                log::info!("Cloning {} to {:?}", &url, &dir);
+                tracing::info!("Cloning {} to {:?}", &url, &dir);
                 RepoBuilder::new()
                     .with_checkout(checkout)
-                    .clone(url.as_ref(), &dir)
+                    .fetch_options(self.credentials.fetch_options())
+                    .clone(&self.credentials.url(&url), &dir)
                     .map_err(|e| format!("{e}"))?
             }
             Ok(_) => {
                 let err = format!("Path {:?} exists but is not a directory", dir);
-                log::warn!("{}", err);
+                tracing::warn!("{}", err);
                 return Err(Box::new(err.into()));
             }
         };
@@ -108,30 +593,45 @@ impl Git {
             head.as_ref(),
             repo,
             self.github_client.clone(),
-        )?;
-        log::info!("Constructed local repo {:?}", repo.dir);
+            self.credentials.clone(),
+        )?
+        .with_commits(self.commits.clone());
+        tracing::info!("Constructed local repo {:?}", repo.dir);
         Ok(repo)
     }
 
     fn repo_dir<U: std::fmt::Display>(&self, url: U) -> PathBuf {
-        log::info!("repos_root: {:?}", &self.root);
+        tracing::info!("repos_root: {:?}", &self.root);
         let full_path = PathBuf::from(&self.root);
         let url = format!("{url}").replace('/', "_");
         let dir_name = format!("{}", &url,);
         let full_path = full_path.join(dir_name);
-        log::debug!("full_path: {:?}", full_path);
+        tracing::debug!("full_path: {:?}", full_path);
         full_path
     }
 }
 
+fn split_repo_name(repo: &str) -> Result<(&str, &str), Box<rhai::EvalAltResult>> {
+    repo.split_once('/')
+        .ok_or_else(|| "Invalid Github Repository name (`owner/repo`)".into())
+}
+
 #[derive(Clone)]
 pub struct LocalRepo {
     dir: PathBuf,
     repo: Arc<Mutex<git2::Repository>>,
-    config: Option<Config>,
+    commits: CommitConfig,
     github_client: Arc<Mutex<octocrab::Octocrab>>,
     github_owner: String,
     github_name: String,
+    credentials: Credentials,
+    /// Where pull requests are opened, the Github repository `github_owner/github_name` if not set
+    forge: Option<Arc<dyn crate::forge::Forge>>,
+    /// What the forge told about the repository, only known for the repository of the job
+    metadata: Option<crate::job::Repository>,
+    pr_policy: PrPolicy,
+    /// Number of the issue of the job, for the names of pull request branches
+    issue: Option<u64>,
     //tokio_handle: tokio::runtime::Handle,
 }
 
@@ -141,12 +641,6 @@ impl std::fmt::Display for LocalRepo {
     }
 }
 
-#[derive(Clone)]
-struct Config {
-    name: String,
-    email: String,
-}
-
 impl LocalRepo {
     //pub(crate) fn new<P: AsRef<Path>, N: AsRef<str>>(dir: P, repo_name: N, repo: git2::Repository, github: Arc<Mutex<octocrab::Octocrab>>, tokio_handle: tokio::runtime::Handle) -> LocalRepo {
     pub(crate) fn new<P: AsRef<Path>, O: AsRef<str>, N: AsRef<str>>(
@@ -159,15 +653,52 @@ impl LocalRepo {
         LocalRepo {
             dir: PathBuf::from(dir.as_ref()),
             repo: Arc::new(Mutex::new(repo)),
-            config: None,
+            commits: Default::default(),
             github_owner: String::from(repo_owner.as_ref()),
             github_name: String::from(repo_name.as_ref()),
             github_client: github,
+            credentials: Credentials::None,
+            forge: None,
+            metadata: None,
+            pr_policy: Default::default(),
+            issue: None,
             //tokio_handle,
         }
     }
 
+    /// Credentials to fetch with
+    pub(crate) fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Forge to open pull requests on
+    pub(crate) fn with_forge(mut self, forge: Arc<dyn crate::forge::Forge>) -> Self {
+        self.forge = Some(forge);
+        self
+    }
+
+    /// Default branch, visibility and topics of the repository
+    pub(crate) fn with_metadata(mut self, repository: &crate::job::Repository) -> Self {
+        self.metadata = Some(repository.clone());
+        self
+    }
+
+    /// Who commits are by and how they're signed
+    pub(crate) fn with_commits(mut self, commits: CommitConfig) -> Self {
+        self.commits = commits;
+        self
+    }
+
+    /// What pull requests may be opened, from branches named after `issue`
+    pub(crate) fn with_pr_policy(mut self, policy: PrPolicy, issue: Option<u64>) -> Self {
+        self.pr_policy = policy;
+        self.issue = issue;
+        self
+    }
+
     //fn with_repo<P: AsRef<Path>, S: AsRef<str>, R: AsRef<str>>(dir: P, repo_name: R, head: S, repo: git2::Repository, github_client: Arc<Mutex<octocrab::Octocrab>>, tokio_handle: tokio::runtime::Handle) -> Result<LocalRepo, Box<rhai::EvalAltResult>>
+    #[allow(clippy::too_many_arguments)]
     fn with_repo<P: AsRef<Path>, S: AsRef<str>, O: AsRef<str>, N: AsRef<str>>(
         dir: P,
         repo_owner: O,
@@ -175,14 +706,20 @@ impl LocalRepo {
         head: S,
         repo: git2::Repository,
         github_client: Arc<Mutex<octocrab::Octocrab>>,
+        credentials: Credentials,
     ) -> Result<LocalRepo, Box<rhai::EvalAltResult>> {
         let mut s = LocalRepo {
             dir: PathBuf::from(dir.as_ref()),
             repo: Arc::new(Mutex::new(repo)),
-            config: None,
+            commits: Default::default(),
             github_client,
             github_owner: String::from(repo_owner.as_ref()),
             github_name: String::from(repo_name.as_ref()),
+            credentials,
+            forge: None,
+            metadata: None,
+            pr_policy: Default::default(),
+            issue: None,
             //tokio_handle,
         };
         s.checkout_remote_head(head.as_ref())
@@ -191,6 +728,7 @@ impl LocalRepo {
     }
 
     // TODO: Return some kind of PR object
+    #[tracing::instrument(level = "debug", skip_all, fields(repository = %self.github_name))]
     fn create_pr(
         &self,
         title: impl Into<String>,
@@ -198,8 +736,40 @@ impl LocalRepo {
         head: impl Into<String>,
         base: impl Into<String>,
     ) -> Result<(), Error> {
+        let (head, base) = (head.into(), base.into());
+        if !self.pr_policy.allows_base(&base) {
+            return Err(Error::PrPolicy(format!("pull requests into {base}")));
+        }
+        if !self.pr_policy.is_bot_branch(&head) {
+            return Err(Error::PrPolicy(format!(
+                "pull requests from {head}, which isn't named like `REPO.pr_branch` names branches"
+            )));
+        }
+        if let Some(forge) = &self.forge {
+            forge.create_pr(&title.into(), &body.into(), &head, &base)?;
+            if let (Some(prefix), Some(max_age)) = (
+                self.pr_policy.bot_branch_prefix(),
+                self.pr_policy.stale_after,
+            ) {
+                match forge.delete_stale_branches(prefix, max_age) {
+                    Ok(deleted) if !deleted.is_empty() => {
+                        tracing::info!("Deleted stale branches {deleted:?}")
+                    }
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!("Failed to delete stale branches: {err}"),
+                }
+            }
+            return Ok(());
+        }
         let token = self.get_access_token()?;
+        let base_url = self
+            .github_client
+            .lock()
+            .map_err(|_| Error::ExclusiveLock)?
+            .base_url
+            .clone();
         let gh_client = octocrab::OctocrabBuilder::new()
+            .base_url(base_url)?
             .personal_token(token)
             .build()?;
         futures_lite::future::block_on(async {
@@ -226,14 +796,25 @@ impl LocalRepo {
             .map_err(|e| format!("{e}").into())
     }
 
+    /// Name of the branch to open a pull request from, see [`PrPolicy::branch_template`]
+    pub fn pub_pr_branch(&mut self, name: &str) -> Result<String, Box<rhai::EvalAltResult>> {
+        self.pr_policy
+            .branch(&self.github_name, self.issue, name)
+            .map_err(|e| format!("{e}").into())
+    }
+
     // fetch and checkout/reset remote head (branch)
     fn checkout_remote_head<S: AsRef<str>>(&mut self, head: S) -> Result<(), Error> {
         let head = head.as_ref();
         let repo = self.repo.lock()?;
-        log::info!("Fetching {} in {:?}", head, self.dir);
+        tracing::info!("Fetching {} in {:?}", head, self.dir);
         //self.repo.lock()?.find_remote("origin")?.fetch(
         let mut remote = repo.find_remote("origin")?;
-        remote.fetch(&[&format!("refs/{}:refs/heads/{}", head, head)], None, None)?;
+        remote.fetch(
+            &[&format!("refs/{}:refs/heads/{}", head, head)],
+            Some(&mut self.credentials.fetch_options()),
+            None,
+        )?;
 
         let rev = repo.revparse_single(head)?;
         repo.reset(
@@ -250,6 +831,101 @@ impl LocalRepo {
         Ok(())
     }
 
+    /// Fetch the full history for scripts that need it, when the job was checked out shallowly
+    pub fn pub_unshallow(&mut self) -> Result<(), Box<rhai::EvalAltResult>> {
+        unshallow(&self.dir, &self.credentials).map_err(|e| format!("{e}").into())
+    }
+
+    /// The commit `base` points to, fetching the branch of that name from `origin` if it isn't
+    /// known here
+    fn find_base<'r>(
+        &self,
+        repo: &'r git2::Repository,
+        base: &str,
+    ) -> Result<git2::Commit<'r>, Error> {
+        let tracking = format!("refs/remotes/origin/{base}");
+        for candidate in [base, tracking.as_str()] {
+            if let Ok(object) = repo.revparse_single(candidate) {
+                return Ok(object.peel_to_commit()?);
+            }
+        }
+        tracing::info!("Fetching {base} in {:?} to diff against", self.dir);
+        repo.find_remote("origin")?.fetch(
+            &[format!("+refs/heads/{base}:{tracking}")],
+            Some(&mut self.credentials.fetch_options()),
+            None,
+        )?;
+        Ok(repo.revparse_single(&tracking)?.peel_to_commit()?)
+    }
+
+    /// The files HEAD changes since it branched off `base`, like `git diff base...HEAD`
+    fn diff(&self, base: &str) -> Result<Vec<ChangedFile>, Error> {
+        let repo = self.repo.lock()?;
+        let head = repo.head()?.peel_to_commit()?;
+        let base = self.find_base(&repo, base)?;
+        let merge_base = repo.find_commit(repo.merge_base(base.id(), head.id())?)?;
+        let mut diff =
+            repo.diff_tree_to_tree(Some(&merge_base.tree()?), Some(&head.tree()?), None)?;
+        diff.find_similar(None)?;
+        let mut files = vec![];
+        for (index, delta) in diff.deltas().enumerate() {
+            let path = |file: git2::DiffFile<'_>| {
+                file.path()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            };
+            let (_, additions, deletions) = match git2::Patch::from_diff(&diff, index)? {
+                Some(patch) => patch.line_stats()?,
+                None => (0, 0, 0),
+            };
+            files.push(ChangedFile {
+                path: path(delta.new_file()),
+                old_path: path(delta.old_file()),
+                status: match delta.status() {
+                    git2::Delta::Added => "added",
+                    git2::Delta::Deleted => "deleted",
+                    git2::Delta::Renamed => "renamed",
+                    git2::Delta::Copied => "copied",
+                    _ => "modified",
+                },
+                additions,
+                deletions,
+            });
+        }
+        Ok(files)
+    }
+
+    /// `diff(base)`, an array of `#{ path, old_path, status, additions, deletions }` with
+    /// `status` one of `"added"`, `"deleted"`, `"modified"`, `"renamed"` and `"copied"`
+    pub fn pub_diff(&mut self, base: &str) -> Result<rhai::Array, Box<rhai::EvalAltResult>> {
+        let files = self.diff(base).map_err(|e| format!("{e}"))?;
+        Ok(files
+            .into_iter()
+            .map(|file| {
+                let mut map = rhai::Map::new();
+                map.insert("path".into(), file.path.into());
+                map.insert("old_path".into(), file.old_path.into());
+                map.insert("status".into(), file.status.into());
+                map.insert("additions".into(), (file.additions as rhai::INT).into());
+                map.insert("deletions".into(), (file.deletions as rhai::INT).into());
+                rhai::Dynamic::from_map(map)
+            })
+            .collect())
+    }
+
+    /// Apply the unified diff `patch` (like `git diff` prints) to the working directory, leaving
+    /// adding and committing the changes to the script
+    fn apply_patch(&self, patch: &str) -> Result<(), Error> {
+        let repo = self.repo.lock()?;
+        let diff = git2::Diff::from_buffer(patch.as_bytes())?;
+        repo.apply(&diff, git2::ApplyLocation::WorkDir, None)?;
+        Ok(())
+    }
+
+    pub fn pub_apply_patch(&mut self, patch: &str) -> Result<(), Box<rhai::EvalAltResult>> {
+        self.apply_patch(patch).map_err(|e| format!("{e}").into())
+    }
+
     // Checkout a possibly new local branch
     pub fn checkout_new_branch<S: AsRef<str>>(&mut self, name: S) -> Result<(), Error> {
         self.checkout_new_branch_target(name, "HEAD")
@@ -290,11 +966,11 @@ impl LocalRepo {
         path: P,
     ) -> Result<Vec<u8>, Box<rhai::EvalAltResult>> {
         let path = path.as_ref();
-        log::debug!("Reading file (before normalization): {:?}", path);
+        tracing::debug!("Reading file (before normalization): {:?}", path);
         let path = self.get_full_path(path)?;
-        log::debug!("Reading file {:?}", path);
+        tracing::debug!("Reading file {:?}", path);
         let bytes = std::fs::read(&path).map_err(|e| format!("{e}"))?;
-        log::debug!("Read file {:?}", path);
+        tracing::debug!("Read file {:?}", path);
         Ok(bytes)
         //Ok(std::fs::read(path).map_err(|e| format!("{e}"))?)
     }
@@ -318,10 +994,10 @@ impl LocalRepo {
             return Err(format!("no `../` allowed in path names").into());
         }
         */
-        log::debug!("Writing file (before normalization): {:?}", path);
+        tracing::debug!("Writing file (before normalization): {:?}", path);
         let path = self.dir.join(&path);
         //let path = self.get_full_path(path)?;
-        log::debug!("Writing file {:?}", path);
+        tracing::debug!("Writing file {:?}", path);
         // TODO: Make sure directory exists
         Ok(std::fs::write(path, contents).map_err(|e| format!("{e}"))?)
     }
@@ -344,17 +1020,27 @@ impl LocalRepo {
 
     /// Similar to `git ls-files`, list all files in the given directory of the current repo. This
     /// will be monomorphised into one function with `ls-files`.
-    pub fn ls_files_in_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
+    pub fn ls_files_in_dir<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+    ) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
         let dir = dir.as_ref();
         let path = self.get_full_path(dir)?;
-        Ok(walkdir::WalkDir::new(path).into_iter().filter_map(|e| e.ok())
+        Ok(walkdir::WalkDir::new(path)
+            .into_iter()
+            .filter_map(|e| e.ok())
             .filter_map(|e| {
                 let metadata = e.metadata().ok()?;
                 let path = e.into_path().to_path_buf();
                 let path = path.strip_prefix(self.get_full_path("./").ok()?).ok()?;
-                Some(DirEntry { metadata, path:  DirEntryPath(path.to_path_buf())})
+                Some(DirEntry {
+                    metadata,
+                    path: DirEntryPath(path.to_path_buf()),
+                })
             })
-            .filter(|e| e.metadata.is_file()).collect::<Vec<_>>().into())
+            .filter(|e| e.metadata.is_file())
+            .collect::<Vec<_>>()
+            .into())
     }
 
     pub fn list_files(&mut self) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
@@ -370,7 +1056,7 @@ impl LocalRepo {
         dir: P,
     ) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
         let path = self.get_full_path(dir)?;
-        log::debug!("More specifically, listing files in {:?}", path);
+        tracing::debug!("More specifically, listing files in {:?}", path);
         Ok(std::fs::read_dir(path)
             .map_err(|e| format!("{e}"))?
             .filter_map(|entry| {
@@ -393,7 +1079,7 @@ impl LocalRepo {
 
     pub fn add<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<rhai::EvalAltResult>> {
         let path = path.as_ref();
-        log::debug!("Adding file {:?}", path);
+        tracing::debug!("Adding file {:?}", path);
         let repo = self.repo.lock().map_err(|e| format!("{e}"))?;
         let mut index = repo.index().map_err(|e| format!("{e}"))?;
         index.add_path(path).map_err(|e| format!("{e}"))?;
@@ -418,25 +1104,56 @@ impl LocalRepo {
         }
     }
 
-    fn commit<S: AsRef<str>>(&mut self, message: S) -> Result<(), Error> {
-        let repo = self.repo.lock()?;
-        let signature = match &self.config {
-            Some(Config { name, email }) => git2::Signature::now(name, email)?,
+    /// Author and committer of new commits
+    fn signatures(&self) -> Result<(git2::Signature<'static>, git2::Signature<'static>), Error> {
+        let author = match &self.commits.author {
+            Some(Identity { name, email }) => git2::Signature::now(name, email)?,
             None => git2::Signature::now("ci-script (TODO: Changeme)", "changeme@parity.io")?,
         };
+        let committer = match &self.commits.committer {
+            Some(Identity { name, email }) => git2::Signature::now(name, email)?,
+            None => author.clone(),
+        };
+        Ok((author, committer))
+    }
+
+    /// Write a commit of `tree` on top of `parent`, signed if there's a signing key, without
+    /// moving any branch to it
+    fn write_commit(
+        &self,
+        repo: &git2::Repository,
+        author: &git2::Signature<'_>,
+        committer: &git2::Signature<'_>,
+        message: &str,
+        tree: &git2::Tree<'_>,
+        parent: &git2::Commit<'_>,
+    ) -> Result<git2::Oid, Error> {
+        match &self.commits.signing_key {
+            Some(key) => {
+                let content =
+                    repo.commit_create_buffer(author, committer, message, tree, &[parent])?;
+                let content = content
+                    .as_str()
+                    .ok_or_else(|| Error::Signing("Commit isn't valid UTF-8".to_string()))?;
+                Ok(repo.commit_signed(content, &key.sign(content)?, None)?)
+            }
+            None => Ok(repo.commit(None, author, committer, message, tree, &[parent])?),
+        }
+    }
+
+    fn commit<S: AsRef<str>>(&mut self, message: S) -> Result<(), Error> {
+        let repo = self.repo.lock()?;
+        let (author, committer) = self.signatures()?;
         let rev = repo.revparse_single("HEAD")?;
         let commit = rev.peel_to_commit()?;
         let mut index = repo.index()?;
         let oid = index.write_tree()?;
         let tree = repo.find_tree(oid)?;
-        repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            message.as_ref(),
-            &tree,
-            &[&commit],
-        )?;
+        let message = message.as_ref();
+        let oid = self.write_commit(&repo, &author, &committer, message, &tree, &commit)?;
+        // Moves the checked out branch, or HEAD itself when it's detached
+        repo.head()?
+            .set_target(oid, &format!("commit: {message}"))?;
         Ok(())
     }
 
@@ -462,90 +1179,203 @@ impl LocalRepo {
 
     fn get_access_token(&self) -> Result<String, Error> {
         let github_client = self.github_client.clone();
-        futures_lite::future::block_on(async {
+        let token = async {
             let github_client = github_client.lock().map_err(|_| Error::ExclusiveLock)?;
-            let installations = github_client
-                .apps()
-                .installations()
-                .send()
-                .await?
-                .take_items();
-            let mut access_token_req =
-                octocrab::params::apps::CreateInstallationAccessToken::default();
-            access_token_req.repositories = vec![];
-            // TODO: Properly fill-in installation
-            log::info!("still doing stuff");
-            let access: octocrab::models::InstallationToken = github_client
-                .post(
-                    installations[0].access_tokens_url.as_ref().unwrap(),
-                    Some(&access_token_req),
-                )
+            crate::installation::token(&github_client, &self.github_owner, &self.github_name, &[])
                 .await
-                .map_err(|e| Error::NoAccessToken(format!("{e}")))?;
-            Ok(access.token)
-        })
+                .map_err(|e| Error::NoAccessToken(format!("{e}")))
+        };
+        futures_lite::future::block_on(token.instrument(tracing::debug_span!("installation_token")))
     }
 
-    fn push<L: AsRef<str>>(
-        &mut self,
-        localref: L,
-    ) -> Result<(), Error> {
-        log::debug!("pushing!");
-        let repo = self.repo.lock()?;
-        let mut remote = repo.find_remote("origin")?;
-        //let github_client = self.github_client.lock().map_err(|_| Error::ExclusiveLock)?.clone();
+    /// A token of the Github App installation, for pushing when the job wasn't given credentials
+    fn installation_token(&self) -> Result<String, Error> {
         let github_client = self.github_client.clone();
+        let (owner, name) = (self.github_owner.clone(), self.github_name.clone());
         // TODO: Fix block_on
-        //let access_token_res: Result<String, Error> = self.tokio_handle.block_on(async {
         let (tx, rx) = channel();
         let handle = tokio::runtime::Handle::current();
+        let span = tracing::debug_span!("installation_token");
         std::thread::spawn(move || {
+            let _span = span.entered();
             let res: Result<String, Error> = handle.block_on(async {
                 let github_client = github_client.lock().map_err(|_| Error::ExclusiveLock)?;
-                let installations = github_client
-                    .apps()
-                    .installations()
-                    .send()
-                    .await?
-                    .take_items();
-                let mut access_token_req =
-                    octocrab::params::apps::CreateInstallationAccessToken::default();
-                access_token_req.repositories = vec![];
-                // TODO: Properly fill-in installation
-                log::info!("still doing stuff");
-                let access: octocrab::models::InstallationToken = github_client
-                    .post(
-                        installations[0].access_tokens_url.as_ref().unwrap(),
-                        Some(&access_token_req),
-                    )
+                crate::installation::token(&github_client, &owner, &name, &[])
                     .await
-                    .map_err(|e| Error::NoAccessToken(format!("{e}")))?;
-                Ok(access.token)
+                    .map_err(|e| Error::NoAccessToken(format!("{e}")))
+            });
+            tx.send(res).unwrap_or_else(|e| {
+                tracing::warn!("Failed to send access token through channel: {e}")
             });
-            tx.send(res)
-                .unwrap_or_else(|e| log::warn!("Failed to send access token through channel: {e}"));
         });
+        rx.recv()?
+    }
 
-        let access_token_res: Result<String, Error> = rx.recv()?;
-        let access_token = access_token_res?;
-        log::debug!("Got an access token!");
-        let mut callbacks = git2::RemoteCallbacks::new();
-        callbacks.credentials(move |_url, _username_from_url, _allowed_types| {
-            git2::Cred::userpass_plaintext("x-access-token", &access_token)
+    /// Push `branch` to `origin`, with the credentials of the job (the installation token on
+    /// Github). Branches of the bot (see [`PrPolicy::branch_template`]) are force pushed, others
+    /// handled as `mode` says when they moved on the remote. Failing to reach the remote is
+    /// retried a few times.
+    fn push(&mut self, branch: &str, mode: PushMode) -> Result<(), Error> {
+        let credentials = match &self.credentials {
+            Credentials::None if self.url()?.starts_with("https://") => {
+                Credentials::Token(self.installation_token()?)
+            }
+            credentials => credentials.clone(),
+        };
+        if mode == PushMode::Rebase {
+            self.rebase_onto_remote(branch, &credentials)?;
+        }
+        let force = mode == PushMode::ForceWithLease
+            || (self.pr_policy.branch_template.is_some() && self.pr_policy.is_bot_branch(branch));
+
+        let mut attempt = 1;
+        loop {
+            match self.push_once(branch, force, mode, &credentials) {
+                Err(Error::Checkout { source })
+                    if attempt < PUSH_ATTEMPTS && is_transient(&source) =>
+                {
+                    tracing::warn!("Failed to push {branch} (attempt {attempt}): {source}");
+                    std::thread::sleep(std::time::Duration::from_secs(2u64.pow(attempt)));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn push_once(
+        &self,
+        branch: &str,
+        force: bool,
+        mode: PushMode,
+        credentials: &Credentials,
+    ) -> Result<(), Error> {
+        let repo = self.repo.lock()?;
+        let mut remote = repo.find_remote("origin")?;
+        let tracking = format!("refs/remotes/origin/{branch}");
+        let local = repo
+            .find_reference(&format!("refs/heads/{branch}"))?
+            .peel_to_commit()?
+            .id();
+
+        if mode == PushMode::ForceWithLease {
+            // What the branch was when it was last fetched or pushed, none if never
+            let expected = repo
+                .find_reference(&tracking)
+                .ok()
+                .and_then(|reference| reference.target());
+            remote.connect_auth(
+                git2::Direction::Push,
+                Some(credentials.remote_callbacks()),
+                None,
+            )?;
+            let current = remote
+                .list()?
+                .iter()
+                .find(|head| head.name() == format!("refs/heads/{branch}"))
+                .map(|head| head.oid());
+            remote.disconnect()?;
+            if current.is_some() && current != expected {
+                return Err(Error::PushRejected {
+                    branch: branch.to_string(),
+                    reason: PushRejection::StaleLease,
+                });
+            }
+        }
+
+        let rejected = std::cell::RefCell::new(None);
+        let mut callbacks = credentials.remote_callbacks();
+        callbacks.push_update_reference(|_refname, status| {
+            *rejected.borrow_mut() = status.map(String::from);
+            Ok(())
         });
-        let mut push_options = git2::PushOptions::new();
-        push_options.remote_callbacks(callbacks);
-        log::debug!("push options including creds callback ready!");
-        // TODO: Check if this error handling is sufficient
-        if let Err(err) = remote.push::<String>(
-            &[format!("refs/heads/{}", localref.as_ref())],
-            Some(&mut push_options),
+        let mut options = git2::PushOptions::new();
+        options.remote_callbacks(callbacks);
+        let force = if force { "+" } else { "" };
+        if let Err(err) = remote.push(&[format!("{force}refs/heads/{branch}")], Some(&mut options))
+        {
+            tracing::debug!("Failed to push: {err}");
+            return Err(match err.code() {
+                git2::ErrorCode::Auth => Error::PushRejected {
+                    branch: branch.to_string(),
+                    reason: PushRejection::Auth(err.message().to_string()),
+                },
+                git2::ErrorCode::NotFastForward => Error::PushRejected {
+                    branch: branch.to_string(),
+                    reason: PushRejection::NonFastForward,
+                },
+                _ => err.into(),
+            });
+        }
+        drop(options);
+        if let Some(status) = rejected.into_inner() {
+            let reason = if status.contains("fast-forward") || status.contains("fetch first") {
+                PushRejection::NonFastForward
+            } else {
+                PushRejection::Remote(status)
+            };
+            return Err(Error::PushRejected {
+                branch: branch.to_string(),
+                reason,
+            });
+        }
+        repo.reference(&tracking, local, true, "push")?;
+        Ok(())
+    }
+
+    /// Replay the commits of `branch` on top of the branch of the same name on `origin`, signed
+    /// like [`LocalRepo::commit`] signs them
+    fn rebase_onto_remote(&self, branch: &str, credentials: &Credentials) -> Result<(), Error> {
+        let repo = self.repo.lock()?;
+        let tracking = format!("refs/remotes/origin/{branch}");
+        let mut remote = repo.find_remote("origin")?;
+        let mut options = credentials.fetch_options();
+        match remote.fetch(
+            &[format!("+refs/heads/{branch}:{tracking}")],
+            Some(&mut options),
+            None,
         ) {
-            log::debug!("Failed to push: {err}");
-            Err(err)?
-        } else {
-            Ok(())
+            Ok(()) => {}
+            // Nothing to rebase onto when the branch isn't pushed yet
+            Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+        let upstream = match repo.find_reference(&tracking) {
+            Ok(reference) => reference.peel_to_commit()?,
+            Err(_) => return Ok(()),
+        };
+        let local_ref = format!("refs/heads/{branch}");
+        let local = repo.find_reference(&local_ref)?.peel_to_commit()?;
+        let mut rebase = repo.rebase(
+            Some(&repo.find_annotated_commit(local.id())?),
+            Some(&repo.find_annotated_commit(upstream.id())?),
+            None,
+            Some(git2::RebaseOptions::new().inmemory(true)),
+        )?;
+        let (_, committer) = self.signatures()?;
+        let mut head = upstream;
+        while let Some(operation) = rebase.next() {
+            let original = repo.find_commit(operation?.id())?;
+            let mut index = rebase.inmemory_index()?;
+            if index.has_conflicts() {
+                rebase.abort()?;
+                return Err(Error::PushRejected {
+                    branch: branch.to_string(),
+                    reason: PushRejection::Conflict(original.id().to_string()),
+                });
+            }
+            let tree = repo.find_tree(index.write_tree_to(&repo)?)?;
+            let message = original.message().unwrap_or_default();
+            let oid =
+                self.write_commit(&repo, &original.author(), &committer, message, &tree, &head)?;
+            head = repo.find_commit(oid)?;
+        }
+        rebase.finish(None)?;
+        repo.reference(&local_ref, head.id(), true, "rebase onto origin")?;
+        if repo.head()?.name() == Some(local_ref.as_str()) {
+            repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
         }
+        Ok(())
     }
 
     /// Make the given branch point to HEAD and perform a clean checkout
@@ -556,13 +1386,12 @@ impl LocalRepo {
         repo.set_head_detached(commit.id())?;
         repo.branch(branch, &commit, true)?;
         repo.set_head(&format!("refs/heads/{branch}"))?;
-        repo.checkout_head(
-            Some(
-                CheckoutBuilder::new()
-                    .remove_untracked(true)
-                    .remove_ignored(true)
-                    .force(),
-            ))?;
+        repo.checkout_head(Some(
+            CheckoutBuilder::new()
+                .remove_untracked(true)
+                .remove_ignored(true)
+                .force(),
+        ))?;
         Ok(())
     }
 
@@ -588,6 +1417,32 @@ impl LocalRepo {
         Ok(res)
     }
 
+    /// Name of the default branch, `()` if the forge didn't tell
+    pub fn get_default_branch(&mut self) -> rhai::Dynamic {
+        self.metadata
+            .as_ref()
+            .and_then(|repository| repository.default_branch.clone())
+            .map(rhai::Dynamic::from)
+            .unwrap_or(rhai::Dynamic::UNIT)
+    }
+
+    /// `"public"`, `"private"` or `"internal"`, `()` if the forge didn't tell
+    pub fn get_visibility(&mut self) -> rhai::Dynamic {
+        self.metadata
+            .as_ref()
+            .and_then(|repository| repository.visibility.clone())
+            .map(rhai::Dynamic::from)
+            .unwrap_or(rhai::Dynamic::UNIT)
+    }
+
+    pub fn get_topics(&mut self) -> rhai::Array {
+        self.metadata
+            .iter()
+            .flat_map(|repository| repository.topics.iter().cloned())
+            .map(rhai::Dynamic::from)
+            .collect()
+    }
+
     pub fn pub_current_branch(&mut self) -> Result<String, Box<rhai::EvalAltResult>> {
         self.current_branch().map_err(|e| format!("{e}").into())
     }
@@ -600,8 +1455,19 @@ impl LocalRepo {
         &mut self,
         localref: L,
     ) -> Result<(), Box<rhai::EvalAltResult>> {
-        self.push(localref)
-            .map_err(|e| format!("{e}").into())
+        self.push(localref.as_ref(), PushMode::FastForward)
+            .map_err(push_error)
+    }
+
+    /// `push(branch, mode)`, with `mode` one of `"fast-forward"`, `"rebase"` and
+    /// `"force-with-lease"`
+    pub fn pub_push_mode(
+        &mut self,
+        branch: &str,
+        mode: &str,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        let mode = mode.parse::<PushMode>()?;
+        self.push(branch, mode).map_err(push_error)
     }
 
     fn status(&self) -> Result<Status, Error> {
@@ -623,6 +1489,16 @@ impl LocalRepo {
     }
 }
 
+/// A file changed since the base of a diff
+struct ChangedFile {
+    path: String,
+    /// Same as `path` unless it was renamed or copied
+    old_path: String,
+    status: &'static str,
+    additions: usize,
+    deletions: usize,
+}
+
 #[derive(Clone)]
 struct StatusEntry {
     path: PathBuf,
@@ -0,0 +1,89 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// HTTP client for scripts that only talks to the hosts the bot is configured to allow.
+///
+/// An allowlist entry is either a host name (`grafana.example.com`) or a wildcard for all its
+/// subdomains (`*.example.com`). Redirects are not followed.
+#[derive(Clone, Debug, Default)]
+pub struct Http {
+    allowlist: Arc<Vec<String>>,
+}
+
+impl Http {
+    pub fn new(allowlist: Vec<String>) -> Self {
+        Http {
+            allowlist: Arc::new(allowlist),
+        }
+    }
+
+    fn allowed(&self, host: &str) -> bool {
+        self.allowlist
+            .iter()
+            .any(|entry| match entry.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.')),
+                None => entry == host,
+            })
+    }
+
+    fn check_url(&self, url: &str) -> Result<url::Url, Box<rhai::EvalAltResult>> {
+        let url = url::Url::parse(url).map_err(|e| format!("Invalid URL {url}: {e}"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Unsupported URL scheme {}", url.scheme()).into());
+        }
+        match url.host_str() {
+            Some(host) if self.allowed(host) => Ok(url),
+            Some(host) => Err(format!("Host {host} is not on the HTTP allowlist").into()),
+            None => Err(format!("URL {url} has no host").into()),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Result<rhai::Map, Box<rhai::EvalAltResult>> {
+        let url = self.check_url(url)?;
+        tracing::info!("HTTP GET {url}");
+        send(surf::get(url).build())
+    }
+
+    pub fn post(
+        &self,
+        url: &str,
+        body: &str,
+        headers: rhai::Map,
+    ) -> Result<rhai::Map, Box<rhai::EvalAltResult>> {
+        let url = self.check_url(url)?;
+        tracing::info!("HTTP POST {url}");
+        let mut req = surf::post(url).body(body.to_string());
+        for (name, value) in headers {
+            req = req.header(name.as_str(), value.to_string());
+        }
+        send(req.build())
+    }
+}
+
+/// Send the request and return the response as `#{ status: INT, body: String }`
+fn send(req: surf::Request) -> Result<rhai::Map, Box<rhai::EvalAltResult>> {
+    let client: surf::Client = surf::Config::new()
+        .set_timeout(Some(Duration::from_secs(30)))
+        .try_into()
+        .map_err(|e| format!("Failed to create HTTP client: {e}"))?;
+    async_std::task::block_on(async {
+        let mut res = client
+            .send(req)
+            .await
+            .map_err(|e| format!("HTTP request failed: {e}"))?;
+        let body = res
+            .body_string()
+            .await
+            .map_err(|e| format!("Failed to read HTTP response: {e}"))?;
+        let mut map = rhai::Map::new();
+        map.insert(
+            "status".into(),
+            (u16::from(res.status()) as rhai::INT).into(),
+        );
+        map.insert("body".into(), body.into());
+        Ok(map)
+    })
+}
@@ -1,4 +1,5 @@
-use std::sync::{Arc, Mutex};
+use std::convert::TryInto;
+use std::sync::Arc;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -11,108 +12,113 @@ pub enum Error {
     ExclusiveLock,
 }
 
+pub mod args;
+pub mod artifacts;
+pub mod baselines;
+pub mod bench;
 pub mod cargo;
+pub mod chart;
 pub mod git;
+pub mod http;
+pub mod outputs;
+pub mod phases;
+pub mod pinning;
+pub mod pr;
+pub mod profile;
+pub mod publish;
+pub mod quota;
+pub mod report;
+pub mod results;
 pub mod rhai;
+pub mod statuses;
+pub mod table;
+pub mod toolchain;
+pub mod units;
+pub mod usage;
+pub mod warnings;
+pub mod weights;
 
-use crate::job::Repository;
+use crate::forge::Forge;
+/// The issue or pull request a job was triggered on, exposed to scripts as `ISSUE`
 #[derive(Clone, Debug)]
 pub struct Issue {
-    client: Arc<Mutex<octocrab::Octocrab>>,
-    repository: Repository,
+    forge: Arc<dyn Forge>,
     issue: octocrab::models::issues::Issue,
+    redactor: crate::secrets::Redactor,
 }
 
-use std::convert::TryInto;
-
 impl Issue {
+    /// Comment on the issue, returning the URL of the comment
     pub fn create_comment<S: AsRef<str>>(
         &mut self,
         body: S,
-    ) -> Result<octocrab::models::issues::Comment, Box<::rhai::EvalAltResult>> {
-        // Unfortunately (like I just found out) octocrab depends on reqwest which depends on
-        // tokio. Octocrab has an issue to fix that though, which I just might do :D
-        //
-        // TODO: Think about ways to re-use the tokio runtime
-        // TODO: Fix https://github.com/XAMPPRocky/octocrab/issues/99
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .map_err(|e| format!("{}", e))?;
+    ) -> Result<String, Box<::rhai::EvalAltResult>> {
+        let number = self.number()?;
+        self.forge
+            .create_comment(number, &self.redactor.redact(body.as_ref()))
+            .map(|url| url.to_string())
+            .map_err(|e| format!("Failed to comment: {e}").into())
+    }
+
+    /// Comment `body` on the issue even if it's too long for one comment, see [`publish`],
+    /// returning the URL of the (first) comment
+    pub fn publish_report<S: AsRef<str>>(
+        &mut self,
+        body: S,
+    ) -> Result<String, Box<::rhai::EvalAltResult>> {
+        let number = self.number()?;
+        let body = self.redactor.redact(body.as_ref());
+        let urls = publish::publish(self.forge.as_ref(), number, &body)
+            .map_err(|e| format!("Failed to publish the report: {e}"))?;
+        Ok(urls.first().map(|url| url.to_string()).unwrap_or_default())
+    }
 
-        let github_installation_client = match rt.block_on(async {
-            // TODO: Get rid of at least the first unwrap (I just introduced it, used to be a ?
-            let installations = self
-                .client
-                .lock()
-                .unwrap()
-                .apps()
-                .installations()
-                .send()
-                .await
-                .unwrap()
-                .take_items();
-            let mut access_token_req =
-                octocrab::params::apps::CreateInstallationAccessToken::default();
-            access_token_req.repository_ids = vec![self.repository.id];
-            // TODO: Properly fill-in installation
-            // TODO: Get rid of at least the first unwrap (I just introduced it, used to be a ?
-            let access: octocrab::models::InstallationToken = self
-                .client
-                .lock()
-                .unwrap()
-                .post(
-                    installations[0].access_tokens_url.as_ref().unwrap(),
-                    Some(&access_token_req),
-                )
-                .await?;
-            octocrab::OctocrabBuilder::new()
-                .personal_token(access.token)
-                .build()
-        }) {
-            Ok(github_installation_client) => github_installation_client,
-            _ => {
-                log::warn!("Failed to require octocrab Github client");
-                return Err(format!("Failed to require octocrab Github client").into());
-            }
-        };
+    /// Add the label `label`, like `performance-regression`
+    pub fn add_label(&mut self, label: &str) -> Result<(), Box<::rhai::EvalAltResult>> {
+        self.forge
+            .add_label(self.number()?, label)
+            .map_err(|e| format!("Failed to add label {label}: {e}").into())
+    }
+
+    /// Remove the label `label`, if the issue has it
+    pub fn remove_label(&mut self, label: &str) -> Result<(), Box<::rhai::EvalAltResult>> {
+        self.forge
+            .remove_label(self.number()?, label)
+            .map_err(|e| format!("Failed to remove label {label}: {e}").into())
+    }
+
+    /// Ask the team `team` to review the pull request
+    pub fn request_review(&mut self, team: &str) -> Result<(), Box<::rhai::EvalAltResult>> {
+        self.forge
+            .request_review(self.number()?, team)
+            .map_err(|e| format!("Failed to request a review of {team}: {e}").into())
+    }
 
-        log::debug!("about to get a list of issues");
-        rt.block_on(async {
-            /*
-            let page = self.client
-                .lock()
-            let page = github_installation_client
-                .issues(&self.repository.owner.login, &self.repository.name)
-                .list()
-                .send()
-                .await
-                .map_err(|e| e.to_string())?;
-                */
+    /// Assign the user `user`
+    pub fn assign(&mut self, user: &str) -> Result<(), Box<::rhai::EvalAltResult>> {
+        self.forge
+            .assign(self.number()?, user)
+            .map_err(|e| format!("Failed to assign {user}: {e}").into())
+    }
 
-            github_installation_client
-                .issues(&self.repository.owner.login, &self.repository.name)
-                .create_comment(
-                    self.issue
-                        .number
-                        .try_into()
-                        .map_err(|e: std::num::TryFromIntError| e.to_string())?,
-                    body,
-                )
-                .await
-                .map_err(|e| e.to_string().into())
-        })
+    fn number(&self) -> Result<u64, Box<::rhai::EvalAltResult>> {
+        self.issue
+            .number
+            .try_into()
+            .map_err(|e: std::num::TryFromIntError| e.to_string().into())
     }
 
-    pub fn new(
-        client: Arc<Mutex<octocrab::Octocrab>>,
-        repository: Repository,
-        issue: octocrab::models::issues::Issue,
-    ) -> Self {
+    pub fn new(forge: Arc<dyn Forge>, issue: octocrab::models::issues::Issue) -> Self {
         Issue {
-            client,
-            repository,
+            forge,
             issue,
+            redactor: Default::default(),
         }
     }
+
+    /// Mask secrets in the comments the script posts
+    pub(crate) fn with_redactor(mut self, redactor: crate::secrets::Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
 }
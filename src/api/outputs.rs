@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Values a script hands to the bot, exposed to scripts as `OUTPUT`. Unlike metrics they can be
+/// anything serializable, like a version string or a map; they're shown in the result comment,
+/// sent along with the result webhook and kept in the results database.
+///
+/// ```rhai
+/// OUTPUT.set("runtime_version", 9430);
+/// OUTPUT.set("wasm", #{ size: 1234567, compressed: true });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Outputs {
+    values: Arc<Mutex<BTreeMap<String, serde_json::Value>>>,
+}
+
+impl Outputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `key` to `value`, replacing what it was set to before
+    pub fn set(&mut self, key: &str, value: rhai::Dynamic) -> Result<(), Box<rhai::EvalAltResult>> {
+        let value: serde_json::Value = rhai::serde::from_dynamic(&value)?;
+        tracing::debug!("Output {key} = {value}");
+        self.values
+            .lock()
+            .map_err(|_| "Failed to gain exclusive lock on the outputs")?
+            .insert(key.to_string(), value);
+        Ok(())
+    }
+
+    /// The value of `key`, `()` if it isn't set
+    pub fn get(&mut self, key: &str) -> Result<rhai::Dynamic, Box<rhai::EvalAltResult>> {
+        let values = self
+            .values
+            .lock()
+            .map_err(|_| "Failed to gain exclusive lock on the outputs")?;
+        match values.get(key) {
+            Some(value) => rhai::serde::to_dynamic(value),
+            None => Ok(rhai::Dynamic::UNIT),
+        }
+    }
+
+    pub(crate) fn values(&self) -> BTreeMap<String, serde_json::Value> {
+        self.values
+            .lock()
+            .map(|values| values.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Render `outputs` as a table for the result comment
+pub fn render(outputs: &BTreeMap<String, serde_json::Value>) -> String {
+    let mut section = String::from("### Outputs\n\n| Output | Value |\n|---|---|\n");
+    for (key, value) in outputs {
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+        section.push_str(&format!("| `{key}` | {} |\n", value.replace('|', "\\|")));
+    }
+    section
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Wall-clock and CPU time spent in a named part of a script
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Phase {
+    pub name: String,
+    pub wall: Duration,
+    /// CPU time of the script itself and all child processes it waited for (like cargo)
+    pub cpu: Duration,
+    /// Run only to warm up caches before measuring, its results were discarded
+    #[serde(default)]
+    pub warm_up: bool,
+}
+
+/// Phases timed during a single run through `phase("name", || ...)`
+#[derive(Clone, Debug, Default)]
+pub struct Phases {
+    phases: Arc<Mutex<Vec<Phase>>>,
+    /// Name of the phase running right now
+    current: Arc<Mutex<Option<String>>>,
+}
+
+impl Phases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `f` and record how long it took under `name`
+    pub fn time<T, F: FnOnce() -> T>(&self, name: &str, f: F) -> T {
+        self.time_phase(name, false, f)
+    }
+
+    /// Call `f` to warm up caches before measuring `name`, recorded as a warm-up phase
+    pub fn warm_up<T, F: FnOnce() -> T>(&self, name: &str, f: F) -> T {
+        self.time_phase(name, true, f)
+    }
+
+    /// Record that `name` took `wall`, like waiting for something outside the script
+    pub fn record(&self, name: &str, wall: Duration) {
+        if let Ok(mut phases) = self.phases.lock() {
+            phases.push(Phase {
+                name: name.into(),
+                wall,
+                cpu: Duration::ZERO,
+                warm_up: false,
+            });
+        }
+    }
+
+    fn time_phase<T, F: FnOnce() -> T>(&self, name: &str, warm_up: bool, f: F) -> T {
+        let wall_start = Instant::now();
+        let cpu_start = crate::platform::cpu_time();
+        let outer = self.enter(Some(name.to_string()));
+        let result = f();
+        self.enter(outer);
+        let phase = Phase {
+            name: name.into(),
+            wall: wall_start.elapsed(),
+            cpu: crate::platform::cpu_time().saturating_sub(cpu_start),
+            warm_up,
+        };
+        tracing::info!(
+            "{} {} took {:?} (cpu: {:?})",
+            if warm_up { "Warm-up of" } else { "Phase" },
+            phase.name,
+            phase.wall,
+            phase.cpu
+        );
+        if let Ok(mut phases) = self.phases.lock() {
+            phases.push(phase);
+        }
+        result
+    }
+
+    /// Make `name` the current phase, returning the one it replaces
+    fn enter(&self, name: Option<String>) -> Option<String> {
+        match self.current.lock() {
+            Ok(mut current) => std::mem::replace(&mut *current, name),
+            Err(_) => None,
+        }
+    }
+
+    /// The phase running right now, the innermost one if phases are nested
+    pub fn current(&self) -> Option<String> {
+        self.current.lock().ok().and_then(|current| current.clone())
+    }
+
+    pub(crate) fn phases(&self) -> Vec<Phase> {
+        self.phases
+            .lock()
+            .map(|phases| phases.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Render the phases as a short footer for a result comment
+pub fn render_footer(phases: &[Phase]) -> String {
+    let phases = phases
+        .iter()
+        .map(|phase| {
+            format!(
+                "{}{}: {} (cpu {})",
+                phase.name,
+                if phase.warm_up { " (warm-up)" } else { "" },
+                format_duration(phase.wall),
+                format_duration(phase.cpu)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" · ");
+    format!("<sub>Phases: {phases}</sub>")
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
@@ -0,0 +1,175 @@
+//! Pinning cargo (and everything it runs, like the benchmarks) to some cores, so results aren't
+//! skewed by the scheduler moving them around or by other processes on those cores. Jobs can be
+//! pinned as a whole (see `--pin-cores`), or scripts pin what they measure:
+//!
+//! ```rhai
+//! with_pinned_cores([2, 3], || cargo "bench -p my-crate");
+//! with_pinned_cores("2-5", || cargo "bench -p my-crate");
+//! ```
+//!
+//! With `--performance-governor` the pinned cores are also switched to the `performance` CPU
+//! frequency governor while they're in use, and back to what they had afterwards.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid cores {0:?}, expected a list of cores and ranges like 2,4-7")]
+    InvalidCores(String),
+}
+
+/// Number of cores there can be to pin to
+#[cfg(target_os = "linux")]
+pub const MAX_CORES: usize = libc::CPU_SETSIZE as usize;
+/// Number of cores there can be to pin to, not that they're pinned to off Linux
+#[cfg(not(target_os = "linux"))]
+pub const MAX_CORES: usize = 1024;
+
+/// Numbers of cores, sorted. Not a `Vec` for flags, which structopt would take to be repeatable.
+pub type Cores = Vec<usize>;
+
+/// Parse a list of cores like `taskset -c` takes, e.g. `2,4-7`
+pub fn parse_cores(s: &str) -> Result<Cores, Error> {
+    let invalid = || Error::InvalidCores(s.to_string());
+    let mut cores = vec![];
+    for part in s.split(',').map(str::trim) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.trim().parse().map_err(|_| invalid())?;
+                let last: usize = last.trim().parse().map_err(|_| invalid())?;
+                if first > last {
+                    return Err(invalid());
+                }
+                cores.extend(first..=last);
+            }
+            None => cores.push(part.parse().map_err(|_| invalid())?),
+        }
+    }
+    if cores.is_empty() || cores.iter().any(|core| *core >= MAX_CORES) {
+        return Err(invalid());
+    }
+    cores.sort_unstable();
+    cores.dedup();
+    Ok(cores)
+}
+
+/// The cores cargo is pinned to right now, shared by a job and its script
+#[derive(Clone, Debug, Default)]
+pub struct Pinning {
+    cores: Arc<Mutex<Option<Vec<usize>>>>,
+    performance_governor: bool,
+}
+
+impl Pinning {
+    /// Pin to `cores`, if any, switching them to the performance governor while they are if
+    /// `performance_governor`
+    pub fn new(cores: Option<Vec<usize>>, performance_governor: bool) -> Self {
+        Pinning {
+            cores: Arc::new(Mutex::new(cores)),
+            performance_governor,
+        }
+    }
+
+    pub fn cores(&self) -> Option<Vec<usize>> {
+        self.cores.lock().ok().and_then(|cores| cores.clone())
+    }
+
+    /// Switch the cores pinned to to the performance governor until the returned guard is
+    /// dropped, if asked to
+    pub fn governor(&self) -> Option<Governors> {
+        match (self.performance_governor, self.cores()) {
+            (true, Some(cores)) => Some(Governors::performance(&cores)),
+            _ => None,
+        }
+    }
+
+    /// Call `f` with cargo pinned to `cores`, then pin to what it was before again
+    pub fn with_cores<T, F: FnOnce() -> T>(&self, cores: Vec<usize>, f: F) -> T {
+        tracing::info!("Pinning to cores {cores:?}");
+        let outer = self.replace(Some(cores));
+        let governors = self.governor();
+        let result = f();
+        drop(governors);
+        self.replace(outer);
+        result
+    }
+
+    fn replace(&self, cores: Option<Vec<usize>>) -> Option<Vec<usize>> {
+        match self.cores.lock() {
+            Ok(mut current) => std::mem::replace(&mut *current, cores),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Make `command` run on `cores` only, and the processes it starts too
+#[cfg(target_os = "linux")]
+pub(crate) fn pin(command: &mut std::process::Command, cores: &[usize]) {
+    use std::os::unix::process::CommandExt;
+
+    // Built before forking, only setting it is left to the child
+    let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+    for core in cores {
+        unsafe { libc::CPU_SET(*core, &mut set) };
+    }
+    unsafe {
+        command.pre_exec(move || {
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Only Linux lets processes be pinned to cores, elsewhere they run on all of them
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin(_command: &mut std::process::Command, cores: &[usize]) {
+    tracing::warn!("Not pinning to cores {cores:?}, that's only supported on Linux");
+}
+
+/// CPU frequency governors changed, restored when dropped. Changing them takes root, without it
+/// they're left alone.
+pub struct Governors {
+    previous: Vec<(PathBuf, String)>,
+}
+
+impl Governors {
+    const PERFORMANCE: &'static str = "performance";
+
+    fn performance(cores: &[usize]) -> Self {
+        let mut previous = vec![];
+        for core in cores {
+            let path = PathBuf::from(format!(
+                "/sys/devices/system/cpu/cpu{core}/cpufreq/scaling_governor"
+            ));
+            let governor = match std::fs::read_to_string(&path) {
+                Ok(governor) => governor.trim().to_string(),
+                // No frequency scaling, like on most VMs
+                Err(_) => continue,
+            };
+            if governor == Self::PERFORMANCE {
+                continue;
+            }
+            match std::fs::write(&path, Self::PERFORMANCE) {
+                Ok(()) => previous.push((path, governor)),
+                Err(err) => {
+                    tracing::warn!("Failed to set the governor of core {core}: {err}");
+                }
+            }
+        }
+        Governors { previous }
+    }
+}
+
+impl Drop for Governors {
+    fn drop(&mut self) {
+        for (path, governor) in self.previous.drain(..) {
+            if let Err(err) = std::fs::write(&path, &governor) {
+                tracing::warn!("Failed to restore governor {governor} in {path:?}: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::Error;
+use crate::job::Repository;
+use std::sync::{Arc, Mutex};
+
+/// Metadata of the pull request a job was triggered on, exposed to scripts as `PR`.
+#[derive(Clone, Debug)]
+pub struct PullRequest {
+    pub number: u64,
+    pub title: String,
+    pub author: String,
+    pub labels: Vec<String>,
+    pub head_ref: String,
+    pub head_sha: String,
+    pub base_ref: String,
+    pub base_sha: String,
+    /// Paths of the files the pull request adds, changes, removes or renames (both the old and
+    /// the new path)
+    pub changed_files: Vec<String>,
+}
+
+impl PullRequest {
+    pub(crate) fn fetch(
+        client: Arc<Mutex<octocrab::Octocrab>>,
+        repository: Repository,
+        number: u64,
+    ) -> Result<Self, Error> {
+        let span = tracing::debug_span!("github", repository = %repository.name, number);
+        // Run on a separate thread so this also works when called from within a tokio runtime
+        std::thread::spawn(move || {
+            let _span = span.entered();
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| Error::GithubApiError(format!("{e}")))?;
+            rt.block_on(async {
+                let client = {
+                    let client = client.lock().map_err(|_| Error::ExclusiveLock)?.clone();
+                    installation_client(&client, &repository).await?
+                };
+                let pr = client
+                    .pulls(&repository.owner.login, &repository.name)
+                    .get(number)
+                    .await?;
+                let changed_files = changed_files(&client, &repository, number).await?;
+                Ok(PullRequest {
+                    number,
+                    title: pr.title.unwrap_or_default(),
+                    author: pr.user.map(|user| user.login).unwrap_or_default(),
+                    labels: pr
+                        .labels
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|label| label.name)
+                        .collect(),
+                    head_ref: pr.head.ref_field,
+                    head_sha: pr.head.sha,
+                    base_ref: pr.base.ref_field,
+                    base_sha: pr.base.sha,
+                    changed_files,
+                })
+            })
+        })
+        .join()
+        .map_err(|_| Error::GithubApiError("Fetching the pull request panicked".into()))?
+    }
+
+    pub fn get_number(&mut self) -> rhai::INT {
+        self.number as rhai::INT
+    }
+
+    pub fn get_title(&mut self) -> String {
+        self.title.clone()
+    }
+
+    pub fn get_author(&mut self) -> String {
+        self.author.clone()
+    }
+
+    pub fn get_labels(&mut self) -> rhai::Array {
+        self.labels
+            .iter()
+            .cloned()
+            .map(rhai::Dynamic::from)
+            .collect()
+    }
+
+    pub fn get_head_ref(&mut self) -> String {
+        self.head_ref.clone()
+    }
+
+    pub fn get_head_sha(&mut self) -> String {
+        self.head_sha.clone()
+    }
+
+    pub fn get_base_ref(&mut self) -> String {
+        self.base_ref.clone()
+    }
+
+    pub fn get_base_sha(&mut self) -> String {
+        self.base_sha.clone()
+    }
+
+    pub fn get_changed_files(&mut self) -> rhai::Array {
+        self.changed_files
+            .iter()
+            .cloned()
+            .map(rhai::Dynamic::from)
+            .collect()
+    }
+}
+
+#[tracing::instrument(level = "debug", skip(client, repository))]
+async fn changed_files(
+    client: &octocrab::Octocrab,
+    repository: &Repository,
+    number: u64,
+) -> Result<Vec<String>, Error> {
+    #[derive(serde::Deserialize)]
+    struct File {
+        filename: String,
+        previous_filename: Option<String>,
+    }
+
+    const PER_PAGE: usize = 100;
+    let route = format!(
+        "repos/{}/{}/pulls/{number}/files",
+        repository.owner.login, repository.name
+    );
+    let mut changed_files = vec![];
+    for page in 1.. {
+        let files: Vec<File> = client
+            .get(
+                &route,
+                Some(&[
+                    ("per_page", PER_PAGE.to_string()),
+                    ("page", page.to_string()),
+                ]),
+            )
+            .await?;
+        let last_page = files.len() < PER_PAGE;
+        for file in files {
+            changed_files.push(file.filename);
+            changed_files.extend(file.previous_filename);
+        }
+        if last_page {
+            break;
+        }
+    }
+    Ok(changed_files)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(repository = %repository.name))]
+pub(crate) async fn installation_client(
+    client: &octocrab::Octocrab,
+    repository: &Repository,
+) -> Result<octocrab::Octocrab, Error> {
+    Ok(crate::installation::client(
+        client,
+        &repository.owner.login,
+        &repository.name,
+        &[repository.id],
+    )
+    .await?)
+}
@@ -0,0 +1,74 @@
+//! Flamegraphs of what a job runs, for reviewers to click through to when a benchmark regressed:
+//!
+//! ```rhai
+//! let url = flamegraph("--bench transfer -- --bench --profile-time 10");
+//! ISSUE.comment(`Transfer regressed, see [the profile](${url})`);
+//! ```
+//!
+//! The arguments are those of `cargo flamegraph`, which samples with `perf`. Both have to be
+//! installed on the runner, and profiling enabled for the repository since `perf` can see more of
+//! the machine than a job otherwise would.
+
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Profiling isn't enabled for this repository")]
+    Disabled,
+    #[error(
+        "perf isn't allowed to profile here (kernel.perf_event_paranoid is {0}), it takes 2 or \
+         less, or running as root"
+    )]
+    NotPermitted(i64),
+}
+
+/// Highest `kernel.perf_event_paranoid` that still lets `perf` sample the user space of its own
+/// processes without root
+const MAX_PARANOID: i64 = 2;
+
+/// Whether the scripts of a job may profile. Nothing is allowed by default.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    enabled: bool,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Profiler { enabled }
+    }
+
+    /// Check that profiling is enabled and `perf` may sample on this machine
+    pub fn check(&self) -> Result<(), Error> {
+        if !self.enabled {
+            return Err(Error::Disabled);
+        }
+        if crate::platform::is_root() {
+            return Ok(());
+        }
+        match std::fs::read_to_string("/proc/sys/kernel/perf_event_paranoid")
+            .ok()
+            .and_then(|level| level.trim().parse::<i64>().ok())
+        {
+            Some(level) if level > MAX_PARANOID => Err(Error::NotPermitted(level)),
+            // Without the setting, perf will tell what's wrong
+            _ => Ok(()),
+        }
+    }
+
+    /// Arguments to cargo to run `cargo flamegraph <args>`, writing the flamegraph to `output`
+    pub fn args(args: &[String], output: &Path) -> Vec<String> {
+        let mut profile = vec![
+            "flamegraph".to_string(),
+            "--output".to_string(),
+            output.to_string_lossy().into_owned(),
+        ];
+        profile.extend(args.iter().cloned());
+        profile
+    }
+
+    /// Where `cargo flamegraph` leaves the samples it took in `dir`, to clean up after it
+    pub fn samples(dir: &Path) -> PathBuf {
+        dir.join("perf.data")
+    }
+}
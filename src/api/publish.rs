@@ -0,0 +1,244 @@
+//! Publishing reports too long for a single comment, like large benchmark tables. Github rejects
+//! comments over 65536 characters, so longer reports are uploaded in full where the forge can
+//! (a secret gist with `--gist-token`) and linked from a short comment, or split over several
+//! comments otherwise:
+//!
+//! ```rhai
+//! ISSUE.publish_report(table);
+//! ```
+//!
+//! Splitting happens between lines, closing and reopening code blocks and repeating the header
+//! of tables so each part renders on its own.
+
+use crate::forge::{Error, Forge};
+
+/// Characters of the start of a report kept in the comment linking to the rest
+const SUMMARY_CHARS: usize = 4096;
+
+/// Room left in each part for its label
+const LABEL_CHARS: usize = 64;
+
+/// Post `body` on the issue `number`, in as many comments as it takes, returning their URLs
+pub fn publish(forge: &dyn Forge, number: u64, body: &str) -> Result<Vec<url::Url>, Error> {
+    let limit = forge.max_comment_chars();
+    if body.chars().count() <= limit {
+        return Ok(vec![forge.create_comment(number, body)?]);
+    }
+    match forge.upload_report(body) {
+        Ok(Some(url)) => {
+            let head = split(body, SUMMARY_CHARS.min(limit - LABEL_CHARS)).swap_remove(0);
+            let comment = format!(
+                "{head}\n\n<sub>The report is too long for a comment, it continues in [the full \
+                 report]({url}).</sub>"
+            );
+            return Ok(vec![forge.create_comment(number, &comment)?]);
+        }
+        Ok(None) => {}
+        Err(err) => tracing::warn!("Failed to upload the report, splitting it instead: {err}"),
+    }
+    let parts = split(body, limit - LABEL_CHARS);
+    let count = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, part)| {
+            let comment = format!("{part}\n\n<sub>Part {} of {count}</sub>", index + 1);
+            forge.create_comment(number, &comment)
+        })
+        .collect()
+}
+
+/// Split `body` into parts of at most `limit` characters, between lines where possible
+pub fn split(body: &str, limit: usize) -> Vec<String> {
+    const FENCE_CLOSE: &str = "```\n";
+
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut part_chars = 0;
+    // What the next part starts with to continue where this one left off
+    let mut fence: Option<String> = None;
+    let mut table_header: Option<String> = None;
+    let mut previous = "";
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let in_table = trimmed.starts_with('|');
+        if !in_table {
+            table_header = None;
+        } else if fence.is_none() && previous.trim().starts_with('|') && is_table_separator(trimmed)
+        {
+            table_header = Some(format!("{}{line}", with_newline(previous)));
+        }
+
+        let closing = if fence.is_some() {
+            FENCE_CLOSE.len()
+        } else {
+            0
+        };
+        let line_chars = line.chars().count();
+        if part_chars > 0 && part_chars + line_chars + closing > limit {
+            let separator = in_table && is_table_separator(trimmed);
+            // The header row of a table moves along with its separator
+            let carried = if separator && part.len() > previous.len() && part.ends_with(previous) {
+                part.truncate(part.len() - previous.len());
+                previous
+            } else {
+                ""
+            };
+            if fence.is_some() {
+                part.push_str(FENCE_CLOSE);
+            }
+            parts.push(std::mem::take(&mut part));
+            if let Some(fence) = &fence {
+                part.push_str(&with_newline(fence));
+            }
+            // Unless the limit is too small for the header and a row
+            let fits = |prefix: &str| {
+                part.chars().count() + prefix.chars().count() + line_chars + closing <= limit
+            };
+            match &table_header {
+                Some(header) if !separator => {
+                    if fits(header) {
+                        part.push_str(header);
+                    }
+                }
+                _ => part.push_str(carried),
+            }
+            part_chars = part.chars().count();
+        }
+        if part_chars + line_chars + closing > limit {
+            // Too long even on its own
+            for c in line.chars() {
+                if part_chars + 1 + closing > limit {
+                    if fence.is_some() {
+                        part.push_str(FENCE_CLOSE);
+                    }
+                    parts.push(std::mem::take(&mut part));
+                    if let Some(fence) = &fence {
+                        part.push_str(&with_newline(fence));
+                    }
+                    part_chars = part.chars().count();
+                }
+                part.push(c);
+                part_chars += 1;
+            }
+        } else {
+            part.push_str(line);
+            part_chars += line_chars;
+        }
+
+        if trimmed.starts_with("```") {
+            fence = match fence {
+                Some(_) => None,
+                None => Some(trimmed.to_string()),
+            };
+        }
+        previous = line;
+    }
+    if !part.is_empty() || parts.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+/// A line like `|---|:---:|`, between the header and the rows of a table
+fn is_table_separator(line: &str) -> bool {
+    line.starts_with('|')
+        && line.contains('-')
+        && line
+            .chars()
+            .all(|c| matches!(c, '|' | '-' | ':' | ' ' | '\t'))
+}
+
+fn with_newline(line: &str) -> String {
+    let line = line.trim_end_matches('\n');
+    format!("{line}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    fn assert_within(parts: &[String], limit: usize) {
+        for part in parts {
+            assert!(
+                part.chars().count() <= limit,
+                "{:?} is over {}",
+                part,
+                limit
+            );
+        }
+    }
+
+    #[test]
+    fn code_blocks_are_closed_and_reopened() {
+        let body = "Intro\n```rust\nlet a = 1;\nlet b = 2;\nlet c = 3;\n```\nAfter\n";
+        let parts = split(body, 30);
+        assert_eq!(
+            parts,
+            vec![
+                "Intro\n```rust\nlet a = 1;\n```\n",
+                "```rust\nlet b = 2;\n```\n",
+                "```rust\nlet c = 3;\n```\nAfter\n",
+            ]
+        );
+        assert_within(&parts, 30);
+    }
+
+    #[test]
+    fn table_headers_are_repeated() {
+        let body = "# Results\n| name | ns |\n|---|---|\n| a | 1 |\n| b | 2 |\n| c | 3 |\nDone\n";
+        let parts = split(body, 45);
+        assert_eq!(
+            parts,
+            vec![
+                "# Results\n| name | ns |\n|---|---|\n| a | 1 |\n",
+                "| name | ns |\n|---|---|\n| b | 2 |\n| c | 3 |\n",
+                // Not for what follows the table
+                "Done\n",
+            ]
+        );
+        assert_within(&parts, 45);
+
+        // A header doesn't stay behind without its separator
+        let parts = split("Some text here\n| name | ns |\n|---|---|\n| a | 1 |\n", 35);
+        assert_eq!(
+            parts,
+            vec!["Some text here\n", "| name | ns |\n|---|---|\n| a | 1 |\n"]
+        );
+
+        // Nor is it repeated without room for a row after it
+        let parts = split("| name | ns |\n|---|---|\n| a | 1 |\n", 25);
+        assert_eq!(parts, vec!["| name | ns |\n|---|---|\n", "| a | 1 |\n"]);
+    }
+
+    #[test]
+    fn long_lines_are_split_anywhere() {
+        let parts = split("short\naaaaaaaaaaaaaaaaaaaaaaaaa\nend", 10);
+        assert_eq!(
+            parts,
+            vec!["short\n", "aaaaaaaaaa", "aaaaaaaaaa", "aaaaa\nend"]
+        );
+        assert_eq!(parts.concat(), "short\naaaaaaaaaaaaaaaaaaaaaaaaa\nend");
+
+        // Even in code blocks, which still render on their own
+        let parts = split("```\n0123456789\n```\n", 12);
+        assert_within(&parts, 12);
+        assert!(parts.iter().all(|part| part.matches("```").count() == 2));
+    }
+
+    #[test]
+    fn limits_are_in_characters() {
+        // 6 characters, 14 bytes
+        let line = "äöü€😀\n";
+        assert_eq!(split(&line.repeat(2), 6), vec![line, line]);
+        assert_eq!(split(&line.repeat(2), 12), vec![line.repeat(2)]);
+        // Not splitting characters apart
+        assert_eq!(split("€€€€€€€€€€", 4), vec!["€€€€", "€€€€", "€€"]);
+    }
+
+    #[test]
+    fn short_bodies_stay_whole() {
+        assert_eq!(split("Hello\n", 100), vec!["Hello\n"]);
+        assert_eq!(split("", 100), vec![""]);
+    }
+}
@@ -0,0 +1,366 @@
+//! Limits on the memory and disk a job's processes may use, so a runaway job fails on its own
+//! instead of taking the whole runner down with it.
+//!
+//! Memory is limited with a cgroup v2 (`memory.max`) the processes run in, one per job under a
+//! cgroup the bot may write to. When the kernel has to kill one of them over the limit, the job
+//! fails. Disk use is the size of the checkout and of the job's own `TMPDIR`, watched while cargo
+//! runs: going over the limit kills what's running and fails the job.
+//!
+//! Every process a job runs gets a process group of its own, so whatever a job left running can
+//! be killed with [`Quota::kill`], limited or not.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("A memory limit needs a cgroup to create the cgroups of jobs in")]
+    NoCgroup,
+    #[error("Failed to set up the limits of the job in {path:?}: {source}")]
+    Setup {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// How often the disk use of a job is checked while cargo runs
+const DISK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Of all the processes of the job together, in bytes
+    pub memory: Option<u64>,
+    /// Of the checkout and the `TMPDIR` of the job, in bytes
+    pub disk: Option<u64>,
+}
+
+/// The limit a job went over
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exceeded {
+    Memory(u64),
+    Disk(u64),
+}
+
+impl std::fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exceeded::Memory(limit) => {
+                write!(f, "memory limit of {}", super::usage::format_bytes(*limit))
+            }
+            Exceeded::Disk(limit) => {
+                write!(f, "disk limit of {}", super::usage::format_bytes(*limit))
+            }
+        }
+    }
+}
+
+/// The limits of a job, shared by everything that runs its processes. Nothing is limited unless
+/// created with [`Quota::new`] and started with [`Quota::start`].
+#[derive(Clone, Debug, Default)]
+pub struct Quota {
+    limits: Limits,
+    /// Where the cgroups of jobs are created
+    cgroup_root: Option<PathBuf>,
+    state: Arc<Mutex<Option<State>>>,
+    exceeded: Arc<Mutex<Option<Exceeded>>>,
+    processes: Arc<Mutex<Processes>>,
+}
+
+/// The process groups of the job that are running
+#[derive(Debug, Default)]
+struct Processes {
+    pids: HashSet<u32>,
+    /// Set by [`Quota::kill`], after which the job may not start anything else
+    killed: bool,
+}
+
+/// What [`Quota::start`] set up for the job, removed when the last handle is dropped
+#[derive(Debug)]
+struct State {
+    cgroup: Option<PathBuf>,
+    /// Where the job's processes keep temporary files, counted towards the disk limit
+    tmpdir: PathBuf,
+    /// Counted towards the disk limit
+    checkout: PathBuf,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        if let Some(cgroup) = &self.cgroup {
+            // Whatever the job left running goes with it
+            let _ = std::fs::write(cgroup.join("cgroup.kill"), "1");
+            for _ in 0..10 {
+                if std::fs::remove_dir(cgroup).is_ok() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+        let _ = std::fs::remove_dir_all(&self.tmpdir);
+    }
+}
+
+impl Quota {
+    pub fn new(limits: Limits, cgroup_root: Option<PathBuf>) -> Self {
+        Quota {
+            limits,
+            cgroup_root,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limits != Limits::default()
+    }
+
+    /// Set up the limits for the job checked out in `checkout`: its cgroup, named after the
+    /// checkout, and its `TMPDIR` next to it
+    pub fn start(&self, checkout: &Path) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let setup = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| Error::Setup { path, source }
+        };
+        let name = checkout
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "job".to_string());
+        let cgroup = match (self.limits.memory, &self.cgroup_root) {
+            (Some(memory), Some(root)) => {
+                // Already enabled, or not allowed in which case creating the cgroup tells
+                let _ = std::fs::write(root.join("cgroup.subtree_control"), "+memory");
+                let cgroup = root.join(format!("ci-script-{name}"));
+                match std::fs::create_dir(&cgroup) {
+                    Err(err) if err.kind() != std::io::ErrorKind::AlreadyExists => {
+                        return Err(setup(&cgroup)(err))
+                    }
+                    _ => {}
+                }
+                std::fs::write(cgroup.join("memory.max"), memory.to_string())
+                    .map_err(setup(&cgroup))?;
+                // Swapping instead would only slow the job down until it times out
+                let _ = std::fs::write(cgroup.join("memory.swap.max"), "0");
+                Some(cgroup)
+            }
+            (Some(_), None) => return Err(Error::NoCgroup),
+            (None, _) => None,
+        };
+        let tmpdir = checkout.with_file_name(format!("{name}.tmp"));
+        std::fs::create_dir_all(&tmpdir).map_err(setup(&tmpdir))?;
+        *self.lock_state() = Some(State {
+            cgroup,
+            tmpdir,
+            checkout: checkout.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// Environment of the job's processes, putting their temporary files in its `TMPDIR`
+    pub fn envs(&self) -> Vec<(String, String)> {
+        match &*self.lock_state() {
+            Some(state) => vec![(
+                "TMPDIR".to_string(),
+                state.tmpdir.to_string_lossy().into_owned(),
+            )],
+            None => vec![],
+        }
+    }
+
+    /// The limit the job went over, if any
+    pub fn exceeded(&self) -> Option<Exceeded> {
+        *self.exceeded.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn exceed(&self, exceeded: Exceeded) {
+        tracing::warn!("Job exceeded its {exceeded}");
+        self.exceeded
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert(exceeded);
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, Option<State>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_processes(&self) -> std::sync::MutexGuard<'_, Processes> {
+        self.processes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Kill everything the job is running, and keep it from starting anything else. For jobs
+    /// that are given up on while still running, before someone else uses their checkout.
+    pub fn kill(&self) {
+        let mut processes = self.lock_processes();
+        processes.killed = true;
+        for pid in &processes.pids {
+            crate::platform::kill_process_group(*pid);
+        }
+        if let Some(State {
+            cgroup: Some(cgroup),
+            ..
+        }) = &*self.lock_state()
+        {
+            let _ = std::fs::write(cgroup.join("cgroup.kill"), "1");
+        }
+    }
+
+    /// Make `command` run in a process group of its own, and in the cgroup of the job if it has
+    /// one. Fails once the job has been killed.
+    pub(crate) fn prepare(&self, command: &mut std::process::Command) -> std::io::Result<()> {
+        if self.lock_processes().killed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "The job has been killed",
+            ));
+        }
+        crate::platform::own_process_group(command);
+        match &*self.lock_state() {
+            Some(State {
+                cgroup: Some(cgroup),
+                ..
+            }) => join_cgroup(command, cgroup),
+            _ => Ok(()),
+        }
+    }
+
+    /// Keep track of `child`, started with a command passed to [`Quota::prepare`], to kill with
+    /// the job, and watch the disk use of the job while it runs, killing it if it goes over the
+    /// limit. Stops when the returned guard is dropped.
+    pub(crate) fn watch(&self, child: &std::process::Child) -> Watch {
+        let pid = child.id();
+        {
+            let mut processes = self.lock_processes();
+            if processes.killed {
+                // Killed while it was starting
+                crate::platform::kill_process_group(pid);
+            }
+            processes.pids.insert(pid);
+        }
+        let mut watch = Watch {
+            pid,
+            processes: self.processes.clone(),
+            _stop: None,
+        };
+        let limit = match self.limits.disk {
+            Some(limit) => limit,
+            None => return watch,
+        };
+        let (dirs, cgroup) = match &*self.lock_state() {
+            Some(state) => (
+                vec![state.checkout.clone(), state.tmpdir.clone()],
+                state.cgroup.clone(),
+            ),
+            None => return watch,
+        };
+        let quota = self.clone();
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            while let Err(std::sync::mpsc::RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(DISK_INTERVAL)
+            {
+                let used: u64 = dirs.iter().map(|dir| disk_usage(dir)).sum();
+                if used <= limit {
+                    continue;
+                }
+                quota.exceed(Exceeded::Disk(limit));
+                match &cgroup {
+                    Some(cgroup) => {
+                        let _ = std::fs::write(cgroup.join("cgroup.kill"), "1");
+                    }
+                    None => crate::platform::kill_process_group(pid),
+                }
+                break;
+            }
+        });
+        watch._stop = Some(stop);
+        watch
+    }
+
+    /// Check whether the kernel killed a process of the job for going over its memory limit
+    pub(crate) fn check(&self) {
+        let (limit, cgroup) = match (self.limits.memory, &*self.lock_state()) {
+            (
+                Some(limit),
+                Some(State {
+                    cgroup: Some(cgroup),
+                    ..
+                }),
+            ) => (limit, cgroup.clone()),
+            _ => return,
+        };
+        let events = std::fs::read_to_string(cgroup.join("memory.events")).unwrap_or_default();
+        let oom_kills = events
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse::<u64>().ok())
+            .unwrap_or_default();
+        if oom_kills > 0 {
+            self.exceed(Exceeded::Memory(limit));
+        }
+    }
+}
+
+/// Stops keeping track of a process of a job, and watching the disk use of the job, when dropped
+pub(crate) struct Watch {
+    pid: u32,
+    processes: Arc<Mutex<Processes>>,
+    _stop: Option<std::sync::mpsc::Sender<()>>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.processes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pids
+            .remove(&self.pid);
+    }
+}
+
+/// Make `command` start in `cgroup`
+#[cfg(unix)]
+fn join_cgroup(command: &mut std::process::Command, cgroup: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    let procs = std::fs::OpenOptions::new()
+        .write(true)
+        .open(cgroup.join("cgroup.procs"))?;
+    unsafe {
+        command.pre_exec(move || {
+            // Opened before forking, only writing to it is left to the child
+            if libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Jobs only have cgroups on Linux
+#[cfg(not(unix))]
+fn join_cgroup(_command: &mut std::process::Command, _cgroup: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Size of what's in `dir` on disk, like `du -s`
+fn disk_usage(dir: &Path) -> u64 {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => disk_usage(&entry.path()),
+            Ok(metadata) => crate::platform::size_on_disk(&metadata),
+            Err(_) => 0,
+        })
+        .sum()
+}
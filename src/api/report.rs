@@ -0,0 +1,184 @@
+use super::units::Quantity;
+use crate::results::Metric;
+use crate::templates::Template;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// What a script reports besides the metrics it records, set through the `report` module and
+/// exposed to scripts as `REPORT`
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    headline: Arc<Mutex<Option<String>>>,
+    /// Where the templates of the script are
+    templates: Option<PathBuf>,
+    bench_reports: Arc<Mutex<Vec<BenchReport>>>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_templates(mut self, dir: PathBuf) -> Self {
+        self.templates = Some(dir);
+        self
+    }
+
+    /// Render the template `name` with `data`, like `REPORT.render("success", #{ ... })`
+    pub fn render(
+        &mut self,
+        name: &str,
+        data: rhai::Dynamic,
+    ) -> Result<String, Box<rhai::EvalAltResult>> {
+        let dir = self
+            .templates
+            .as_ref()
+            .ok_or("Templates aren't available to this script")?;
+        let template = Template::load(dir, name).map_err(|e| format!("{e}"))?;
+        let data: serde_json::Value = rhai::serde::from_dynamic(&data)?;
+        Ok(template.render(&data))
+    }
+
+    /// Set the one line summary of the results, like `ref time +2.3%`, shown in the commit status
+    pub fn set_headline<S: Into<String>>(&self, headline: S) {
+        if let Ok(mut current) = self.headline.lock() {
+            *current = Some(headline.into());
+        }
+    }
+
+    /// Start a report on benchmarks titled `title`, which is included in the result comment
+    pub fn bench(&mut self, title: &str) -> BenchReport {
+        let report = BenchReport {
+            inner: Arc::new(Mutex::new(BenchReportData {
+                title: title.to_string(),
+                ..Default::default()
+            })),
+        };
+        if let Ok(mut reports) = self.bench_reports.lock() {
+            reports.push(report.clone());
+        }
+        report
+    }
+
+    pub(crate) fn bench_reports(&self) -> Vec<BenchReport> {
+        self.bench_reports
+            .lock()
+            .map(|reports| reports.clone())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn headline(&self) -> Option<String> {
+        self.headline
+            .lock()
+            .ok()
+            .and_then(|headline| headline.clone())
+    }
+}
+
+#[derive(Debug, Default)]
+struct BenchReportData {
+    title: String,
+    metrics: Vec<Metric>,
+    sections: Vec<(String, String)>,
+    failures: Vec<String>,
+}
+
+/// Results of benchmarks built up by a script with `REPORT.bench(title)`, which the runner then
+/// renders, records and sets the status of the job from. Clones share the report, and all methods
+/// return it so calls can be chained.
+#[derive(Clone, Debug)]
+pub struct BenchReport {
+    inner: Arc<Mutex<BenchReportData>>,
+}
+
+impl BenchReport {
+    fn update(&self, f: impl FnOnce(&mut BenchReportData)) -> Self {
+        if let Ok(mut data) = self.inner.lock() {
+            f(&mut data);
+        }
+        self.clone()
+    }
+
+    /// Add a metric, which is also recorded like those of `RESULTS.record(name, value, unit)`
+    pub fn metric(&mut self, name: &str, value: rhai::FLOAT, unit: &str) -> Self {
+        self.update(|data| {
+            data.metrics.push(Metric {
+                name: name.into(),
+                value,
+                unit: unit.into(),
+            })
+        })
+    }
+
+    pub fn metric_int(&mut self, name: &str, value: rhai::INT, unit: &str) -> Self {
+        self.metric(name, value as rhai::FLOAT, unit)
+    }
+
+    pub fn metric_quantity(&mut self, name: &str, quantity: Quantity) -> Self {
+        self.metric(name, quantity.value, quantity.unit())
+    }
+
+    /// Add a markdown section below the metrics
+    pub fn section(&mut self, title: &str, body: &str) -> Self {
+        self.update(|data| data.sections.push((title.into(), body.into())))
+    }
+
+    /// Mark the results as failed because of `reason`, which fails the job
+    pub fn fail(&mut self, reason: &str) -> Self {
+        self.update(|data| data.failures.push(reason.into()))
+    }
+
+    pub(crate) fn metrics(&self) -> Vec<Metric> {
+        self.inner
+            .lock()
+            .map(|data| data.metrics.clone())
+            .unwrap_or_default()
+    }
+
+    /// Why the results failed, titled like `<title>: <reason>`
+    pub(crate) fn failures(&self) -> Vec<String> {
+        self.inner
+            .lock()
+            .map(|data| {
+                data.failures
+                    .iter()
+                    .map(|reason| format!("{}: {reason}", data.title))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Render as a markdown section of the result comment
+    pub(crate) fn render(&self) -> String {
+        let data = match self.inner.lock() {
+            Ok(data) => data,
+            Err(_) => return String::new(),
+        };
+        let mark = if data.failures.is_empty() {
+            "✅"
+        } else {
+            "❌"
+        };
+        let mut out = format!("## {mark} {}\n", data.title);
+        for reason in &data.failures {
+            out.push_str(&format!("\n- {reason}"));
+        }
+        if !data.failures.is_empty() {
+            out.push('\n');
+        }
+        if !data.metrics.is_empty() {
+            out.push_str("\n| Metric | Value |\n|--------|-------|\n");
+            for metric in &data.metrics {
+                let value = match Quantity::new(metric.value, &metric.unit) {
+                    Ok(quantity) => quantity.to_string(),
+                    Err(_) => format!("{} {}", metric.value, metric.unit),
+                };
+                out.push_str(&format!("| {} | {} |\n", metric.name, value.trim()));
+            }
+        }
+        for (title, body) in &data.sections {
+            out.push_str(&format!("\n### {title}\n\n{body}\n"));
+        }
+        out
+    }
+}
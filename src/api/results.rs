@@ -0,0 +1,138 @@
+use crate::results::Metric;
+use std::sync::{Arc, Mutex};
+
+/// Metrics recorded by a script during a single run, exposed to scripts as `RESULTS`.
+#[derive(Clone, Debug, Default)]
+pub struct Results {
+    metrics: Arc<Mutex<Vec<Metric>>>,
+    history: Option<History>,
+}
+
+/// The results recorded by earlier runs of a repository, for scripts to chart or compare against
+#[derive(Clone)]
+pub struct History {
+    pub(super) store: crate::results::Store,
+    /// As `owner/name`
+    pub(super) repo: String,
+}
+
+impl History {
+    pub fn new<R: Into<String>>(store: crate::results::Store, repo: R) -> Self {
+        History {
+            store,
+            repo: repo.into(),
+        }
+    }
+}
+
+impl std::fmt::Debug for History {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("History").field("repo", &self.repo).finish()
+    }
+}
+
+impl Results {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let scripts look up the results of earlier runs through `RESULTS.history`
+    pub fn with_history(mut self, history: Option<History>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// The values of the metric `name` in the last `count` runs on `branch`, oldest first
+    pub fn history<N: AsRef<str>, B: AsRef<str>>(
+        &mut self,
+        name: N,
+        branch: B,
+        count: rhai::INT,
+    ) -> Result<rhai::Array, Box<rhai::EvalAltResult>> {
+        let history = self
+            .history
+            .as_ref()
+            .ok_or("There are no earlier results on this runner")?;
+        let mut values = history
+            .store
+            .history(&history.repo, branch, name.as_ref(), count.max(0) as usize)
+            .map_err(|e| format!("{e}"))?;
+        values.reverse();
+        Ok(values.into_iter().map(rhai::Dynamic::from_float).collect())
+    }
+
+    pub fn record<N: AsRef<str>, U: AsRef<str>>(
+        &mut self,
+        name: N,
+        value: rhai::FLOAT,
+        unit: U,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        tracing::debug!("Recording {} = {} {}", name.as_ref(), value, unit.as_ref());
+        self.metrics
+            .lock()
+            .map_err(|_| "Failed to gain exclusive lock on the results")?
+            .push(Metric {
+                name: name.as_ref().into(),
+                value,
+                unit: unit.as_ref().into(),
+            });
+        Ok(())
+    }
+
+    pub fn record_int<N: AsRef<str>, U: AsRef<str>>(
+        &mut self,
+        name: N,
+        value: rhai::INT,
+        unit: U,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        self.record(name, value as rhai::FLOAT, unit)
+    }
+
+    /// Record a quantity in the base unit of its kind
+    pub fn record_quantity<N: AsRef<str>>(
+        &mut self,
+        name: N,
+        quantity: super::units::Quantity,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        self.record(name, quantity.value, quantity.unit())
+    }
+
+    /// Record the size of the file at `path` as `size/<file name>`, to flag when it grows
+    pub fn track_size(
+        &mut self,
+        path: &std::path::Path,
+    ) -> Result<super::units::Quantity, Box<rhai::EvalAltResult>> {
+        let size = std::fs::metadata(path)
+            .map_err(|e| format!("Failed to get the size of {path:?}: {e}"))?
+            .len();
+        let name = path
+            .file_name()
+            .ok_or_else(|| format!("{path:?} isn't a file"))?
+            .to_string_lossy();
+        let size = super::units::Quantity::new(size as f64, "B").expect("B is a known unit");
+        self.record_quantity(format!("{}{name}", crate::results::SIZE_PREFIX), size)?;
+        Ok(size)
+    }
+
+    /// Number of metrics recorded so far
+    pub(crate) fn len(&self) -> usize {
+        self.metrics
+            .lock()
+            .map(|metrics| metrics.len())
+            .unwrap_or_default()
+    }
+
+    /// Discard the metrics recorded after the first `len`
+    pub(crate) fn truncate(&self, len: usize) {
+        if let Ok(mut metrics) = self.metrics.lock() {
+            metrics.truncate(len);
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> Vec<Metric> {
+        self.metrics
+            .lock()
+            .map(|metrics| metrics.clone())
+            .unwrap_or_default()
+    }
+}
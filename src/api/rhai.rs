@@ -51,3 +51,61 @@ pub mod toml {
         Ok(doc.to_string().into_bytes())
     }
 }
+
+/// Conversions between JSON/TOML and rhai values, registered globally so scripts can call e.g.
+/// `parse_json(REPO.read("results.json"))` directly.
+#[export_module]
+pub mod data {
+    use rhai::{Array, Blob, Dynamic};
+
+    fn utf8(blob: Blob) -> Result<String, Box<rhai::EvalAltResult>> {
+        String::from_utf8(blob).map_err(|_| "Data is not valid UTF-8".into())
+    }
+
+    /// Parse a JSON document into a map, array or plain value
+    #[rhai_fn(return_raw)]
+    pub fn parse_json(json: &str) -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| format!("Invalid JSON: {e}"))?;
+        rhai::serde::to_dynamic(value)
+    }
+
+    #[rhai_fn(name = "parse_json", return_raw)]
+    pub fn parse_json_blob(json: Blob) -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+        parse_json(&utf8(json)?)
+    }
+
+    /// Parse newline delimited JSON (like the output of `cargo --message-format=json`) into an
+    /// array, skipping empty lines
+    #[rhai_fn(return_raw)]
+    pub fn parse_json_lines(json: &str) -> Result<Array, Box<rhai::EvalAltResult>> {
+        json.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_json)
+            .collect()
+    }
+
+    #[rhai_fn(name = "parse_json_lines", return_raw)]
+    pub fn parse_json_lines_blob(json: Blob) -> Result<Array, Box<rhai::EvalAltResult>> {
+        parse_json_lines(&utf8(json)?)
+    }
+
+    #[rhai_fn(return_raw)]
+    pub fn to_json(value: Dynamic) -> Result<String, Box<rhai::EvalAltResult>> {
+        let value: serde_json::Value = rhai::serde::from_dynamic(&value)?;
+        Ok(value.to_string())
+    }
+
+    /// Parse a TOML document into a map
+    #[rhai_fn(return_raw)]
+    pub fn parse_toml(toml: &str) -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+        let value: ::toml::Value =
+            ::toml::from_str(toml).map_err(|e| format!("Invalid TOML: {e}"))?;
+        rhai::serde::to_dynamic(value)
+    }
+
+    #[rhai_fn(name = "parse_toml", return_raw)]
+    pub fn parse_toml_blob(toml: Blob) -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+        parse_toml(&utf8(toml)?)
+    }
+}
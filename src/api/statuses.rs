@@ -0,0 +1,82 @@
+use crate::forge::{CommitStatus, Forge};
+use octocrab::models::StatusState;
+use std::sync::Arc;
+
+/// Commit statuses of the commit a job runs on (the head of its pull request), exposed to
+/// scripts as `STATUS`, so pull requests can require benchmarks to pass before merging.
+#[derive(Clone, Debug)]
+pub struct Statuses {
+    forge: Arc<dyn Forge>,
+    sha: String,
+    redactor: crate::secrets::Redactor,
+}
+
+impl Statuses {
+    pub(crate) fn new(
+        forge: Arc<dyn Forge>,
+        sha: String,
+        redactor: crate::secrets::Redactor,
+    ) -> Self {
+        Statuses {
+            forge,
+            sha,
+            redactor,
+        }
+    }
+
+    /// Set the status named `context` to `state` (`pending`, `success`, `failure` or `error`),
+    /// linking to `target_url` if it isn't empty
+    pub fn set(
+        &mut self,
+        context: &str,
+        state: &str,
+        target_url: &str,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        self.set_with_description(context, state, target_url, "")
+    }
+
+    pub fn set_without_url(
+        &mut self,
+        context: &str,
+        state: &str,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        self.set_with_description(context, state, "", "")
+    }
+
+    pub fn set_with_description(
+        &mut self,
+        context: &str,
+        state: &str,
+        target_url: &str,
+        description: &str,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        let state = match state {
+            "pending" => StatusState::Pending,
+            "success" => StatusState::Success,
+            "failure" => StatusState::Failure,
+            "error" => StatusState::Error,
+            _ => {
+                return Err(format!(
+                    "Invalid status {state:?}, expected pending, success, failure or error"
+                )
+                .into())
+            }
+        };
+        let target_url = Some(target_url.to_string()).filter(|url| !url.is_empty());
+        // Github rejects longer descriptions
+        let description: String = self
+            .redactor
+            .redact(description)
+            .chars()
+            .take(140)
+            .collect();
+        let status = CommitStatus {
+            state,
+            description: Some(description).filter(|description| !description.is_empty()),
+            target_url,
+        };
+        self.forge
+            .set_status(&self.sha, context, &status)
+            .map_err(|e| format!("Failed to set commit status: {e}").into())
+    }
+}
@@ -0,0 +1,352 @@
+//! Markdown tables for scripts to report results in, instead of concatenating pipes by hand.
+//! Columns of numbers are aligned to the right, floats get the same number of decimals and
+//! relative changes are marked by whether they're regressions:
+//!
+//! ```rhai
+//! let t = table(["Benchmark", "Before", "After", "Change"]);
+//! t.add_row(["transfer", before, after, change(before, after)]);
+//! t.set_threshold(2.0); // Changes within ±2% are noise
+//! ISSUE.comment(t.markdown());
+//! // | Benchmark |   Before |    After |    Change |
+//! // |:----------|---------:|---------:|----------:|
+//! // | transfer  | 52.10 µs | 55.30 µs | 🔴 +6.14% |
+//! ```
+use super::units::Quantity;
+use rhai::plugin::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+impl std::str::FromStr for Align {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "left" => Ok(Align::Left),
+            "right" => Ok(Align::Right),
+            "center" => Ok(Align::Center),
+            _ => Err(format!(
+                "Invalid alignment {s:?}, expected left, right or center"
+            )),
+        }
+    }
+}
+
+/// Relative change from one result to another, in percent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Change {
+    pub percent: f64,
+}
+
+impl Change {
+    /// The change with its sign, marked 🔴 if it's a regression beyond `threshold` percent, 🟢 if
+    /// it's an improvement beyond it and ⚪ otherwise
+    fn render(&self, threshold: f64, lower_is_better: bool) -> String {
+        if !self.percent.is_finite() {
+            return "n/a".to_string();
+        }
+        let worse = if lower_is_better {
+            self.percent > threshold
+        } else {
+            self.percent < -threshold
+        };
+        let better = if lower_is_better {
+            self.percent < -threshold
+        } else {
+            self.percent > threshold
+        };
+        let mark = match (worse, better) {
+            (true, _) => "🔴",
+            (_, true) => "🟢",
+            _ => "⚪",
+        };
+        format!("{mark} {:+.2}%", self.percent)
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:+.2}%", self.percent)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Cell {
+    Text(String),
+    Int(rhai::INT),
+    Float(rhai::FLOAT),
+    Quantity(Quantity),
+    Change(Change),
+}
+
+impl Cell {
+    fn from_dynamic(value: Dynamic) -> Self {
+        if value.is::<Quantity>() {
+            Cell::Quantity(value.cast())
+        } else if value.is::<Change>() {
+            Cell::Change(value.cast())
+        } else if let Ok(int) = value.as_int() {
+            Cell::Int(int)
+        } else if let Ok(float) = value.as_float() {
+            Cell::Float(float)
+        } else if value.is::<()>() {
+            Cell::Text(String::new())
+        } else {
+            Cell::Text(value.to_string())
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        !matches!(self, Cell::Text(_))
+    }
+}
+
+/// A markdown table, built up row by row
+#[derive(Clone, Debug)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+    /// Set with `align`, the others are aligned to the right if they only hold numbers
+    align: Vec<Option<Align>>,
+    /// Decimals of floats
+    precision: usize,
+    /// Changes within this many percent either way aren't marked as regressions or
+    /// improvements
+    threshold: f64,
+    /// Whether changes are regressions when they go up, like for timings, or down
+    lower_is_better: bool,
+}
+
+impl Table {
+    pub fn new<S: ToString>(header: &[S]) -> Self {
+        Table {
+            header: header.iter().map(|name| name.to_string()).collect(),
+            rows: vec![],
+            align: vec![None; header.len()],
+            precision: 2,
+            threshold: 0.0,
+            lower_is_better: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn render_cell(&self, cell: &Cell) -> String {
+        let text = match cell {
+            Cell::Text(text) => text.clone(),
+            Cell::Int(int) => int.to_string(),
+            Cell::Float(float) => format!("{float:.*}", self.precision),
+            Cell::Quantity(quantity) => quantity.to_string(),
+            Cell::Change(change) => change.render(self.threshold, self.lower_is_better),
+        };
+        // Pipes would end the cell, line breaks the row
+        text.replace('|', "\\|").replace('\n', "<br>")
+    }
+
+    /// Render as a markdown table, padded so it also lines up as text
+    pub fn markdown(&self) -> String {
+        let columns = self
+            .rows
+            .iter()
+            .map(Vec::len)
+            .chain([self.header.len()])
+            .max()
+            .unwrap_or_default();
+        let header: Vec<String> = (0..columns)
+            .map(|column| {
+                let name = self.header.get(column).map(String::as_str);
+                name.unwrap_or_default().replace('|', "\\|")
+            })
+            .collect();
+        let rows: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                (0..columns)
+                    .map(|column| match row.get(column) {
+                        Some(cell) => self.render_cell(cell),
+                        None => String::new(),
+                    })
+                    .collect()
+            })
+            .collect();
+        let align: Vec<Align> = (0..columns)
+            .map(|column| match self.align.get(column).copied().flatten() {
+                Some(align) => align,
+                None => {
+                    let mut cells = self.rows.iter().filter_map(|row| row.get(column));
+                    let numbers = cells.all(Cell::is_number);
+                    let any = self.rows.iter().any(|row| row.get(column).is_some());
+                    if numbers && any {
+                        Align::Right
+                    } else {
+                        Align::Left
+                    }
+                }
+            })
+            .collect();
+        let widths: Vec<usize> = (0..columns)
+            .map(|column| {
+                rows.iter()
+                    .map(|row| &row[column])
+                    .chain([&header[column]])
+                    .map(|text| text.chars().count())
+                    .max()
+                    .unwrap_or_default()
+                    // The shortest separator with alignment markers, like `:-:`
+                    .max(3)
+            })
+            .collect();
+
+        let line = |cells: &[String]| {
+            let cells: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .zip(&align)
+                .map(|((text, width), align)| pad(text, *width, *align))
+                .collect();
+            format!("| {} |\n", cells.join(" | "))
+        };
+        let separator: Vec<String> = widths
+            .iter()
+            .zip(&align)
+            .map(|(width, align)| match align {
+                Align::Left => format!(":{}", "-".repeat(width + 1)),
+                Align::Right => format!("{}:", "-".repeat(width + 1)),
+                Align::Center => format!(":{}:", "-".repeat(*width)),
+            })
+            .collect();
+        let mut out = line(&header);
+        out.push_str(&format!("|{}|\n", separator.join("|")));
+        for row in &rows {
+            out.push_str(&line(row));
+        }
+        out
+    }
+}
+
+fn pad(text: &str, width: usize, align: Align) -> String {
+    let missing = width.saturating_sub(text.chars().count());
+    match align {
+        Align::Left => format!("{text}{}", " ".repeat(missing)),
+        Align::Right => format!("{}{text}", " ".repeat(missing)),
+        Align::Center => format!(
+            "{}{text}{}",
+            " ".repeat(missing / 2),
+            " ".repeat(missing - missing / 2)
+        ),
+    }
+}
+
+#[export_module]
+pub mod functions {
+    use super::{Change, Table};
+    use crate::api::units::Quantity;
+    use rhai::{Array, FLOAT, INT};
+    use std::convert::TryFrom;
+
+    /// A table with the column names in `header`, like `table(["Benchmark", "Time"])`
+    pub fn table(header: Array) -> Table {
+        Table::new(&header)
+    }
+
+    /// Add a row of cells: strings, numbers, quantities or changes. Missing cells are left empty.
+    #[rhai_fn(global)]
+    pub fn add_row(table: &mut Table, row: Array) {
+        table
+            .rows
+            .push(row.into_iter().map(super::Cell::from_dynamic).collect());
+    }
+
+    /// Align the column at `index` (from 0) `"left"`, `"right"` or `"center"`
+    #[rhai_fn(global, return_raw)]
+    pub fn align(table: &mut Table, index: INT, align: &str) -> Result<(), Box<EvalAltResult>> {
+        let align = align.parse()?;
+        let index = usize::try_from(index).map_err(|_| format!("Invalid column {index}"))?;
+        if table.align.len() <= index {
+            table.align.resize(index + 1, None);
+        }
+        table.align[index] = Some(align);
+        Ok(())
+    }
+
+    /// Show floats with `decimals` decimals, 2 by default
+    #[rhai_fn(global)]
+    pub fn set_precision(table: &mut Table, decimals: INT) {
+        table.precision = decimals.clamp(0, 16) as usize;
+    }
+
+    /// Only mark changes beyond `percent` either way as regressions or improvements
+    #[rhai_fn(global)]
+    pub fn set_threshold(table: &mut Table, percent: FLOAT) {
+        table.threshold = percent.abs();
+    }
+
+    /// Count changes going up as improvements, like for throughput, instead of regressions
+    #[rhai_fn(global)]
+    pub fn higher_is_better(table: &mut Table) {
+        table.lower_is_better = false;
+    }
+
+    #[rhai_fn(global, pure)]
+    pub fn markdown(table: &mut Table) -> String {
+        table.markdown()
+    }
+
+    #[rhai_fn(global, name = "len", pure)]
+    pub fn len(table: &mut Table) -> INT {
+        table.rows.len() as INT
+    }
+
+    #[rhai_fn(global, name = "to_string", name = "to_debug", pure)]
+    pub fn table_to_string(table: &mut Table) -> String {
+        table.markdown()
+    }
+
+    /// The relative change from `before` to `after`, rendered in tables as a regression or an
+    /// improvement
+    #[rhai_fn(name = "change")]
+    pub fn change(before: FLOAT, after: FLOAT) -> Change {
+        Change {
+            percent: (after - before) / before * 100.0,
+        }
+    }
+
+    #[rhai_fn(name = "change")]
+    pub fn change_int(before: INT, after: INT) -> Change {
+        change(before as FLOAT, after as FLOAT)
+    }
+
+    #[rhai_fn(name = "change", return_raw)]
+    pub fn change_quantity(
+        before: Quantity,
+        after: Quantity,
+    ) -> Result<Change, Box<EvalAltResult>> {
+        let ratio = crate::api::units::functions::ratio(after, before)?;
+        Ok(Change {
+            percent: (ratio - 1.0) * 100.0,
+        })
+    }
+
+    /// A change already in percent, like `percent_of` returns
+    #[rhai_fn(name = "change_percent")]
+    pub fn change_percent(percent: FLOAT) -> Change {
+        Change { percent }
+    }
+
+    #[rhai_fn(global, get = "percent", pure)]
+    pub fn get_percent(change: &mut Change) -> FLOAT {
+        change.percent
+    }
+
+    #[rhai_fn(global, name = "to_string", name = "to_debug", pure)]
+    pub fn change_to_string(change: &mut Change) -> String {
+        change.to_string()
+    }
+}
@@ -0,0 +1,237 @@
+//! The Rust toolchain cargo runs with, managed through rustup. The toolchain of the checkout's
+//! `rust-toolchain.toml` (or `rust-toolchain`) is installed before the script runs, with its
+//! components and targets, and scripts can switch to another one, exposed to them as `TOOLCHAIN`:
+//!
+//! ```rhai
+//! TOOLCHAIN.select("nightly-2024-05-01", ["rust-src"]);
+//! cargo "build --release";
+//! TOOLCHAIN.reset(); // Back to the one of the checkout
+//! ```
+//!
+//! (`use` would read better than `select`, but it's a reserved keyword in rhai.)
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to run rustup: {0}")]
+    Spawn(#[source] std::io::Error),
+    #[error("rustup {args} failed: {stderr}")]
+    Rustup { args: String, stderr: String },
+    #[error("Failed to read {path:?}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+}
+
+/// Installing into the same `RUSTUP_HOME` from several jobs at once can leave it broken, so the
+/// jobs of a process install one toolchain at a time
+static INSTALLS: Mutex<()> = Mutex::new(());
+
+/// What the toolchain file of a checkout asks for, see
+/// <https://rust-lang.github.io/rustup/overrides.html#the-toolchain-file>
+#[derive(Clone, Debug, Default)]
+pub struct Requested {
+    /// Like `stable`, `1.78.0` or `nightly-2024-05-01`
+    pub channel: String,
+    pub components: Vec<String>,
+    pub targets: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ToolchainFile {
+    toolchain: ToolchainSection,
+}
+
+#[derive(Deserialize)]
+struct ToolchainSection {
+    /// Absent when the file points to a custom toolchain with `path`, which rustup handles
+    channel: Option<String>,
+    #[serde(default)]
+    components: Vec<String>,
+    #[serde(default)]
+    targets: Vec<String>,
+}
+
+impl Requested {
+    /// The toolchain the file in `dir` asks for, if there is one
+    pub fn read<P: AsRef<Path>>(dir: P) -> Result<Option<Self>, Error> {
+        for name in ["rust-toolchain.toml", "rust-toolchain"] {
+            let path = dir.as_ref().join(name);
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(source) => return Err(Error::Read { path, source }),
+            };
+            // The legacy `rust-toolchain` may only have the name of the channel
+            let legacy = content.trim();
+            if name == "rust-toolchain" && !legacy.is_empty() && !legacy.contains(['[', '=']) {
+                return Ok(Some(Requested {
+                    channel: legacy.to_string(),
+                    ..Default::default()
+                }));
+            }
+            let file: ToolchainFile =
+                toml::from_str(&content).map_err(|source| Error::Parse { path, source })?;
+            let ToolchainSection {
+                channel,
+                components,
+                targets,
+            } = file.toolchain;
+            return Ok(channel.map(|channel| Requested {
+                channel,
+                components,
+                targets,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+/// Where toolchains are installed and the one picked by the script, shared by its `cargo` calls
+#[derive(Clone, Debug, Default)]
+pub struct Toolchain {
+    /// `RUSTUP_HOME` of the job, the one of the user if not set
+    rustup_home: Option<PathBuf>,
+    selected: Arc<Mutex<Option<String>>>,
+    /// The toolchain of the checkout, once installed
+    checkout: Arc<Mutex<Option<String>>>,
+}
+
+impl Toolchain {
+    pub fn new(rustup_home: Option<PathBuf>) -> Self {
+        Toolchain {
+            rustup_home,
+            ..Default::default()
+        }
+    }
+
+    pub fn rustup_home(&self) -> Option<&Path> {
+        self.rustup_home.as_deref()
+    }
+
+    /// Install the toolchain the checkout in `dir` asks for, or make sure there's a default one
+    /// in a `RUSTUP_HOME` of its own
+    pub fn prepare<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        match Requested::read(&dir)? {
+            Some(requested) => {
+                self.install(&requested)?;
+                *self.checkout.lock().unwrap_or_else(|e| e.into_inner()) = Some(requested.channel);
+            }
+            None if self.rustup_home.is_some() => {
+                let _installing = INSTALLS.lock().unwrap_or_else(|e| e.into_inner());
+                if self.rustup(&["default"]).is_err() {
+                    tracing::info!("Installing stable as the default toolchain");
+                    self.rustup(&["toolchain", "install", "stable", "--profile", "minimal"])?;
+                    self.rustup(&["default", "stable"])?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Install `requested` unless it already is, along with its components and targets
+    pub fn install(&self, requested: &Requested) -> Result<(), Error> {
+        let mut args = vec![
+            "toolchain",
+            "install",
+            requested.channel.as_str(),
+            "--profile",
+            "minimal",
+        ];
+        for component in &requested.components {
+            args.extend(["--component", component.as_str()]);
+        }
+        for target in &requested.targets {
+            args.extend(["--target", target.as_str()]);
+        }
+        tracing::info!("Installing toolchain {}", requested.channel);
+        let _installing = INSTALLS.lock().unwrap_or_else(|e| e.into_inner());
+        self.rustup(&args)?;
+        Ok(())
+    }
+
+    /// Environment of cargo picking the toolchain selected by the script, if any, over the one
+    /// of the checkout
+    pub fn envs(&self) -> Vec<(String, String)> {
+        self.selected()
+            .map(|name| ("RUSTUP_TOOLCHAIN".to_string(), name))
+            .into_iter()
+            .collect()
+    }
+
+    fn selected(&self) -> Option<String> {
+        self.selected
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn rustup(&self, args: &[&str]) -> Result<(), Error> {
+        let mut command = std::process::Command::new("rustup");
+        command.args(args);
+        if let Some(home) = &self.rustup_home {
+            command.env("RUSTUP_HOME", home);
+        }
+        let output = command.output().map_err(Error::Spawn)?;
+        if !output.status.success() {
+            return Err(Error::Rustup {
+                args: args.join(" "),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Install the toolchain `name` and run cargo with it from now on
+    pub fn select(&mut self, name: &str) -> Result<(), Box<rhai::EvalAltResult>> {
+        self.select_with_components(name, rhai::Array::new())
+    }
+
+    /// Install the toolchain `name` with `components`, like `["rust-src", "clippy"]`, and run
+    /// cargo with it from now on
+    pub fn select_with_components(
+        &mut self,
+        name: &str,
+        components: rhai::Array,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        let requested = Requested {
+            channel: name.to_string(),
+            components: components.into_iter().map(|c| c.to_string()).collect(),
+            targets: vec![],
+        };
+        self.install(&requested).map_err(|e| format!("{e}"))?;
+        *self.selected.lock().unwrap_or_else(|e| e.into_inner()) = Some(requested.channel);
+        Ok(())
+    }
+
+    /// Run cargo with the toolchain of the checkout again
+    pub fn reset(&mut self) {
+        *self.selected.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// The toolchain cargo runs with: the one selected by the script or asked for by the
+    /// checkout, `()` if it's rustup's default
+    pub fn get_name(&mut self) -> rhai::Dynamic {
+        let checkout = || {
+            self.checkout
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone()
+        };
+        match self.selected().or_else(checkout) {
+            Some(name) => name.into(),
+            None => rhai::Dynamic::UNIT,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Results with a unit, like durations, weights and byte sizes. Scripts comparing results can
+//! calculate with them directly and print them in a fitting unit:
+//!
+//! ```rhai
+//! let before = quantity(1.25, "ms");
+//! let after = quantity(1300, "us");
+//! print(`${after - before} (${format_percent((after - before).percent_of(before))})`);
+//! // 50.00 µs (+4.00%)
+//! ```
+use rhai::plugin::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Duration,
+    Weight,
+    Bytes,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Duration => "duration",
+            Kind::Weight => "weight",
+            Kind::Bytes => "byte size",
+        }
+    }
+
+    /// Name of the unit values of this kind are kept in
+    fn base_unit(self) -> &'static str {
+        match self {
+            Kind::Duration => "ns",
+            Kind::Weight => "weight",
+            Kind::Bytes => "B",
+        }
+    }
+
+    /// Units to format values with, from small to large, with their size in the base unit
+    fn units(self) -> &'static [(&'static str, f64)] {
+        match self {
+            Kind::Duration => &[("ns", 1.0), ("µs", 1e3), ("ms", 1e6), ("s", 1e9)],
+            Kind::Weight => &[("", 1.0), ("k", 1e3), ("M", 1e6), ("G", 1e9), ("T", 1e12)],
+            Kind::Bytes => &[
+                ("B", 1.0),
+                ("KiB", 1024.0),
+                ("MiB", 1024.0 * 1024.0),
+                ("GiB", 1024.0 * 1024.0 * 1024.0),
+                ("TiB", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+            ],
+        }
+    }
+}
+
+/// Kind and size in the base unit of `unit`
+fn parse_unit(unit: &str) -> Option<(Kind, f64)> {
+    let parsed = match unit {
+        "ps" => (Kind::Duration, 1e-3),
+        "ns" => (Kind::Duration, 1.0),
+        "us" | "µs" => (Kind::Duration, 1e3),
+        "ms" => (Kind::Duration, 1e6),
+        "s" => (Kind::Duration, 1e9),
+        "weight" => (Kind::Weight, 1.0),
+        "kB" => (Kind::Bytes, 1e3),
+        "MB" => (Kind::Bytes, 1e6),
+        "GB" => (Kind::Bytes, 1e9),
+        _ => {
+            let kind = [Kind::Duration, Kind::Weight, Kind::Bytes]
+                .iter()
+                .copied()
+                .find(|kind| kind.units().iter().any(|(name, _)| *name == unit))?;
+            let (_, size) = kind.units().iter().find(|(name, _)| *name == unit)?;
+            (kind, *size)
+        }
+    };
+    Some(parsed)
+}
+
+/// A value of some kind, in the base unit of that kind (nanoseconds, weight or bytes)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub kind: Kind,
+}
+
+impl Quantity {
+    pub fn new(value: f64, unit: &str) -> Result<Self, Box<EvalAltResult>> {
+        let (kind, size) = parse_unit(unit).ok_or_else(|| format!("Unknown unit {unit:?}"))?;
+        Ok(Quantity {
+            value: value * size,
+            kind,
+        })
+    }
+
+    pub fn unit(&self) -> &'static str {
+        self.kind.base_unit()
+    }
+
+    /// The other quantity's value, if it's of the same kind
+    fn value_of(&self, other: &Quantity) -> Result<f64, Box<EvalAltResult>> {
+        if self.kind != other.kind {
+            return Err(format!(
+                "Can't combine a {} with a {}",
+                self.kind.name(),
+                other.kind.name()
+            )
+            .into());
+        }
+        Ok(other.value)
+    }
+
+    fn with_value(&self, value: f64) -> Self {
+        Quantity {
+            value,
+            kind: self.kind,
+        }
+    }
+}
+
+impl std::fmt::Display for Quantity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let units = self.kind.units();
+        let (name, size) = units
+            .iter()
+            .rev()
+            .find(|(_, size)| self.value.abs() >= *size)
+            .unwrap_or(&units[0]);
+        let value = self.value / size;
+        match (self.kind, *name) {
+            (Kind::Bytes, "B") => write!(f, "{value:.0} B"),
+            (Kind::Weight, prefix) => write!(f, "{value:.2}{prefix} weight"),
+            (_, name) => write!(f, "{value:.2} {name}"),
+        }
+    }
+}
+
+#[export_module]
+pub mod functions {
+    use super::Quantity;
+    use rhai::{FLOAT, INT};
+
+    /// A quantity of `value` in `unit`, like `quantity(1.5, "ms")` or `quantity(512, "MiB")`
+    #[rhai_fn(return_raw)]
+    pub fn quantity(value: FLOAT, unit: &str) -> Result<Quantity, Box<EvalAltResult>> {
+        Quantity::new(value, unit)
+    }
+
+    #[rhai_fn(name = "quantity", return_raw)]
+    pub fn quantity_int(value: INT, unit: &str) -> Result<Quantity, Box<EvalAltResult>> {
+        Quantity::new(value as FLOAT, unit)
+    }
+
+    /// The value in the base unit (`unit`)
+    #[rhai_fn(get = "value", pure)]
+    pub fn value(quantity: &mut Quantity) -> FLOAT {
+        quantity.value
+    }
+
+    #[rhai_fn(get = "unit", pure)]
+    pub fn unit(quantity: &mut Quantity) -> String {
+        quantity.unit().to_string()
+    }
+
+    /// The value in `unit`, like `duration.to("ms")`
+    #[rhai_fn(pure, return_raw)]
+    pub fn to(quantity: &mut Quantity, unit: &str) -> Result<FLOAT, Box<EvalAltResult>> {
+        let one = Quantity::new(1.0, unit)?;
+        Ok(one.value_of(quantity)? / one.value)
+    }
+
+    /// How much this is of `base` in percent, like `(after - before).percent_of(before)`
+    #[rhai_fn(pure, return_raw)]
+    pub fn percent_of(
+        quantity: &mut Quantity,
+        base: Quantity,
+    ) -> Result<FLOAT, Box<EvalAltResult>> {
+        Ok(quantity.value / quantity.value_of(&base)? * 100.0)
+    }
+
+    #[rhai_fn(pure)]
+    pub fn abs(quantity: &mut Quantity) -> Quantity {
+        quantity.with_value(quantity.value.abs())
+    }
+
+    #[rhai_fn(name = "to_string", name = "to_debug", pure)]
+    pub fn to_string(quantity: &mut Quantity) -> String {
+        quantity.to_string()
+    }
+
+    #[rhai_fn(name = "+", return_raw)]
+    pub fn add(a: Quantity, b: Quantity) -> Result<Quantity, Box<EvalAltResult>> {
+        Ok(a.with_value(a.value + a.value_of(&b)?))
+    }
+
+    #[rhai_fn(name = "-", return_raw)]
+    pub fn subtract(a: Quantity, b: Quantity) -> Result<Quantity, Box<EvalAltResult>> {
+        Ok(a.with_value(a.value - a.value_of(&b)?))
+    }
+
+    #[rhai_fn(name = "-")]
+    pub fn negate(a: Quantity) -> Quantity {
+        a.with_value(-a.value)
+    }
+
+    /// The ratio between two quantities, like `after / before`
+    #[rhai_fn(name = "/", return_raw)]
+    pub fn ratio(a: Quantity, b: Quantity) -> Result<FLOAT, Box<EvalAltResult>> {
+        Ok(a.value / a.value_of(&b)?)
+    }
+
+    #[rhai_fn(name = "/")]
+    pub fn divide(a: Quantity, b: FLOAT) -> Quantity {
+        a.with_value(a.value / b)
+    }
+
+    #[rhai_fn(name = "/")]
+    pub fn divide_int(a: Quantity, b: INT) -> Quantity {
+        a.with_value(a.value / b as FLOAT)
+    }
+
+    #[rhai_fn(name = "*")]
+    pub fn multiply(a: Quantity, b: FLOAT) -> Quantity {
+        a.with_value(a.value * b)
+    }
+
+    #[rhai_fn(name = "*")]
+    pub fn multiply_int(a: Quantity, b: INT) -> Quantity {
+        a.with_value(a.value * b as FLOAT)
+    }
+
+    #[rhai_fn(name = "*")]
+    pub fn multiply_by(a: FLOAT, b: Quantity) -> Quantity {
+        b.with_value(a * b.value)
+    }
+
+    #[rhai_fn(name = "*")]
+    pub fn multiply_by_int(a: INT, b: Quantity) -> Quantity {
+        b.with_value(a as FLOAT * b.value)
+    }
+
+    #[rhai_fn(name = "==")]
+    pub fn eq(a: Quantity, b: Quantity) -> bool {
+        a == b
+    }
+
+    #[rhai_fn(name = "!=")]
+    pub fn ne(a: Quantity, b: Quantity) -> bool {
+        a != b
+    }
+
+    #[rhai_fn(name = "<", return_raw)]
+    pub fn lt(a: Quantity, b: Quantity) -> Result<bool, Box<EvalAltResult>> {
+        Ok(a.value < a.value_of(&b)?)
+    }
+
+    #[rhai_fn(name = "<=", return_raw)]
+    pub fn le(a: Quantity, b: Quantity) -> Result<bool, Box<EvalAltResult>> {
+        Ok(a.value <= a.value_of(&b)?)
+    }
+
+    #[rhai_fn(name = ">", return_raw)]
+    pub fn gt(a: Quantity, b: Quantity) -> Result<bool, Box<EvalAltResult>> {
+        Ok(a.value > a.value_of(&b)?)
+    }
+
+    #[rhai_fn(name = ">=", return_raw)]
+    pub fn ge(a: Quantity, b: Quantity) -> Result<bool, Box<EvalAltResult>> {
+        Ok(a.value >= a.value_of(&b)?)
+    }
+
+    /// A percentage like `percent_of` returns, with its sign and two decimals: `+4.00%`
+    pub fn format_percent(percent: FLOAT) -> String {
+        format!("{percent:+.2}%")
+    }
+}
@@ -0,0 +1,177 @@
+//! Resources used by the processes a job runs, like cargo, taken from what the kernel reports when
+//! they're waited for (`wait4`). They include whatever those processes waited for in turn, like
+//! rustc and the benchmarks themselves, so runaway benchmarks stand out and runners can be sized
+//! after what jobs actually need.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Blocks `ru_inblock` and `ru_oublock` are counted in
+const BLOCK_SIZE: u64 = 512;
+
+/// `ru_maxrss` is in kilobytes on Linux and the BSDs
+#[cfg(all(unix, not(target_os = "macos")))]
+fn max_rss_bytes(max_rss: u64) -> u64 {
+    max_rss * 1024
+}
+
+/// `ru_maxrss` is in bytes on macOS
+#[cfg(target_os = "macos")]
+fn max_rss_bytes(max_rss: u64) -> u64 {
+    max_rss
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Usage {
+    /// CPU time spent in user space
+    #[serde(rename = "user_secs", with = "secs")]
+    pub user: Duration,
+    /// CPU time spent in the kernel
+    #[serde(rename = "system_secs", with = "secs")]
+    pub system: Duration,
+    /// Peak resident set size of the largest process, in bytes
+    pub max_rss: u64,
+    /// Read from disk, not counting what was already cached, in bytes
+    pub read_bytes: u64,
+    /// Written to disk, in bytes
+    pub written_bytes: u64,
+}
+
+impl Usage {
+    #[cfg(unix)]
+    fn from_rusage(rusage: &libc::rusage) -> Self {
+        let duration = |time: libc::timeval| {
+            Duration::from_secs(time.tv_sec.max(0) as u64)
+                + Duration::from_micros(time.tv_usec.max(0) as u64)
+        };
+        Usage {
+            user: duration(rusage.ru_utime),
+            system: duration(rusage.ru_stime),
+            max_rss: max_rss_bytes(rusage.ru_maxrss.max(0) as u64),
+            read_bytes: rusage.ru_inblock.max(0) as u64 * BLOCK_SIZE,
+            written_bytes: rusage.ru_oublock.max(0) as u64 * BLOCK_SIZE,
+        }
+    }
+
+    pub fn cpu(&self) -> Duration {
+        self.user + self.system
+    }
+
+    /// Add the usage of processes that ran after these, the peak being the larger of both
+    pub fn add(&mut self, other: Usage) {
+        self.user += other.user;
+        self.system += other.system;
+        self.max_rss = self.max_rss.max(other.max_rss);
+        self.read_bytes += other.read_bytes;
+        self.written_bytes += other.written_bytes;
+    }
+
+    /// Render as a short footer for a result comment
+    pub fn render(&self) -> String {
+        format!(
+            "<sub>Resources: cpu {} · peak memory {} · disk read {}, written {}</sub>",
+            super::phases::format_duration(self.cpu()),
+            format_bytes(self.max_rss),
+            format_bytes(self.read_bytes),
+            format_bytes(self.written_bytes)
+        )
+    }
+
+    pub(crate) fn to_dynamic(self) -> rhai::Dynamic {
+        let mut map = rhai::Map::new();
+        map.insert("cpu_secs".into(), self.cpu().as_secs_f64().into());
+        map.insert("user_secs".into(), self.user.as_secs_f64().into());
+        map.insert("system_secs".into(), self.system.as_secs_f64().into());
+        map.insert("max_rss".into(), (self.max_rss as rhai::INT).into());
+        map.insert("read_bytes".into(), (self.read_bytes as rhai::INT).into());
+        map.insert(
+            "written_bytes".into(),
+            (self.written_bytes as rhai::INT).into(),
+        );
+        rhai::Dynamic::from_map(map)
+    }
+}
+
+/// Durations as fractional seconds, like the rest of what's recorded about jobs
+mod secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Ok(Duration::try_from_secs_f64(secs).unwrap_or_default())
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
+
+/// Adds up the usage of the processes of a job, shared by everything that runs them
+#[derive(Clone, Debug, Default)]
+pub struct Accounting(Arc<Mutex<Option<Usage>>>);
+
+impl Accounting {
+    pub fn record(&self, usage: Usage) {
+        self.lock().get_or_insert_with(Usage::default).add(usage);
+    }
+
+    /// The usage of all processes so far, none if the job didn't run any
+    pub fn total(&self) -> Option<Usage> {
+        *self.lock()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Usage>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Wait for `child` to exit like [`std::process::Child::wait`], along with what it used
+#[cfg(unix)]
+pub fn wait(
+    child: &mut std::process::Child,
+) -> std::io::Result<(std::process::ExitStatus, Option<Usage>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // Plain old data, zeroes are valid
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        let pid = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut rusage) };
+        if pid >= 0 {
+            break;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    Ok((
+        std::process::ExitStatus::from_raw(status),
+        Some(Usage::from_rusage(&rusage)),
+    ))
+}
+
+/// Wait for `child` to exit like [`std::process::Child::wait`], what it used is only known on
+/// Unix
+#[cfg(not(unix))]
+pub fn wait(
+    child: &mut std::process::Child,
+) -> std::io::Result<(std::process::ExitStatus, Option<Usage>)> {
+    Ok((child.wait()?, None))
+}
@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex};
+
+/// Constants still available to scripts that will be removed in a future version, together with
+/// what to use instead.
+pub const DEPRECATED_CONSTANTS: &[(&str, &str)] =
+    &[("Git", "the `git` module (e.g. `git::clone(...)`)")];
+
+/// Warnings collected while running a script, reported back to the user together with the result
+/// of the job.
+#[derive(Clone, Debug, Default)]
+pub struct Warnings {
+    warnings: Arc<Mutex<Vec<String>>>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a warning, unless the exact same warning was already reported during this run
+    pub fn push<S: Into<String>>(&self, warning: S) {
+        let warning = warning.into();
+        if let Ok(mut warnings) = self.warnings.lock() {
+            if !warnings.contains(&warning) {
+                tracing::warn!("Script warning: {}", warning);
+                warnings.push(warning);
+            }
+        }
+    }
+
+    pub fn deprecated<O: std::fmt::Display, R: std::fmt::Display>(&self, old: O, replacement: R) {
+        self.push(format!("`{old}` is deprecated, use {replacement} instead"))
+    }
+
+    pub(crate) fn warnings(&self) -> Vec<String> {
+        self.warnings
+            .lock()
+            .map(|warnings| warnings.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Render the warnings as a markdown section suitable for a result comment
+pub fn render(warnings: &[String]) -> String {
+    let mut out = String::from("### Script warnings\n\n");
+    for warning in warnings {
+        out.push_str(&format!("- {warning}\n"));
+    }
+    out
+}
@@ -0,0 +1,515 @@
+//! Regenerating the weights of runtime pallets, what the bot mostly runs for Substrate
+//! repositories. `WEIGHTS.update(pallets)` runs the benchmarks of each pallet with the configured
+//! bencher, which writes its weight file, and compares the weights of its extrinsics to those of
+//! the file it replaced:
+//!
+//! ```rhai
+//! let deltas = WEIGHTS.update(["pallet_balances", "pallet_staking"]);
+//! ISSUE.comment(WEIGHTS.summary(deltas));
+//! ```
+//!
+//! `RUNTIME.pallets()` lists the pallets the runtime has benchmarks for, to update all of them or
+//! the ones a pull request touched instead of keeping a list in the script:
+//!
+//! ```rhai
+//! let pallets = RUNTIME.pallets().filter(|pallet| PR.changed_files.some(|f| f.contains(pallet)));
+//! ```
+//!
+//! How the benchmarks are run is configured in `weights.toml` next to the scripts of the bot, see
+//! [`Config`]. The built-in `weights` script (see [`crate::builtin`]) does the rest: it commits
+//! the weight files on a branch and opens a pull request with the summary.
+
+use super::cargo::CargoResult;
+use super::table::{Change, Table};
+use super::units::Quantity;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the weights configuration {0:?}: {1}")]
+    ReadConfig(PathBuf, std::io::Error),
+    #[error("Invalid weights configuration {0:?}: {1}")]
+    ParseConfig(PathBuf, toml::de::Error),
+    #[error("Invalid pallet name {0:?}")]
+    InvalidPallet(String),
+    #[error("Failed to parse the benchmark command {0:?}")]
+    InvalidCommand(String),
+    #[error("The benchmark command needs the `runtime` to benchmark, set it in weights.toml")]
+    NoRuntime,
+    #[error("Building the benchmarks failed:\n{0}")]
+    Build(String),
+    #[error("Listing the benchmarks failed:\n{0}")]
+    List(String),
+    #[error("Benchmarking {pallet} failed:\n{stderr}")]
+    Failed { pallet: String, stderr: String },
+    #[error("Failed to write the weights of {0}: {1}")]
+    Output(String, std::io::Error),
+}
+
+/// Name of the configuration, in the directory of the scripts of the bot
+pub const CONFIG_FILE: &str = "weights.toml";
+
+/// How to benchmark a pallet, from `weights.toml`:
+///
+/// ```toml
+/// build = "build --release --features runtime-benchmarks -p my-runtime"
+/// runtime = "target/release/wbuild/my-runtime/my_runtime.compact.compressed.wasm"
+/// output = "runtime/src/weights/{pallet}.rs"
+/// template = ".maintain/frame-weight-template.hbs"
+/// ```
+///
+/// Commands and paths have `{pallet}`, `{runtime}`, `{output}`, `{template}`, `{steps}` and
+/// `{repeat}` filled in. Paths are relative to the checkout.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Benchmarks a pallet and writes its weight file to `{output}`. Commands starting with
+    /// `cargo`, like `cargo run --release --features runtime-benchmarks -- benchmark pallet ...`,
+    /// are run like the `cargo` of scripts.
+    #[serde(default = "default_command")]
+    pub command: String,
+    /// Lists the benchmarks of the runtime, one `pallet, extrinsic` per line
+    #[serde(default = "default_list")]
+    pub list: String,
+    /// Arguments to cargo to build what `command` runs first, like the runtime
+    pub build: Option<String>,
+    /// The runtime to benchmark, for `frame-omni-bencher`
+    pub runtime: Option<String>,
+    /// Where the weight file of a pallet goes
+    #[serde(default = "default_output")]
+    pub output: String,
+    /// Handlebars template of the weight files, passed to the bencher with `--template`
+    pub template: Option<String>,
+    #[serde(default = "default_steps")]
+    pub steps: u32,
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+}
+
+fn default_command() -> String {
+    "frame-omni-bencher v1 benchmark pallet --runtime {runtime} --pallet {pallet} \
+     --extrinsic * --steps {steps} --repeat {repeat} --output {output}"
+        .to_string()
+}
+
+fn default_list() -> String {
+    "frame-omni-bencher v1 benchmark pallet --runtime {runtime} --list".to_string()
+}
+
+fn default_output() -> String {
+    "weights/{pallet}.rs".to_string()
+}
+
+fn default_steps() -> u32 {
+    50
+}
+
+fn default_repeat() -> u32 {
+    20
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            command: default_command(),
+            list: default_list(),
+            build: None,
+            runtime: None,
+            output: default_output(),
+            template: None,
+            steps: default_steps(),
+            repeat: default_repeat(),
+        }
+    }
+}
+
+impl Config {
+    /// Read the configuration at `path`, the defaults if there's none
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(config) => {
+                toml::from_str(&config).map_err(|err| Error::ParseConfig(path.to_owned(), err))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(Error::ReadConfig(path.to_owned(), err)),
+        }
+    }
+
+    /// The weight file of `pallet`, relative to the checkout
+    fn output(&self, pallet: &str) -> String {
+        self.output.replace("{pallet}", pallet)
+    }
+
+    /// Build what the commands run, if there's anything to build
+    fn build(&self, runner: &Runner) -> Result<(), Error> {
+        if let Some(build) = &self.build {
+            let args =
+                shell_words::split(build).map_err(|_| Error::InvalidCommand(build.clone()))?;
+            tracing::info!("Building the benchmarks");
+            let mut result = runner("cargo", &args);
+            if !result.is_ok() {
+                return Err(Error::Build(tail(&result.stderr)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The program and arguments benchmarking `pallet` into `output`
+    fn command(&self, pallet: &str, output: &Path) -> Result<(String, Vec<String>), Error> {
+        let mut args = self.args(&self.command, pallet, output)?;
+        if let (Some(template), false) = (&self.template, self.command.contains("{template}")) {
+            args.extend(["--template".to_string(), template.clone()]);
+        }
+        let program = args.remove(0);
+        Ok((program, args))
+    }
+
+    /// The program and arguments listing the benchmarks
+    fn list(&self) -> Result<(String, Vec<String>), Error> {
+        let mut args = self.args(&self.list, "", Path::new(""))?;
+        let program = args.remove(0);
+        Ok((program, args))
+    }
+
+    /// `command` split into arguments with the placeholders filled in, never empty
+    fn args(&self, command: &str, pallet: &str, output: &Path) -> Result<Vec<String>, Error> {
+        let runtime = self.runtime.as_deref();
+        if runtime.is_none() && command.contains("{runtime}") {
+            return Err(Error::NoRuntime);
+        }
+        let fill = |arg: &str| {
+            arg.replace("{pallet}", pallet)
+                .replace("{runtime}", runtime.unwrap_or_default())
+                .replace("{output}", &output.to_string_lossy())
+                .replace("{template}", self.template.as_deref().unwrap_or_default())
+                .replace("{steps}", &self.steps.to_string())
+                .replace("{repeat}", &self.repeat.to_string())
+        };
+        // Split before filling in, so names and paths stay one argument each
+        let args: Vec<String> = shell_words::split(command)
+            .map_err(|_| Error::InvalidCommand(command.to_string()))?
+            .iter()
+            .map(|arg| fill(arg))
+            .collect();
+        if args.is_empty() {
+            return Err(Error::InvalidCommand(command.to_string()));
+        }
+        Ok(args)
+    }
+}
+
+/// Runs a program in the checkout the way the `cargo` of scripts runs cargo
+pub type Runner = Arc<dyn Fn(&str, &[String]) -> CargoResult + Send + Sync>;
+
+/// The weights of the extrinsics of a pallet, exposed to scripts as `WEIGHTS`
+#[derive(Clone)]
+pub struct Weights {
+    /// The checkout
+    dir: PathBuf,
+    /// Of the configuration, which may not exist
+    config: PathBuf,
+    runner: Runner,
+}
+
+impl std::fmt::Debug for Weights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Weights")
+            .field("dir", &self.dir)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// How the base weight of an extrinsic changed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delta {
+    pub pallet: String,
+    pub extrinsic: String,
+    /// None if the extrinsic is new
+    pub before: Option<u64>,
+    /// None if the extrinsic was removed
+    pub after: Option<u64>,
+}
+
+impl Delta {
+    fn to_dynamic(&self) -> rhai::Dynamic {
+        let weight = |weight: Option<u64>| match weight {
+            Some(weight) => rhai::Dynamic::from(weight_quantity(weight)),
+            None => rhai::Dynamic::UNIT,
+        };
+        let mut map = rhai::Map::new();
+        map.insert("pallet".into(), self.pallet.clone().into());
+        map.insert("extrinsic".into(), self.extrinsic.clone().into());
+        map.insert("before".into(), weight(self.before));
+        map.insert("after".into(), weight(self.after));
+        let change = match (self.before, self.after) {
+            (Some(before), Some(after)) if before > 0 => rhai::Dynamic::from(Change {
+                percent: (after as f64 - before as f64) / before as f64 * 100.0,
+            }),
+            _ => rhai::Dynamic::UNIT,
+        };
+        map.insert("change".into(), change);
+        rhai::Dynamic::from_map(map)
+    }
+}
+
+fn weight_quantity(weight: u64) -> Quantity {
+    Quantity::new(weight as f64, "weight").expect("weight is a known unit")
+}
+
+impl Weights {
+    /// Weights of the checkout `dir`, configured in `scripts/weights.toml` if there is one
+    pub fn new(dir: &Path, scripts: &Path, runner: Runner) -> Self {
+        Weights {
+            dir: dir.to_owned(),
+            config: scripts.join(CONFIG_FILE),
+            runner,
+        }
+    }
+
+    /// Benchmark each of `pallets` and compare the weights they got to the ones before
+    pub fn run<S: AsRef<str>>(&self, pallets: &[S]) -> Result<Vec<Delta>, Error> {
+        let config = Config::load(&self.config)?;
+        config.build(&self.runner)?;
+        let mut deltas = vec![];
+        for pallet in pallets {
+            let pallet = pallet.as_ref();
+            if !is_pallet(pallet) {
+                return Err(Error::InvalidPallet(pallet.to_string()));
+            }
+            let output = self.dir.join(config.output(pallet));
+            let before = std::fs::read_to_string(&output)
+                .map(|source| parse(&source))
+                .unwrap_or_default();
+            if let Some(parent) = output.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|err| Error::Output(pallet.to_string(), err))?;
+            }
+            let (program, args) = config.command(pallet, &output)?;
+            tracing::info!("Benchmarking the weights of {pallet}");
+            let mut result = (self.runner)(&program, &args);
+            if !result.is_ok() {
+                return Err(Error::Failed {
+                    pallet: pallet.to_string(),
+                    stderr: tail(&result.stderr),
+                });
+            }
+            let after = std::fs::read_to_string(&output)
+                .map(|source| parse(&source))
+                .map_err(|err| Error::Output(pallet.to_string(), err))?;
+            deltas.extend(compare(pallet, &before, &after));
+        }
+        Ok(deltas)
+    }
+
+    pub fn update(
+        &mut self,
+        pallets: rhai::Array,
+    ) -> Result<rhai::Array, Box<rhai::EvalAltResult>> {
+        let pallets: Vec<String> = pallets.into_iter().map(|p| p.to_string()).collect();
+        let deltas = self.run(&pallets).map_err(|e| format!("{e}"))?;
+        Ok(deltas.iter().map(Delta::to_dynamic).collect())
+    }
+
+    /// A table of what `update` returned, largest changes first
+    pub fn summary(&mut self, deltas: rhai::Array) -> String {
+        let mut deltas: Vec<rhai::Map> = deltas
+            .into_iter()
+            .filter_map(|delta| delta.try_cast::<rhai::Map>())
+            .collect();
+        let size = |delta: &rhai::Map| {
+            delta
+                .get("change")
+                .and_then(|change| change.clone().try_cast::<Change>())
+                // New and removed extrinsics come first
+                .map_or(f64::INFINITY, |change| change.percent.abs())
+        };
+        deltas.sort_by(|a, b| size(b).total_cmp(&size(a)));
+        let mut table = Table::new(&["Pallet", "Extrinsic", "Before", "After", "Change"]);
+        let mut unchanged = 0;
+        for delta in &deltas {
+            let get = |key: &str| delta.get(key).cloned().unwrap_or(rhai::Dynamic::UNIT);
+            let change = get("change");
+            if change
+                .clone()
+                .try_cast::<Change>()
+                .is_some_and(|change| change.percent == 0.0)
+            {
+                unchanged += 1;
+                continue;
+            }
+            let change = match (change.is::<()>(), get("before").is::<()>()) {
+                (false, _) => change,
+                (true, true) => "new".into(),
+                (true, false) => "removed".into(),
+            };
+            let row = vec![
+                get("pallet"),
+                get("extrinsic"),
+                get("before"),
+                get("after"),
+                change,
+            ];
+            super::table::functions::add_row(&mut table, row);
+        }
+        let mut summary = if table.is_empty() {
+            "No weights changed.\n".to_string()
+        } else {
+            table.markdown()
+        };
+        if unchanged > 0 {
+            summary.push_str(&format!("\n{unchanged} unchanged.\n"));
+        }
+        summary
+    }
+}
+
+/// The runtime being benchmarked, exposed to scripts as `RUNTIME`
+#[derive(Clone)]
+pub struct Runtime {
+    /// Of the configuration, which may not exist
+    config: PathBuf,
+    runner: Runner,
+}
+
+impl std::fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Runtime")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl Runtime {
+    /// The runtime configured in `scripts/weights.toml`
+    pub fn new(scripts: &Path, runner: Runner) -> Self {
+        Runtime {
+            config: scripts.join(CONFIG_FILE),
+            runner,
+        }
+    }
+
+    /// The pallets with benchmarks, in the order the bencher lists them
+    pub fn list(&self) -> Result<Vec<String>, Error> {
+        let config = Config::load(&self.config)?;
+        config.build(&self.runner)?;
+        let (program, args) = config.list()?;
+        tracing::info!("Listing the benchmarks of the runtime");
+        let mut result = (self.runner)(&program, &args);
+        if !result.is_ok() {
+            return Err(Error::List(tail(&result.stderr)));
+        }
+        // The list of a whole runtime can be longer than what scripts get of the output
+        let stdout = match &result.stdout_path {
+            Some(path) => std::fs::read_to_string(path).unwrap_or(result.stdout),
+            None => result.stdout,
+        };
+        Ok(parse_list(&stdout))
+    }
+
+    pub fn pallets(&mut self) -> Result<rhai::Array, Box<rhai::EvalAltResult>> {
+        let pallets = self.list().map_err(|e| format!("{e}"))?;
+        Ok(pallets.into_iter().map(rhai::Dynamic::from).collect())
+    }
+}
+
+/// The pallets in the output of the list command, like
+///
+/// ```text
+/// pallet, extrinsic
+/// pallet_balances, transfer_allow_death
+/// pallet_balances, force_transfer
+/// ```
+pub fn parse_list(output: &str) -> Vec<String> {
+    let mut pallets: Vec<String> = vec![];
+    for line in output.lines() {
+        // Skipping the header, and whatever else the bencher prints that isn't a benchmark
+        let pallet = match line.split_once(',') {
+            Some((pallet, _)) if is_pallet(pallet.trim()) && pallet.trim() != "pallet" => {
+                pallet.trim()
+            }
+            _ => continue,
+        };
+        if !pallets.iter().any(|known| known == pallet) {
+            pallets.push(pallet.to_string());
+        }
+    }
+    pallets
+}
+
+/// Whether `name` can be the name of a pallet, and nothing else in a command line
+fn is_pallet(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | ':'))
+}
+
+/// The last lines of `stderr`, where what went wrong is, after whatever was built
+fn tail(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr.lines().rev().take(20).collect();
+    lines.into_iter().rev().collect::<Vec<_>>().join("\n")
+}
+
+/// The base weights (`ref_time`) of the extrinsics in a weight file, in the order they're in.
+/// Only the first implementation of each counts, the one for the runtime rather than for tests.
+pub fn parse(source: &str) -> Vec<(String, u64)> {
+    let mut weights: Vec<(String, u64)> = vec![];
+    let mut extrinsic: Option<String> = None;
+    for line in source.lines().map(str::trim) {
+        if let Some(signature) = line.strip_prefix("fn ") {
+            extrinsic = signature
+                .split(['(', '<'])
+                .next()
+                .map(|name| name.trim().to_string());
+            continue;
+        }
+        let weight = ["Weight::from_parts(", "Weight::from_ref_time("]
+            .iter()
+            .find_map(|call| line.split_once(call))
+            .map(|(_, args)| args);
+        if let (Some(name), Some(args)) = (&extrinsic, weight) {
+            let digits: String = args
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '_')
+                .filter(|c| *c != '_')
+                .collect();
+            if let Ok(weight) = digits.parse() {
+                if !weights.iter().any(|(known, _)| known == name) {
+                    weights.push((name.clone(), weight));
+                }
+            }
+            // Later ones are per component
+            extrinsic = None;
+        }
+    }
+    weights
+}
+
+fn compare(pallet: &str, before: &[(String, u64)], after: &[(String, u64)]) -> Vec<Delta> {
+    let find = |weights: &[(String, u64)], name: &str| {
+        weights
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, weight)| *weight)
+    };
+    let current = after.iter().map(|(name, weight)| Delta {
+        pallet: pallet.to_string(),
+        extrinsic: name.clone(),
+        before: find(before, name),
+        after: Some(*weight),
+    });
+    let removed = before
+        .iter()
+        .filter(|(name, _)| find(after, name).is_none())
+        .map(|(name, weight)| Delta {
+            pallet: pallet.to_string(),
+            extrinsic: name.clone(),
+            before: Some(*weight),
+            after: None,
+        });
+    current.chain(removed).collect()
+}
@@ -0,0 +1,327 @@
+//! Authentication of the reactor's HTTP endpoints.
+//!
+//! Every group of endpoints (the worker API, the dashboard, ...) is guarded by its own
+//! [`Authenticate`] middleware, which lets a request through if any of its [`Scheme`]s accepts
+//! it. A group without schemes is open to everyone.
+
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Unknown authentication scheme {0:?}, expected token, client-cert or github-oauth")]
+    UnknownScheme(String),
+    #[error("Failed to read tokens: {0}")]
+    ReadTokens(#[from] std::io::Error),
+    #[error("The {0} authentication scheme needs {1}")]
+    MissingConfig(Kind, &'static str),
+}
+
+/// The available authentication schemes, as named in the configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Token,
+    ClientCert,
+    GithubOAuth,
+}
+
+impl std::str::FromStr for Kind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "token" => Ok(Kind::Token),
+            "client-cert" => Ok(Kind::ClientCert),
+            "github-oauth" => Ok(Kind::GithubOAuth),
+            _ => Err(Error::UnknownScheme(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Token => write!(f, "token"),
+            Kind::ClientCert => write!(f, "client-cert"),
+            Kind::GithubOAuth => write!(f, "github-oauth"),
+        }
+    }
+}
+
+/// Who an authenticated request was made by, available to endpoints as a request extension
+#[derive(Clone, Debug)]
+pub struct Identity(pub String);
+
+#[tide::utils::async_trait]
+pub trait Scheme<State: Clone + Send + Sync + 'static>: Send + Sync + 'static {
+    /// The identity the request was made with, if it carries valid credentials
+    async fn authenticate(&self, req: &tide::Request<State>) -> Option<String>;
+
+    /// How to respond to a request without valid credentials, if not with a plain 401
+    async fn challenge(&self, _req: &mut tide::Request<State>) -> Option<tide::Response> {
+        None
+    }
+}
+
+/// Middleware letting requests through that any of its schemes accepts
+#[derive(Clone)]
+pub struct Authenticate<State> {
+    schemes: Vec<Arc<dyn Scheme<State>>>,
+}
+
+impl<State: Clone + Send + Sync + 'static> Authenticate<State> {
+    pub fn new() -> Self {
+        Authenticate { schemes: vec![] }
+    }
+
+    pub fn with<S: Scheme<State>>(self, scheme: S) -> Self {
+        self.with_shared(Arc::new(scheme))
+    }
+
+    /// Add a scheme that's also used elsewhere, like for the routes of [`GithubOAuth`]
+    pub fn with_shared(mut self, scheme: Arc<dyn Scheme<State>>) -> Self {
+        self.schemes.push(scheme);
+        self
+    }
+}
+
+impl<State: Clone + Send + Sync + 'static> Default for Authenticate<State> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for Authenticate<State> {
+    async fn handle(
+        &self,
+        mut req: tide::Request<State>,
+        next: tide::Next<'_, State>,
+    ) -> tide::Result {
+        if self.schemes.is_empty() {
+            return Ok(next.run(req).await);
+        }
+        for scheme in &self.schemes {
+            if let Some(identity) = scheme.authenticate(&req).await {
+                tracing::debug!("{} {} by {identity}", req.method(), req.url().path());
+                req.set_ext(Identity(identity));
+                return Ok(next.run(req).await);
+            }
+        }
+        for scheme in &self.schemes {
+            if let Some(res) = scheme.challenge(&mut req).await {
+                return Ok(res);
+            }
+        }
+        tracing::info!(
+            "Rejecting unauthenticated {} {}",
+            req.method(),
+            req.url().path()
+        );
+        Ok(tide::Response::builder(401)
+            .header("WWW-Authenticate", "Bearer")
+            .build())
+    }
+}
+
+/// Static tokens, sent as `Authorization: Bearer <token>`
+pub struct Tokens {
+    tokens: Vec<String>,
+}
+
+impl Tokens {
+    pub fn new(tokens: Vec<String>) -> Self {
+        Tokens { tokens }
+    }
+
+    /// Read the tokens from a file with one token per line. Empty lines and lines starting with
+    /// `#` are ignored.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let tokens = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        Ok(Tokens { tokens })
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Scheme<State> for Tokens {
+    async fn authenticate(&self, req: &tide::Request<State>) -> Option<String> {
+        let header = req.header("Authorization")?.as_str();
+        let token = header.strip_prefix("Bearer ")?.trim();
+        self.tokens
+            .iter()
+            .position(|known| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|n| format!("token #{}", n + 1))
+    }
+}
+
+/// Compare without leaking the length of the common prefix through timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Client certificates, verified by the proxy terminating TLS in front of the reactor, which
+/// passes on the subject of the certificate in a header.
+///
+/// The proxy has to overwrite that header on every request, and the reactor must not be reachable
+/// without going through the proxy.
+pub struct ClientCert {
+    header: String,
+    subjects: Vec<String>,
+}
+
+impl ClientCert {
+    pub fn new(header: String, subjects: Vec<String>) -> Self {
+        ClientCert { header, subjects }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Scheme<State> for ClientCert {
+    async fn authenticate(&self, req: &tide::Request<State>) -> Option<String> {
+        let subject = req.header(self.header.as_str())?.as_str();
+        self.subjects
+            .iter()
+            .find(|known| known.as_str() == subject)
+            .cloned()
+    }
+}
+
+const SESSION_USER: &str = "github_user";
+const SESSION_STATE: &str = "github_oauth_state";
+const SESSION_RETURN_TO: &str = "github_oauth_return_to";
+
+/// Logging in with a Github OAuth App, for the pages people look at in their browser. Requires
+/// `tide::sessions::SessionMiddleware` and the [`GithubOAuth::callback`] endpoint.
+pub struct GithubOAuth {
+    client_id: String,
+    client_secret: String,
+    /// Where Github sends users back to after logging in, i.e. the URL of the callback endpoint
+    redirect_url: String,
+    /// Path the endpoints are nested under, to send users back to the page they came from
+    base_path: String,
+    /// Github users allowed in
+    users: Vec<String>,
+}
+
+impl GithubOAuth {
+    pub fn new(
+        client_id: String,
+        client_secret: String,
+        redirect_url: String,
+        base_path: String,
+        users: Vec<String>,
+    ) -> Self {
+        GithubOAuth {
+            client_id,
+            client_secret,
+            redirect_url,
+            base_path,
+            users,
+        }
+    }
+
+    /// Endpoint Github redirects to after logging in, at the path of `redirect_url`
+    pub async fn callback<State: Clone + Send + Sync + 'static>(
+        &self,
+        mut req: tide::Request<State>,
+    ) -> tide::Result {
+        #[derive(Deserialize)]
+        struct Callback {
+            code: String,
+            state: String,
+        }
+        #[derive(Deserialize)]
+        struct AccessToken {
+            access_token: String,
+        }
+        #[derive(Deserialize)]
+        struct User {
+            login: String,
+        }
+
+        let Callback { code, state } = req.query()?;
+        let expected: Option<String> = req.session().get(SESSION_STATE);
+        if expected.as_deref() != Some(state.as_str()) {
+            return Ok(tide::Response::builder(400)
+                .body("Invalid OAuth state, try logging in again")
+                .build());
+        }
+
+        let AccessToken { access_token } =
+            surf::post("https://github.com/login/oauth/access_token")
+                .header("Accept", "application/json")
+                .body_json(&serde_json::json!({
+                    "client_id": self.client_id,
+                    "client_secret": self.client_secret,
+                    "code": code,
+                    "redirect_uri": self.redirect_url,
+                }))?
+                .recv_json()
+                .await?;
+        let User { login } = surf::get("https://api.github.com/user")
+            .header("Accept", "application/vnd.github+json")
+            .header("Authorization", format!("Bearer {access_token}"))
+            .header("User-Agent", "ci-script")
+            .recv_json()
+            .await?;
+
+        if !self
+            .users
+            .iter()
+            .any(|user| user.eq_ignore_ascii_case(&login))
+        {
+            tracing::info!("Github user {login} is not allowed in");
+            return Ok(tide::Response::builder(403)
+                .body(format!("Github user {login} is not allowed in"))
+                .build());
+        }
+        tracing::info!("Github user {login} logged in");
+        let return_to: String = req
+            .session()
+            .get(SESSION_RETURN_TO)
+            .unwrap_or_else(|| format!("{}/", self.base_path));
+        let session = req.session_mut();
+        session.remove(SESSION_STATE);
+        session.remove(SESSION_RETURN_TO);
+        session.insert(SESSION_USER, login)?;
+        Ok(tide::Redirect::new(return_to).into())
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Scheme<State> for GithubOAuth {
+    async fn authenticate(&self, req: &tide::Request<State>) -> Option<String> {
+        let login: String = req.ext::<tide::sessions::Session>()?.get(SESSION_USER)?;
+        Some(format!("github:{login}"))
+    }
+
+    /// Send browsers off to log in with Github
+    async fn challenge(&self, req: &mut tide::Request<State>) -> Option<tide::Response> {
+        let state = uuid::Uuid::new_v4().simple().to_string();
+        let return_to = match req.url().query() {
+            Some(query) => format!("{}{}?{query}", self.base_path, req.url().path()),
+            None => format!("{}{}", self.base_path, req.url().path()),
+        };
+        let session = req.ext_mut::<tide::sessions::Session>()?;
+        session.insert(SESSION_STATE, &state).ok()?;
+        session.insert(SESSION_RETURN_TO, return_to).ok()?;
+        let url = url::Url::parse_with_params(
+            "https://github.com/login/oauth/authorize",
+            &[
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", self.redirect_url.as_str()),
+                ("state", state.as_str()),
+                ("allow_signup", "false"),
+            ],
+        )
+        .ok()?;
+        Some(tide::Redirect::new(url).into())
+    }
+}
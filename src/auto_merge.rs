@@ -0,0 +1,96 @@
+//! Merging the pull requests the bot opened itself, like the ones updating weights, once a job on
+//! them succeeded without regressions (see `--repo-auto-merge`). They're approved and put into
+//! auto-merge, so Github merges them once the checks the base branch requires pass, the commit
+//! status of the job among them.
+
+use crate::api::Error;
+use octocrab::Octocrab;
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("Invalid merge method {0:?}, expected merge, squash or rebase")]
+pub struct InvalidMethod(String);
+
+/// How Github merges a pull request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Merge,
+    Squash,
+    Rebase,
+}
+
+impl std::str::FromStr for Method {
+    type Err = InvalidMethod;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(Method::Merge),
+            "squash" => Ok(Method::Squash),
+            "rebase" => Ok(Method::Rebase),
+            _ => Err(InvalidMethod(s.to_string())),
+        }
+    }
+}
+
+impl Method {
+    /// As the GraphQL API names it
+    fn graphql(&self) -> &'static str {
+        match self {
+            Method::Merge => "MERGE",
+            Method::Squash => "SQUASH",
+            Method::Rebase => "REBASE",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GraphqlResponse {
+    #[serde(default)]
+    errors: Vec<GraphqlError>,
+}
+
+#[derive(Deserialize)]
+struct GraphqlError {
+    message: String,
+}
+
+/// Approve pull request `number` of `owner/name` with a review saying `body`. Github doesn't let
+/// anyone approve their own pull requests, so `client` has to be someone else's.
+pub async fn approve(
+    client: &Octocrab,
+    owner: &str,
+    name: &str,
+    number: u64,
+    body: &str,
+) -> Result<(), Error> {
+    let _: serde_json::Value = client
+        .post(
+            format!("repos/{owner}/{name}/pulls/{number}/reviews"),
+            Some(&serde_json::json!({ "event": "APPROVE", "body": body })),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Have Github merge the pull request with the GraphQL ID `node_id` with `method` once the checks
+/// its base branch requires pass. Only the GraphQL API can.
+pub async fn enable(client: &Octocrab, node_id: &str, method: Method) -> Result<(), Error> {
+    let query = "mutation($id: ID!, $method: PullRequestMergeMethod!) { \
+                 enablePullRequestAutoMerge(input: { pullRequestId: $id, mergeMethod: $method }) \
+                 { clientMutationId } }";
+    let response: GraphqlResponse = client
+        .post(
+            "graphql",
+            Some(&serde_json::json!({
+                "query": query,
+                "variables": { "id": node_id, "method": method.graphql() },
+            })),
+        )
+        .await?;
+    // Errors come with a successful response
+    match response.errors.first() {
+        Some(error) => Err(Error::GithubApiError(error.message.clone())),
+        None => Ok(()),
+    }
+}
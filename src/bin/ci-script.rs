@@ -1,11 +1,16 @@
 use anyhow::Result;
+use ci_script::logging::{LogFormat, Logging};
 use octocrab::Octocrab;
 use std::convert::TryInto;
 use structopt::StructOpt;
 use thiserror::Error;
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "ci-scripts", about = "Run CI scripts, like from a CI/CD job")]
+#[structopt(
+    name = "ci-scripts",
+    about = "Run CI scripts, like from a CI/CD job",
+    after_help = ci_script::cli::GENERATE_HELP
+)]
 struct Opt {
     /// Path to the repository
     #[structopt(long, env, default_value = "./")]
@@ -19,6 +24,9 @@ struct Opt {
     /// Github App key
     #[structopt(long, env, hide_env_values = true)]
     github_app_key: String,
+    /// Base URL of the Github API, like that of a GitHub Enterprise Server
+    #[structopt(long, env, default_value = "https://api.github.com/")]
+    github_api_base: url::Url,
     /// Owner of the upstream Github repository
     #[structopt(long, env)]
     github_owner: String,
@@ -28,22 +36,173 @@ struct Opt {
     /// Path to the script to execute relative to the root of the script's repository
     #[structopt(env)]
     script: std::path::PathBuf,
-    /// Arguments to pass to the script
+    /// Arguments to pass to the script, `&& <script>` to run another one after it
     #[structopt(env)]
     script_args: Vec<String>,
     /// Log level
     #[structopt(short, long, env, default_value = "info")]
     log_level: log::LevelFilter,
+    /// Path to the SQLite database to store recorded results in
+    #[structopt(long, env)]
+    results_db: Option<std::path::PathBuf>,
+    /// Branch to record results against [default: the current branch of the repository]
+    #[structopt(long, env)]
+    results_branch: Option<String>,
+    /// Branch whose result history is used to detect regressions [default: the results branch]
+    #[structopt(long, env)]
+    baseline_branch: Option<String>,
+    /// Number of standard deviations above the historical mean that counts as a regression
+    #[structopt(long, env, default_value = "3.0")]
+    regression_threshold: f64,
+    /// Percent a size tracked with `binary_size` may grow by before it counts as a regression
+    #[structopt(long, env, default_value = "1.0")]
+    size_regression_threshold: f64,
+    /// Hosts scripts may send HTTP requests to (`*.example.com` allows all subdomains)
+    #[structopt(long, env, use_delimiter = true)]
+    http_allowlist: Vec<String>,
+    /// Warm up before measuring: pipelines run every step once first, scripts see `WARM_UP`
+    #[structopt(long, env)]
+    warm_up: bool,
+    /// Directory to store the files the script uploads with `upload_artifact` in
+    #[structopt(long, env)]
+    artifacts_dir: Option<std::path::PathBuf>,
+    /// PEM certificates to encrypt artifacts to
+    #[structopt(long, env, use_delimiter = true)]
+    artifact_recipients: Vec<std::path::PathBuf>,
+    /// Fail before running the script if `Cargo.lock` is out of date with `Cargo.toml`
+    #[structopt(long, env)]
+    check_lockfile: bool,
+    /// Let the script take flamegraphs with `flamegraph`, which runs `cargo flamegraph` and
+    /// thereby `perf`
+    #[structopt(long, env)]
+    allow_profiling: bool,
+    /// Directory of the modules scripts import as `lib:<name>`, like a checkout of the shared
+    /// bot scripts
+    #[structopt(long, env)]
+    script_library: Option<std::path::PathBuf>,
+    /// Bytes of each stream of cargo's output scripts get, like `1M`, half from its start and
+    /// half from its end. All of it is written to the temporary directory.
+    #[structopt(long, env, parse(try_from_str = ci_script::janitor::parse_size))]
+    cargo_output_limit: Option<u64>,
+    /// Cores to pin cargo (and the benchmarks it runs) to, like `2-5`
+    #[structopt(long, env, parse(try_from_str = ci_script::api::pinning::parse_cores))]
+    pin_cores: Option<ci_script::api::pinning::Cores>,
+    /// Switch the pinned cores to the `performance` CPU frequency governor while they are
+    #[structopt(long, env)]
+    performance_governor: bool,
+    /// Branches the script may open pull requests into (`release-*` matches all release
+    /// branches), any if not given
+    #[structopt(long, env, use_delimiter = true)]
+    pr_base: Vec<String>,
+    /// Name of the branches the script opens pull requests from, with `{repo}`, `{issue}` and
+    /// `{name}` filled in, like `benchbot/{repo}/{issue}-{name}`. The script gets it from
+    /// `REPO.pr_branch(name)`, can't open pull requests from other branches and force pushes
+    /// these.
+    #[structopt(long, env)]
+    pr_branch_template: Option<String>,
+    /// Delete branches named like `--pr-branch-template` that no pull request is open from once
+    /// their last commit is this many days old, whenever the script opens a pull request
+    #[structopt(long, env)]
+    pr_stale_branch_days: Option<u64>,
+    /// Author of the commits the script makes, as `Name <email>`
+    #[structopt(long, env)]
+    commit_author: Option<ci_script::api::git::Identity>,
+    /// Committer of the commits the script makes, `--commit-author` if not given
+    #[structopt(long, env)]
+    commit_committer: Option<ci_script::api::git::Identity>,
+    /// GPG key (ID or fingerprint) to sign the commits the script makes with
+    #[structopt(long, env, conflicts_with = "commit-ssh-key")]
+    commit_gpg_key: Option<String>,
+    /// SSH private key to sign the commits the script makes with instead of `--commit-gpg-key`
+    #[structopt(long, env)]
+    commit_ssh_key: Option<std::path::PathBuf>,
+    /// `RUSTUP_HOME` to install toolchains into and run cargo with, instead of the user's
+    #[structopt(long, env)]
+    rustup_home: Option<std::path::PathBuf>,
+    /// Build through sccache and print its hits and misses
+    #[structopt(long, env)]
+    sccache: bool,
+    /// Cache shared with other machines building through sccache, like `redis://...` or
+    /// `s3://<bucket>[/<prefix>]`
+    #[structopt(long, env, requires = "sccache")]
+    sccache_endpoint: Option<ci_script::api::cargo::sccache::Endpoint>,
+    /// Credentials of `--sccache-endpoint`
+    #[structopt(long, env, hide_env_values = true)]
+    sccache_credentials: Option<String>,
+    /// Memory the processes of the script may use together, like `8G`. Needs `--cgroup-root`.
+    #[structopt(long, env, requires = "cgroup-root", parse(try_from_str = ci_script::janitor::parse_size))]
+    memory_limit: Option<u64>,
+    /// Disk the checkout and the temporary files of the script may use, like `50G`
+    #[structopt(long, env, parse(try_from_str = ci_script::janitor::parse_size))]
+    disk_limit: Option<u64>,
+    /// cgroup v2 directory to create the cgroup of the script in, to enforce `--memory-limit`
+    #[structopt(long, env)]
+    cgroup_root: Option<std::path::PathBuf>,
+}
+
+/// Options of `cis run-local`
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cis run-local",
+    about = "Try out a script on a local checkout, printing the comments, pull requests and \
+             commit statuses it would post instead"
+)]
+struct LocalOpt {
+    /// Path to the checkout
+    #[structopt(long, default_value = "./")]
+    repo: std::path::PathBuf,
+    /// Path to the directory where the script can clone repositories to
+    #[structopt(long, default_value = "/tmp")]
+    clone_dir: std::path::PathBuf,
+    /// Path to the script to execute relative to the root of the checkout
+    #[structopt(long)]
+    script: std::path::PathBuf,
+    /// Arguments to pass to the script, `&& <script>` to run another one after it
+    script_args: Vec<String>,
+    /// Number of the issue `ISSUE` stands for
+    #[structopt(long, default_value = "1")]
+    issue: i64,
+    /// Log level
+    #[structopt(short, long, default_value = "info")]
+    log_level: log::LevelFilter,
+    /// Hosts scripts may send HTTP requests to (`*.example.com` allows all subdomains)
+    #[structopt(long, use_delimiter = true)]
+    http_allowlist: Vec<String>,
+    /// Warm up before measuring: pipelines run every step once first, scripts see `WARM_UP`
+    #[structopt(long)]
+    warm_up: bool,
+    /// Directory of the modules scripts import as `lib:<name>`
+    #[structopt(long)]
+    script_library: Option<std::path::PathBuf>,
+    /// Bytes of each stream of cargo's output scripts get, see `cis --help`
+    #[structopt(long, parse(try_from_str = ci_script::janitor::parse_size))]
+    cargo_output_limit: Option<u64>,
+    /// Cores to pin cargo to, see `cis --help`
+    #[structopt(long, parse(try_from_str = ci_script::api::pinning::parse_cores))]
+    pin_cores: Option<ci_script::api::pinning::Cores>,
+    /// Switch the pinned cores to the `performance` governor while they are
+    #[structopt(long)]
+    performance_governor: bool,
+    /// Branches the script may open pull requests into, see `cis --help`
+    #[structopt(long, use_delimiter = true)]
+    pr_base: Vec<String>,
+    /// Name of the branches the script opens pull requests from, see `cis --help`
+    #[structopt(long)]
+    pr_branch_template: Option<String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // A subcommand of its own, so the options of running a script for real stay as they are
+    if std::env::args().nth(1).as_deref() == Some("run-local") {
+        return run_local(LocalOpt::from_iter(std::env::args().skip(1)));
+    }
+    ci_script::cli::generate::<Opt>("cis");
     let opt = Opt::from_args();
-    pretty_env_logger::formatted_timed_builder()
-        .filter(None, opt.log_level)
-        .init();
+    let _logging = Logging::new(LogFormat::Pretty, opt.log_level).init()?;
 
-    let master_client = get_github_client(opt.github_app_id, &opt.github_app_key)?;
+    let master_client =
+        get_github_client(opt.github_app_id, &opt.github_app_key, &opt.github_api_base)?;
     let gh_client =
         get_github_repo_client(&master_client, &opt.github_owner, &opt.github_name).await?;
     let gh_repo = get_github_repo(&gh_client, &opt.github_owner, &opt.github_name).await?;
@@ -53,54 +212,251 @@ async fn main() -> Result<()> {
         x
     };
     let dir = std::fs::canonicalize(&opt.repo)?;
+    let results_branch = match opt.results_branch {
+        Some(branch) => branch,
+        None => git2::Repository::open(&dir)?
+            .head()?
+            .shorthand()
+            .ok_or(Error::CurrentBranchInvalidUTF8)?
+            .to_string(),
+    };
+    let results_store = match &opt.results_db {
+        Some(results_db) => Some(ci_script::results::Store::open(results_db)?),
+        None => None,
+    };
+    let results_repo = format!("{}/{}", opt.github_owner, opt.github_name);
+    let artifacts = match opt.artifacts_dir {
+        Some(dir) => {
+            let dir = std::env::current_dir()?.join(dir);
+            let recipients = ci_script::api::artifacts::load_recipients(&opt.artifact_recipients)?;
+            Some(ci_script::api::artifacts::Artifacts::new(
+                &dir,
+                dir.to_string_lossy(),
+                recipients,
+            ))
+        }
+        None => None,
+    };
+    let redactor = ci_script::secrets::Redactor::new();
+    redactor.add_pem(&opt.github_app_key);
+    if let Some(credentials) = &opt.sccache_credentials {
+        redactor.add(credentials.as_str());
+    }
     let job = ci_script::job::CheckedoutJob {
         command,
         dir,
         clone_dir: opt.clone_dir,
         gh_repo,
         gh_issue: None,
+        http_allowlist: opt.http_allowlist,
+        credentials: Default::default(),
+        cargo_env: vec![],
+        cargo_output: ci_script::api::cargo::Output {
+            // Where it can still be read once the script is done
+            log_dir: opt.cargo_output_limit.map(|_| std::env::temp_dir()),
+            limit: opt.cargo_output_limit.map(|limit| limit as usize),
+        },
+        env: Default::default(),
+        warm_up: opt.warm_up,
+        artifacts,
+        redactor,
+        shard: None,
+        forge: None,
+        phases: Default::default(),
+        script_library: opt.script_library,
+        pinning: ci_script::api::pinning::Pinning::new(opt.pin_cores, opt.performance_governor),
+        pr_policy: ci_script::api::git::PrPolicy {
+            bases: opt.pr_base,
+            branch_template: opt.pr_branch_template,
+            stale_after: opt
+                .pr_stale_branch_days
+                .map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60)),
+        },
+        commits: ci_script::api::git::CommitConfig {
+            author: opt.commit_author,
+            committer: opt.commit_committer,
+            signing_key: match (opt.commit_gpg_key, opt.commit_ssh_key) {
+                (Some(key), _) => Some(ci_script::api::git::SigningKey::Gpg(key)),
+                (_, Some(key)) => Some(ci_script::api::git::SigningKey::Ssh(key)),
+                _ => None,
+            },
+        },
+        toolchain: ci_script::api::toolchain::Toolchain::new(opt.rustup_home),
+        sccache: if opt.sccache {
+            ci_script::api::cargo::sccache::Sccache::new(ci_script::api::cargo::sccache::Config {
+                endpoint: opt.sccache_endpoint,
+                credentials: opt.sccache_credentials,
+            })
+        } else {
+            Default::default()
+        },
+        accounting: Default::default(),
+        quota: ci_script::api::quota::Quota::new(
+            ci_script::api::quota::Limits {
+                memory: opt.memory_limit,
+                disk: opt.disk_limit,
+            },
+            opt.cgroup_root,
+        ),
+        results_history: results_store
+            .clone()
+            .map(|store| ci_script::api::results::History::new(store, results_repo.clone())),
+        profiler: ci_script::api::profile::Profiler::new(opt.allow_profiling),
+        checkout_wait: Default::default(),
+        triggered_by: None,
+    };
+    job.install_toolchain()?;
+    if opt.check_lockfile {
+        job.check_lockfile()?;
+    }
+    let outcome = job.prepare_script(master_client)?.run()?;
+    print_outcome(&outcome);
+
+    if let Some(store) = results_store {
+        let baseline = opt
+            .baseline_branch
+            .unwrap_or_else(|| results_branch.clone());
+        let policy = ci_script::results::Policy {
+            threshold: opt.regression_threshold,
+            size_threshold: opt.size_regression_threshold,
+            ..Default::default()
+        };
+        let regressions = store.compare_and_record(
+            &uuid::Uuid::new_v4().to_string(),
+            &results_repo,
+            &results_branch,
+            outcome.commit.as_deref(),
+            &baseline,
+            &outcome.metrics,
+            &policy,
+        )?;
+        if !regressions.is_empty() {
+            tracing::warn!("Detected {} possible regression(s)", regressions.len());
+            println!(
+                "{}",
+                ci_script::results::render_regressions(&baseline, &regressions)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_outcome(outcome: &ci_script::job::Outcome) {
+    for section in &outcome.report {
+        println!("{section}");
+    }
+    if let Some(headline) = &outcome.headline {
+        tracing::info!("Headline: {headline}");
+    }
+    if let Some(failure) = &outcome.failure {
+        tracing::warn!("Failed: {failure}");
+    }
+    if !outcome.outputs.is_empty() {
+        println!("{}", ci_script::api::outputs::render(&outcome.outputs));
+    }
+    if !outcome.artifacts.is_empty() {
+        println!("{}", ci_script::api::artifacts::render(&outcome.artifacts));
+    }
+    if outcome.steps.len() > 1 {
+        println!("{}", ci_script::job::render_steps(&outcome.steps));
+    }
+    if let Some(machine) = &outcome.machine {
+        tracing::info!("{}", machine.render());
+    }
+    if let Some(sccache) = &outcome.sccache {
+        println!("{}", sccache.render());
+    }
+    if let Some(usage) = &outcome.usage {
+        println!("{}", usage.render());
+    }
+}
+
+/// Run the script on a checkout without Github, see [`LocalOpt`]
+fn run_local(opt: LocalOpt) -> Result<()> {
+    let _logging = Logging::new(LogFormat::Pretty, opt.log_level).init()?;
+
+    let dir = std::fs::canonicalize(&opt.repo)?;
+    let forge = ci_script::forge::DryRun::new(&dir)?;
+    let mut command = vec![opt.script.to_string_lossy().into_owned()];
+    command.extend(opt.script_args);
+    let job = ci_script::job::CheckedoutJob {
+        command,
+        dir,
+        clone_dir: opt.clone_dir,
+        gh_repo: forge.repository().clone(),
+        gh_issue: Some(forge.issue(opt.issue)?),
+        http_allowlist: opt.http_allowlist,
+        credentials: Default::default(),
+        cargo_env: vec![],
+        cargo_output: ci_script::api::cargo::Output {
+            // Where it can still be read once the script is done
+            log_dir: opt.cargo_output_limit.map(|_| std::env::temp_dir()),
+            limit: opt.cargo_output_limit.map(|limit| limit as usize),
+        },
+        env: Default::default(),
+        warm_up: opt.warm_up,
+        artifacts: None,
+        redactor: Default::default(),
+        shard: None,
+        forge: Some(std::sync::Arc::new(forge)),
+        phases: Default::default(),
+        script_library: opt.script_library,
+        pinning: ci_script::api::pinning::Pinning::new(opt.pin_cores, opt.performance_governor),
+        pr_policy: ci_script::api::git::PrPolicy {
+            bases: opt.pr_base,
+            branch_template: opt.pr_branch_template,
+            stale_after: None,
+        },
+        commits: Default::default(),
+        toolchain: Default::default(),
+        sccache: Default::default(),
+        accounting: Default::default(),
+        quota: Default::default(),
+        results_history: None,
+        profiler: Default::default(),
+        checkout_wait: Default::default(),
+        triggered_by: None,
     };
-    job.prepare_script(master_client)?.run()?;
+    job.install_toolchain()?;
+    // Anonymous, nothing is posted to Github
+    let outcome = job.prepare_script(Octocrab::default())?.run()?;
+    print_outcome(&outcome);
+    for metric in &outcome.metrics {
+        println!("{}: {} {}", metric.name, metric.value, metric.unit);
+    }
     Ok(())
 }
 
-fn get_github_client<K: ToString>(github_app_id: u64, github_app_key: K) -> Result<Octocrab> {
+fn get_github_client<K: ToString>(
+    github_app_id: u64,
+    github_app_key: K,
+    api_base: &url::Url,
+) -> Result<Octocrab> {
     let github_app_key = github_app_key.to_string();
     let token = {
         let app_id = octocrab::models::AppId::from(github_app_id);
         let app_key = jsonwebtoken::EncodingKey::from_rsa_pem(github_app_key.as_bytes())?;
         octocrab::auth::create_jwt(app_id, &app_key)?
     };
-    Ok(Octocrab::builder().personal_token(token).build()?)
+    Ok(Octocrab::builder()
+        .base_url(api_base.as_str())?
+        .personal_token(token)
+        .build()?)
 }
 
 #[derive(Error, Debug)]
 enum Error {
-    #[error("Failed to acquire access token URL")]
-    NoAccessTokenURL,
+    #[error("Current branch name contains invalid UTF-8")]
+    CurrentBranchInvalidUTF8,
 }
 
 async fn get_github_repo_client<O: AsRef<str>, N: AsRef<str>>(
     gh_client: &octocrab::Octocrab,
-    _owner: O,
-    _name: N,
+    owner: O,
+    name: N,
 ) -> Result<octocrab::Octocrab> {
     // TODO: Consider requesting a token with more fine-grained access.
-    // TODO: Figure out what installation to use instead of hardcoding
-    use octocrab::params::apps::CreateInstallationAccessToken;
-    let installations = gh_client.apps().installations().send().await?.take_items();
-    let mut access_token_req = CreateInstallationAccessToken::default();
-    access_token_req.repositories = vec![];
-    let access_token_url = installations[0]
-        .access_tokens_url
-        .as_ref()
-        .ok_or(Error::NoAccessTokenURL)?;
-    let access: octocrab::models::InstallationToken = gh_client
-        .post(access_token_url, Some(&access_token_req))
-        .await?;
-    Ok(octocrab::OctocrabBuilder::new()
-        .personal_token(access.token)
-        .build()?)
+    Ok(ci_script::installation::client(gh_client, owner.as_ref(), name.as_ref(), &[]).await?)
 }
 
 async fn get_github_repo<O: AsRef<str>, N: AsRef<str>>(
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use ci_script::export::{self, Format, JobRow, ResultRow};
+use ci_script::journal::Journal;
+use ci_script::logging::{LogFormat, Logging};
+use ci_script::Job;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cis-export",
+    about = "Export the job queue, job history and recorded results for offline analysis",
+    after_help = ci_script::cli::GENERATE_HELP
+)]
+struct Opt {
+    /// State database of the reactor (`--state-db`), to export the queued and finished jobs from
+    #[structopt(long, env)]
+    state_db: Option<PathBuf>,
+    /// Results database (`--results-db`), to export the recorded results from
+    #[structopt(long, env)]
+    results_db: Option<PathBuf>,
+    /// Output format: csv or jsonl
+    #[structopt(long, default_value = "csv")]
+    format: Format,
+    /// Only export jobs and results of this recent period, like `12h` or `30d`
+    #[structopt(long, parse(try_from_str = export::parse_period))]
+    since: Option<Duration>,
+    /// Directory to write `jobs.<format>` and `results.<format>` to
+    #[structopt(short, long, default_value = ".")]
+    output: PathBuf,
+    /// Log level
+    #[structopt(short, long, env, default_value = "info")]
+    log_level: log::LevelFilter,
+}
+
+fn main() -> Result<()> {
+    ci_script::cli::generate::<Opt>("cis-export");
+    let opt = Opt::from_args();
+    let _logging = Logging::new(LogFormat::Pretty, opt.log_level).init()?;
+
+    let since = match opt.since {
+        Some(period) => SystemTime::now() - period,
+        None => SystemTime::UNIX_EPOCH,
+    };
+    std::fs::create_dir_all(&opt.output)?;
+
+    if let Some(path) = &opt.state_db {
+        let journal = Journal::open(path)?;
+        let mut rows: Vec<JobRow> = journal
+            .archived(since)?
+            .iter()
+            .map(|(tenant, record)| JobRow::finished(tenant, record))
+            .collect();
+        // Whatever is still queued is recent enough
+        rows.extend(
+            journal
+                .all_queued::<Job>()?
+                .iter()
+                .map(|(tenant, _, job)| JobRow::queued(tenant, job)),
+        );
+        let path = opt.output.join(format!("jobs.{}", opt.format.extension()));
+        let file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        export::write(file, opt.format, JobRow::COLUMNS, &rows)?;
+        tracing::info!("Exported {} jobs to {:?}", rows.len(), path);
+    }
+
+    if let Some(path) = &opt.results_db {
+        let store = ci_script::results::Store::open(path)?;
+        let rows: Vec<ResultRow> = store
+            .recorded_since(since)?
+            .into_iter()
+            .map(ResultRow::from)
+            .collect();
+        let path = opt
+            .output
+            .join(format!("results.{}", opt.format.extension()));
+        let file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        export::write(file, opt.format, ResultRow::COLUMNS, &rows)?;
+        tracing::info!("Exported {} results to {:?}", rows.len(), path);
+    }
+
+    if opt.state_db.is_none() && opt.results_db.is_none() {
+        tracing::warn!("Nothing to export, pass --state-db and/or --results-db");
+    }
+    Ok(())
+}
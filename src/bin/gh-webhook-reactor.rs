@@ -1,20 +1,52 @@
 use async_std::sync::{Arc, Mutex};
-use ci_script::{job::Repository, Job, LocalQueue, Queue};
-use octocrab::params::apps::CreateInstallationAccessToken;
-use octocrab::Octocrab;
+use ci_script::api::artifacts::{self, Artifacts};
+use ci_script::api::phases::Phases;
+use ci_script::api::quota::Quota;
+use ci_script::auth::{self, Authenticate};
+use ci_script::forge::{self, Forge, Gitlab};
+use ci_script::history::{History, LogTail, SharedHistory, Status};
+use ci_script::janitor::{self, Collected, Janitor};
+use ci_script::job::{
+    BuildCache, CheckedoutJob, CheckoutOptions, CloneStrategy, Handoff, Outcome, Repository,
+    STEP_SEPARATOR,
+};
+use ci_script::journal::Journal;
+use ci_script::labels::{LabelTrigger, PullRequestEvent};
+use ci_script::logging::{job_span, LevelHandle, LogFormat, Logging};
+use ci_script::metrics::Metrics;
+use ci_script::rate_limit::{Rate, RateLimiter};
+use ci_script::review_comments::ReviewCommentEvent;
+use ci_script::runners::{self, Heartbeat, Runners};
+use ci_script::secrets::{self, JobEnv, Redactor};
+use ci_script::shards::{self, Coordinator, Shard, ShardResult};
+use ci_script::webhook::{self, Webhook};
+use ci_script::{Fair, Job, PersistentQueue, Queue};
+use futures_lite::StreamExt;
+use octocrab::models::issues::Issue;
+use octocrab::{Octocrab, Page};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
+use std::net::ToSocketAddrs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 use thiserror::Error;
 use tide::prelude::*;
 use tide_github::Event;
+use tracing::Instrument;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "ci-script",
-    about = "Simply automate your CI needs with the powers of the CI Scripting Language"
+    about = "Simply automate your CI needs with the powers of the CI Scripting Language",
+    after_help = ci_script::cli::GENERATE_HELP
 )]
 struct Config {
+    /// TOML file with any of these settings, by their long name (see `ci_script::config`).
+    /// Flags and environment variables take precedence over it.
+    #[structopt(long, env)]
+    config: Option<PathBuf>,
     /// Github Webhook secret
     #[structopt(short, long, env, hide_env_values = true)]
     webhook_secret: String,
@@ -24,29 +56,584 @@ struct Config {
     /// Github App key
     #[structopt(long, env, hide_env_values = true)]
     app_key: String,
+    /// Base URL of the Github API, like that of a GitHub Enterprise Server or of a mock in tests
+    #[structopt(long, env, default_value = "https://api.github.com/")]
+    github_api_base: url::Url,
     /// Port to listen on
     #[structopt(short, long, env, default_value = "3000")]
     port: u16,
     /// Address to listen on
     #[structopt(short, long, env, default_value = "127.0.0.1")]
     address: String,
+    /// Allow a new version to listen on the same port while this one drains its running jobs
+    /// (`SO_REUSEPORT`). Not needed with systemd socket activation.
+    #[structopt(long, env)]
+    reuse_port: bool,
     /// Log level
     #[structopt(short, long, env, default_value = "info")]
     log_level: log::LevelFilter,
+    /// `pretty`, or `json` for a JSON object per line with the id, repository and command of the
+    /// job it's about
+    #[structopt(long, env, default_value = "pretty")]
+    log_format: LogFormat,
+    /// Export spans over OTLP/HTTP to this endpoint, like `http://localhost:4318/v1/traces`
+    /// (needs the `otlp` feature)
+    #[structopt(long, env)]
+    otlp_endpoint: Option<url::Url>,
     /// Bot command prefix
     #[structopt(short, long, env, default_value = "/benchbot")]
     command_prefix: String,
     /// Repositories root working directory
     #[structopt(short, long, env, default_value = "./repos")]
     repos_root: PathBuf,
+    /// Replace a queued job when the same command is issued again on the same issue, instead of
+    /// rejecting the new one
+    #[structopt(long, env)]
+    supersede: bool,
+    /// Path to the SQLite database to store recorded results in
+    #[structopt(long, env)]
+    results_db: Option<PathBuf>,
+    /// Number of standard deviations above the historical mean that counts as a regression
+    #[structopt(long, env, default_value = "3.0")]
+    regression_threshold: f64,
+    /// Percent a size tracked with `binary_size` may grow by before it counts as a regression
+    #[structopt(long, env, default_value = "1.0")]
+    size_regression_threshold: f64,
+    /// TOML file listing additional Github Apps to serve from this process, each with its own
+    /// queue and repositories root (`<repos-root>/<tenant name>`)
+    #[structopt(long, env)]
+    tenants: Option<PathBuf>,
+    /// Path to the SQLite database to keep the queues and running jobs in, so they survive a
+    /// restart
+    #[structopt(long, env)]
+    state_db: Option<PathBuf>,
+    /// Seconds to wait for running jobs when asked to stop. Jobs still running then are handed
+    /// off to the next reactor sharing `--state-db`, which picks them up first.
+    #[structopt(long, env)]
+    drain_deadline: Option<u64>,
+    /// On startup, have Github deliver again the comments whose webhooks failed in this many
+    /// hours before (at most 72), like while the reactor was down. Deliveries that were already
+    /// processed are skipped.
+    #[structopt(long, env)]
+    backfill_deliveries: Option<u64>,
+    /// Refresh the results of a base branch when it advanced more than this many commits since
+    /// they were recorded
+    #[structopt(long, env)]
+    baseline_max_commits: Option<u64>,
+    /// Refresh the results of a base branch when they are older than this many hours
+    #[structopt(long, env)]
+    baseline_max_age: Option<u64>,
+    /// Number of jobs of each tenant to run at the same time, like the shards of a job. Every
+    /// worker gets its own repositories root (`<repos-root>/worker-<n>`) if there are several.
+    #[structopt(long, env, default_value = "1")]
+    workers: usize,
+    /// Pool the workers belong to, like `big-machine`, so they only take the jobs run with
+    /// `--runner big-machine`. The ordinary jobs are taken by workers without a pool.
+    #[structopt(long, env)]
+    runner: Option<String>,
+    /// OS the workers run on, like `macos` or `windows`, so they only take the jobs run with
+    /// `--runner-os <os>` (in their pool). Workers without one take the jobs that don't ask for
+    /// an OS.
+    #[structopt(long, env)]
+    runner_os: Option<String>,
+    /// Pool of workers to run the jobs of specific repositories on unless the command picks
+    /// one, as `<owner>/<name>=<runner>`
+    #[structopt(long, env, use_delimiter = true, parse(try_from_str = parse_repo_setting))]
+    repo_runner: Vec<(String, String)>,
+    /// Maximum number of shards a command can be split into with `--shards <n>`
+    #[structopt(long, env, default_value = "8")]
+    max_shards: usize,
+    /// Number of times a job is attempted when the reactor is restarted or its runner is lost
+    /// while running it. Failures retried with `--max-job-retries` don't count against it.
+    #[structopt(long, env, default_value = "3")]
+    max_attempts: u32,
+    /// Seconds after which a worker running a job that stopped sending heartbeats is reported
+    #[structopt(long, env, default_value = "120")]
+    runner_timeout: u64,
+    /// Seconds after which the job of a worker that stopped sending heartbeats is queued again
+    #[structopt(long, env, default_value = "600")]
+    runner_grace: u64,
+    /// Comment on the issue of a job when its worker stops sending heartbeats
+    #[structopt(long, env)]
+    comment_lost_runners: bool,
+    /// URL to post the results of finished jobs to, see `ci_script::webhook`
+    #[structopt(long, env)]
+    result_webhook: Option<url::Url>,
+    /// Secret to sign the result webhooks with (`X-Hub-Signature-256`)
+    #[structopt(long, env, hide_env_values = true)]
+    result_webhook_secret: Option<String>,
+    /// Number of times a job is queued again when it fails in a way that may not happen again,
+    /// like Github or the network failing while checking it out. Interruptions don't count
+    /// against it, see `--max-attempts`.
+    #[structopt(long, env, default_value = "2")]
+    max_job_retries: u32,
+    /// Seconds to wait before queueing a failed job again, doubled with every retry
+    #[structopt(long, env, default_value = "30")]
+    job_retry_delay: u64,
+    /// Hosts scripts may send HTTP requests to (`*.example.com` allows all subdomains)
+    #[structopt(long, env, use_delimiter = true)]
+    http_allowlist: Vec<String>,
+    /// Branches scripts may open pull requests into (`release-*` matches all release branches),
+    /// any if not given
+    #[structopt(long, env, use_delimiter = true)]
+    pr_base: Vec<String>,
+    /// Name of the branches scripts open pull requests from, with `{repo}`, `{issue}` and
+    /// `{name}` filled in, like `benchbot/{repo}/{issue}-{name}`. Scripts get it from
+    /// `REPO.pr_branch(name)`, can't open pull requests from other branches and force push
+    /// these.
+    #[structopt(long, env)]
+    pr_branch_template: Option<String>,
+    /// Delete branches named like `--pr-branch-template` that no pull request is open from once
+    /// their last commit is this many days old, whenever a script opens a pull request
+    #[structopt(long, env)]
+    pr_stale_branch_days: Option<u64>,
+    /// Every this many hours, delete the branches named like `--pr-branch-template` whose pull
+    /// requests are all merged or closed, in all repositories the app is installed on. `POST
+    /// /admin/branches/clean-up` does it right away.
+    #[structopt(long, env)]
+    branch_cleanup_hours: Option<u64>,
+    /// Warm up before measuring: pipelines run every step once first, scripts see `WARM_UP`
+    #[structopt(long, env)]
+    warm_up: bool,
+    /// Set a commit status on the pull request: pending while a job runs, then summarizing the
+    /// results with the headline of the script (`report::headline`) or the worst regression
+    #[structopt(long, env)]
+    commit_status: bool,
+    /// Approve the pull requests the bot opened in specific repositories and have Github merge
+    /// them with `<method>` (`merge`, `squash` or `rebase`) once a job on them succeeded without
+    /// regressions, as `<owner>/<name>=<method>`. Merged once the checks the base branch
+    /// requires pass, like the status of `--commit-status`.
+    #[structopt(long, env, use_delimiter = true, parse(try_from_str = parse_repo_setting))]
+    repo_auto_merge: Vec<(String, ci_script::auto_merge::Method)>,
+    /// Personal access token of the user approving the pull requests of `--repo-auto-merge`,
+    /// since the bot can't approve its own. They're merged without approval if not given.
+    #[structopt(long, env, hide_env_values = true)]
+    auto_merge_token: Option<String>,
+    /// Add the CPU time, peak memory and disk I/O of the job to the comment of its result
+    #[structopt(long, env)]
+    report_usage: bool,
+    /// Memory the processes of a job may use together, like `8G`. Jobs going over it fail
+    /// instead of the whole node running out. Needs `--cgroup-root`.
+    #[structopt(long, env, requires = "cgroup-root", parse(try_from_str = janitor::parse_size))]
+    job_memory_limit: Option<u64>,
+    /// Disk the checkout and the temporary files of a job may use, like `50G`. Jobs going over
+    /// it are stopped and fail.
+    #[structopt(long, env, parse(try_from_str = janitor::parse_size))]
+    job_disk_limit: Option<u64>,
+    /// cgroup v2 directory the bot may write to (like one delegated by systemd with
+    /// `Delegate=yes`), to create a cgroup per job in for `--job-memory-limit`
+    #[structopt(long, env)]
+    cgroup_root: Option<PathBuf>,
+    /// Environment variable to set for every job, as `KEY=VALUE`. Scripts read them with
+    /// `env::get`.
+    #[structopt(long, env, number_of_values = 1, parse(try_from_str = secrets::parse_var))]
+    job_env: Vec<(String, String)>,
+    /// File with secrets to set for every job like `--job-env`, one `KEY=VALUE` per line. Their
+    /// values are masked in logs and comments.
+    #[structopt(long, env)]
+    job_secrets: Option<PathBuf>,
+    /// Hold commands of users without write access to the repository until a maintainer
+    /// approves them with `<command-prefix> approve <id>`
+    #[structopt(long, env)]
+    require_approval: bool,
+    /// Number of commands each user may run in a period of time, like `10/1h`
+    #[structopt(long, env)]
+    user_rate_limit: Option<Rate>,
+    /// Maximum number of jobs queued for the same issue or pull request
+    #[structopt(long, env)]
+    max_queued_per_issue: Option<usize>,
+    /// Maximum number of jobs queued for the same repository
+    #[structopt(long, env)]
+    max_queued_per_repo: Option<usize>,
+    /// Share of the queue of specific repositories, as `<owner>/<name>=<weight>`. Repositories
+    /// take turns, those with weight 2 twice as often as the others, which have weight 1.
+    #[structopt(long, env, use_delimiter = true, parse(try_from_str = parse_repo_setting))]
+    repo_queue_weight: Vec<(String, u32)>,
+    /// Command to queue when a label is added to a pull request of a specific repository, as
+    /// `<owner>/<name>=<label>=<command>`, like `acme/node=bench:extrinsics=bench extrinsics`.
+    /// Removing the label cancels the jobs it queued that haven't started yet. Needs the Github
+    /// App to receive `Pull request` events.
+    #[structopt(long, env, number_of_values = 1, parse(try_from_str = parse_repo_setting))]
+    repo_label_trigger: Vec<(String, LabelTrigger)>,
+    /// How to clone repositories: `full`, `shallow[:<depth>]`, `blobless` or `treeless`
+    #[structopt(long, env, default_value = "full")]
+    clone_strategy: CloneStrategy,
+    /// Clone strategy of specific repositories, as `<owner>/<name>=<strategy>`
+    #[structopt(long, env, use_delimiter = true, parse(try_from_str = parse_repo_setting))]
+    repo_clone_strategy: Vec<(String, CloneStrategy)>,
+    /// Read-only mirror to clone specific repositories from anonymously, like a cache close by,
+    /// as `<owner>/<name>=<url>`. Pull requests are still fetched from Github.
+    #[structopt(long, env, use_delimiter = true, parse(try_from_str = parse_repo_setting))]
+    repo_clone_mirror: Vec<(String, url::Url)>,
+    /// Repositories (`<owner>/<name>`) to check out without initializing their submodules
+    #[structopt(long, env, use_delimiter = true)]
+    skip_submodules: Vec<String>,
+    /// Repositories (`<owner>/<name>`) whose `Cargo.lock` has to be up to date with their
+    /// manifests, jobs fail before running the script otherwise
+    #[structopt(long, env, use_delimiter = true)]
+    check_lockfile: Vec<String>,
+    /// Repositories (`<owner>/<name>`) whose scripts may take flamegraphs with `flamegraph`,
+    /// which runs `cargo flamegraph` and thereby `perf`. Both have to be installed.
+    #[structopt(long, env, use_delimiter = true)]
+    profile_repos: Vec<String>,
+    /// Where cargo keeps build artifacts: `job` (in the checkout, removed after the job),
+    /// `shared` (one target directory per repository, kept between jobs) or `sccache` (like
+    /// `job`, with rustc wrapped by sccache)
+    #[structopt(long, env, default_value = "job")]
+    build_cache: BuildCache,
+    /// Build cache of specific repositories, as `<owner>/<name>=<build-cache>`
+    #[structopt(long, env, use_delimiter = true, parse(try_from_str = parse_repo_setting))]
+    repo_build_cache: Vec<(String, BuildCache)>,
+    /// Cache shared by the machines building with `--build-cache sccache`: `redis://...`,
+    /// `s3://<bucket>[/<prefix>]`, `gs://<bucket>[/<prefix>]` or a WebDAV `https://...` URL.
+    /// Local to each machine if not given.
+    #[structopt(long, env)]
+    sccache_endpoint: Option<ci_script::api::cargo::sccache::Endpoint>,
+    /// Credentials of `--sccache-endpoint`: `<access key ID>:<secret access key>` for S3, the
+    /// path of a service account key for GCS or the token for WebDAV
+    #[structopt(long, env, hide_env_values = true)]
+    sccache_credentials: Option<String>,
+    /// Personal access token with the `gist` scope, to upload reports too long for a comment as
+    /// secret gists and link to them. They're split over several comments without one.
+    #[structopt(long, env, hide_env_values = true)]
+    gist_token: Option<String>,
+    /// SSH key to clone and fetch repositories with, instead of the installation token
+    #[structopt(long, env)]
+    ssh_key: Option<PathBuf>,
+    /// Passphrase of `--ssh-key`
+    #[structopt(long, env, hide_env_values = true)]
+    ssh_key_passphrase: Option<String>,
+    /// Author of the commits scripts make, as `Name <email>`
+    #[structopt(long, env)]
+    commit_author: Option<ci_script::api::git::Identity>,
+    /// Committer of the commits scripts make, `--commit-author` if not given
+    #[structopt(long, env)]
+    commit_committer: Option<ci_script::api::git::Identity>,
+    /// GPG key (ID or fingerprint, in the keyring of the user running the reactor) to sign the
+    /// commits scripts make with, for repositories that require signed commits
+    #[structopt(long, env, conflicts_with = "commit-ssh-key")]
+    commit_gpg_key: Option<String>,
+    /// SSH private key to sign the commits scripts make with instead of `--commit-gpg-key`
+    #[structopt(long, env)]
+    commit_ssh_key: Option<PathBuf>,
+    /// Give every worker a `RUSTUP_HOME` of its own (`<repos-root>/rustup`) to install the
+    /// toolchains of its jobs into, so workers don't race installing them into the same one
+    #[structopt(long, env)]
+    isolate_toolchains: bool,
+    /// Repository of rhai modules shared by the scripts of all repositories, which they import
+    /// as `lib:<name>`. Checked out to `<repos-root>/.script-library` on start, with `--ssh-key`
+    /// if given.
+    #[structopt(long, env)]
+    script_library: Option<String>,
+    /// Commit, tag or branch of `--script-library` to check out. What scripts import only
+    /// changes on restart.
+    #[structopt(long, env, default_value = "HEAD")]
+    script_library_rev: String,
+    /// Maximum size of each tenant's repositories root, like `200G`. Once exceeded after a job,
+    /// the `target` directories of clones and then the clones themselves are removed, least
+    /// recently used first.
+    #[structopt(long, env, parse(try_from_str = janitor::parse_size))]
+    max_repos_size: Option<u64>,
+    /// Bytes of each stream of cargo's output scripts get, half from its start and half from its
+    /// end. All of it is written next to the checkout, at `stdout_path` and `stderr_path` of the
+    /// result.
+    #[structopt(long, env, default_value = "1M", parse(try_from_str = janitor::parse_size))]
+    cargo_output_limit: u64,
+    /// Cores to pin cargo (and the benchmarks it runs) to, like `2-5`, instead of letting it
+    /// run on any. Shared by all workers. Scripts can pin parts of their runs with
+    /// `with_pinned_cores` instead.
+    #[structopt(long, env, parse(try_from_str = ci_script::api::pinning::parse_cores))]
+    pin_cores: Option<ci_script::api::pinning::Cores>,
+    /// Switch the pinned cores to the `performance` CPU frequency governor while they are, and
+    /// back afterwards. Takes root.
+    #[structopt(long, env)]
+    performance_governor: bool,
+    /// Directory to keep the files scripts upload with `upload_artifact` in (`<artifacts-dir>/
+    /// <tenant name>`), served at `/artifacts/...` to those passing `--dashboard-auth`. Scripts
+    /// can't upload artifacts without it.
+    #[structopt(long, env)]
+    artifacts_dir: Option<PathBuf>,
+    /// PEM certificate to encrypt the artifacts of a repository to, as `<owner>/<name>=<path>`.
+    /// Give several for the same repository to encrypt to all of them.
+    #[structopt(long, env, use_delimiter = true, parse(try_from_str = parse_repo_setting))]
+    repo_artifact_recipients: Vec<(String, PathBuf)>,
+    /// Authentication of the worker API (`/queue/...`), any of `token` and `client-cert`. Open
+    /// to everyone if not given.
+    #[structopt(long, env, use_delimiter = true)]
+    worker_auth: Vec<auth::Kind>,
+    /// Authentication of the dashboard and results API, any of `token`, `client-cert` and
+    /// `github-oauth`. Open to everyone if not given.
+    #[structopt(long, env, use_delimiter = true)]
+    dashboard_auth: Vec<auth::Kind>,
+    /// Authentication of the admin API (`/admin/...`), any of `token` and `client-cert`. Open
+    /// to everyone if not given, except for exporting and importing the queue, which aren't
+    /// served then.
+    #[structopt(long, env, use_delimiter = true)]
+    admin_auth: Vec<auth::Kind>,
+    /// Github users who may pause and resume the queue with `<prefix> admin pause` and `<prefix>
+    /// admin resume`
+    #[structopt(long, env, use_delimiter = true)]
+    admins: Vec<String>,
+    /// Authentication of the job API (`/jobs`), any of `token`, `client-cert` and
+    /// `github-oauth`. Jobs can only be submitted through it with one.
+    #[structopt(long, env, use_delimiter = true)]
+    submit_auth: Vec<auth::Kind>,
+    /// File with the tokens accepted by the `token` scheme (`Authorization: Bearer <token>`),
+    /// one per line
+    #[structopt(long, env)]
+    auth_tokens: Option<PathBuf>,
+    /// Header in which the proxy terminating TLS passes on the subject of a verified client
+    /// certificate, for the `client-cert` scheme
+    #[structopt(long, env, default_value = "X-Client-Cert-Subject")]
+    client_cert_header: String,
+    /// Client certificate subjects accepted by the `client-cert` scheme
+    #[structopt(long, env, use_delimiter = true)]
+    client_cert_subjects: Vec<String>,
+    /// Client ID of the Github OAuth App used by the `github-oauth` scheme
+    #[structopt(long, env)]
+    github_oauth_client_id: Option<String>,
+    /// Client secret of the Github OAuth App used by the `github-oauth` scheme
+    #[structopt(long, env, hide_env_values = true)]
+    github_oauth_client_secret: Option<String>,
+    /// Github users allowed in by the `github-oauth` scheme
+    #[structopt(long, env, use_delimiter = true)]
+    github_oauth_users: Vec<String>,
+    /// URL browsers reach the reactor at, for Github to send users back to after logging in
+    #[structopt(long, env)]
+    public_url: Option<String>,
+    /// GitLab instance to serve along with Github, see `--gitlab-token`
+    #[structopt(long, env, default_value = "https://gitlab.com")]
+    gitlab_url: url::Url,
+    /// Access token (with the `api` scope) of the bot's GitLab user. Enables the webhook for
+    /// comments on merge requests at `/gitlab`, which GitLab projects send their `Comments`
+    /// events to.
+    #[structopt(long, env, hide_env_values = true)]
+    gitlab_token: Option<String>,
+    /// Secret token of the GitLab webhook, required with `--gitlab-token`
+    #[structopt(long, env, hide_env_values = true)]
+    gitlab_webhook_secret: Option<String>,
+}
+
+/// Parse a setting of a specific repository, like `<owner>/<name>=<strategy>`
+fn parse_repo_setting<T>(s: &str) -> anyhow::Result<(String, T)>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let (repo, setting) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected <owner>/<name>=<setting>, got {s:?}"))?;
+    Ok((repo.to_string(), setting.parse()?))
+}
+
+/// Tenant as listed in the `--tenants` file:
+///
+/// ```toml
+/// [[tenant]]
+/// name = "acme"
+/// app_id = 1234
+/// app_key_file = "/etc/cis/acme.pem"
+/// webhook_secret_file = "/etc/cis/acme.secret"
+/// command_prefix = "/acmebot" # optional
+/// ```
+#[derive(Debug, Deserialize)]
+struct TenantConfig {
+    name: String,
+    app_id: u64,
+    app_key_file: PathBuf,
+    webhook_secret_file: PathBuf,
+    command_prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TenantsFile {
+    #[serde(default, rename = "tenant")]
+    tenants: Vec<TenantConfig>,
+}
+
+/// A Github App served by this process. The tenant configured on the command line is called
+/// `default`.
+struct Tenant {
+    name: String,
+    app_id: u64,
+    app_key: String,
+    webhook_secret: String,
+    command_prefix: String,
+    repos_root: PathBuf,
+}
+
+impl Tenant {
+    /// Path the tenant's routes are mounted on
+    fn prefix(&self) -> String {
+        if self.name == DEFAULT_TENANT {
+            String::new()
+        } else {
+            format!("/tenants/{}", self.name)
+        }
+    }
+
+    /// Where the results of the tenant are stored with `--results-db <path>`: `path` itself for
+    /// the default tenant, next to it for the others, like `results.acme.db`
+    fn results_db(&self, path: &Path) -> PathBuf {
+        if self.name == DEFAULT_TENANT {
+            return path.to_owned();
+        }
+        let mut name = path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!(".{}", self.name));
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        path.with_file_name(name)
+    }
+
+    fn github_client(&self, api_base: &url::Url) -> anyhow::Result<Octocrab> {
+        Ok(Octocrab::builder()
+            .base_url(api_base.as_str())?
+            .personal_token(app_jwt(self.app_id, &self.app_key)?)
+            .build()?)
+    }
+}
+
+/// JWT authenticating as the Github App
+fn app_jwt(app_id: u64, app_key: &str) -> anyhow::Result<String> {
+    let app_id = octocrab::models::AppId::from(app_id);
+    let app_key = jsonwebtoken::EncodingKey::from_rsa_pem(app_key.as_bytes())?;
+    Ok(octocrab::auth::create_jwt(app_id, &app_key)?)
+}
+
+const DEFAULT_TENANT: &str = "default";
+
+/// Schemes every endpoint group can choose from, shared between the groups of a tenant
+struct AuthSchemes {
+    tokens: Option<Arc<auth::Tokens>>,
+    client_cert: Option<Arc<auth::ClientCert>>,
+    github_oauth: Option<Arc<auth::GithubOAuth>>,
+}
+
+impl AuthSchemes {
+    /// Middleware accepting any of the given schemes
+    fn authenticate(&self, kinds: &[auth::Kind]) -> anyhow::Result<Authenticate<State>> {
+        let mut authenticate = Authenticate::new();
+        for kind in kinds {
+            authenticate = match kind {
+                auth::Kind::Token => authenticate.with_shared(
+                    self.tokens
+                        .clone()
+                        .ok_or(auth::Error::MissingConfig(*kind, "--auth-tokens"))?,
+                ),
+                auth::Kind::ClientCert => authenticate.with_shared(
+                    self.client_cert
+                        .clone()
+                        .ok_or(auth::Error::MissingConfig(*kind, "--client-cert-subjects"))?,
+                ),
+                auth::Kind::GithubOAuth => {
+                    authenticate
+                        .with_shared(self.github_oauth.clone().ok_or(auth::Error::MissingConfig(
+                        *kind,
+                        "--github-oauth-client-id, --github-oauth-client-secret and --public-url",
+                    ))?)
+                }
+            };
+        }
+        Ok(authenticate)
+    }
+}
+
+fn load_tenants(config: &Config) -> anyhow::Result<Vec<Tenant>> {
+    let mut tenants = vec![Tenant {
+        name: DEFAULT_TENANT.into(),
+        app_id: config.app_id,
+        app_key: config.app_key.clone(),
+        webhook_secret: config.webhook_secret.clone(),
+        command_prefix: config.command_prefix.clone(),
+        repos_root: config.repos_root.clone(),
+    }];
+    if let Some(path) = &config.tenants {
+        let file: TenantsFile = toml::from_str(&std::fs::read_to_string(path)?)?;
+        for tenant in file.tenants {
+            let valid_name = !tenant.name.is_empty()
+                && tenant
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name {
+                return Err(Error::InvalidTenantName(tenant.name).into());
+            }
+            if tenants
+                .iter()
+                .any(|t| t.name == tenant.name || t.app_id == tenant.app_id)
+            {
+                return Err(Error::DuplicateTenant(tenant.name).into());
+            }
+            tenants.push(Tenant {
+                repos_root: config.repos_root.join(&tenant.name),
+                name: tenant.name,
+                app_id: tenant.app_id,
+                app_key: std::fs::read_to_string(tenant.app_key_file)?,
+                webhook_secret: std::fs::read_to_string(tenant.webhook_secret_file)?
+                    .trim()
+                    .to_string(),
+                command_prefix: tenant
+                    .command_prefix
+                    .unwrap_or_else(|| config.command_prefix.clone()),
+            });
+        }
+    }
+    Ok(tenants)
 }
 
-type State = Arc<Mutex<LocalQueue<String, Job>>>;
+#[derive(Clone)]
+struct State {
+    tenant: String,
+    queue: Arc<Mutex<PersistentQueue<Job>>>,
+    pending: Arc<Mutex<Pending>>,
+    history: SharedHistory,
+    log_tail: LogTail,
+    results_store: Option<ci_script::results::Store>,
+    /// One per worker
+    janitors: Vec<Janitor>,
+    /// Keeps the results of the jobs submitted through the API
+    journal: Journal,
+    reloader: Reloader,
+    /// Running the jobs of the queue, to tell when queued ones start
+    workers: usize,
+    /// Workers that said who they are when taking jobs
+    runners: Runners,
+    /// Served at `/metrics`, kept up to date by the workers
+    metrics: Metrics,
+}
 
 #[derive(Error, Debug)]
 enum Error {
     #[error("Missing bot command")]
     NoCmd,
+    #[error("Failed to gain exclusive lock on the job history")]
+    ExclusiveLock,
+    #[error("Invalid tenant name {0:?}, only ASCII letters, digits, '-' and '_' are allowed")]
+    InvalidTenantName(String),
+    #[error("Tenant {0} has the same name or app ID as another tenant")]
+    DuplicateTenant(String),
+    #[error("No results are stored, see --results-db")]
+    NoResultsStore,
+    #[error("Unknown run {0}")]
+    UnknownRun(String),
+    #[error("Unknown baseline {0}, see the `baseline list` command")]
+    UnknownBaseline(String),
+    #[error("Invalid number of shards {0:?}")]
+    InvalidShards(String),
+    #[error("GitLab isn't enabled, see --gitlab-token")]
+    NoGitlab,
+    #[error("The job wasn't requested on a pull request")]
+    NoIssue,
+    #[error("Scripts don't open pull requests from branches of the bot, see --pr-branch-template")]
+    NoBotBranches,
+    #[error("Invalid repository {0:?}, expected <owner>/<name> or its Github URL")]
+    InvalidRepository(String),
+    #[error("Invalid script path {0:?}, expected a path relative to the repository's root")]
+    InvalidScript(String),
+    #[error("Github didn't tell the GraphQL ID of the pull request")]
+    NoNodeId,
 }
 
 async fn remove_from_queue(req: tide::Request<State>) -> tide::Result {
@@ -54,23 +641,52 @@ async fn remove_from_queue(req: tide::Request<State>) -> tide::Result {
     #[serde(default)]
     struct Options {
         long_poll: bool,
+        /// Pool of the worker, only the jobs run on it are taken
+        runner: Option<String>,
+        /// OS of the worker, only the jobs asking for it are taken
+        runner_os: Option<String>,
+        /// Of the worker, to tell whether it's still there while it runs the job, see
+        /// `runners::Runners`
+        name: Option<String>,
+        version: Option<String>,
     }
 
+    let Options {
+        long_poll,
+        runner,
+        runner_os,
+        name,
+        version,
+    } = req.query()?;
+    let runners = &req.state().runners;
+    if let Some(name) = &name {
+        runners.seen(Heartbeat {
+            name: name.clone(),
+            version,
+            runner: runner.clone(),
+            os: runner_os.clone(),
+            job: None,
+        });
+    }
+    let route = runners::route(runner.as_deref(), runner_os.as_deref()).map(Cow::into_owned);
+    let took = |job: &Job| {
+        if let Some(name) = &name {
+            runners.took(name, job);
+        }
+    };
     // We lock the Mutex in a separate scope so it can be unlocked (dropped)
     // before we try to .await another future (MutexGuard is not Send).
     let recv = {
-        let queue = req.state();
+        let mut queue = req.state().queue.lock().await;
 
-        let mut queue = queue.lock().await;
-
-        match queue.remove() {
-            Some(job) => return Ok(tide::Body::from_json(&job)?.into()),
+        match queue.remove_routed(route.as_deref()) {
+            Some(job) => {
+                took(&job);
+                return Ok(tide::Body::from_json(&job)?.into());
+            }
             None => {
-                let Options { long_poll } = req.query()?;
                 if long_poll {
-                    let (send, recv) = async_std::channel::bounded(1);
-                    queue.register_watcher(send);
-                    Some(recv)
+                    Some(queue.watch_routed(route))
                 } else {
                     None
                 }
@@ -82,6 +698,7 @@ async fn remove_from_queue(req: tide::Request<State>) -> tide::Result {
         Some(recv) => {
             let mut res = tide::Response::new(200);
             let job = recv.recv().await?;
+            took(&job);
             res.set_body(tide::Body::from_json(&job)?);
             Ok(res)
         }
@@ -89,212 +706,4195 @@ async fn remove_from_queue(req: tide::Request<State>) -> tide::Result {
     }
 }
 
-fn prepare_command(command: Vec<String>) -> Result<Vec<String>, Error> {
-    // The first argument (.e.g `/bot` is also the name of the directory the script is in
-    let dir = command
-        .iter()
-        .next()
-        .map(|cmd| {
-            if let Some(cmd) = cmd.strip_prefix('/') {
-                String::from(cmd)
-            } else {
-                String::from(cmd)
-            }
-        })
-        .ok_or(Error::NoCmd)?;
-    let file = command
-        .iter()
-        .nth(1)
-        .map(|cmd| format!("{}.rhai", cmd))
-        .ok_or(Error::NoCmd)?;
-    let mut args: Vec<String> = command.into_iter().skip(2).collect();
-    let script_path = String::from(Path::new(".github").join(dir).join(file).to_string_lossy());
-    let mut res = vec![script_path];
-    res.append(&mut args);
-    Ok(res)
+/// Remember that the worker in the body is still there
+async fn heartbeat(mut req: tide::Request<State>) -> tide::Result {
+    let heartbeat: Heartbeat = req.body_json().await?;
+    req.state().runners.seen(heartbeat);
+    Ok(tide::Response::new(204))
 }
 
-#[async_std::main]
-async fn main() -> tide::Result<()> {
-    let config = Config::from_args();
-    pretty_env_logger::formatted_timed_builder()
-        .filter(None, config.log_level)
-        .init();
+/// The workers that said who they are, with the job they're running, as JSON
+async fn runners_status(req: tide::Request<State>) -> tide::Result {
+    #[derive(Serialize)]
+    struct Runner {
+        name: String,
+        version: Option<String>,
+        runner: Option<String>,
+        os: Option<String>,
+        job: Option<String>,
+        last_seen: chrono::DateTime<chrono::Utc>,
+        /// Whether it stopped sending heartbeats while running its job
+        silent: bool,
+    }
 
-    let command_prefix = config.command_prefix.clone();
+    let runners: Vec<_> = req
+        .state()
+        .runners
+        .list()
+        .into_iter()
+        .map(|runner| Runner {
+            name: runner.name,
+            version: runner.version,
+            runner: runner.runner,
+            os: runner.os,
+            job: runner.job.map(|job| job.id),
+            last_seen: runner.last_seen,
+            silent: runner.silent,
+        })
+        .collect();
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&runners)?)
+        .build())
+}
 
-    let queue = Arc::new(Mutex::new(LocalQueue::new()));
+/// The metrics of the jobs in the Prometheus text format
+async fn metrics(req: tide::Request<State>) -> tide::Result {
+    Ok(tide::Response::builder(200)
+        .content_type("text/plain; version=0.0.4")
+        .body(req.state().metrics.render())
+        .build())
+}
 
-    let mut app = tide::with_state(queue.clone());
-    let github = tide_github::new(&config.webhook_secret)
-        .on(Event::IssueComment, move |payload| {
-            let payload: tide_github::payload::IssueCommentPayload = match payload.try_into() {
-                Ok(payload) => payload,
-                Err(e) => {
-                    log::warn!("Failed to parse payload: {}", e);
-                    return;
-                }
+/// Report the workers that stopped sending heartbeats while running a job after `timeout`, and
+/// queue their jobs again after `grace`, unless they've been attempted too often already
+async fn watch_runners(
+    intake: Intake,
+    runners: Runners,
+    (timeout, grace): (Duration, Duration),
+    comment: bool,
+    max_attempts: u32,
+) {
+    let tenant = &intake.tenant;
+    loop {
+        async_std::task::sleep(runners::HEARTBEAT_INTERVAL).await;
+        for runner in runners.newly_silent(timeout) {
+            let job = match &runner.job {
+                Some(job) => job,
+                None => continue,
+            };
+            tracing::warn!(
+                "[{tenant}] Runner {} running job {} wasn't heard from for {}s",
+                runner.name,
+                job.id,
+                timeout.as_secs()
+            );
+            if comment {
+                let body = format!(
+                    "The runner `{}` running this job stopped responding, the job is queued \
+                     again if it doesn't respond within {}.",
+                    runner.name,
+                    ci_script::api::phases::format_duration(grace)
+                );
+                intake.comment(job, body).await;
+            }
+        }
+        for (name, job) in runners.take_lost(grace) {
+            let attempts = job.retries + 1;
+            let body = if attempts < max_attempts {
+                tracing::warn!(
+                    "[{tenant}] Queueing job {} of lost runner {name} again",
+                    job.id
+                );
+                let mut retry = job.clone();
+                retry.retries += 1;
+                intake
+                    .queue
+                    .lock()
+                    .await
+                    .add(retry.id.clone(), retry.dedup_key(), retry);
+                format!(
+                    "The runner `{name}` running this job was lost, it has been queued again \
+                     (attempt {} of {max_attempts}).",
+                    attempts + 1
+                )
+            } else {
+                tracing::warn!(
+                    "[{tenant}] Giving up on job {} of lost runner {name}",
+                    job.id
+                );
+                format!(
+                    "The runner `{name}` running this job was lost, giving up after {attempts} \
+                     attempts."
+                )
             };
+            if comment {
+                intake.comment(&job, body).await;
+            }
+        }
+    }
+}
 
-            if let Some(body) = payload.comment.body {
-                if body.starts_with(&command_prefix) {
-                    let command = body
-                        .split_once('\n')
-                        .map(|(cmd, _)| cmd.into())
-                        .map(|cmd| {
-                            shell_words::split(cmd).expect("Failed to split command as shell words")
-                        })
-                        .unwrap_or_else(|| body.split(" ").map(|x| x.to_string()).collect());
+async fn dashboard(req: tide::Request<State>) -> tide::Result {
+    let State {
+        tenant,
+        queue,
+        pending,
+        history,
+        log_tail,
+        ..
+    } = req.state();
+    let queue = queue.lock().await;
+    let pending = pending.lock().await;
+    let body = {
+        let history = history.lock().map_err(|_| Error::ExclusiveLock)?;
+        ci_script::dashboard::render(
+            tenant,
+            queue.items(),
+            pending.iter(),
+            &history,
+            &log_tail.lines(),
+        )
+    };
+    Ok(tide::Response::builder(200)
+        .content_type(tide::http::mime::HTML)
+        .body(body)
+        .build())
+}
 
-                    let command = match prepare_command(command) {
-                        Ok(command) => command,
-                        Err(e) => {
-                            log::warn!("Failed to determine command: {e}");
-                            return;
-                        }
-                    };
+/// Days of finished jobs to learn how long jobs take from on start
+const LEARN_DAYS: u64 = 30;
 
-                    let id = format!(
-                        "{}_{}_{}",
-                        payload.repository.name,
-                        command.join(" "),
-                        uuid::Uuid::new_v4(),
-                    );
+/// The history of `tenant`, knowing how long the jobs it finished lately took
+fn learned_history(journal: &Journal, tenant: &str) -> History {
+    let mut history = History::default();
+    let since = std::time::SystemTime::now() - Duration::from_secs(LEARN_DAYS * 24 * 3600);
+    match journal.archived(since) {
+        Ok(records) => records
+            .iter()
+            .filter(|(queue, _)| queue == tenant)
+            .for_each(|(_, record)| history.learn(record)),
+        Err(err) => tracing::warn!("[{tenant}] Failed to read finished jobs: {err}"),
+    }
+    history
+}
 
-                    let repo: Repository = match payload.repository.try_into() {
-                        Ok(repo) => repo,
-                        Err(err) => {
-                            log::warn!("Failed to parse repository payload: {}", err);
-                            return;
-                        }
-                    };
+/// `<owner>/<name>` of the repository of `job`, which takes turns in the queue with the others
+fn repository_of(job: &Job) -> String {
+    format!("{}/{}", job.repository.owner.login, job.repository.name)
+}
 
-                    let job = Job {
-                        command,
-                        // user: payload.comment.user,
-                        repository: repo,
-                        issue: payload.issue,
-                    };
+/// When a job expected to start in `start` from now does
+fn estimated_start(start: Option<Duration>) -> Option<chrono::DateTime<chrono::Utc>> {
+    Some(chrono::Utc::now() + chrono::Duration::from_std(start?).ok()?)
+}
 
-                    let q = queue.clone();
-                    async_std::task::spawn(async move {
-                        q.lock().await.add(id, job);
-                    });
-                }
-            }
+/// The queued jobs with their position and when they're expected to start, as JSON
+async fn queue_status(req: tide::Request<State>) -> tide::Result {
+    #[derive(Serialize)]
+    struct Queued<'a> {
+        id: &'a str,
+        /// 1 being next
+        position: usize,
+        repository: String,
+        command: String,
+        issue: Option<i64>,
+        branch: Option<&'a str>,
+        runner: Option<&'a str>,
+        estimated_start: Option<chrono::DateTime<chrono::Utc>>,
+        /// Times it was interrupted, see `Job::retries`
+        retries: u32,
+        /// Times it failed and was retried, see `Job::attempts`
+        attempts: u32,
+        last_error: Option<&'a str>,
+    }
+
+    let State {
+        queue,
+        history,
+        workers,
+        ..
+    } = req.state();
+    let queue = queue.lock().await;
+    let queued = queue.items();
+    let starts = if queue.is_paused() {
+        vec![None; queued.len()]
+    } else {
+        let history = history.lock().map_err(|_| Error::ExclusiveLock)?;
+        history.starts(&queued, *workers)
+    };
+    let queued: Vec<_> = queued
+        .iter()
+        .zip(starts)
+        .enumerate()
+        .map(|(pos, (job, start))| Queued {
+            id: &job.id,
+            position: pos + 1,
+            repository: format!("{}/{}", job.repository.owner.login, job.repository.name),
+            command: job.command.join(" "),
+            issue: job.issue.as_ref().map(|issue| issue.number),
+            branch: job.branch.as_deref(),
+            runner: job.runner.as_deref(),
+            estimated_start: estimated_start(start),
+            retries: job.retries,
+            attempts: job.attempts,
+            last_error: job.last_error.as_deref(),
         })
-        .build();
-    app.at("/").nest(github);
-    app.at("/queue/remove").post(remove_from_queue);
+        .collect();
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&queued)?)
+        .build())
+}
 
-    let self_url = format!("http://{}:{}", config.address, config.port);
-    let repos_root = config.repos_root.clone();
-    let github_client = {
-        let token = {
-            let app_id = octocrab::models::AppId::from(config.app_id);
-            let app_key = jsonwebtoken::EncodingKey::from_rsa_pem(config.app_key.as_bytes())?;
-            octocrab::auth::create_jwt(app_id, &app_key)?
-        };
-        Octocrab::builder().personal_token(token).build()?
+/// Look up two stored runs and render their comparison. Runs of `repo` can also be given as
+/// `baseline:<name>`, the run saved as that baseline.
+fn compare_runs(
+    store: Option<&ci_script::results::Store>,
+    repo: Option<&str>,
+    a: &str,
+    b: &str,
+) -> anyhow::Result<(ci_script::results::Run, ci_script::results::Run, String)> {
+    let store = store.ok_or(Error::NoResultsStore)?;
+    let lookup = |id: &str| -> anyhow::Result<ci_script::results::Run> {
+        if let (Some(repo), Some(name)) = (repo, id.strip_prefix("baseline:")) {
+            return Ok(store
+                .baseline(repo, name)?
+                .map(|(_, run)| run)
+                .ok_or_else(|| Error::UnknownBaseline(name.to_string()))?);
+        }
+        Ok(store
+            .run(id)?
+            .ok_or_else(|| Error::UnknownRun(id.to_string()))?)
     };
+    let (a, b) = (lookup(a)?, lookup(b)?);
+    let report = ci_script::results::render_comparison(&a, &b);
+    Ok((a, b, report))
+}
 
-    let tokio_rt = tokio::runtime::Runtime::new()?;
-    async_std::task::spawn(async move {
-        async fn run<P: AsRef<std::path::Path> + AsRef<std::ffi::OsStr>>(
-            repos_root: P,
-            job: Job,
-            github_client: octocrab::Octocrab,
-            //tokio_handle: tokio::runtime::Handle,
-        ) -> anyhow::Result<()> {
-            //let github = Arc::try_unwrap(github_client).into_inner();
-            //let github = std::sync::Arc::new(std::sync::Mutex::new(github));
-            job.checkout(&repos_root)?
-                .prepare_script(github_client)?
-                .run()?;
-            Ok(())
+async fn results_compare(req: tide::Request<State>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Options {
+        a: String,
+        b: String,
+        /// As `owner/name`, to compare baselines of
+        repo: Option<String>,
+    }
+
+    let Options { a, b, repo } = req.query()?;
+    match compare_runs(req.state().results_store.as_ref(), repo.as_deref(), &a, &b) {
+        Ok((_, _, report)) => Ok(tide::Response::builder(200)
+            .content_type(tide::http::mime::PLAIN)
+            .body(report)
+            .build()),
+        Err(err) => Ok(tide::Response::builder(404)
+            .content_type(tide::http::mime::PLAIN)
+            .body(err.to_string())
+            .build()),
+    }
+}
+
+/// Apply changes to the settings, see [`Reloader`]
+async fn reload_settings(req: tide::Request<State>) -> tide::Result {
+    let reloader = req.state().reloader.clone();
+    match async_std::task::spawn_blocking(move || reloader.reload()).await {
+        Ok(()) => Ok(tide::Response::new(204)),
+        Err(err) => {
+            tracing::warn!("Failed to reload settings: {err:#}");
+            Ok(tide::Response::builder(400)
+                .body(format!("{err:#}"))
+                .build())
         }
+    }
+}
 
-        async fn get_job<D: std::fmt::Display>(url: D) -> anyhow::Result<Job> {
-            let mut res = surf::post(format!("{}/queue/remove?long_poll=true", url))
-                .await
-                .map_err(|e| e.into_inner())?;
-            res.body_json::<Job>().await.map_err(|e| e.into_inner())
+/// Stop handing out jobs, still queuing new ones, like for maintenance of the workers' machines
+async fn pause_queue(req: tide::Request<State>) -> tide::Result {
+    set_paused(req, true).await
+}
+
+async fn resume_queue(req: tide::Request<State>) -> tide::Result {
+    set_paused(req, false).await
+}
+
+async fn set_paused(req: tide::Request<State>, paused: bool) -> tide::Result {
+    #[derive(Serialize)]
+    struct Paused {
+        paused: bool,
+        queued: usize,
+    }
+
+    let State { tenant, queue, .. } = req.state();
+    let mut queue = queue.lock().await;
+    if paused {
+        queue.pause()?;
+        tracing::info!("[{tenant}] Paused the queue");
+    } else {
+        queue.resume()?;
+        tracing::info!("[{tenant}] Resumed the queue");
+    }
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&Paused {
+            paused: queue.is_paused(),
+            queued: queue.len(),
+        })?)
+        .build())
+}
+
+/// The queued jobs of a tenant, to move them to another reactor through
+/// `GET /admin/queue/export` and `POST /admin/queue/import`
+#[derive(Deserialize, Serialize)]
+struct QueueSnapshot {
+    /// Of the reactor the jobs were exported from
+    tenant: String,
+    exported_at: chrono::DateTime<chrono::Utc>,
+    paused: bool,
+    /// In the order they would have run in
+    jobs: Vec<Job>,
+}
+
+async fn export_queue(req: tide::Request<State>) -> tide::Result {
+    let State { tenant, queue, .. } = req.state();
+    let queue = queue.lock().await;
+    let snapshot = QueueSnapshot {
+        tenant: tenant.clone(),
+        exported_at: chrono::Utc::now(),
+        paused: queue.is_paused(),
+        jobs: queue.items().into_iter().cloned().collect(),
+    };
+    tracing::info!("[{tenant}] Exported {} queued jobs", snapshot.jobs.len());
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&snapshot)?)
+        .build())
+}
+
+/// Queue the jobs of a snapshot after the ones already queued, in their order, skipping the ones
+/// that are queued already. They're queued like any other job, so the limits of the queue apply.
+/// The queue isn't paused or resumed to match the snapshot.
+async fn import_queue(mut req: tide::Request<State>, intake: Intake) -> tide::Result {
+    #[derive(Serialize)]
+    struct Imported {
+        imported: usize,
+        /// Already queued
+        skipped: usize,
+        /// Over the limits of the queue or queued already by their command, with why
+        rejected: Vec<Rejected>,
+        queued: usize,
+    }
+
+    #[derive(Serialize)]
+    struct Rejected {
+        id: String,
+        reason: String,
+    }
+
+    let snapshot: QueueSnapshot = req.body_json().await?;
+    let tenant = &req.state().tenant;
+    let mut imported = Imported {
+        imported: 0,
+        skipped: 0,
+        rejected: vec![],
+        queued: 0,
+    };
+    for job in snapshot.jobs {
+        if intake.queue.lock().await.pos(job.id.clone()).is_some() {
+            imported.skipped += 1;
+            continue;
+        }
+        let id = job.id.clone();
+        match intake.try_enqueue(job).await {
+            Ok(()) => imported.imported += 1,
+            Err(reason) => imported.rejected.push(Rejected { id, reason }),
         }
+    }
+    imported.queued = intake.queue.lock().await.len();
+    tracing::info!(
+        "[{tenant}] Imported {} jobs exported by {} at {}, skipping {} already queued and \
+         rejecting {}",
+        imported.imported,
+        snapshot.tenant,
+        snapshot.exported_at,
+        imported.skipped,
+        imported.rejected.len()
+    );
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&imported)?)
+        .build())
+}
+
+/// Deletes the branches scripts opened pull requests from once those are merged or closed
+#[derive(Clone)]
+struct BranchCleanup {
+    tenant: String,
+    github_client: Octocrab,
+    /// For the names of the branches, which may be reloaded
+    settings: LiveSettings,
+    tokio_handle: tokio::runtime::Handle,
+}
 
-        let rt_handle = tokio_rt.handle();
+impl BranchCleanup {
+    async fn run(&self) -> anyhow::Result<ci_script::branches::Cleaned> {
+        let prefix = self
+            .settings
+            .get()
+            .pr_policy
+            .bot_branch_prefix()
+            .ok_or(Error::NoBotBranches)?
+            .to_string();
+        let github_client = self.github_client.clone();
+        let cleaned = self
+            .tokio_handle
+            .spawn(async move { ci_script::branches::clean_up(&github_client, &prefix).await })
+            .await??;
+        tracing::info!(
+            "[{}] Deleted {} branches of pull requests that are done in {} repositories ({} \
+             failed)",
+            self.tenant,
+            cleaned.deleted.len(),
+            cleaned.repositories,
+            cleaned.failed.len()
+        );
+        Ok(cleaned)
+    }
+
+    /// Clean up every `interval`, forever
+    async fn every(self, interval: Duration) {
         loop {
-            let github_client = github_client.clone();
-            match get_job(&self_url).await {
-                Ok(ref job) => {
-                    log::info!(
-                        "Processing command {} in repo {}",
-                        job.command.join(" "),
-                        job.repository.url
-                    );
+            async_std::task::sleep(interval).await;
+            if let Err(err) = self.run().await {
+                tracing::warn!("[{}] Failed to clean up branches: {err:#}", self.tenant);
+            }
+        }
+    }
+}
 
-                    // TODO: Fix block_on
-                    let gh_client = github_client.clone();
-                    let github_installation_client = match rt_handle.block_on(async move {
-                        let installations = gh_client
-                            .apps()
-                            .installations()
-                            .send()
-                            .await
-                            .unwrap()
-                            .take_items();
-                        let mut access_token_req = CreateInstallationAccessToken::default();
-                        access_token_req.repository_ids = vec![job.repository.id];
-                        // TODO: Properly fill-in installation
-                        let access: octocrab::models::InstallationToken = gh_client
-                            .post(
-                                installations[0].access_tokens_url.as_ref().unwrap(),
-                                Some(&access_token_req),
-                            )
-                            .await?;
-                        octocrab::OctocrabBuilder::new()
-                            .personal_token(access.token)
-                            .build()
-                    }) {
-                        Ok(github_installation_client) => github_installation_client,
-                        _ => {
-                            log::warn!("Failed to require octocrab Github client");
-                            return;
-                        }
-                    };
+async fn clean_up_branches(cleanup: BranchCleanup) -> tide::Result {
+    let cleaned = cleanup.run().await?;
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&cleaned)?)
+        .build())
+}
 
-                    let repo_owner = job.repository.owner.login.clone();
-                    let repo_name = job.repository.name.clone();
-                    let issue_nr = job.issue.number.try_into();
-
-                    let gh_client = github_client.clone();
-                    let job = job.clone();
-                    //if let Err(job_err) = run(&repos_root, job, gh_client, rt_handle.clone()).await {
-                    if let Err(job_err) = run(&repos_root, job, gh_client).await {
-                        log::warn!("Error running job: {job_err}");
-
-                        // TODO: create separate tokio threadpool and send messages to
-                        // it
-                        if let Ok(issue_nr) = issue_nr {
-                            match rt_handle.block_on(async {
-                                github_installation_client
-                                    .issues(&repo_owner, &repo_name)
-                                    .create_comment(
-                                        issue_nr,
-                                        format!("Error running job: {job_err}"),
-                                    )
-                                    .await
-                            }) {
-                                Ok(_) => {}
-                                Err(err) => log::warn!("Failed to comment on issue: {err}"),
-                            };
-                        };
-                    };
-                }
-                Err(e) => log::warn!("Failed to retrieve job from queue: {}", e),
+/// Clean up the repositories roots right away, rather than after the next job
+async fn collect_garbage(req: tide::Request<State>) -> tide::Result {
+    let janitors = req.state().janitors.clone();
+    let collected = async_std::task::spawn_blocking(move || {
+        let mut total = Collected::default();
+        for janitor in janitors {
+            let collected = janitor.collect()?;
+            total.size_before += collected.size_before;
+            total.size_after += collected.size_after;
+            total.removed.extend(collected.removed);
+        }
+        anyhow::Ok(total)
+    })
+    .await?;
+    tracing::info!(
+        "[{}] Collected {} bytes in the repositories root",
+        req.state().tenant,
+        collected.size_before - collected.size_after
+    );
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&collected)?)
+        .build())
+}
+
+/// A job to run, submitted through `POST /jobs`
+#[derive(Deserialize)]
+struct Submission {
+    /// `<owner>/<name>` or the Github URL of the repository
+    repository: String,
+    /// Branch or full ref (like `refs/tags/v1.0`) to run on, the default branch if not given
+    #[serde(default, rename = "ref")]
+    git_ref: Option<String>,
+    /// Path of the script relative to the root of the repository
+    script: String,
+    #[serde(default)]
+    args: Vec<String>,
+    /// Pool of workers to run on, see `--runner`
+    #[serde(default)]
+    runner: Option<String>,
+    /// OS of the workers to run on, see `--runner-os`
+    #[serde(default)]
+    runner_os: Option<String>,
+    /// Run even if there's a cached result, like `--force` in comments
+    #[serde(default)]
+    force: bool,
+}
+
+impl Submission {
+    fn owner_and_name(&self) -> Result<(&str, &str), Error> {
+        let repository = self.repository.trim_end_matches('/');
+        let repository = repository
+            .strip_prefix("https://github.com/")
+            .unwrap_or(repository);
+        let repository = repository.strip_suffix(".git").unwrap_or(repository);
+        match repository.split_once('/') {
+            Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/') => {
+                Ok((owner, name))
             }
+            _ => Err(Error::InvalidRepository(self.repository.clone())),
         }
-    });
+    }
+
+    fn command(&self) -> Result<Vec<String>, Error> {
+        let script = Path::new(&self.script);
+        let escapes = script
+            .components()
+            .any(|component| !matches!(component, std::path::Component::Normal(_)));
+        if self.script.is_empty() || escapes {
+            return Err(Error::InvalidScript(self.script.clone()));
+        }
+        let mut command = vec![self.script.clone()];
+        command.extend(self.args.iter().cloned());
+        Ok(command)
+    }
+}
+
+/// Where a job is at, served at `/jobs/<id>`
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum JobStatus {
+    /// Waiting in the queue, `position` 1 being next
+    Queued {
+        position: usize,
+        /// Unknown until jobs of the repository finished on this node
+        #[serde(default)]
+        estimated_start: Option<chrono::DateTime<chrono::Utc>>,
+    },
+    Running,
+    /// Only kept for jobs submitted through the API
+    Finished {
+        succeeded: bool,
+        error: Option<String>,
+        commit: Option<String>,
+        headline: Option<String>,
+        /// What would have been commented on an issue
+        report: Option<String>,
+        metrics: Vec<ci_script::results::Metric>,
+        /// What the script set with `OUTPUT.set`
+        #[serde(default)]
+        outputs: BTreeMap<String, serde_json::Value>,
+        /// What the processes of the job used
+        #[serde(default)]
+        usage: Option<ci_script::api::usage::Usage>,
+    },
+}
+
+/// Queue the job in the body, responding with its ID and where to find its result
+async fn submit_job(
+    mut req: tide::Request<State>,
+    intake: Intake,
+    jobs_url: Arc<String>,
+    tokio_handle: tokio::runtime::Handle,
+) -> tide::Result {
+    let bad_request = |err: &dyn std::fmt::Display| {
+        Ok(tide::Response::builder(400)
+            .content_type(tide::http::mime::PLAIN)
+            .body(err.to_string())
+            .build())
+    };
+    let submission: Submission = match req.body_json().await {
+        Ok(submission) => submission,
+        Err(err) => return bad_request(&err),
+    };
+    let ((owner, name), command) = match (submission.owner_and_name(), submission.command()) {
+        (Ok(repository), Ok(command)) => (repository, command),
+        (Err(err), _) | (_, Err(err)) => return bad_request(&err),
+    };
+    let user = req
+        .ext::<auth::Identity>()
+        .map(|identity| identity.0.clone())
+        .unwrap_or_default();
+    if !intake.user_rate.try_hit(&user) {
+        tracing::info!(
+            "[{}] Rejecting job of {user}: rate limit exceeded",
+            intake.tenant
+        );
+        return Ok(tide::Response::builder(429).build());
+    }
+
+    let github_client = intake.github_client.clone();
+    let (owner, name) = (owner.to_string(), name.to_string());
+    let found = tokio_handle
+        .spawn(async move { find_repository(&github_client, &owner, &name).await })
+        .await?;
+    let (repository, default_branch) = match found {
+        Ok(found) => found,
+        Err(err) => {
+            tracing::info!(
+                "[{}] Rejecting job of {user} on {}: {err}",
+                intake.tenant,
+                submission.repository
+            );
+            return Ok(tide::Response::builder(404)
+                .content_type(tide::http::mime::PLAIN)
+                .body(format!("Can't find {}: {err}", submission.repository))
+                .build());
+        }
+    };
+    let id = format!("{}_{}", repository.name, uuid::Uuid::new_v4().simple());
+    let job = Job {
+        id: id.clone(),
+        command,
+        user: Some(user.clone()).filter(|user| !user.is_empty()),
+        request: None,
+        repository,
+        issue: None,
+        retries: 0,
+        branch: Some(submission.git_ref.unwrap_or(default_branch)),
+        shard: None,
+        attempts: 0,
+        last_error: None,
+        forge: forge::Kind::Github,
+        handoff: None,
+        runner: submission.runner,
+        runner_os: submission.runner_os,
+        force: submission.force,
+        baseline: None,
+        label: None,
+        review_comment: None,
+    };
+    tracing::info!(
+        "[{}] {user} submitted job {id}: {}",
+        intake.tenant,
+        job.command.join(" ")
+    );
+    if let Err(reason) = intake.try_enqueue(job).await {
+        return Ok(tide::Response::builder(409)
+            .content_type(tide::http::mime::PLAIN)
+            .body(reason)
+            .build());
+    }
+    let results_url = format!("{jobs_url}/{id}");
+    Ok(tide::Response::builder(202)
+        .header("Location", results_url.as_str())
+        .body(json!({ "id": id, "results_url": results_url }))
+        .build())
+}
+
+async fn job_status(req: tide::Request<State>) -> tide::Result {
+    let id = req.param("id")?;
+    let State {
+        queue,
+        history,
+        journal,
+        workers,
+        ..
+    } = req.state();
+    let queue = queue.lock().await;
+    let status = match journal.result::<JobStatus>(id)? {
+        Some(result) => Some(result),
+        None => match queue.pos(id.to_string()) {
+            Some(pos) => {
+                let history = history.lock().map_err(|_| Error::ExclusiveLock)?;
+                let starts = history.starts(&queue.items()[..=pos], *workers);
+                Some(JobStatus::Queued {
+                    position: pos + 1,
+                    estimated_start: estimated_start(starts[pos].filter(|_| !queue.is_paused())),
+                })
+            }
+            None => {
+                let history = history.lock().map_err(|_| Error::ExclusiveLock)?;
+                history
+                    .running()
+                    .iter()
+                    .any(|record| record.id == id)
+                    .then_some(JobStatus::Running)
+            }
+        },
+    };
+    match status {
+        Some(status) => Ok(tide::Response::builder(200)
+            .body(tide::Body::from_json(&status)?)
+            .build()),
+        None => Ok(tide::Response::builder(404).build()),
+    }
+}
 
-    app.listen((config.address, config.port)).await?;
+fn prepare_command(command: Vec<String>) -> Result<Vec<String>, Error> {
+    // The first argument (.e.g `/bot` is also the name of the directory the script is in
+    let dir = command
+        .iter()
+        .next()
+        .map(|cmd| {
+            if let Some(cmd) = cmd.strip_prefix('/') {
+                String::from(cmd)
+            } else {
+                String::from(cmd)
+            }
+        })
+        .ok_or(Error::NoCmd)?;
+    // Scripts to run one after the other, like `/bot bench && report`
+    let mut res = vec![];
+    for step in command[1..].split(|arg| arg == STEP_SEPARATOR) {
+        let (script, args) = step.split_first().ok_or(Error::NoCmd)?;
+        let file = if script.ends_with(".rhai") {
+            script.clone()
+        } else {
+            format!("{}.rhai", script)
+        };
+        if !res.is_empty() {
+            res.push(STEP_SEPARATOR.to_string());
+        }
+        // Separated by `/` whatever the OS of the worker running it, which takes it too
+        res.push(format!(".github/{dir}/{file}"));
+        res.extend(args.iter().cloned());
+    }
+    Ok(res)
+}
+
+/// Client of the app's installation on the repository, scoped to it
+async fn installation_client(
+    github_client: &Octocrab,
+    repository: &Repository,
+) -> anyhow::Result<Octocrab> {
+    Ok(ci_script::installation::client(
+        github_client,
+        &repository.owner.login,
+        &repository.name,
+        &[repository.id],
+    )
+    .await?)
+}
+
+/// Access token of the app's installation on the repository, scoped to it
+async fn installation_token(
+    github_client: &Octocrab,
+    repository: &Repository,
+) -> anyhow::Result<String> {
+    Ok(ci_script::installation::token(
+        github_client,
+        &repository.owner.login,
+        &repository.name,
+        &[repository.id],
+    )
+    .await?)
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(repository = %repository.name, issue_nr))]
+async fn create_comment<B: AsRef<str>>(
+    github_client: &Octocrab,
+    repository: &Repository,
+    issue_nr: i64,
+    body: B,
+) -> anyhow::Result<octocrab::models::issues::Comment> {
+    let client = installation_client(github_client, repository).await?;
+    let comment = client
+        .issues(&repository.owner.login, &repository.name)
+        .create_comment(issue_nr.try_into()?, body)
+        .await?;
+    Ok(comment)
+}
+
+/// Reply `body` in the thread of the review comment `thread` on pull request `pr_nr`
+#[tracing::instrument(level = "debug", skip_all, fields(repository = %repository.name, pr_nr))]
+async fn create_review_reply<B: AsRef<str>>(
+    github_client: &Octocrab,
+    repository: &Repository,
+    pr_nr: i64,
+    thread: u64,
+    body: B,
+) -> anyhow::Result<()> {
+    let client = installation_client(github_client, repository).await?;
+    let route = format!(
+        "repos/{}/{}/pulls/{pr_nr}/comments/{thread}/replies",
+        repository.owner.login, repository.name
+    );
+    let _: serde_json::Value = client
+        .post(route, Some(&json!({ "body": body.as_ref() })))
+        .await?;
+    Ok(())
+}
+
+/// The repository `owner/name` and its default branch, as seen by the app's installation
+#[tracing::instrument(level = "debug", skip(github_client))]
+async fn find_repository(
+    github_client: &Octocrab,
+    owner: &str,
+    name: &str,
+) -> anyhow::Result<(Repository, String)> {
+    let client = ci_script::installation::client(github_client, owner, name, &[]).await?;
+    let repository: Repository = client.repos(owner, name).get().await?.try_into()?;
+    let default_branch = repository
+        .default_branch
+        .clone()
+        .unwrap_or_else(|| "master".to_string());
+    Ok((repository, default_branch))
+}
+
+/// Number of commits `head` is ahead of `base`
+#[tracing::instrument(level = "debug", skip(github_client))]
+async fn commits_between(
+    github_client: &Octocrab,
+    (repo_owner, repo_name): (&str, &str),
+    base: &str,
+    head: &str,
+) -> anyhow::Result<u64> {
+    #[derive(Deserialize)]
+    struct Comparison {
+        ahead_by: u64,
+    }
+
+    let route = format!("/repos/{repo_owner}/{repo_name}/compare/{base}...{head}");
+    let comparison: Comparison = github_client.get(route, None::<&()>).await?;
+    Ok(comparison.ahead_by)
+}
+
+/// Whether `user` has write access to the repository of `job`
+#[tracing::instrument(
+    level = "debug",
+    skip(github_client, repository),
+    fields(repository = %repository.name)
+)]
+async fn has_write_access(
+    github_client: &Octocrab,
+    repository: &Repository,
+    user: &str,
+) -> anyhow::Result<bool> {
+    #[derive(Deserialize)]
+    struct Permission {
+        permission: String,
+    }
+
+    let client = installation_client(github_client, repository).await?;
+    let route = format!(
+        "/repos/{}/{}/collaborators/{user}/permission",
+        repository.owner.login, repository.name
+    );
+    let permission: Permission = client.get(route, None::<&()>).await?;
+    // The `maintain` role is reported as `write` here
+    Ok(matches!(permission.permission.as_str(), "admin" | "write"))
+}
+
+/// Take `--<name> <value>` (or `--<name>=<value>`) out of the arguments of `command`, returning
+/// the last value
+fn take_arg(command: Vec<String>, name: &str) -> (Vec<String>, Option<String>) {
+    let flag = format!("--{name}");
+    let mut rest = vec![];
+    let mut value = None;
+    let mut args = command.into_iter();
+    while let Some(arg) = args.next() {
+        value = match arg.strip_prefix(&flag) {
+            Some("") => Some(args.next().unwrap_or_default()),
+            Some(value) if value.starts_with('=') => Some(value[1..].to_string()),
+            _ => {
+                rest.push(arg);
+                continue;
+            }
+        };
+    }
+    (rest, value)
+}
+
+/// Take the flag `--<name>` out of the arguments of `command`, returning whether it was given
+fn take_flag(command: Vec<String>, name: &str) -> (Vec<String>, bool) {
+    let flag = format!("--{name}");
+    let given = command.contains(&flag);
+    let rest = command.into_iter().filter(|arg| *arg != flag).collect();
+    (rest, given)
+}
+
+/// Take `--shards <n>` (or `--shards=<n>`) out of the arguments of `command`, returning the number
+/// of shards to split the job into
+fn take_shards(
+    command: Vec<String>,
+    max_shards: usize,
+) -> Result<(Vec<String>, Option<usize>), Error> {
+    let (rest, count) = take_arg(command, "shards");
+    let shards = match count {
+        Some(count) => match count.parse() {
+            Ok(count) if count >= 1 && count <= max_shards => Some(count),
+            _ => return Err(Error::InvalidShards(count)),
+        },
+        None => None,
+    };
+    Ok((rest, shards))
+}
+
+/// A user's commands on an issue: `(repository ID, issue number, user)`
+type Conversation = (u64, i64, String);
+
+/// Replace the arguments of `previous` given in `overrides` (`--name=value` or `--name value`
+/// replace earlier values of `--name`) and append the others
+fn merge_args(previous: &[String], overrides: &[String]) -> Vec<String> {
+    fn name(arg: &str) -> Option<&str> {
+        arg.strip_prefix("--")
+            .map(|arg| arg.split_once('=').map_or(arg, |(name, _)| name))
+    }
+
+    let overridden = overrides
+        .iter()
+        .filter_map(|arg| name(arg))
+        .collect::<Vec<_>>();
+    let mut merged = vec![];
+    let mut args = previous.iter().peekable();
+    while let Some(arg) = args.next() {
+        match name(arg) {
+            Some(name) if overridden.contains(&name) => {
+                // Skip the separate value of `--name value`
+                if !arg.contains('=') && args.peek().is_some_and(|next| !next.starts_with("--")) {
+                    args.next();
+                }
+            }
+            _ => merged.push(arg.clone()),
+        }
+    }
+    merged.extend(overrides.iter().cloned());
+    merged
+}
+
+/// Settings of a tenant that can change while running, see [`Reloader`]
+#[derive(Clone, Debug)]
+struct Settings {
+    command_prefix: String,
+    http_allowlist: Vec<String>,
+    pr_policy: ci_script::api::git::PrPolicy,
+    max_queued_per_issue: Option<usize>,
+    max_queued_per_repo: Option<usize>,
+    admins: Vec<String>,
+}
+
+impl Settings {
+    fn new(config: &Config, tenant: &Tenant) -> Self {
+        Settings {
+            command_prefix: tenant.command_prefix.clone(),
+            http_allowlist: config.http_allowlist.clone(),
+            pr_policy: ci_script::api::git::PrPolicy {
+                bases: config.pr_base.clone(),
+                branch_template: config.pr_branch_template.clone(),
+                stale_after: config
+                    .pr_stale_branch_days
+                    .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            },
+            max_queued_per_issue: config.max_queued_per_issue,
+            max_queued_per_repo: config.max_queued_per_repo,
+            admins: config.admins.clone(),
+        }
+    }
+}
+
+/// The current settings of a tenant, shared by its intake and workers
+#[derive(Clone)]
+struct LiveSettings(Arc<std::sync::RwLock<Settings>>);
+
+impl LiveSettings {
+    fn new(settings: Settings) -> Self {
+        LiveSettings(Arc::new(std::sync::RwLock::new(settings)))
+    }
+
+    fn get(&self) -> Settings {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn set(&self, settings: Settings) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = settings;
+    }
+}
+
+/// Applies changes to the command line, environment or `--config` file to the [`Settings`] and
+/// user rate limit of every tenant and to the log level, on `POST /admin/reload` or `SIGHUP`.
+/// Everything else is only read when starting, and the queues are left as they are.
+#[derive(Clone)]
+struct Reloader {
+    tenants: Arc<std::sync::Mutex<Vec<(String, LiveSettings, RateLimiter)>>>,
+    level: LevelHandle,
+}
+
+impl Reloader {
+    fn new(level: LevelHandle) -> Self {
+        Reloader {
+            tenants: Default::default(),
+            level,
+        }
+    }
+
+    fn add(&self, tenant: &str, settings: LiveSettings, user_rate: RateLimiter) {
+        let mut tenants = self.tenants.lock().unwrap_or_else(|e| e.into_inner());
+        tenants.push((tenant.to_string(), settings, user_rate));
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        let config = Config::from_iter_safe(ci_script::config::args::<Config>()?)?;
+        let configured = load_tenants(&config)?;
+        self.level.set(config.log_level)?;
+        let tenants = self.tenants.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, settings, user_rate) in tenants.iter() {
+            match configured.iter().find(|tenant| &tenant.name == name) {
+                Some(tenant) => {
+                    let reloaded = Settings::new(&config, tenant);
+                    tracing::info!("[{name}] Reloaded settings: {reloaded:?}");
+                    settings.set(reloaded);
+                    user_rate.set_rate(config.user_rate_limit);
+                }
+                None => tracing::warn!(
+                    "[{name}] Not configured anymore, keeping it as it is until restarted"
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Jobs of contributors without write access that wait for a maintainer's approval, by approval
+/// ID. They're kept in the journal next to the queue, so they can still be approved after a
+/// restart or a handover.
+struct Pending {
+    jobs: HashMap<String, Job>,
+    journal: Journal,
+}
+
+impl Pending {
+    fn restore(journal: Journal) -> Result<Self, ci_script::journal::Error> {
+        Ok(Pending {
+            jobs: journal.held()?.into_iter().collect(),
+            journal,
+        })
+    }
+
+    fn get(&self, approval_id: &str) -> Option<&Job> {
+        self.jobs.get(approval_id)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&String, &Job)> {
+        self.jobs.iter()
+    }
+
+    fn insert(&mut self, approval_id: String, job: Job) {
+        if let Err(err) = self.journal.hold(&approval_id, &job) {
+            tracing::warn!("Failed to journal job {} held for approval: {err}", job.id);
+        }
+        self.jobs.insert(approval_id, job);
+    }
+
+    fn remove(&mut self, approval_id: &str) -> Option<Job> {
+        if let Err(err) = self.journal.release(approval_id) {
+            tracing::warn!("Failed to journal the release of {approval_id}: {err}");
+        }
+        self.jobs.remove(approval_id)
+    }
+
+    /// Keep only the jobs `keep` returns true for
+    fn retain(&mut self, mut keep: impl FnMut(&Job) -> bool) {
+        let released: Vec<String> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| !keep(job))
+            .map(|(approval_id, _)| approval_id.clone())
+            .collect();
+        for approval_id in released {
+            self.remove(&approval_id);
+        }
+    }
+}
+
+/// Turns bot commands into queued jobs for a tenant
+#[derive(Clone)]
+struct Intake {
+    tenant: String,
+    queue: Arc<Mutex<PersistentQueue<Job>>>,
+    pending: Arc<Mutex<Pending>>,
+    /// The last command each user ran on an issue, to repeat with `again` and `with`
+    conversations: Arc<std::sync::Mutex<HashMap<Conversation, Vec<String>>>>,
+    github_client: Octocrab,
+    settings: LiveSettings,
+    supersede: bool,
+    require_approval: bool,
+    /// Commands per user
+    user_rate: RateLimiter,
+    max_shards: usize,
+    results_store: Option<ci_script::results::Store>,
+    /// To comment on and check the permissions of GitLab projects, if enabled
+    gitlab: Option<Gitlab>,
+    /// To tell when queued jobs start
+    history: SharedHistory,
+    workers: usize,
+    /// Pools of workers to run the jobs of repositories on by `owner/name`, see `--repo-runner`
+    repo_runners: Arc<HashMap<String, String>>,
+    /// Commands labels of pull requests queue, by the `owner/name` of their repository, see
+    /// `--repo-label-trigger`
+    label_triggers: Arc<HashMap<String, Vec<LabelTrigger>>>,
+}
+
+impl Intake {
+    /// Queue the job, unless it's already queued or too many jobs are queued for its issue or
+    /// repository, commenting why not
+    async fn enqueue(&self, job: Job) {
+        if let Err(body) = self.try_enqueue(job.clone()).await {
+            self.comment(&job, body).await;
+        }
+    }
+
+    /// Queue the job, or tell why it wasn't
+    #[tracing::instrument(name = "enqueue", skip_all, fields(tenant = %self.tenant, job = %job.id))]
+    async fn try_enqueue(&self, mut job: Job) -> Result<(), String> {
+        if job.runner.is_none() {
+            job.runner = self.repo_runners.get(&repository_of(&job)).cloned();
+        }
+        let mut queue = self.queue.lock().await;
+        let id = job.id.clone();
+        let key = job.dedup_key();
+        // Jobs it would supersede don't count
+        let queued: Vec<&Job> = queue
+            .items()
+            .into_iter()
+            .filter(|queued| queued.repository.id == job.repository.id)
+            .filter(|queued| !self.supersede || queued.dedup_key() != key)
+            .collect();
+        let queued_on_issue = queued
+            .iter()
+            .filter(|queued| {
+                let number = |job: &Job| job.issue.as_ref().map(|issue| issue.number);
+                queued.branch.is_none() && number(queued) == number(&job)
+            })
+            .count();
+        let settings = self.settings.get();
+        let limit = match (settings.max_queued_per_issue, settings.max_queued_per_repo) {
+            (Some(max), _) if queued_on_issue >= max => Some(format!(
+                "There are already {queued_on_issue} jobs queued for this issue, please wait for \
+                 them to finish before queuing more."
+            )),
+            (_, Some(max)) if queued.len() >= max => Some(format!(
+                "There are already {} jobs queued for this repository, please try again once some \
+                 of them finished.",
+                queued.len()
+            )),
+            _ => None,
+        };
+        if let Some(body) = limit {
+            tracing::info!("[{}] Rejecting job {id}: too many jobs queued", self.tenant);
+            return Err(body);
+        } else if self.supersede {
+            for job in job.split() {
+                let (id, key) = (job.id.clone(), job.dedup_key());
+                if let Some(superseded) = queue.supersede(id, key, job) {
+                    tracing::info!("[{}] Superseded queued job {}", self.tenant, superseded.id);
+                }
+            }
+        } else if let Some(pos) = queue.pos_by_key(&key) {
+            tracing::info!(
+                "[{}] Rejecting job {id}: the same command is already queued",
+                self.tenant
+            );
+            return Err(format!(
+                "This command is already queued (position {}), ignoring it.",
+                pos + 1
+            ));
+        } else {
+            for job in job.split() {
+                queue.add(job.id.clone(), job.dedup_key(), job);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the command in the comment `body` of `user` on the issue, or in the review comment
+    /// `review_comment` on it
+    async fn command(
+        &self,
+        body: &str,
+        repo: Repository,
+        issue: Issue,
+        user: String,
+        forge: forge::Kind,
+        review_comment: Option<u64>,
+    ) {
+        let tenant_name = &self.tenant;
+        let command = body
+            .split_once('\n')
+            .map(|(cmd, _)| cmd.into())
+            .map(|cmd| shell_words::split(cmd).expect("Failed to split command as shell words"))
+            .unwrap_or_else(|| body.split(" ").map(|x| x.to_string()).collect());
+
+        match command.as_slice() {
+            [_, subcommand, approval_id] if subcommand == "approve" => {
+                return self.approve(approval_id, &issue, &repo, &user).await;
+            }
+            [_, subcommand, a, b] if subcommand == "compare-runs" => {
+                return self.compare_runs(a, b, &repo, issue.number, forge).await;
+            }
+            [_, subcommand, action] if subcommand == "baseline" && action == "list" => {
+                return self.list_baselines(&repo, issue.number, forge).await;
+            }
+            [_, subcommand, action, name, commit @ ..]
+                if subcommand == "baseline" && action == "save" && commit.len() <= 1 =>
+            {
+                let commit = commit.first().map(String::as_str);
+                return self
+                    .save_baseline(name, commit, &repo, &issue, &user, forge)
+                    .await;
+            }
+            [_, subcommand] if subcommand == "status" => {
+                return self.status(&repo, issue.number, forge).await;
+            }
+            [_, subcommand, action] if subcommand == "admin" => {
+                return self.admin(action, &repo, issue.number, &user, forge).await;
+            }
+            _ => {}
+        }
+
+        let conversation = (*repo.id.as_ref(), issue.number, user.clone());
+        let follow_up = match command.get(1).map(String::as_str) {
+            Some("again") => Some(None),
+            Some("with") => Some(Some(&command[2..])),
+            _ => None,
+        };
+        let command = match follow_up {
+            Some(args) => match self.follow_up(&conversation, args) {
+                Some(command) => command,
+                None => {
+                    tracing::info!("[{tenant_name}] No previous command of {user} to repeat");
+                    let body = format!(
+                        "@{user} You haven't run a command on this issue yet, so there's nothing \
+                         to repeat."
+                    );
+                    return self.comment_on(forge, &repo, issue.number, body).await;
+                }
+            },
+            None => match prepare_command(command) {
+                Ok(command) => command,
+                Err(e) => {
+                    tracing::warn!("[{tenant_name}] Failed to determine command: {e}");
+                    return;
+                }
+            },
+        };
+        self.remember(conversation, &command);
+        let job = match self.job(command, &repo, &issue, forge) {
+            Ok(job) => Job {
+                user: Some(user.clone()),
+                request: body.lines().next().map(|line| line.trim().to_string()),
+                review_comment,
+                ..job
+            },
+            Err(err) => {
+                tracing::info!("[{tenant_name}] Rejecting command of {user}: {err}");
+                let body = format!(
+                    "@{user} {err}, expected a number up to {}.",
+                    self.max_shards
+                );
+                return self.comment_on(forge, &repo, issue.number, body).await;
+            }
+        };
+        self.submit(job, user).await
+    }
+
+    /// The job running the prepared `command` on the issue, with the flags meant for the bot
+    /// taken out of it
+    fn job(
+        &self,
+        command: Vec<String>,
+        repo: &Repository,
+        issue: &Issue,
+        forge: forge::Kind,
+    ) -> Result<Job, Error> {
+        let (command, runner) = take_arg(command, "runner");
+        let (command, runner_os) = take_arg(command, "runner-os");
+        let (command, force) = take_flag(command, "force");
+        let (command, baseline) = take_arg(command, "baseline");
+        let baseline = baseline.map(|name| match name.strip_prefix("baseline:") {
+            Some(name) => name.to_string(),
+            None => name,
+        });
+        let (command, shards) = take_shards(command, self.max_shards)?;
+
+        let id = format!(
+            "{}_{}_{}",
+            repo.name,
+            command.join(" "),
+            uuid::Uuid::new_v4(),
+        );
+
+        Ok(Job {
+            id: id.clone(),
+            command,
+            user: None,
+            request: None,
+            repository: repo.clone(),
+            issue: Some(issue.clone()),
+            retries: 0,
+            branch: None,
+            shard: shards.map(|count| Shard {
+                group: id.clone(),
+                index: 0,
+                count,
+            }),
+            attempts: 0,
+            last_error: None,
+            forge,
+            handoff: None,
+            runner,
+            runner_os,
+            force,
+            baseline,
+            label: None,
+            review_comment: None,
+        })
+    }
+
+    /// Queue the job requested by `user`, or hold it until a maintainer approves it if `user`
+    /// doesn't have write access to the repository
+    async fn submit(&self, job: Job, user: String) {
+        if !self.user_rate.try_hit(&user) {
+            tracing::info!(
+                "[{}] Rejecting job {} of {user}: rate limit exceeded",
+                self.tenant,
+                job.id
+            );
+            let rate = self.user_rate.rate().map(|rate| rate.to_string());
+            let body = format!(
+                "@{user} You're running commands faster than allowed ({}), please wait a bit \
+                 before running more.",
+                rate.unwrap_or_default()
+            );
+            self.comment(&job, body).await;
+            return;
+        }
+        if self.require_approval {
+            let trusted = match self
+                .has_write_access(&job.repository, job.forge, &user)
+                .await
+            {
+                Ok(trusted) => trusted,
+                Err(err) => {
+                    tracing::warn!(
+                        "[{}] Failed to check permissions of {user}: {err}",
+                        self.tenant
+                    );
+                    false
+                }
+            };
+            if !trusted {
+                let approval_id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+                tracing::info!(
+                    "[{}] Holding job {} of {user} for approval ({approval_id})",
+                    self.tenant,
+                    job.id
+                );
+                let body = format!(
+                    "@{user} isn't a maintainer of this repository, so this command needs to be \
+                     approved first. A maintainer can run it by replying `{} approve {approval_id}`.",
+                    self.settings.get().command_prefix
+                );
+                self.pending.lock().await.insert(approval_id, job.clone());
+                self.comment(&job, body).await;
+                return;
+            }
+        }
+        self.enqueue(job).await
+    }
+
+    /// Queue the job held under `approval_id` if `user` is allowed to approve it
+    async fn approve(&self, approval_id: &str, issue: &Issue, repository: &Repository, user: &str) {
+        let job = {
+            let pending = self.pending.lock().await;
+            match pending.get(approval_id) {
+                Some(job)
+                    if job.repository.id == repository.id
+                        && job.issue.as_ref().map(|issue| issue.number) == Some(issue.number) =>
+                {
+                    job.clone()
+                }
+                _ => {
+                    tracing::info!("[{}] Unknown approval ID {approval_id}", self.tenant);
+                    return;
+                }
+            }
+        };
+        match self
+            .has_write_access(&job.repository, job.forge, user)
+            .await
+        {
+            Ok(true) => {
+                tracing::info!("[{}] {user} approved job {}", self.tenant, job.id);
+                self.pending.lock().await.remove(approval_id);
+                self.enqueue(job).await
+            }
+            Ok(false) => {
+                let body = format!("@{user} Only maintainers can approve commands.");
+                self.comment(&job, body).await;
+            }
+            Err(err) => tracing::warn!(
+                "[{}] Failed to check permissions of {user}: {err}",
+                self.tenant
+            ),
+        }
+    }
+
+    /// Queue the commands `label` triggers on the repository, now that `user` added it to pull
+    /// request `number`
+    async fn labeled(&self, repo: Repository, number: u64, label: &str, user: String) {
+        let tenant = &self.tenant;
+        let full_name = format!("{}/{}", repo.owner.login, repo.name);
+        let triggers: Vec<&LabelTrigger> = self
+            .label_triggers
+            .get(&full_name)
+            .into_iter()
+            .flatten()
+            .filter(|trigger| trigger.label == label)
+            .collect();
+        if triggers.is_empty() {
+            return;
+        }
+        let issue = match self.fetch_issue(&repo, number).await {
+            Ok(issue) => issue,
+            Err(err) => {
+                tracing::warn!("[{tenant}] Failed to fetch pull request #{number}: {err}");
+                return;
+            }
+        };
+        let prefix = self.settings.get().command_prefix;
+        for trigger in triggers {
+            let command: Vec<String> = std::iter::once(prefix.clone())
+                .chain(trigger.command.iter().cloned())
+                .collect();
+            let request = shell_words::join(&command);
+            let job = prepare_command(command)
+                .and_then(|command| self.job(command, &repo, &issue, forge::Kind::Github));
+            match job {
+                Ok(mut job) => {
+                    tracing::info!(
+                        "[{tenant}] {user} labeled #{number} with {label}, queuing {}",
+                        job.command.join(" ")
+                    );
+                    job.label = Some(label.to_string());
+                    job.user = Some(user.clone());
+                    job.request = Some(request);
+                    self.submit(job, user.clone()).await
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "[{tenant}] Invalid command of label {label} of {full_name}: {err}"
+                    )
+                }
+            }
+        }
+    }
+
+    /// Run the command in the review comment `body` of `user` on pull request `number`, replying
+    /// in the thread of the review comment `thread`
+    async fn review_command(
+        &self,
+        body: &str,
+        repo: Repository,
+        number: u64,
+        user: String,
+        thread: u64,
+    ) {
+        let issue = match self.fetch_issue(&repo, number).await {
+            Ok(issue) => issue,
+            Err(err) => {
+                tracing::warn!(
+                    "[{}] Failed to fetch pull request #{number}: {err}",
+                    self.tenant
+                );
+                return;
+            }
+        };
+        self.command(body, repo, issue, user, forge::Kind::Github, Some(thread))
+            .await
+    }
+
+    /// Issue (or pull request) `number` of `repo` on Github
+    async fn fetch_issue(&self, repo: &Repository, number: u64) -> anyhow::Result<Issue> {
+        let client = installation_client(&self.github_client, repo).await?;
+        Ok(client
+            .issues(&repo.owner.login, &repo.name)
+            .get(number)
+            .await?)
+    }
+
+    /// Cancel the jobs `label` queued for pull request `number` that haven't started yet, now
+    /// that it was removed
+    async fn unlabeled(&self, repo: Repository, number: u64, label: &str) {
+        let of_label = |job: &Job| {
+            job.repository.id == repo.id
+                && job.label.as_deref() == Some(label)
+                && job.issue.as_ref().map(|issue| issue.number) == Some(number as i64)
+        };
+        let mut cancelled = vec![];
+        let mut queue = self.queue.lock().await;
+        let ids: Vec<String> = queue
+            .items()
+            .into_iter()
+            .filter(|job| of_label(job))
+            .map(|job| job.id.clone())
+            .collect();
+        for id in ids {
+            cancelled.extend(queue.cancel(&id));
+        }
+        drop(queue);
+        // Still waiting for a maintainer's approval
+        self.pending.lock().await.retain(|job| {
+            if of_label(job) {
+                cancelled.push(job.clone());
+            }
+            !of_label(job)
+        });
+        if cancelled.is_empty() {
+            return;
+        }
+        let commands: Vec<String> = cancelled
+            .iter()
+            .map(|job| format!("`{}`", job.command.join(" ")))
+            .collect();
+        tracing::info!(
+            "[{}] Label {label} was removed from #{number}, cancelled {}",
+            self.tenant,
+            commands.join(", ")
+        );
+        let body = format!(
+            "The label `{label}` was removed, cancelled the queued {}.",
+            commands.join(", ")
+        );
+        self.comment_on(forge::Kind::Github, &repo, number as i64, body)
+            .await
+    }
+
+    /// Post the comparison of runs `a` and `b` of the repository on the issue
+    async fn compare_runs(
+        &self,
+        a: &str,
+        b: &str,
+        repository: &Repository,
+        issue_nr: i64,
+        forge: forge::Kind,
+    ) {
+        let repo = format!("{}/{}", repository.owner.login, repository.name);
+        let body = match compare_runs(self.results_store.as_ref(), Some(&repo), a, b) {
+            // Don't leak the results of other repositories
+            Ok((a, b, _)) if a.repo != repo || b.repo != repo => {
+                "Both runs need to be of this repository.".to_string()
+            }
+            Ok((_, _, report)) => report,
+            Err(err) => format!("Failed to compare runs: {err}"),
+        };
+        self.comment_on(forge, repository, issue_nr, body).await
+    }
+
+    /// Save the results recorded on `commit` (the head of the pull request if not given) as the
+    /// baseline `name` of the repository, if `user` may write to it
+    async fn save_baseline(
+        &self,
+        name: &str,
+        commit: Option<&str>,
+        repository: &Repository,
+        issue: &Issue,
+        user: &str,
+        forge: forge::Kind,
+    ) {
+        let repo = format!("{}/{}", repository.owner.login, repository.name);
+        let saved = async {
+            let store = self.results_store.as_ref().ok_or(Error::NoResultsStore)?;
+            if !self.has_write_access(repository, forge, user).await? {
+                return Ok(format!("@{user} Only maintainers can save baselines."));
+            }
+            let commit = match commit {
+                Some(commit) => commit.to_string(),
+                None if issue.pull_request.is_some() && forge == forge::Kind::Github => {
+                    let client = installation_client(&self.github_client, repository).await?;
+                    let pr = client
+                        .pulls(&repository.owner.login, &repository.name)
+                        .get(issue.number as u64)
+                        .await?;
+                    pr.head.sha
+                }
+                None => {
+                    return Ok(format!(
+                        "@{user} Which commit's results should be saved? Add it to the command."
+                    ))
+                }
+            };
+            anyhow::Ok(match store.save_baseline(&repo, name, &commit)? {
+                Some(baseline) => {
+                    tracing::info!(
+                        "[{}] {user} saved run {} as the baseline {name} of {repo}",
+                        self.tenant,
+                        baseline.run_id
+                    );
+                    format!(
+                        "Saved the results of `{}` on {commit} as the baseline `{name}`. Add \
+                         `--baseline {name}` to a command to compare its results against them.",
+                        baseline.run_id
+                    )
+                }
+                None => format!("There are no results of {commit} to save."),
+            })
+        };
+        let body = match saved.await {
+            Ok(body) => body,
+            Err(err) => format!("Failed to save the baseline: {err}"),
+        };
+        self.comment_on(forge, repository, issue.number, body).await
+    }
+
+    /// Post the baselines saved for the repository on the issue
+    async fn list_baselines(&self, repository: &Repository, issue_nr: i64, forge: forge::Kind) {
+        let repo = format!("{}/{}", repository.owner.login, repository.name);
+        let baselines = self
+            .results_store
+            .as_ref()
+            .ok_or(Error::NoResultsStore)
+            .map_err(anyhow::Error::from)
+            .and_then(|store| Ok(store.baselines(&repo)?));
+        let body = match baselines {
+            Ok(baselines) if baselines.is_empty() => "No baselines were saved yet.".to_string(),
+            Ok(baselines) => baselines
+                .iter()
+                .map(|baseline| {
+                    let commit = baseline.commit.as_deref().unwrap_or_default();
+                    format!(
+                        "- `{}`: `{}` on {}",
+                        baseline.name,
+                        baseline.run_id,
+                        &commit[..commit.len().min(8)]
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Err(err) => format!("Failed to list the baselines: {err}"),
+        };
+        self.comment_on(forge, repository, issue_nr, body).await
+    }
+
+    /// Pause or resume the queue if `user` is one of the admins
+    async fn admin(
+        &self,
+        action: &str,
+        repository: &Repository,
+        issue_nr: i64,
+        user: &str,
+        forge: forge::Kind,
+    ) {
+        let tenant = &self.tenant;
+        let body = if !self.settings.get().admins.iter().any(|admin| admin == user) {
+            tracing::info!("[{tenant}] Ignoring admin command of {user}, who isn't an admin");
+            format!("@{user} Only admins of the bot can do that.")
+        } else {
+            let mut queue = self.queue.lock().await;
+            let result = match action {
+                "pause" => queue.pause(),
+                "resume" => queue.resume(),
+                _ => {
+                    drop(queue);
+                    let body = format!(
+                        "@{user} Unknown admin command `{action}`, expected `pause` or `resume`."
+                    );
+                    return self.comment_on(forge, repository, issue_nr, body).await;
+                }
+            };
+            match result {
+                Ok(()) => {
+                    tracing::info!("[{tenant}] {user} ran admin command {action}");
+                    let queued = queue.len();
+                    if queue.is_paused() {
+                        format!(
+                            "Paused the queue, {queued} jobs are queued. New jobs are still \
+                             queued, and start once it's resumed with `{} admin resume`.",
+                            self.settings.get().command_prefix
+                        )
+                    } else {
+                        format!("Resumed the queue, {queued} jobs are queued.")
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!("[{tenant}] Failed to {action} the queue: {err}");
+                    format!("Failed to {action} the queue: {err}")
+                }
+            }
+        };
+        self.comment_on(forge, repository, issue_nr, body).await
+    }
+
+    /// Post which jobs of the repository are running, and where the queued ones are and when
+    /// they're expected to start
+    async fn status(&self, repository: &Repository, issue_nr: i64, forge: forge::Kind) {
+        let queue = self.queue.lock().await;
+        let queued = queue.items();
+        let (running, starts) = match self.history.lock() {
+            Ok(history) => (
+                history.running().to_vec(),
+                history.starts(&queued, self.workers),
+            ),
+            Err(_) => (vec![], vec![None; queued.len()]),
+        };
+        let repo = format!("{}/{}", repository.owner.login, repository.name);
+        let mut lines: Vec<String> = running
+            .iter()
+            .filter(|record| record.repository == repo)
+            .map(|record| format!("- Running: `{}`", record.command))
+            .collect();
+        lines.extend(
+            queued
+                .iter()
+                .zip(starts)
+                .enumerate()
+                .filter(|(_, (job, _))| job.repository.id == repository.id)
+                .map(|(pos, (job, start))| {
+                    let on = match (&job.issue, &job.branch) {
+                        (_, Some(branch)) => format!(" on {branch}"),
+                        (Some(issue), None) => format!(" on #{}", issue.number),
+                        (None, None) => String::new(),
+                    };
+                    let start = match start {
+                        _ if queue.is_paused() => "once the queue is resumed".to_string(),
+                        Some(start) if start.is_zero() => "next".to_string(),
+                        Some(start) => format!(
+                            "in about {}",
+                            ci_script::api::phases::format_duration(start)
+                        ),
+                        None => "once it's known how long the jobs ahead take".to_string(),
+                    };
+                    format!(
+                        "- Queued at position {}: `{}`{on}, starting {start}",
+                        pos + 1,
+                        job.command.join(" ")
+                    )
+                }),
+        );
+        if queue.is_paused() {
+            lines.insert(
+                0,
+                "The queue is paused, no jobs start until it's resumed.".to_string(),
+            );
+        }
+        drop(queue);
+        let body = if lines.is_empty() {
+            "No jobs of this repository are running or queued.".to_string()
+        } else {
+            lines.join("\n")
+        };
+        self.comment_on(forge, repository, issue_nr, body).await
+    }
+
+    /// Remember `command` as the last one `user` ran on the issue
+    fn remember(&self, conversation: Conversation, command: &[String]) {
+        if let Ok(mut conversations) = self.conversations.lock() {
+            conversations.insert(conversation, command.to_vec());
+        }
+    }
+
+    /// The command to run for `again` (no arguments) or `with <arguments>`, based on the last
+    /// command of the conversation
+    fn follow_up(
+        &self,
+        conversation: &Conversation,
+        args: Option<&[String]>,
+    ) -> Option<Vec<String>> {
+        let conversations = self.conversations.lock().ok()?;
+        let previous = conversations.get(conversation)?;
+        Some(match args {
+            None => previous.clone(),
+            Some(args) => {
+                let (script, previous_args) = previous.split_first()?;
+                let mut command = vec![script.clone()];
+                command.extend(merge_args(previous_args, args));
+                command
+            }
+        })
+    }
+
+    /// Whether `user` has write access to `repository`, on the forge it's on
+    async fn has_write_access(
+        &self,
+        repository: &Repository,
+        forge: forge::Kind,
+        user: &str,
+    ) -> anyhow::Result<bool> {
+        match forge {
+            forge::Kind::Github => has_write_access(&self.github_client, repository, user).await,
+            forge::Kind::Gitlab => {
+                let gitlab = self.gitlab.as_ref().ok_or(Error::NoGitlab)?;
+                Ok(gitlab.project(repository).has_write_access(user).await?)
+            }
+        }
+    }
+
+    /// Comment on the issue of `job`, if it was requested on one, in the thread of the review
+    /// comment it was requested in if it was
+    async fn comment(&self, job: &Job, body: String) {
+        let issue = match &job.issue {
+            Some(issue) => issue,
+            None => return,
+        };
+        match (job.forge, job.review_comment) {
+            (forge::Kind::Github, Some(thread)) => {
+                let reply = create_review_reply(
+                    &self.github_client,
+                    &job.repository,
+                    issue.number,
+                    thread,
+                    body,
+                );
+                if let Err(err) = reply.await {
+                    tracing::warn!("[{}] Failed to reply to review comment: {err}", self.tenant);
+                }
+            }
+            _ => {
+                self.comment_on(job.forge, &job.repository, issue.number, body)
+                    .await
+            }
+        }
+    }
+
+    async fn comment_on(
+        &self,
+        forge: forge::Kind,
+        repository: &Repository,
+        issue_nr: i64,
+        body: String,
+    ) {
+        let result = match (forge, &self.gitlab) {
+            (forge::Kind::Github, _) => {
+                create_comment(&self.github_client, repository, issue_nr, body)
+                    .await
+                    .map(drop)
+            }
+            (forge::Kind::Gitlab, Some(gitlab)) => {
+                async {
+                    gitlab
+                        .project(repository)
+                        .comment(issue_nr.try_into()?, &body)
+                        .await?;
+                    anyhow::Ok(())
+                }
+                .await
+            }
+            (forge::Kind::Gitlab, None) => Err(Error::NoGitlab.into()),
+        };
+        if let Err(err) = result {
+            tracing::warn!("[{}] Failed to comment on issue: {err}", self.tenant);
+        }
+    }
+}
+
+/// Build the webhook endpoint that queues the commands addressed to `tenant`
+fn webhook(
+    tenant: &Tenant,
+    intake: Intake,
+    tokio_handle: tokio::runtime::Handle,
+) -> tide::Server<()> {
+    let tenant_name = tenant.name.clone();
+    tide_github::new(&tenant.webhook_secret)
+        .on(Event::IssueComment, move |payload| {
+            let payload: tide_github::payload::IssueCommentPayload = match payload.try_into() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    tracing::warn!("[{tenant_name}] Failed to parse payload: {}", e);
+                    return;
+                }
+            };
+
+            if let Some(body) = payload.comment.body {
+                if body.starts_with(&intake.settings.get().command_prefix) {
+                    let span = tracing::info_span!(
+                        "webhook",
+                        tenant = %tenant_name,
+                        event = "issue_comment",
+                        repository = %payload.repository.name,
+                        issue = payload.issue.number,
+                    );
+                    let _entered = span.enter();
+                    let repo: Repository = match payload.repository.try_into() {
+                        Ok(repo) => repo,
+                        Err(err) => {
+                            tracing::warn!(
+                                "[{tenant_name}] Failed to parse repository payload: {}",
+                                err
+                            );
+                            return;
+                        }
+                    };
+                    let intake = intake.clone();
+                    let user = payload.comment.user.login;
+                    let issue = payload.issue;
+                    tokio_handle.spawn(
+                        async move {
+                            intake
+                                .command(&body, repo, issue, user, forge::Kind::Github, None)
+                                .await
+                        }
+                        .instrument(span.clone()),
+                    );
+                }
+            }
+        })
+        .build()
+}
+
+/// Queue the commands in comments on GitLab merge requests, delivered by the webhook of their
+/// project
+async fn gitlab_webhook(
+    mut req: tide::Request<State>,
+    intake: Intake,
+    secret: Arc<String>,
+    tokio_handle: tokio::runtime::Handle,
+) -> tide::Result {
+    let token = req
+        .header("X-Gitlab-Token")
+        .map(|token| token.as_str().to_string())
+        .unwrap_or_default();
+    if !auth::constant_time_eq(token.as_bytes(), secret.as_bytes()) {
+        return Ok(tide::Response::new(401));
+    }
+    if req.header("X-Gitlab-Event").map(|event| event.as_str()) != Some("Note Hook") {
+        return Ok(tide::Response::new(200));
+    }
+    let event: forge::NoteEvent = req.body_json().await?;
+    if !event
+        .object_attributes
+        .note
+        .starts_with(&intake.settings.get().command_prefix)
+    {
+        return Ok(tide::Response::new(200));
+    }
+    match event.merge_request() {
+        Ok(Some((repo, issue))) => {
+            let span = tracing::info_span!(
+                "webhook",
+                event = "note",
+                repository = %repo.name,
+                issue = issue.number,
+            );
+            tokio_handle.spawn(
+                async move {
+                    intake
+                        .command(
+                            &event.object_attributes.note,
+                            repo,
+                            issue,
+                            event.user.username,
+                            forge::Kind::Gitlab,
+                            None,
+                        )
+                        .await
+                }
+                .instrument(span),
+            );
+        }
+        // Only merge requests have something to check out
+        Ok(None) => {}
+        Err(err) => tracing::warn!("[{}] Failed to parse GitLab payload: {err}", intake.tenant),
+    }
+    Ok(tide::Response::new(200))
+}
+
+/// Forwards webhook deliveries to the tenant owning the Github App they were sent for, so all
+/// Apps can be pointed at the same URL
+struct TenantRouter {
+    servers: HashMap<u64, tide::Server<State>>,
+}
+
+#[tide::utils::async_trait]
+impl tide::Middleware<()> for TenantRouter {
+    async fn handle(&self, req: tide::Request<()>, next: tide::Next<'_, ()>) -> tide::Result {
+        let app_id = req
+            .header("X-GitHub-Hook-Installation-Target-ID")
+            .and_then(|id| id.as_str().parse::<u64>().ok());
+        match app_id.and_then(|app_id| self.servers.get(&app_id)) {
+            Some(server) if req.url().path() == "/" => {
+                let res: tide::http::Response = server.respond(req).await?;
+                Ok(res.into())
+            }
+            _ => Ok(next.run(req).await),
+        }
+    }
+}
+
+/// What `/readyz` checks of a tenant before the reactor takes traffic
+#[derive(Clone)]
+struct Probe {
+    tenant: String,
+    app_id: u64,
+    app_key: String,
+    journal: Journal,
+    repos_root: PathBuf,
+}
+
+impl Probe {
+    /// The outcome of each check, `None` when it passed
+    fn check(&self) -> Vec<(&'static str, Option<String>)> {
+        let writable = || -> std::io::Result<()> {
+            std::fs::create_dir_all(&self.repos_root)?;
+            let path = self.repos_root.join(".readyz");
+            std::fs::write(&path, b"")?;
+            std::fs::remove_file(path)
+        };
+        vec![
+            (
+                "github_app",
+                app_jwt(self.app_id, &self.app_key)
+                    .err()
+                    .map(|e| e.to_string()),
+            ),
+            ("queue", self.journal.check().err().map(|e| e.to_string())),
+            ("repos_root", writable().err().map(|e| e.to_string())),
+        ]
+    }
+}
+
+/// `/readyz`: 200 once every tenant can mint the JWT of its app, read its queue and write to its
+/// repositories root, 503 otherwise. The body has the outcome of each check by tenant.
+async fn readyz(probes: Arc<Vec<Probe>>) -> tide::Result {
+    let checks = async_std::task::spawn_blocking(move || {
+        probes
+            .iter()
+            .map(|probe| (probe.tenant.clone(), probe.check()))
+            .collect::<Vec<_>>()
+    })
+    .await;
+    let mut ready = true;
+    let mut body = serde_json::Map::new();
+    for (tenant, checks) in checks {
+        let mut outcomes = serde_json::Map::new();
+        for (check, failure) in checks {
+            if let Some(failure) = &failure {
+                tracing::warn!("[{tenant}] Not ready, {check} check failed: {failure}");
+                ready = false;
+            }
+            outcomes.insert(
+                check.to_string(),
+                json!(failure.unwrap_or_else(|| "ok".to_string())),
+            );
+        }
+        body.insert(tenant, outcomes.into());
+    }
+    Ok(tide::Response::builder(if ready { 200 } else { 503 })
+        .body(serde_json::Value::from(body))
+        .build())
+}
+
+/// Skips webhook deliveries that were already processed, like the ones Github (or GitLab)
+/// delivers again after a timeout
+struct DeliveryDedup {
+    tenant: String,
+    journal: Journal,
+}
+
+#[tide::utils::async_trait]
+impl<S: Clone + Send + Sync + 'static> tide::Middleware<S> for DeliveryDedup {
+    async fn handle(&self, req: tide::Request<S>, next: tide::Next<'_, S>) -> tide::Result {
+        let delivery = match req
+            .header("X-GitHub-Delivery")
+            .or_else(|| req.header("X-Gitlab-Event-UUID"))
+        {
+            Some(delivery) => delivery.as_str().to_string(),
+            None => return Ok(next.run(req).await),
+        };
+        match self.journal.claim_delivery(&delivery) {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::info!(
+                    "[{}] Skipping delivery {delivery}, it was already processed",
+                    self.tenant
+                );
+                return Ok(tide::Response::new(200));
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "[{}] Failed to check delivery {delivery}: {err}",
+                    self.tenant
+                );
+                return Ok(next.run(req).await);
+            }
+        }
+        let res = next.run(req).await;
+        // Rejected deliveries (like ones with an invalid signature) may be delivered again
+        if !res.status().is_success() {
+            if let Err(err) = self.journal.release_delivery(&delivery) {
+                tracing::warn!(
+                    "[{}] Failed to forget delivery {delivery}: {err}",
+                    self.tenant
+                );
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Queues the commands of labels added to pull requests and cancels them when the labels are
+/// removed again, see `--repo-label-trigger`. `tide_github` only dispatches comments, so the
+/// `pull_request` deliveries are taken before they reach it.
+struct LabelEvents {
+    intake: Intake,
+    webhook_secret: String,
+    tokio_handle: tokio::runtime::Handle,
+}
+
+#[tide::utils::async_trait]
+impl<S: Clone + Send + Sync + 'static> tide::Middleware<S> for LabelEvents {
+    async fn handle(&self, mut req: tide::Request<S>, next: tide::Next<'_, S>) -> tide::Result {
+        if req.header("X-GitHub-Event").map(|event| event.as_str()) != Some("pull_request") {
+            return Ok(next.run(req).await);
+        }
+        let tenant = &self.intake.tenant;
+        let body = match signed_body(&mut req, &self.webhook_secret).await? {
+            Some(body) => body,
+            None => {
+                tracing::warn!(
+                    "[{tenant}] Ignoring pull_request delivery with an invalid signature"
+                );
+                return Ok(tide::Response::new(400));
+            }
+        };
+        let event: PullRequestEvent = match serde_json::from_slice(&body) {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::warn!("[{tenant}] Failed to parse payload: {err}");
+                return Ok(tide::Response::new(400));
+            }
+        };
+        let (labeled, unlabeled) = (event.labeled(), event.unlabeled());
+        let label = match labeled.or(unlabeled) {
+            Some(label) => label.to_string(),
+            None => return Ok(tide::Response::new(200)),
+        };
+        let added = labeled.is_some();
+        let repo: Repository = match event.repository.try_into() {
+            Ok(repo) => repo,
+            Err(err) => {
+                tracing::warn!("[{tenant}] Failed to parse repository payload: {err}");
+                return Ok(tide::Response::new(400));
+            }
+        };
+        let span = tracing::info_span!(
+            "webhook",
+            tenant = %tenant,
+            event = "pull_request",
+            repository = %repo.name,
+            issue = event.number,
+        );
+        let intake = self.intake.clone();
+        let (number, user) = (event.number, event.sender.login);
+        self.tokio_handle.spawn(
+            async move {
+                match added {
+                    true => intake.labeled(repo, number, &label, user).await,
+                    false => intake.unlabeled(repo, number, &label).await,
+                }
+            }
+            .instrument(span),
+        );
+        Ok(tide::Response::new(200))
+    }
+}
+
+/// Queues the commands in review comments on pull requests, see `ci_script::review_comments`.
+/// Like the `pull_request` deliveries, `tide_github` doesn't dispatch these.
+struct ReviewComments {
+    intake: Intake,
+    webhook_secret: String,
+    tokio_handle: tokio::runtime::Handle,
+}
+
+#[tide::utils::async_trait]
+impl<S: Clone + Send + Sync + 'static> tide::Middleware<S> for ReviewComments {
+    async fn handle(&self, mut req: tide::Request<S>, next: tide::Next<'_, S>) -> tide::Result {
+        if req.header("X-GitHub-Event").map(|event| event.as_str())
+            != Some("pull_request_review_comment")
+        {
+            return Ok(next.run(req).await);
+        }
+        let tenant = &self.intake.tenant;
+        let body = match signed_body(&mut req, &self.webhook_secret).await? {
+            Some(body) => body,
+            None => {
+                tracing::warn!(
+                    "[{tenant}] Ignoring pull_request_review_comment delivery with an invalid \
+                     signature"
+                );
+                return Ok(tide::Response::new(400));
+            }
+        };
+        let event: ReviewCommentEvent = match serde_json::from_slice(&body) {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::warn!("[{tenant}] Failed to parse payload: {err}");
+                return Ok(tide::Response::new(400));
+            }
+        };
+        let command = match event.command(&self.intake.settings.get().command_prefix) {
+            Some(command) => command.to_string(),
+            None => return Ok(tide::Response::new(200)),
+        };
+        let thread = event.thread();
+        let repo: Repository = match event.repository.try_into() {
+            Ok(repo) => repo,
+            Err(err) => {
+                tracing::warn!("[{tenant}] Failed to parse repository payload: {err}");
+                return Ok(tide::Response::new(400));
+            }
+        };
+        let span = tracing::info_span!(
+            "webhook",
+            tenant = %tenant,
+            event = "pull_request_review_comment",
+            repository = %repo.name,
+            issue = event.pull_request.number,
+        );
+        let intake = self.intake.clone();
+        let (number, user) = (event.pull_request.number, event.comment.user.login);
+        self.tokio_handle.spawn(
+            async move {
+                intake
+                    .review_command(&command, repo, number, user, thread)
+                    .await
+            }
+            .instrument(span),
+        );
+        Ok(tide::Response::new(200))
+    }
+}
+
+/// The body of the webhook delivery `req`, if it's signed with `secret`
+async fn signed_body<S>(req: &mut tide::Request<S>, secret: &str) -> tide::Result<Option<Vec<u8>>> {
+    let body = req.body_bytes().await?;
+    let signature = req
+        .header("X-Hub-Signature-256")
+        .map(|signature| signature.as_str().to_string())
+        .unwrap_or_default();
+    let expected = webhook::sign(secret, &body)?;
+    Ok(auth::constant_time_eq(signature.as_bytes(), expected.as_bytes()).then_some(body))
+}
+
+/// Have Github deliver again the comment webhooks of the last `hours` that never arrived, like
+/// the ones sent while the reactor was down. Deliveries `journal` knows were processed are left
+/// alone. Returns the number of deliveries asked for again.
+#[tracing::instrument(level = "debug", skip(github_client, journal))]
+async fn backfill_deliveries(
+    github_client: &Octocrab,
+    journal: &Journal,
+    hours: u64,
+) -> anyhow::Result<usize> {
+    #[derive(Deserialize)]
+    struct Delivery {
+        id: u64,
+        guid: String,
+        delivered_at: chrono::DateTime<chrono::Utc>,
+        status_code: u16,
+        event: String,
+    }
+
+    // Github only delivers the deliveries of the last 3 days again
+    let since = chrono::Utc::now() - chrono::Duration::hours(hours.min(72) as i64);
+    // Every attempt of a delivery is listed (with the same `guid`), newest first
+    let mut succeeded = HashSet::new();
+    let mut failed: Vec<(String, u64)> = vec![];
+    let mut page: Page<Delivery> = github_client
+        .get("/app/hook/deliveries", Some(&[("per_page", 100)]))
+        .await?;
+    loop {
+        let next = page.next.clone();
+        let deliveries = page.take_items();
+        // Older pages are all out of the window
+        let done = deliveries
+            .last()
+            .is_none_or(|delivery| delivery.delivered_at < since);
+        for delivery in deliveries {
+            let comment = matches!(
+                delivery.event.as_str(),
+                "issue_comment" | "pull_request_review_comment"
+            );
+            if delivery.delivered_at < since || !comment {
+                continue;
+            }
+            if (200..300).contains(&delivery.status_code) {
+                succeeded.insert(delivery.guid);
+            } else if !failed.iter().any(|(guid, _)| *guid == delivery.guid) {
+                failed.push((delivery.guid, delivery.id));
+            }
+        }
+        if done {
+            break;
+        }
+        match github_client.get_page(&next).await? {
+            Some(next) => page = next,
+            None => break,
+        }
+    }
+
+    let mut requested = 0;
+    // Oldest first, so commands are queued in the order they were given
+    for (guid, id) in failed.into_iter().rev() {
+        if succeeded.contains(&guid) || journal.knows_delivery(&guid)? {
+            continue;
+        }
+        let url = github_client.absolute_url(format!("/app/hook/deliveries/{id}/attempts"))?;
+        octocrab::map_github_error(github_client._post(url, None::<&()>).await?).await?;
+        requested += 1;
+    }
+    Ok(requested)
+}
+
+/// Takes jobs off a tenant's queue and runs them one at a time. A tenant can have several.
+#[derive(Clone)]
+struct Worker {
+    queue: Arc<Mutex<PersistentQueue<Job>>>,
+    /// Token the worker API accepts from this worker
+    queue_token: String,
+    journal: Journal,
+    /// Collects the results of the shards of jobs, shared by the workers of the tenant
+    shards: Coordinator,
+    max_attempts: u32,
+    max_job_retries: u32,
+    /// Delay before the first retry of a failed job
+    job_retry_delay: Duration,
+    settings: LiveSettings,
+    warm_up: bool,
+    /// Set a commit status on pull requests, named `status_context`
+    commit_status: bool,
+    status_context: String,
+    /// How to merge the pull requests the bot opened by `owner/name`, see `--repo-auto-merge`
+    auto_merge: HashMap<String, ci_script::auto_merge::Method>,
+    /// Of the user approving them
+    auto_merge_token: Option<String>,
+    report_usage: bool,
+    /// Where reports too long for a comment are uploaded to
+    gist_token: Option<String>,
+    /// Memory and disk each job may use
+    job_limits: ci_script::api::quota::Limits,
+    cgroup_root: Option<PathBuf>,
+    job_env: JobEnv,
+    /// Keeps the secrets of `job_env` out of comments
+    redactor: Redactor,
+    clone_strategy: CloneStrategy,
+    /// Overrides of `clone_strategy` by `owner/name`
+    repo_clone_strategies: HashMap<String, CloneStrategy>,
+    /// Mirrors to clone from by `owner/name`
+    repo_clone_mirrors: HashMap<String, url::Url>,
+    build_cache: BuildCache,
+    /// Overrides of `build_cache` by `owner/name`
+    repo_build_caches: HashMap<String, BuildCache>,
+    /// Where sccache keeps the cache, with `BuildCache::Sccache`
+    sccache: ci_script::api::cargo::sccache::Config,
+    /// Repositories (`owner/name`) whose submodules aren't initialized
+    skip_submodules: Vec<String>,
+    /// Repositories (`owner/name`) whose `Cargo.lock` is checked before running the script
+    check_lockfile: Vec<String>,
+    /// Repositories (`owner/name`) whose scripts may take flamegraphs
+    profile_repos: Vec<String>,
+    /// SSH key and its passphrase to clone with, instead of the installation token
+    ssh_key: Option<(PathBuf, Option<String>)>,
+    /// Checkout of the modules scripts import as `lib:<name>`
+    script_library: Option<PathBuf>,
+    /// Who the commits scripts make are by and how they're signed
+    commits: ci_script::api::git::CommitConfig,
+    /// Where the toolchains of jobs are installed, the `RUSTUP_HOME` of the user if not set
+    rustup_home: Option<PathBuf>,
+    /// Bytes of each stream of cargo's output scripts get
+    cargo_output_limit: usize,
+    /// Cores to pin jobs to, and whether to switch them to the performance governor
+    pin_cores: Option<(Vec<usize>, bool)>,
+    /// Pool of workers this one belongs to, see `--runner`
+    runner: Option<String>,
+    /// See `--runner-os`
+    runner_os: Option<String>,
+    /// Given when taking jobs and in heartbeats, see `runners::Runners`
+    name: String,
+    /// Told about every finished job
+    result_webhook: Option<Webhook>,
+    /// Where jobs store their artifacts, and the URL it's served at
+    artifacts: Option<(PathBuf, String)>,
+    /// Certificates to encrypt artifacts to by `owner/name`
+    artifact_recipients: Arc<HashMap<String, Vec<openssl::x509::X509>>>,
+    tenant: String,
+    queue_url: String,
+    repos_root: PathBuf,
+    github_client: Octocrab,
+    /// To check out and comment on GitLab projects, if enabled
+    gitlab: Option<Gitlab>,
+    tokio_handle: tokio::runtime::Handle,
+    results_store: Option<ci_script::results::Store>,
+    results_policy: ci_script::results::Policy,
+    refresh_policy: ci_script::results::RefreshPolicy,
+    history: SharedHistory,
+    log_tail: LogTail,
+    metrics: Metrics,
+    janitor: Janitor,
+    /// Closed when the reactor is asked to stop, after which no more jobs are taken
+    draining: async_std::channel::Receiver<()>,
+    /// How long a running job may keep the reactor from stopping before it's handed off
+    drain_deadline: Option<Duration>,
+}
+
+impl Worker {
+    async fn run(self) {
+        async fn get_job(worker: &Worker) -> anyhow::Result<Job> {
+            #[derive(Serialize)]
+            struct Options<'a> {
+                long_poll: bool,
+                runner: Option<&'a str>,
+                runner_os: Option<&'a str>,
+                name: &'a str,
+                version: &'a str,
+            }
+
+            let mut res = surf::post(format!("{}/queue/remove", worker.queue_url))
+                .query(&Options {
+                    long_poll: true,
+                    runner: worker.runner.as_deref(),
+                    runner_os: worker.runner_os.as_deref(),
+                    name: &worker.name,
+                    version: env!("CARGO_PKG_VERSION"),
+                })
+                .map_err(|e| e.into_inner())?
+                .header("Authorization", format!("Bearer {}", worker.queue_token))
+                .await
+                .map_err(|e| e.into_inner())?;
+            res.body_json::<Job>().await.map_err(|e| e.into_inner())
+        }
+
+        self.recover().await;
+        let worker = Arc::new(self);
+        while !worker.draining.is_closed() {
+            let job = futures_lite::future::or(
+                async {
+                    let dequeue = tracing::debug_span!("dequeue", tenant = %worker.tenant);
+                    Some(get_job(&worker).instrument(dequeue).await)
+                },
+                async {
+                    let _ = worker.draining.recv().await;
+                    None
+                },
+            )
+            .await;
+            match job {
+                Some(Ok(job)) => {
+                    let phases = Phases::new();
+                    let quota = Quota::new(worker.job_limits, worker.cgroup_root.clone());
+                    let started = Instant::now();
+                    let running = {
+                        // Jobs block, keep them off the threads serving requests
+                        let (worker, job, phases, quota) =
+                            (worker.clone(), job.clone(), phases.clone(), quota.clone());
+                        async_std::task::spawn_blocking(move || {
+                            let span = job_span(&job);
+                            span.record("tenant", worker.tenant.as_str());
+                            let _span = span.entered();
+                            worker.process(job, phases, quota)
+                        })
+                    };
+                    let heartbeats =
+                        async_std::task::spawn(worker.clone().heartbeats(job.id.clone()));
+                    let overdue = futures_lite::future::or(
+                        async {
+                            running.await;
+                            heartbeats.cancel().await;
+                            false
+                        },
+                        async {
+                            worker.drain_deadline_passed().await;
+                            true
+                        },
+                    )
+                    .await;
+                    if overdue {
+                        worker.hand_off(job, &phases, &quota, started.elapsed());
+                        return;
+                    }
+                }
+                Some(Err(e)) => {
+                    tracing::warn!(
+                        "[{}] Failed to retrieve job from queue: {}",
+                        worker.tenant,
+                        e
+                    )
+                }
+                None => break,
+            }
+        }
+        tracing::info!("[{}] Drained", worker.tenant);
+    }
+
+    /// Tell the queue the worker is still running `job`, until cancelled
+    async fn heartbeats(self: Arc<Self>, job: String) {
+        loop {
+            async_std::task::sleep(runners::HEARTBEAT_INTERVAL).await;
+            let heartbeat = Heartbeat {
+                name: self.name.clone(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                runner: self.runner.clone(),
+                os: self.runner_os.clone(),
+                job: Some(job.clone()),
+            };
+            let sent = async {
+                surf::post(format!("{}/queue/heartbeat", self.queue_url))
+                    .header("Authorization", format!("Bearer {}", self.queue_token))
+                    .body_json(&heartbeat)?
+                    .await
+            };
+            if let Err(err) = sent.await {
+                tracing::warn!("[{}] Failed to send heartbeat: {err}", self.tenant);
+            }
+        }
+    }
+
+    /// Resolves once the reactor has been draining for longer than the drain deadline, never
+    /// without one
+    async fn drain_deadline_passed(&self) {
+        let _ = self.draining.recv().await;
+        match self.drain_deadline {
+            Some(deadline) => async_std::task::sleep(deadline).await,
+            None => futures_lite::future::pending().await,
+        }
+    }
+
+    /// Queue `job`, still running past the drain deadline, for the next reactor sharing the
+    /// journal to pick up first. What it's running is killed first, so the next reactor can
+    /// reuse its checkout.
+    fn hand_off(&self, mut job: Job, phases: &Phases, quota: &Quota, running_for: Duration) {
+        quota.kill();
+        let phase = phases.current();
+        job.handoff = Some(Handoff {
+            checkout: job.checkout_dir(&self.repos_root),
+            phase: phase.clone(),
+            running_for,
+        });
+        match self.journal.hand_off(&job.id, &job.dedup_key(), &job) {
+            Ok(()) => tracing::info!(
+                "[{}] Handed off job {} after running for {}s{}",
+                self.tenant,
+                job.id,
+                running_for.as_secs(),
+                phase
+                    .map(|phase| format!(" (in phase {phase})"))
+                    .unwrap_or_default()
+            ),
+            // Still in flight, so it's queued again as interrupted
+            Err(err) => {
+                tracing::warn!("[{}] Failed to hand off job {}: {err}", self.tenant, job.id)
+            }
+        }
+    }
+
+    /// Queue the job that was running when the reactor went down again, unless it has been
+    /// attempted too often already
+    async fn recover(&self) {
+        let job: Job = match self.journal.in_flight() {
+            Ok(Some(job)) => job,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::warn!("[{}] Failed to read interrupted job: {err}", self.tenant);
+                return;
+            }
+        };
+        let attempts = job.retries + 1;
+        let note = if attempts < self.max_attempts {
+            tracing::warn!(
+                "[{}] Queueing interrupted job {} again",
+                self.tenant,
+                job.id
+            );
+            let mut retry = job.clone();
+            retry.retries += 1;
+            self.queue
+                .lock()
+                .await
+                .add(retry.id.clone(), retry.dedup_key(), retry);
+            format!(
+                "The bot was restarted while running this job, it has been queued again \
+                 (attempt {} of {}).",
+                attempts + 1,
+                self.max_attempts
+            )
+        } else {
+            tracing::warn!("[{}] Giving up on interrupted job {}", self.tenant, job.id);
+            format!(
+                "The bot was restarted while running this job, giving up after {attempts} \
+                 attempts."
+            )
+        };
+        if let Err(err) = self.journal.finish() {
+            tracing::warn!("[{}] Failed to clear interrupted job: {err}", self.tenant);
+        }
+        // Jobs submitted through the API show up as queued again instead
+        if let Some(issue) = &job.issue {
+            let (client, repository) = (&self.github_client, &job.repository);
+            let comment = async {
+                match job.review_comment {
+                    Some(thread) => {
+                        create_review_reply(client, repository, issue.number, thread, note).await
+                    }
+                    None => create_comment(client, repository, issue.number, note)
+                        .await
+                        .map(drop),
+                }
+            };
+            if let Err(err) = self.tokio_handle.block_on(comment) {
+                tracing::warn!("[{}] Failed to comment on issue: {err}", self.tenant);
+            }
+        }
+    }
+
+    /// Compare the metrics of a pull request job against the history of its base branch, and
+    /// schedule a refresh of the base branch's results if they are out of date. The metrics are
+    /// recorded too if `record`, which cached results already were. Returns the report and
+    /// headline of the regressions, if there are any.
+    fn pr_results(
+        &self,
+        client: &Octocrab,
+        store: &ci_script::results::Store,
+        job: &Job,
+        pr_nr: u64,
+        outcome: &Outcome,
+        record: bool,
+    ) -> anyhow::Result<Option<(String, Option<String>)>> {
+        let (owner, name) = (&job.repository.owner.login, &job.repository.name);
+        let repo = format!("{owner}/{name}");
+        let pr = self
+            .tokio_handle
+            .block_on(client.pulls(owner, name).get(pr_nr))?;
+        // Not the head branch, which a fork may name like a branch of the repository
+        let branch = format!("pr/{pr_nr}");
+        if let Some(baseline) = &job.baseline {
+            if record {
+                store.record(
+                    &job.id,
+                    &repo,
+                    &branch,
+                    outcome.commit.as_deref(),
+                    &outcome.metrics,
+                )?;
+            }
+            let run = match store.baseline(&repo, baseline)? {
+                Some((_, run)) => run,
+                None => {
+                    let report = format!(
+                        "There's no baseline `{baseline}` to compare against, the saved ones are \
+                         listed by the `baseline list` command."
+                    );
+                    return Ok(Some((report, None)));
+                }
+            };
+            let current = ci_script::results::Run {
+                id: job.id.clone(),
+                repo,
+                branch,
+                commit: outcome.commit.clone(),
+                metrics: outcome.metrics.clone(),
+            };
+            let changes = ci_script::results::changes(&run, &outcome.metrics);
+            let report = format!(
+                "{}\n**A** is the baseline `{baseline}`.",
+                ci_script::results::render_comparison(&run, &current)
+            );
+            return Ok(Some((report, ci_script::results::headline(&changes))));
+        }
+        // Cached results were recorded when they were first reported
+        let regressions = if record {
+            store.compare_and_record(
+                &job.id,
+                &repo,
+                &branch,
+                outcome.commit.as_deref(),
+                &pr.base.ref_field,
+                &outcome.metrics,
+                &self.results_policy,
+            )?
+        } else {
+            store.regressions(
+                &repo,
+                &pr.base.ref_field,
+                &outcome.metrics,
+                &self.results_policy,
+            )?
+        };
+
+        if self.refresh_policy.is_enabled() {
+            let stale = match store.latest(&repo, &pr.base.ref_field)? {
+                Some(baseline) => {
+                    let behind = match (&baseline.commit, self.refresh_policy.max_commits) {
+                        (Some(commit), Some(_)) => Some(self.tokio_handle.block_on(
+                            commits_between(client, (owner, name), commit, &pr.base.sha),
+                        )?),
+                        _ => None,
+                    };
+                    self.refresh_policy.is_stale(&baseline, behind)
+                }
+                None => true,
+            };
+            if stale {
+                self.schedule_refresh(job, &pr.base.ref_field);
+            }
+        }
+
+        Ok(ci_script::results::headline(&regressions).map(|headline| {
+            let mut report =
+                ci_script::results::render_regressions(&pr.base.ref_field, &regressions);
+            // Comparisons with results of another machine don't mean much
+            if let Some(machine) = &outcome.machine {
+                report = format!("{report}\n\n{}", machine.render());
+            }
+            (report, Some(headline))
+        }))
+    }
+
+    /// Set the status of the commit a pull request job ran on, or the head of the pull request if
+    /// it didn't get that far
+    fn set_commit_status(
+        &self,
+        client: &Octocrab,
+        job: &Job,
+        commit: Option<String>,
+        state: octocrab::models::StatusState,
+        description: &str,
+        target: Option<String>,
+    ) -> anyhow::Result<()> {
+        let (owner, name) = (&job.repository.owner.login, &job.repository.name);
+        self.tokio_handle.block_on(async {
+            let sha = match commit {
+                Some(commit) => commit,
+                None => {
+                    let pr_nr = job.issue.as_ref().ok_or(Error::NoIssue)?.number;
+                    let pr_nr = pr_nr.try_into()?;
+                    client.pulls(owner, name).get(pr_nr).await?.head.sha
+                }
+            };
+            // Github rejects longer descriptions
+            let description: String = self
+                .redactor
+                .redact(description)
+                .chars()
+                .take(140)
+                .collect();
+            let repo = client.repos(owner, name);
+            let mut status = repo
+                .create_status(sha, state)
+                .context(self.status_context.clone())
+                .description(description);
+            if let Some(target) = target {
+                status = status.target(target);
+            }
+            status.send().await?;
+            anyhow::Ok(())
+        })
+    }
+
+    /// Approve pull request `pr_nr` and have Github merge it with `method`, if the bot opened it
+    /// and it's still at the `commit` the job ran on
+    fn auto_merge(
+        &self,
+        client: &Octocrab,
+        job: &Job,
+        pr_nr: u64,
+        commit: Option<&str>,
+        method: ci_script::auto_merge::Method,
+    ) -> anyhow::Result<()> {
+        let (owner, name) = (&job.repository.owner.login, &job.repository.name);
+        self.tokio_handle.block_on(async {
+            let pr = client.pulls(owner, name).get(pr_nr).await?;
+            let app = self.github_client.current().app().await?;
+            let bot = format!("{}[bot]", app.slug.unwrap_or_default());
+            if pr.user.as_ref().map(|user| user.login.as_str()) != Some(bot.as_str()) {
+                return Ok(());
+            }
+            // Commits pushed since weren't benchmarked
+            if commit != Some(pr.head.sha.as_str()) {
+                tracing::info!(
+                    "[{}] Not auto-merging #{pr_nr}, it moved on from {}",
+                    self.tenant,
+                    commit.unwrap_or_default()
+                );
+                return Ok(());
+            }
+            if let Some(token) = &self.auto_merge_token {
+                let approver = octocrab::OctocrabBuilder::new()
+                    .base_url(client.base_url.clone())?
+                    .personal_token(token.clone())
+                    .build()?;
+                let body = format!("No regressions in `{}`.", job.command.join(" "));
+                ci_script::auto_merge::approve(&approver, owner, name, pr_nr, &body).await?;
+            }
+            let node_id = pr.node_id.ok_or(Error::NoNodeId)?;
+            ci_script::auto_merge::enable(client, &node_id, method).await?;
+            tracing::info!(
+                "[{}] Approved #{pr_nr} of {owner}/{name} and enabled auto-merge",
+                self.tenant
+            );
+            anyhow::Ok(())
+        })
+    }
+
+    /// Queue the command of `job` on `branch`, unless that's already queued
+    fn schedule_refresh(&self, job: &Job, branch: &str) {
+        let mut refresh = job.clone();
+        refresh.id = format!(
+            "{}_{}_{}_{}",
+            job.repository.name,
+            branch,
+            job.command.join(" "),
+            uuid::Uuid::new_v4()
+        );
+        refresh.retries = 0;
+        refresh.attempts = 0;
+        refresh.last_error = None;
+        refresh.branch = Some(branch.to_string());
+        // Split like the job was
+        refresh.shard = job.shard.as_ref().map(|shard| Shard {
+            group: refresh.id.clone(),
+            index: 0,
+            count: shard.count,
+        });
+        let key = refresh.dedup_key();
+        let mut queue = async_std::task::block_on(self.queue.lock());
+        if queue.pos_by_key(&key).is_none() {
+            tracing::info!(
+                "[{}] Results of {branch} are out of date, scheduling a refresh",
+                self.tenant
+            );
+            for refresh in refresh.split() {
+                queue.add(refresh.id.clone(), refresh.dedup_key(), refresh);
+            }
+        }
+    }
+
+    /// What the result comment of `job` (running as `running_id`) starts with: the command quoted
+    /// and whoever gave it mentioned. Tagged with the ID of the job when other commands are queued
+    /// or running on the same issue, so their interleaved results can be told apart.
+    fn result_header(&self, job: &Job, running_id: &str) -> Option<String> {
+        let issue_url = job.issue_url();
+        let running = self.history.lock().is_ok_and(|history| {
+            history
+                .running()
+                .iter()
+                .any(|record| record.id != running_id && record.issue_url == issue_url)
+        });
+        // Refreshes of the base branch aren't commands
+        let queued = || {
+            async_std::task::block_on(self.queue.lock())
+                .items()
+                .iter()
+                .any(|queued| queued.branch.is_none() && queued.issue_url() == issue_url)
+        };
+        let tag = (running || queued()).then(|| format!("job `{}`", job.id));
+        let mention = match (&job.user, tag) {
+            (Some(user), Some(tag)) => Some(format!("@{user} ({tag})")),
+            (Some(user), None) => Some(format!("@{user}")),
+            (None, Some(tag)) => Some(format!("Result of {tag}")),
+            (None, None) => None,
+        };
+        let quote = job.request.as_ref().map(|request| format!("> {request}"));
+        let header: Vec<String> = quote.into_iter().chain(mention).collect();
+        (!header.is_empty()).then(|| header.join("\n\n"))
+    }
+
+    /// Hand the result of a shard to the coordinator. Returns the results of all shards of its
+    /// job, merged, and the job to report them as, once the last one is done.
+    fn gather_shards(
+        &self,
+        job: &Job,
+        shard: &Shard,
+        result: ShardResult,
+    ) -> Option<(Job, ShardResult, Vec<String>)> {
+        let results = match self.shards.report(shard, &result) {
+            Ok(Some(results)) => results,
+            Ok(None) => {
+                tracing::info!(
+                    "[{}] Shard {shard} of job {} finished, waiting for the others",
+                    self.tenant,
+                    shard.group
+                );
+                return None;
+            }
+            Err(err) => {
+                // Better to report the shard on its own than not at all
+                tracing::warn!("[{}] Failed to record shard {shard}: {err}", self.tenant);
+                return Some((job.clone(), result, vec![]));
+            }
+        };
+        tracing::info!(
+            "[{}] All {} shards of job {} finished",
+            self.tenant,
+            shard.count,
+            shard.group
+        );
+        let (outcome, errors) = shards::merge(results);
+        let mut job = job.clone();
+        job.id = shard.group.clone();
+        job.shard = Some(Shard {
+            index: 0,
+            ..shard.clone()
+        });
+        Some((job, Ok(outcome), errors))
+    }
+
+    /// `outputs` without the secrets they contain, none if that fails
+    fn redact_outputs(
+        &self,
+        outputs: &BTreeMap<String, serde_json::Value>,
+    ) -> BTreeMap<String, serde_json::Value> {
+        serde_json::to_string(outputs)
+            .ok()
+            .and_then(|json| serde_json::from_str(&self.redactor.redact(&json)).ok())
+            .unwrap_or_default()
+    }
+
+    fn record_outputs(&self, job: &Job, outputs: &BTreeMap<String, serde_json::Value>) {
+        let script = job.command.first().map_or("", String::as_str);
+        self.metrics
+            .record_outputs(&repository_of(job), script, outputs);
+        if let (Some(store), false) = (&self.results_store, outputs.is_empty()) {
+            if let Err(err) = store.record_outputs(&job.id, repository_of(job), outputs) {
+                tracing::warn!("[{}] Failed to record outputs: {err}", self.tenant);
+            }
+        }
+    }
+
+    fn record_refresh(&self, job: &Job, outcome: &Outcome) {
+        if let (Some(store), Some(branch)) = (&self.results_store, &job.branch) {
+            let repo = format!("{}/{}", job.repository.owner.login, job.repository.name);
+            if let Err(err) = store.record(
+                &job.id,
+                &repo,
+                branch,
+                outcome.commit.as_deref(),
+                &outcome.metrics,
+            ) {
+                tracing::warn!("[{}] Failed to record results: {err}", self.tenant);
+            }
+        }
+    }
+
+    fn process(&self, job: Job, phases: Phases, quota: Quota) {
+        if let Err(err) = self.journal.start(&job.id, &job) {
+            tracing::warn!("[{}] Failed to journal job {}: {err}", self.tenant, job.id);
+        }
+        {
+            let _busy = self.janitor.lock();
+            self.execute(job, phases, quota);
+        }
+        if let Err(err) = self.journal.finish() {
+            tracing::warn!("[{}] Failed to journal finished job: {err}", self.tenant);
+        }
+        if self.janitor.max_size().is_some() {
+            match self.janitor.collect() {
+                Ok(collected) if !collected.removed.is_empty() => tracing::info!(
+                    "[{}] Removed {:?} from the repositories root",
+                    self.tenant,
+                    collected.removed
+                ),
+                Ok(_) => {}
+                Err(err) => tracing::warn!(
+                    "[{}] Failed to clean up the repositories root: {err}",
+                    self.tenant
+                ),
+            }
+        }
+    }
+
+    /// Queue `job` again after a while, unless it has been retried too often already. Returns
+    /// whether it was.
+    fn retry(&self, job: &Job, error: &str) -> bool {
+        if job.attempts >= self.max_job_retries {
+            return false;
+        }
+        let mut retry = job.clone();
+        retry.attempts += 1;
+        retry.last_error = Some(error.to_string());
+        let delay = self.job_retry_delay * 2u32.saturating_pow(job.attempts);
+        // Journaled right away, so it's queued right away after a restart
+        let key = retry.dedup_key();
+        if let Err(err) = self
+            .tokio_handle
+            .block_on(async { self.queue.lock().await.defer(&retry.id, &key, &retry) })
+        {
+            tracing::warn!(
+                "[{}] Failed to queue job {} again: {err}",
+                self.tenant,
+                job.id
+            );
+            return false;
+        }
+        tracing::info!(
+            "[{}] Queueing job {} again in {}s (retry {} of {})",
+            self.tenant,
+            job.id,
+            delay.as_secs(),
+            retry.attempts,
+            self.max_job_retries
+        );
+        let queue = self.queue.clone();
+        let tenant = self.tenant.clone();
+        async_std::task::spawn(async move {
+            async_std::task::sleep(delay).await;
+            let id = retry.id.clone();
+            if let Err(err) = queue.lock().await.undefer(id.clone(), key, retry) {
+                tracing::warn!("[{tenant}] Failed to queue job {id} again: {err}");
+            }
+        });
+        true
+    }
+
+    fn execute(&self, job: Job, phases: Phases, quota: Quota) {
+        let shard = match &job.shard {
+            Some(shard) => format!(" (shard {shard})"),
+            None => String::new(),
+        };
+        let user = match &job.user {
+            Some(user) => format!(" of {user}"),
+            None => String::new(),
+        };
+        tracing::info!(
+            "[{}] Processing command {}{shard}{user} in repo {}",
+            self.tenant,
+            job.command.join(" "),
+            job.repository.url
+        );
+        if let Some(handoff) = &job.handoff {
+            tracing::info!(
+                "[{}] Resuming job {} handed off after running for {}s{}",
+                self.tenant,
+                job.id,
+                handoff.running_for.as_secs(),
+                handoff
+                    .phase
+                    .as_ref()
+                    .map(|phase| format!(" (in phase {phase})"))
+                    .unwrap_or_default()
+            );
+        }
+
+        // TODO: Fix block_on
+        let installation = self.tokio_handle.block_on(async {
+            match job.forge {
+                forge::Kind::Github => {
+                    let token = installation_token(&self.github_client, &job.repository).await?;
+                    let client = octocrab::OctocrabBuilder::new()
+                        .base_url(self.github_client.base_url.clone())?
+                        .personal_token(token.clone())
+                        .build()?;
+                    anyhow::Ok((token, client))
+                }
+                // Github isn't asked for anything on behalf of GitLab jobs, which aren't pull
+                // requests
+                forge::Kind::Gitlab => {
+                    let gitlab = self.gitlab.as_ref().ok_or(Error::NoGitlab)?;
+                    Ok((gitlab.token().to_string(), self.github_client.clone()))
+                }
+            }
+        });
+        let (installation_token, github_installation_client) = match installation {
+            Ok(installation) => installation,
+            Err(err) => {
+                tracing::warn!(
+                    "[{}] Failed to require octocrab Github client: {err}",
+                    self.tenant
+                );
+                self.retry(&job, &err.to_string());
+                return;
+            }
+        };
+        let forge: Arc<dyn Forge> = match &self.gitlab {
+            Some(gitlab) if job.forge == forge::Kind::Gitlab => {
+                Arc::new(gitlab.project(&job.repository))
+            }
+            _ => Arc::new(
+                forge::Github::new(
+                    Arc::new(std::sync::Mutex::new(self.github_client.clone())),
+                    job.repository.clone(),
+                )
+                .with_gist_token(self.gist_token.clone())
+                .with_review_thread(job.review_comment),
+            ),
+        };
+        // Only valid for a while, so only needs redacting while the job runs. The GitLab token
+        // is redacted for as long as the reactor runs instead.
+        let redacted_token = (job.forge == forge::Kind::Github).then(|| installation_token.clone());
+        if let Some(token) = &redacted_token {
+            self.redactor.add(token.as_str());
+        }
+
+        let repo_owner = job.repository.owner.login.clone();
+        let repo_name = job.repository.name.clone();
+        let issue_nr: Option<u64> = job
+            .issue
+            .as_ref()
+            .and_then(|issue| issue.number.try_into().ok());
+        let is_pr = job
+            .issue
+            .as_ref()
+            .is_some_and(|issue| issue.pull_request.is_some());
+        let finished_job = job.clone();
+
+        self.log_tail.clear();
+        if let Ok(mut history) = self.history.lock() {
+            history.start(&job);
+        }
+        if self.commit_status && is_pr && job.branch.is_none() {
+            if let Err(err) = self.set_commit_status(
+                &github_installation_client,
+                &job,
+                None,
+                octocrab::models::StatusState::Pending,
+                "Running",
+                None,
+            ) {
+                tracing::warn!("[{}] Failed to set commit status: {err}", self.tenant);
+            }
+        }
+
+        let full_name = format!("{repo_owner}/{repo_name}");
+        let checkout_options = CheckoutOptions {
+            clone_strategy: self
+                .repo_clone_strategies
+                .get(&full_name)
+                .copied()
+                .unwrap_or(self.clone_strategy),
+            submodules: !self.skip_submodules.contains(&full_name),
+            credentials: match &self.ssh_key {
+                Some((private_key, passphrase)) => ci_script::api::git::Credentials::SshKey {
+                    private_key: private_key.clone(),
+                    passphrase: passphrase.clone(),
+                },
+                None => ci_script::api::git::Credentials::Token(installation_token),
+            },
+            build_cache: self
+                .repo_build_caches
+                .get(&full_name)
+                .copied()
+                .unwrap_or(self.build_cache),
+            sccache: self.sccache.clone(),
+            // Only when the job was handed off by a worker sharing the repositories root
+            reuse_checkout: job
+                .handoff
+                .as_ref()
+                .is_some_and(|handoff| handoff.checkout == job.checkout_dir(&self.repos_root)),
+            clone_mirror: self.repo_clone_mirrors.get(&full_name).cloned(),
+        };
+
+        let artifacts = self.artifacts.as_ref().map(|(dir, url)| {
+            // Unguessable, so the links to artifacts can't be used to find others
+            let id = uuid::Uuid::new_v4().simple().to_string();
+            Artifacts::new(
+                dir.join(&id),
+                format!("{url}/{id}"),
+                self.artifact_recipients
+                    .get(&full_name)
+                    .cloned()
+                    .unwrap_or_default(),
+            )
+        });
+
+        let mut status = Status::Succeeded;
+        let script_phases = phases;
+        let mut phases = vec![];
+        let mut steps = vec![];
+        let mut sccache = None;
+        let mut usage = None;
+        let mut commit = None;
+        let mut headline = None;
+        let mut metrics = vec![];
+        let mut outputs = BTreeMap::new();
+        // Compared against the history of the base branch and nothing regressed
+        let mut without_regressions = false;
+        let job_id = job.id.clone();
+        // Shards only have part of the result, and refreshes are there to record results again
+        let cache = self
+            .results_store
+            .as_ref()
+            .filter(|_| job.shard.is_none() && !(job.branch.is_some() && job.issue.is_some()));
+        let mut from_cache = None;
+        let result = run(
+            &self.repos_root,
+            job,
+            &checkout_options,
+            self.check_lockfile.contains(&full_name),
+            self.github_client.clone(),
+            cache,
+            |checkout| {
+                let settings = self.settings.get();
+                checkout.http_allowlist = settings.http_allowlist;
+                checkout.pr_policy = settings.pr_policy;
+                checkout.warm_up = self.warm_up;
+                checkout.env = self.job_env.clone();
+                checkout.artifacts = artifacts;
+                checkout.redactor = self.redactor.clone();
+                checkout.forge = Some(forge.clone());
+                checkout.phases = script_phases;
+                checkout.script_library = self.script_library.clone();
+                checkout.commits = self.commits.clone();
+                checkout.toolchain =
+                    ci_script::api::toolchain::Toolchain::new(self.rustup_home.clone());
+                checkout.cargo_output.limit = Some(self.cargo_output_limit);
+                checkout.quota = quota;
+                checkout.profiler =
+                    ci_script::api::profile::Profiler::new(self.profile_repos.contains(&full_name));
+                checkout.results_history = self
+                    .results_store
+                    .clone()
+                    .map(|store| ci_script::api::results::History::new(store, full_name.clone()));
+                if let Some((cores, performance_governor)) = &self.pin_cores {
+                    checkout.pinning = ci_script::api::pinning::Pinning::new(
+                        Some(cores.clone()),
+                        *performance_governor,
+                    );
+                }
+            },
+        )
+        .map(|(outcome, from)| {
+            from_cache = from;
+            outcome
+        });
+        let retryable = result.as_ref().is_err_and(|err| {
+            err.downcast_ref::<ci_script::job::Error>()
+                .is_some_and(|err| err.is_retryable())
+        });
+        let result = result.map_err(|err| self.redactor.redact(&err.to_string()));
+        // Failures that may not happen again are only reported once out of retries
+        if let (true, Err(err)) = (retryable, &result) {
+            if self.retry(&finished_job, err) {
+                if let Some(token) = &redacted_token {
+                    self.redactor.remove(token);
+                }
+                if let Ok(mut history) = self.history.lock() {
+                    history.finish(&job_id, Status::Failed(err.clone()), None, vec![], None);
+                }
+                return;
+            }
+        }
+        // The last shard of a job to finish reports on all of them, as the whole job
+        let mut shard_errors = vec![];
+        let (finished_job, result) = match finished_job.shard.clone() {
+            Some(shard) => {
+                match &result {
+                    Ok(outcome) => {
+                        phases = outcome.phases.clone();
+                        usage = outcome.usage;
+                    }
+                    Err(err) => {
+                        tracing::warn!("[{}] Error running shard {shard}: {err}", self.tenant);
+                        status = Status::Failed(err.clone());
+                    }
+                }
+                match self.gather_shards(&finished_job, &shard, result) {
+                    Some((job, result, errors)) => {
+                        shard_errors = errors;
+                        (job, Some(result))
+                    }
+                    None => (finished_job, None),
+                }
+            }
+            None => (finished_job, Some(result)),
+        };
+        if !shard_errors.is_empty() {
+            status = Status::Failed(shard_errors.join("\n"));
+        }
+        // Shards only report once all of them finished
+        let reported = result.is_some();
+        let mut sections = match result {
+            None => vec![],
+            Some(Ok(outcome)) if finished_job.branch.is_some() && finished_job.issue.is_some() => {
+                phases = outcome.phases.clone();
+                usage = outcome.usage;
+                self.record_refresh(&finished_job, &outcome);
+                self.record_outputs(&finished_job, &self.redact_outputs(&outcome.outputs));
+                // Refreshes aren't requested by anyone, so there's no one to report to
+                vec![]
+            }
+            Some(Ok(outcome)) => {
+                phases = outcome.phases.clone();
+                steps = outcome.steps.clone();
+                sccache = outcome.sccache;
+                usage = outcome.usage;
+                commit = outcome.commit.clone();
+                headline = outcome.headline.clone();
+                metrics = outcome.metrics.clone();
+                outputs = self.redact_outputs(&outcome.outputs);
+                // Cached results were recorded by the run they're of
+                if from_cache.is_none() {
+                    self.record_outputs(&finished_job, &outputs);
+                }
+                if let Some(failure) = &outcome.failure {
+                    status = Status::Failed(failure.clone());
+                }
+                let mut sections = vec![];
+                if let Some(from) = &from_cache {
+                    sections.push(from.render());
+                }
+                if let (Some(store), Some(issue_nr)) = (&self.results_store, issue_nr) {
+                    if is_pr && !outcome.metrics.is_empty() {
+                        match self.pr_results(
+                            &github_installation_client,
+                            store,
+                            &finished_job,
+                            issue_nr,
+                            &outcome,
+                            from_cache.is_none(),
+                        ) {
+                            Ok(Some((report, worst))) => {
+                                sections.push(report);
+                                headline = headline.or(worst);
+                            }
+                            Ok(None) => without_regressions = true,
+                            Err(err) => {
+                                tracing::warn!("[{}] Failed to process results: {err}", self.tenant)
+                            }
+                        }
+                    }
+                }
+                sections.extend(outcome.report);
+                sections.extend(shard_errors);
+                if !outcome.outputs.is_empty() {
+                    sections.push(ci_script::api::outputs::render(&outcome.outputs));
+                }
+                if !outcome.artifacts.is_empty() {
+                    sections.push(artifacts::render(&outcome.artifacts));
+                }
+                if !outcome.warnings.is_empty() {
+                    sections.push(ci_script::api::warnings::render(&outcome.warnings));
+                }
+                sections
+            }
+            Some(Err(job_err)) => {
+                tracing::warn!("[{}] Error running job: {job_err}", self.tenant);
+                status = Status::Failed(job_err.clone());
+                // Retried failures and interruptions have budgets of their own
+                let attempts = match (finished_job.attempts, finished_job.retries) {
+                    (0, 0) => String::new(),
+                    (failed, 0) => format!(" (after {} attempts)", failed + 1),
+                    (failed, interrupted) => format!(
+                        " (after {} attempts, {interrupted} of them interrupted)",
+                        failed + interrupted + 1
+                    ),
+                };
+                vec![format!("Error running job{attempts}: {job_err}")]
+            }
+        };
+        if !sections.is_empty() {
+            if let Some(header) = self.result_header(&finished_job, &job_id) {
+                sections.insert(0, header);
+            }
+        }
+        let mut footer = vec![];
+        if steps.len() > 1 {
+            footer.push(ci_script::job::render_steps(&steps));
+        }
+        if !phases.is_empty() {
+            footer.push(ci_script::api::phases::render_footer(&phases));
+        }
+        if let Some(sccache) = sccache {
+            footer.push(sccache.render());
+        }
+        if let (true, Some(usage)) = (self.report_usage, usage) {
+            footer.push(usage.render());
+        }
+        let comment = if sections.is_empty() {
+            None
+        } else if footer.is_empty() {
+            Some(sections.join("\n\n"))
+        } else {
+            Some(format!(
+                "{}\n\n{}",
+                sections.join("\n\n"),
+                footer.join("<br>\n")
+            ))
+        }
+        .map(|comment| self.redactor.redact(&comment));
+
+        // TODO: create separate tokio threadpool and send messages to
+        // it
+        let mut comment_url = None;
+        if let (Some(comment), Some(issue_nr)) = (&comment, issue_nr) {
+            match ci_script::api::publish::publish(forge.as_ref(), issue_nr, comment) {
+                Ok(urls) => comment_url = urls.into_iter().next(),
+                Err(err) => tracing::warn!("[{}] Failed to comment on issue: {err}", self.tenant),
+            };
+        };
+        let webhook = match &self.result_webhook {
+            Some(webhook) if reported => Some((
+                webhook,
+                commit.clone(),
+                headline
+                    .as_deref()
+                    .map(|headline| self.redactor.redact(headline)),
+                metrics.clone(),
+                outputs.clone(),
+            )),
+            _ => None,
+        };
+        // Whoever submitted the job through the API fetches the result instead
+        if finished_job.issue.is_none() && reported {
+            let result = JobStatus::Finished {
+                succeeded: !matches!(status, Status::Failed(_)),
+                error: match &status {
+                    Status::Failed(err) => Some(self.redactor.redact(err)),
+                    _ => None,
+                },
+                commit: commit.clone(),
+                headline: headline
+                    .as_deref()
+                    .map(|headline| self.redactor.redact(headline)),
+                report: comment,
+                metrics,
+                outputs,
+                usage,
+            };
+            if let Err(err) = self.journal.record_result(&finished_job.id, &result) {
+                tracing::warn!(
+                    "[{}] Failed to record the result of job {}: {err}",
+                    self.tenant,
+                    finished_job.id
+                );
+            }
+        }
+        if self.commit_status && is_pr && finished_job.branch.is_none() && reported {
+            let (state, description) = match &status {
+                Status::Failed(err) => (
+                    octocrab::models::StatusState::Failure,
+                    format!("Failed: {}", err.lines().next().unwrap_or_default()),
+                ),
+                _ => (
+                    octocrab::models::StatusState::Success,
+                    headline.unwrap_or_else(|| "Finished".to_string()),
+                ),
+            };
+            if let Err(err) = self.set_commit_status(
+                &github_installation_client,
+                &finished_job,
+                commit.clone(),
+                state,
+                &description,
+                comment_url.as_ref().map(|url| url.to_string()),
+            ) {
+                tracing::warn!("[{}] Failed to set commit status: {err}", self.tenant);
+            }
+        }
+        let merge_method = self.auto_merge.get(&full_name).copied();
+        if let (Some(method), Some(pr_nr), true) = (merge_method, issue_nr, without_regressions) {
+            if !matches!(status, Status::Failed(_)) && finished_job.branch.is_none() {
+                if let Err(err) = self.auto_merge(
+                    &github_installation_client,
+                    &finished_job,
+                    pr_nr,
+                    commit.as_deref(),
+                    method,
+                ) {
+                    tracing::warn!("[{}] Failed to auto-merge #{pr_nr}: {err}", self.tenant);
+                }
+            }
+        }
+        if let Some(token) = &redacted_token {
+            self.redactor.remove(token);
+        }
+
+        // What a cached result used was accounted for by the run it's of
+        if let (Some(usage), None) = (&usage, &from_cache) {
+            let script = finished_job.command.first().map_or("", String::as_str);
+            self.metrics
+                .record_usage(&repository_of(&finished_job), script, usage);
+        }
+        let record = match self.history.lock() {
+            Ok(mut history) => history.finish(&job_id, status, comment_url, phases, usage),
+            Err(_) => None,
+        };
+        if let Some(record) = record {
+            if let Err(err) = self.journal.archive(&record) {
+                tracing::warn!(
+                    "[{}] Failed to archive job {}: {err}",
+                    self.tenant,
+                    record.id
+                );
+            }
+            if let Some((webhook, commit, headline, metrics, outputs)) = webhook {
+                let mut finished =
+                    webhook::Finished::new(&record, commit, headline, metrics, outputs);
+                finished.error = finished.error.map(|err| self.redactor.redact(&err));
+                if let Err(err) = async_std::task::block_on(webhook.send(&finished)) {
+                    tracing::warn!(
+                        "[{}] Failed to send the result webhook of job {}: {err}",
+                        self.tenant,
+                        record.id
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The run a result was cached from
+struct FromCache {
+    run_id: String,
+    recorded_at: std::time::SystemTime,
+}
+
+impl FromCache {
+    /// Note on the report of a job whose result was taken from the cache
+    fn render(&self) -> String {
+        let age = self.recorded_at.elapsed().unwrap_or_default();
+        format!(
+            "Cached result of the run `{}` of the same scripts on this commit {} ago, add \
+             `--force` to the command to run them again.",
+            self.run_id,
+            ci_script::api::phases::format_duration(age)
+        )
+    }
+}
+
+/// Check out and run `job`, with `configure` setting what the checkout doesn't know about
+fn run<P: AsRef<std::path::Path> + AsRef<std::ffi::OsStr>>(
+    repos_root: P,
+    job: Job,
+    checkout_options: &CheckoutOptions,
+    check_lockfile: bool,
+    github_client: octocrab::Octocrab,
+    cache: Option<&ci_script::results::Store>,
+    configure: impl FnOnce(&mut CheckedoutJob),
+) -> anyhow::Result<(Outcome, Option<FromCache>)> {
+    let mut checkout = job.checkout_with(&repos_root, checkout_options)?;
+    configure(&mut checkout);
+    let checked = checkout.install_toolchain().and_then(|()| {
+        if check_lockfile {
+            checkout.check_lockfile()
+        } else {
+            Ok(())
+        }
+    });
+    let outcome = checked
+        .and_then(|()| checkout.prepare_script(github_client))
+        .and_then(|script| {
+            let repo = format!("{}/{}", job.repository.owner.login, job.repository.name);
+            let key = cache.and_then(|_| {
+                script.cache_key(
+                    &repo,
+                    &job.command,
+                    job.runner.as_deref(),
+                    job.runner_os.as_deref(),
+                )
+            });
+            if let (Some(store), Some(key), false) = (cache, &key, job.force) {
+                match store.cached::<Outcome>(key) {
+                    Ok(Some(cached)) => {
+                        tracing::info!(
+                            "Reporting the result of {} cached for job {}",
+                            cached.run_id,
+                            job.id
+                        );
+                        let from = FromCache {
+                            run_id: cached.run_id,
+                            recorded_at: cached.recorded_at,
+                        };
+                        return Ok((cached.result, Some(from)));
+                    }
+                    Ok(None) => {}
+                    Err(err) => tracing::warn!("Failed to look up a cached result: {err}"),
+                }
+            }
+            let outcome = script.run()?;
+            if let (Some(store), Some(key), None) = (cache, &key, &outcome.failure) {
+                if let Err(err) = store.cache(key, &job.id, &outcome) {
+                    tracing::warn!("Failed to cache the result of job {}: {err}", job.id);
+                }
+            }
+            Ok((outcome, None))
+        });
+    if let Err(err) = job.remove_checkout(&repos_root) {
+        tracing::warn!("Failed to remove checkout of job {}: {err}", job.id);
+    }
+    Ok(outcome?)
+}
+
+#[async_std::main]
+async fn main() -> tide::Result<()> {
+    ci_script::cli::generate::<Config>("cis-gh-reactor");
+    let config = Config::from_iter(ci_script::config::args::<Config>()?);
+    let tenants = load_tenants(&config)?;
+    // Each tenant's dashboard only shows what's logged for it
+    let log_tails: HashMap<String, LogTail> = tenants
+        .iter()
+        .map(|tenant| {
+            let tail = LogTail::new(200).of_tenant(&tenant.name);
+            (tenant.name.clone(), tail)
+        })
+        .collect();
+    let redactor = Redactor::new();
+    let logging = log_tails
+        .values()
+        .fold(
+            Logging::new(config.log_format, config.log_level).redactor(&redactor),
+            |logging, tail| logging.tail(tail),
+        )
+        .otlp(config.otlp_endpoint.clone(), "cis-gh-reactor")
+        .init()?;
+    if let Some(path) = &config.config {
+        tracing::info!("Read settings from {}", path.display());
+    }
+
+    let mut job_env = JobEnv::new(config.job_env.clone());
+    if let Some(path) = &config.job_secrets {
+        job_env = job_env.with_secrets_file(path)?;
+    }
+    for secret in job_env.secret_values() {
+        redactor.add(secret);
+    }
+    for secret in config
+        .github_oauth_client_secret
+        .iter()
+        .chain(&config.ssh_key_passphrase)
+        .chain(&config.gitlab_token)
+        .chain(&config.gitlab_webhook_secret)
+        .chain(&config.sccache_credentials)
+        .chain(&config.gist_token)
+        .chain(&config.auto_merge_token)
+    {
+        redactor.add(secret.as_str());
+    }
+    let gitlab = match (&config.gitlab_token, &config.gitlab_webhook_secret) {
+        (Some(token), Some(secret)) => Some((
+            Gitlab::new(config.gitlab_url.clone(), token.clone()),
+            Arc::new(secret.clone()),
+        )),
+        (Some(_), None) => Err(anyhow::anyhow!(
+            "--gitlab-token requires --gitlab-webhook-secret"
+        ))?,
+        _ => None,
+    };
+    if config.drain_deadline.is_some() && config.state_db.is_none() {
+        Err(anyhow::anyhow!(
+            "--drain-deadline requires --state-db to hand off jobs"
+        ))?
+    }
+
+    let script_library = match &config.script_library {
+        Some(url) => {
+            let dir = config.repos_root.join(".script-library");
+            let credentials = match &config.ssh_key {
+                Some(private_key) => ci_script::api::git::Credentials::SshKey {
+                    private_key: private_key.clone(),
+                    passphrase: config.ssh_key_passphrase.clone(),
+                },
+                None => ci_script::api::git::Credentials::None,
+            };
+            let commit =
+                ci_script::library::sync(url, &config.script_library_rev, &dir, &credentials)?;
+            tracing::info!("Checked out script library {url} at {commit}");
+            Some(dir)
+        }
+        None => None,
+    };
+
+    let tokio_rt = tokio::runtime::Runtime::new()?;
+    let self_url = format!("http://{}:{}", config.address, config.port);
+
+    let mut artifact_recipients: HashMap<String, Vec<openssl::x509::X509>> = HashMap::new();
+    for (repo, path) in &config.repo_artifact_recipients {
+        artifact_recipients
+            .entry(repo.clone())
+            .or_default()
+            .extend(artifacts::load_recipients(&[path])?);
+    }
+    let artifact_recipients = Arc::new(artifact_recipients);
+
+    let results_policy = ci_script::results::Policy {
+        threshold: config.regression_threshold,
+        size_threshold: config.size_regression_threshold,
+        ..Default::default()
+    };
+
+    let journal = match &config.state_db {
+        Some(path) => Journal::open(path)?,
+        None => Journal::open_in_memory()?,
+    };
+
+    let tokens = match &config.auth_tokens {
+        Some(path) => Some(Arc::new(auth::Tokens::from_file(path)?)),
+        None => None,
+    };
+    let client_cert = if config.client_cert_subjects.is_empty() {
+        None
+    } else {
+        Some(Arc::new(auth::ClientCert::new(
+            config.client_cert_header.clone(),
+            config.client_cert_subjects.clone(),
+        )))
+    };
+    // Sessions only live in memory, so a random secret is good enough
+    let session_secret = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let queue_token = uuid::Uuid::new_v4().simple().to_string();
+
+    let listener = match ci_script::handover::inherited_listener() {
+        Some(listener) => {
+            tracing::info!("Listening on the socket passed by systemd");
+            listener
+        }
+        None => {
+            let address = (config.address.as_str(), config.port)
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Can't resolve {}", config.address))?;
+            ci_script::handover::bind(address, config.reuse_port)?
+        }
+    };
+    let (drain, draining) = async_std::channel::bounded::<()>(1);
+
+    let mut app = tide::new();
+    let mut servers = HashMap::new();
+    let mut workers = vec![];
+    let mut backfills = vec![];
+    let mut queues = vec![];
+    let mut probes = vec![];
+    let reloader = Reloader::new(logging.level());
+    for tenant in tenants {
+        redactor.add(tenant.webhook_secret.as_str());
+        redactor.add_pem(&tenant.app_key);
+        let prefix = tenant.prefix();
+        let log_tail = log_tails[&tenant.name].clone();
+        let results_store = match &config.results_db {
+            Some(path) => Some(ci_script::results::Store::open(tenant.results_db(path))?),
+            None => None,
+        };
+        let github_client = tenant.github_client(&config.github_api_base)?;
+        // Workers don't share clones, fetching into the same one at the same time isn't safe
+        let worker_roots: Vec<PathBuf> = if config.workers > 1 {
+            (0..config.workers)
+                .map(|index| tenant.repos_root.join(format!("worker-{index}")))
+                .collect()
+        } else {
+            vec![tenant.repos_root.clone()]
+        };
+        let max_root_size = config
+            .max_repos_size
+            .map(|size| size / worker_roots.len() as u64);
+        let state = State {
+            tenant: tenant.name.clone(),
+            pending: Arc::new(Mutex::new(Pending::restore(journal.scoped(&tenant.name))?)),
+            queue: Arc::new(Mutex::new({
+                let mut queue = PersistentQueue::restore(journal.scoped(&tenant.name))?;
+                let weights = config.repo_queue_weight.iter().cloned().collect();
+                queue.set_fair(Fair::new(repository_of, weights));
+                queue
+            })),
+            history: Arc::new(std::sync::Mutex::new(learned_history(
+                &journal,
+                &tenant.name,
+            ))),
+            log_tail: log_tail.clone(),
+            results_store: results_store.clone(),
+            janitors: worker_roots
+                .iter()
+                .map(|root| Janitor::new(root, max_root_size))
+                .collect(),
+            journal: journal.scoped(&tenant.name),
+            reloader: reloader.clone(),
+            workers: config.workers,
+            runners: Runners::default(),
+            metrics: Metrics::new(&tenant.name),
+        };
+        let settings = LiveSettings::new(Settings::new(&config, &tenant));
+
+        let mut server = tide::with_state(state.clone());
+        let intake = Intake {
+            tenant: tenant.name.clone(),
+            queue: state.queue.clone(),
+            pending: state.pending.clone(),
+            conversations: Default::default(),
+            github_client: github_client.clone(),
+            settings: settings.clone(),
+            supersede: config.supersede,
+            require_approval: config.require_approval,
+            user_rate: RateLimiter::new(config.user_rate_limit),
+            max_shards: config.max_shards,
+            results_store: results_store.clone(),
+            gitlab: gitlab.as_ref().map(|(gitlab, _)| gitlab.clone()),
+            history: state.history.clone(),
+            workers: config.workers,
+            repo_runners: Arc::new(config.repo_runner.iter().cloned().collect()),
+            label_triggers: Arc::new(config.repo_label_trigger.iter().cloned().fold(
+                HashMap::new(),
+                |mut triggers, (repo, trigger)| {
+                    triggers.entry(repo).or_default().push(trigger);
+                    triggers
+                },
+            )),
+        };
+        tokio_rt.spawn(watch_runners(
+            intake.clone(),
+            state.runners.clone(),
+            (
+                Duration::from_secs(config.runner_timeout),
+                Duration::from_secs(config.runner_grace),
+            ),
+            config.comment_lost_runners,
+            config.max_attempts,
+        ));
+        // GitLab has no Apps, its projects are all served by the default tenant
+        if let (Some((_, secret)), DEFAULT_TENANT) = (&gitlab, tenant.name.as_str()) {
+            let intake = intake.clone();
+            let secret = secret.clone();
+            let tokio_handle = tokio_rt.handle().clone();
+            server
+                .at("/gitlab")
+                .with(DeliveryDedup {
+                    tenant: tenant.name.clone(),
+                    journal: journal.scoped(&tenant.name),
+                })
+                .post(move |req| {
+                    gitlab_webhook(req, intake.clone(), secret.clone(), tokio_handle.clone())
+                });
+            tracing::info!("Serving GitLab projects of {}", config.gitlab_url);
+        }
+        reloader.add(&tenant.name, settings.clone(), intake.user_rate.clone());
+        let api_intake = intake.clone();
+        server
+            .at("/")
+            .with(DeliveryDedup {
+                tenant: tenant.name.clone(),
+                journal: journal.scoped(&tenant.name),
+            })
+            .with(LabelEvents {
+                intake: intake.clone(),
+                webhook_secret: tenant.webhook_secret.clone(),
+                tokio_handle: tokio_rt.handle().clone(),
+            })
+            .with(ReviewComments {
+                intake: intake.clone(),
+                webhook_secret: tenant.webhook_secret.clone(),
+                tokio_handle: tokio_rt.handle().clone(),
+            })
+            .nest(webhook(&tenant, intake, tokio_rt.handle().clone()));
+
+        let github_oauth = match (
+            &config.github_oauth_client_id,
+            &config.github_oauth_client_secret,
+            &config.public_url,
+        ) {
+            (Some(client_id), Some(client_secret), Some(public_url)) => {
+                Some(Arc::new(auth::GithubOAuth::new(
+                    client_id.clone(),
+                    client_secret.clone(),
+                    format!("{}{prefix}/auth/callback", public_url.trim_end_matches('/')),
+                    prefix.clone(),
+                    config.github_oauth_users.clone(),
+                )))
+            }
+            _ => None,
+        };
+        let schemes = AuthSchemes {
+            tokens: tokens.clone(),
+            client_cert: client_cert.clone(),
+            github_oauth: github_oauth.clone(),
+        };
+        let mut worker_auth = schemes.authenticate(&config.worker_auth)?;
+        if !config.worker_auth.is_empty() {
+            worker_auth = worker_auth.with(auth::Tokens::new(vec![queue_token.clone()]));
+        }
+        let dashboard_auth = schemes.authenticate(&config.dashboard_auth)?;
+        let admin_auth = schemes.authenticate(&config.admin_auth)?;
+        if let Some(github_oauth) = github_oauth {
+            server.with(
+                tide::sessions::SessionMiddleware::new(
+                    tide::sessions::MemoryStore::new(),
+                    session_secret.as_bytes(),
+                )
+                .with_cookie_name(format!("cis.{}.sid", tenant.name))
+                .with_same_site_policy(tide::http::cookies::SameSite::Lax)
+                .without_save_unchanged(),
+            );
+            server.at("/auth/callback").get(move |req| {
+                let github_oauth = github_oauth.clone();
+                async move { github_oauth.callback(req).await }
+            });
+        }
+
+        server
+            .at("/queue/remove")
+            .with(worker_auth.clone())
+            .post(remove_from_queue);
+        server
+            .at("/queue/heartbeat")
+            .with(worker_auth)
+            .post(heartbeat);
+        server
+            .at("/runners")
+            .with(dashboard_auth.clone())
+            .get(runners_status);
+        server
+            .at("/queue")
+            .with(dashboard_auth.clone())
+            .get(queue_status);
+        server
+            .at("/dashboard")
+            .with(dashboard_auth.clone())
+            .get(dashboard);
+        server
+            .at("/metrics")
+            .with(dashboard_auth.clone())
+            .get(metrics);
+        server
+            .at("/results/compare")
+            .with(dashboard_auth.clone())
+            .get(results_compare);
+        server
+            .at("/admin/gc")
+            .with(admin_auth.clone())
+            .post(collect_garbage);
+        server
+            .at("/admin/reload")
+            .with(admin_auth.clone())
+            .post(reload_settings);
+        server
+            .at("/admin/queue/pause")
+            .with(admin_auth.clone())
+            .post(pause_queue);
+        server
+            .at("/admin/queue/resume")
+            .with(admin_auth.clone())
+            .post(resume_queue);
+        // Imported jobs run whatever they say, so only admins may
+        if !config.admin_auth.is_empty() {
+            server
+                .at("/admin/queue/export")
+                .with(admin_auth.clone())
+                .get(export_queue);
+            let import_intake = api_intake.clone();
+            server
+                .at("/admin/queue/import")
+                .with(admin_auth.clone())
+                .post(move |req| import_queue(req, import_intake.clone()));
+        }
+        let branch_cleanup = BranchCleanup {
+            tenant: tenant.name.clone(),
+            github_client: github_client.clone(),
+            settings: settings.clone(),
+            tokio_handle: tokio_rt.handle().clone(),
+        };
+        if let Some(hours) = config.branch_cleanup_hours {
+            async_std::task::spawn(
+                branch_cleanup
+                    .clone()
+                    .every(Duration::from_secs(hours * 60 * 60)),
+            );
+        }
+        server
+            .at("/admin/branches/clean-up")
+            .with(admin_auth)
+            .post(move |_| clean_up_branches(branch_cleanup.clone()));
+        if !config.submit_auth.is_empty() {
+            let submit_auth = schemes.authenticate(&config.submit_auth)?;
+            let url = config.public_url.as_deref().unwrap_or(&self_url);
+            let jobs_url = Arc::new(format!("{}{prefix}/jobs", url.trim_end_matches('/')));
+            let tokio_handle = tokio_rt.handle().clone();
+            server
+                .at("/jobs")
+                .with(submit_auth.clone())
+                .post(move |req| {
+                    submit_job(
+                        req,
+                        api_intake.clone(),
+                        jobs_url.clone(),
+                        tokio_handle.clone(),
+                    )
+                });
+            server.at("/jobs/:id").with(submit_auth).get(job_status);
+        }
+        let artifacts = match &config.artifacts_dir {
+            Some(dir) => {
+                let dir = dir.join(&tenant.name);
+                std::fs::create_dir_all(&dir)?;
+                server
+                    .at("/artifacts")
+                    .with(dashboard_auth)
+                    .serve_dir(&dir)?;
+                let url = config.public_url.as_deref().unwrap_or(&self_url);
+                Some((
+                    dir,
+                    format!("{}{prefix}/artifacts", url.trim_end_matches('/')),
+                ))
+            }
+            None => None,
+        };
+
+        tracing::info!(
+            "Serving tenant {} (app {}) on {self_url}{prefix}/",
+            tenant.name,
+            tenant.app_id
+        );
+        servers.insert(tenant.app_id, server.clone());
+        app.at(if prefix.is_empty() { "/" } else { &prefix })
+            .nest(server);
+
+        if config.backfill_deliveries.is_some() {
+            backfills.push((
+                tenant.name.clone(),
+                github_client.clone(),
+                journal.scoped(&tenant.name),
+            ));
+        }
+
+        let worker = Worker {
+            queue: state.queue.clone(),
+            queue_token: queue_token.clone(),
+            journal: journal.scoped(&tenant.name),
+            shards: Coordinator::new(journal.scoped(&tenant.name)),
+            max_attempts: config.max_attempts,
+            max_job_retries: config.max_job_retries,
+            job_retry_delay: Duration::from_secs(config.job_retry_delay),
+            settings,
+            warm_up: config.warm_up,
+            commit_status: config.commit_status,
+            auto_merge: config.repo_auto_merge.iter().cloned().collect(),
+            auto_merge_token: config.auto_merge_token.clone(),
+            report_usage: config.report_usage,
+            gist_token: config.gist_token.clone(),
+            job_limits: ci_script::api::quota::Limits {
+                memory: config.job_memory_limit,
+                disk: config.job_disk_limit,
+            },
+            cgroup_root: config.cgroup_root.clone(),
+            status_context: tenant.command_prefix.trim_start_matches('/').to_string(),
+            job_env: job_env.clone(),
+            redactor: redactor.clone(),
+            clone_strategy: config.clone_strategy,
+            repo_clone_strategies: config.repo_clone_strategy.iter().cloned().collect(),
+            repo_clone_mirrors: config.repo_clone_mirror.iter().cloned().collect(),
+            build_cache: config.build_cache,
+            repo_build_caches: config.repo_build_cache.iter().cloned().collect(),
+            sccache: ci_script::api::cargo::sccache::Config {
+                endpoint: config.sccache_endpoint.clone(),
+                credentials: config.sccache_credentials.clone(),
+            },
+            skip_submodules: config.skip_submodules.clone(),
+            check_lockfile: config.check_lockfile.clone(),
+            profile_repos: config.profile_repos.clone(),
+            ssh_key: config
+                .ssh_key
+                .clone()
+                .map(|key| (key, config.ssh_key_passphrase.clone())),
+            script_library: script_library.clone(),
+            commits: ci_script::api::git::CommitConfig {
+                author: config.commit_author.clone(),
+                committer: config.commit_committer.clone(),
+                signing_key: match (&config.commit_gpg_key, &config.commit_ssh_key) {
+                    (Some(key), _) => Some(ci_script::api::git::SigningKey::Gpg(key.clone())),
+                    (_, Some(key)) => Some(ci_script::api::git::SigningKey::Ssh(key.clone())),
+                    _ => None,
+                },
+            },
+            rustup_home: None,
+            cargo_output_limit: config.cargo_output_limit as usize,
+            pin_cores: config
+                .pin_cores
+                .clone()
+                .map(|cores| (cores, config.performance_governor)),
+            runner: config.runner.clone(),
+            runner_os: config.runner_os.clone(),
+            name: ci_script::platform::hostname(),
+            result_webhook: config
+                .result_webhook
+                .clone()
+                .map(|url| Webhook::new(url, config.result_webhook_secret.clone())),
+            artifacts,
+            artifact_recipients: artifact_recipients.clone(),
+            tenant: tenant.name,
+            queue_url: format!("{self_url}{prefix}"),
+            repos_root: tenant.repos_root,
+            github_client,
+            gitlab: gitlab.as_ref().map(|(gitlab, _)| gitlab.clone()),
+            tokio_handle: tokio_rt.handle().clone(),
+            results_store: results_store.clone(),
+            results_policy,
+            refresh_policy: ci_script::results::RefreshPolicy {
+                max_commits: config.baseline_max_commits,
+                max_age: config
+                    .baseline_max_age
+                    .map(|hours| std::time::Duration::from_secs(hours * 3600)),
+            },
+            history: state.history,
+            log_tail: log_tail.clone(),
+            metrics: state.metrics.clone(),
+            janitor: state.janitors[0].clone(),
+            draining: draining.clone(),
+            drain_deadline: config.drain_deadline.map(Duration::from_secs),
+        };
+        queues.push((worker.tenant.clone(), state.queue.clone()));
+        probes.push(Probe {
+            tenant: worker.tenant.clone(),
+            app_id: tenant.app_id,
+            app_key: tenant.app_key.clone(),
+            journal: worker.journal.clone(),
+            repos_root: worker.repos_root.clone(),
+        });
+        for (index, (repos_root, janitor)) in
+            worker_roots.into_iter().zip(state.janitors).enumerate()
+        {
+            workers.push(Worker {
+                name: format!("{}/{}#{index}", worker.name, worker.tenant),
+                journal: worker.journal.worker(index),
+                rustup_home: config.isolate_toolchains.then(|| repos_root.join("rustup")),
+                repos_root,
+                janitor,
+                ..worker.clone()
+            });
+        }
+    }
+    app.with(TenantRouter { servers });
+    // Unauthenticated, for the likes of Kubernetes to tell whether to route traffic here
+    app.at("/healthz").get(|_| async { Ok("ok") });
+    let probes = Arc::new(probes);
+    app.at("/readyz").get(move |_| readyz(probes.clone()));
+
+    let state_db = config.state_db.clone();
+    let backfill_hours = config.backfill_deliveries;
+    let tokio_handle = tokio_rt.handle().clone();
+    // Like `systemctl reload`, Windows has no such signal
+    #[cfg(unix)]
+    {
+        let mut hangups = async_signal::Signals::new([async_signal::Signal::Hup])?;
+        async_std::task::spawn(async move {
+            while let Some(Ok(_)) = hangups.next().await {
+                if let Err(err) = reloader.reload() {
+                    tracing::warn!("Failed to reload settings: {err:#}");
+                }
+            }
+        });
+    }
+    let run = async move {
+        // Processes sharing the state take turns running jobs, a new one accepts webhooks while
+        // the previous one drains
+        let lock = match state_db {
+            Some(path) => {
+                let mut path = path.into_os_string();
+                path.push(".lock");
+                let lock = async_std::task::spawn_blocking(move || {
+                    ci_script::handover::ProcessLock::acquire(path)
+                });
+                Some(lock.await?)
+            }
+            None => None,
+        };
+        // Another process sharing the journal may have taken some queued jobs before handing over
+        for (tenant, queue) in queues {
+            if let Err(err) = queue.lock().await.reload() {
+                tracing::warn!("[{tenant}] Failed to reload the queue: {err}");
+            }
+        }
+        let workers: Vec<_> = workers
+            .into_iter()
+            .map(|worker| async_std::task::spawn(worker.run()))
+            .collect();
+        // Once the previous process is done, so it's not asked for what it's still processing
+        if let Some(hours) = backfill_hours {
+            for (tenant, github_client, journal) in backfills {
+                tokio_handle.spawn(async move {
+                    match backfill_deliveries(&github_client, &journal, hours).await {
+                        Ok(0) => {}
+                        Ok(requested) => tracing::info!(
+                            "[{tenant}] Asked Github to deliver {requested} missed webhooks again"
+                        ),
+                        Err(err) => {
+                            tracing::warn!("[{tenant}] Failed to backfill missed webhooks: {err}")
+                        }
+                    }
+                });
+            }
+        }
+        ci_script::handover::stop_requested().await?;
+        anyhow::Ok((lock, workers))
+    };
+    // Stops listening when asked to stop, by dropping the listener
+    let (_lock, workers) = futures_lite::future::or(
+        async {
+            app.listen(listener).await?;
+            anyhow::bail!("Stopped listening")
+        },
+        run,
+    )
+    .await?;
+    tracing::info!("Draining: no longer accepting webhooks, finishing running jobs");
+    drop(drain);
+    for worker in workers {
+        worker.await;
+    }
     Ok(())
 }
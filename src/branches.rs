@@ -0,0 +1,172 @@
+//! Deleting the branches scripts opened pull requests from (see `--pr-branch-template`) once
+//! those pull requests are merged or closed. Scripts only clean up after themselves when they
+//! open another pull request on the same repository, which leaves hundreds of `benchbot/...`
+//! branches behind on repositories they rarely open pull requests on.
+
+use crate::api::Error;
+use octocrab::Octocrab;
+use serde::{Deserialize, Serialize};
+
+/// Repositories on a page of the repositories of an installation, Github's largest page
+const PER_PAGE: usize = 100;
+
+/// What a cleanup went through and deleted
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Cleaned {
+    /// Number of repositories the branches were looked for in
+    pub repositories: usize,
+    /// As `owner/name:branch`
+    pub deleted: Vec<String>,
+    /// Repositories (as `owner/name`) or installations (as their ID) that couldn't be cleaned
+    /// up, with why
+    pub failed: Vec<(String, String)>,
+}
+
+#[derive(Deserialize)]
+struct Repositories {
+    repositories: Vec<Repository>,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    name: String,
+    owner: Owner,
+}
+
+#[derive(Deserialize)]
+struct Owner {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Ref {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+/// Delete the branches starting with `prefix` in all repositories the app of `app_client` is
+/// installed on, whose pull requests are all merged or closed. Branches no pull request was ever
+/// opened from are left alone, a script may still be about to.
+///
+/// Repositories and installations that fail are logged and skipped, so one of them going wrong
+/// doesn't keep the others from being cleaned up.
+pub async fn clean_up(app_client: &Octocrab, prefix: &str) -> Result<Cleaned, Error> {
+    let mut cleaned = Cleaned::default();
+    let first_page = app_client
+        .apps()
+        .installations()
+        .per_page(PER_PAGE as u8)
+        .send()
+        .await?;
+    let installations = app_client.all_pages(first_page).await?;
+    for installation in installations {
+        if let Err(err) =
+            clean_up_installation(app_client, &installation, prefix, &mut cleaned).await
+        {
+            tracing::warn!(
+                "Failed to clean up the branches of installation {}: {err}",
+                installation.id
+            );
+            cleaned
+                .failed
+                .push((installation.id.to_string(), err.to_string()));
+        }
+    }
+    Ok(cleaned)
+}
+
+/// Clean up the repositories of `installation`, adding them to `cleaned`
+async fn clean_up_installation(
+    app_client: &Octocrab,
+    installation: &octocrab::models::Installation,
+    prefix: &str,
+    cleaned: &mut Cleaned,
+) -> Result<(), Error> {
+    let access_tokens_url = match &installation.access_tokens_url {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+    // Of all repositories of the installation
+    let access: octocrab::models::InstallationToken = app_client
+        .post(
+            access_tokens_url,
+            Some(&octocrab::params::apps::CreateInstallationAccessToken::default()),
+        )
+        .await?;
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(app_client.base_url.clone())?
+        .personal_token(access.token)
+        .build()?;
+    for page in 1.. {
+        let repositories: Repositories = client
+            .get(
+                format!("installation/repositories?per_page={PER_PAGE}&page={page}"),
+                None::<&()>,
+            )
+            .await?;
+        let last_page = repositories.repositories.len() < PER_PAGE;
+        for repository in repositories.repositories {
+            cleaned.repositories += 1;
+            let full_name = format!("{}/{}", repository.owner.login, repository.name);
+            match clean_up_repository(&client, &repository.owner.login, &repository.name, prefix)
+                .await
+            {
+                Ok(deleted) => cleaned.deleted.extend(
+                    deleted
+                        .into_iter()
+                        .map(|branch| format!("{full_name}:{branch}")),
+                ),
+                Err(err) => {
+                    tracing::warn!("Failed to clean up the branches of {full_name}: {err}");
+                    cleaned.failed.push((full_name, err.to_string()));
+                }
+            }
+        }
+        if last_page {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Delete the branches starting with `prefix` of the repository `owner/name` whose pull
+/// requests are all merged or closed, returning their names
+async fn clean_up_repository(
+    client: &Octocrab,
+    owner: &str,
+    name: &str,
+    prefix: &str,
+) -> Result<Vec<String>, Error> {
+    let refs: Vec<Ref> = client
+        .get(
+            format!("repos/{owner}/{name}/git/matching-refs/heads/{prefix}"),
+            None::<&()>,
+        )
+        .await?;
+    let mut deleted = vec![];
+    for branch in refs {
+        let branch = branch.name.trim_start_matches("refs/heads/").to_string();
+        let pulls = client
+            .pulls(owner, name)
+            .list()
+            .state(octocrab::params::State::All)
+            .head(format!("{owner}:{branch}"))
+            .send()
+            .await?;
+        let done = !pulls.items.is_empty()
+            && pulls
+                .items
+                .iter()
+                .all(|pull| pull.state == Some(octocrab::models::IssueState::Closed));
+        if !done {
+            continue;
+        }
+        tracing::info!(
+            "Deleting the branch {branch} of {owner}/{name}, its pull requests are done"
+        );
+        let url = client.absolute_url(format!("repos/{owner}/{name}/git/refs/heads/{branch}"))?;
+        octocrab::map_github_error(client._delete(url, None::<&()>).await?).await?;
+        deleted.push(branch);
+    }
+    Ok(deleted)
+}
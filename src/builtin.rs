@@ -0,0 +1,12 @@
+//! Scripts the bot comes with, for what most repositories would otherwise copy into their own.
+//! A command runs the built-in script of its name when the repository has neither a script nor a
+//! pipeline of that name, like `/benchbot weights pallet_balances`. Like any script, built-in ones
+//! get the arguments after their name as `ARGS`, see [`crate::api::args`].
+
+/// The source of the built-in script `name`
+pub fn script(name: &str) -> Option<&'static str> {
+    match name {
+        "weights" => Some(include_str!("builtin/weights.rhai")),
+        _ => None,
+    }
+}
@@ -0,0 +1,63 @@
+//! Shell completions and man pages of the command line tools, generated from their options.
+
+use std::io::Write;
+use structopt::clap::{App, Shell};
+use structopt::StructOpt;
+
+/// Shown at the end of the `--help` of every tool
+pub const GENERATE_HELP: &str = "Run with `--completions <bash|zsh|fish|powershell|elvish>` to \
+                                 print shell completions, or with `--man` to print a man page.";
+
+/// Print shell completions or a man page for the options `T` of the tool `bin`, and exit, if
+/// that's what the command line asks for. Has to be checked before parsing the options, which
+/// fails without the required ones.
+pub fn generate<T: StructOpt>(bin: &str) {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut app = T::clap();
+    match args.as_slice() {
+        ["--completions", shell] => match shell.parse::<Shell>() {
+            Ok(shell) => app.gen_completions_to(bin, shell, &mut std::io::stdout()),
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(2);
+            }
+        },
+        ["--man"] => {
+            if let Err(err) = write_man_page(&mut app, bin, &mut std::io::stdout()) {
+                eprintln!("Failed to write man page: {err}");
+                std::process::exit(1);
+            }
+        }
+        _ => return,
+    }
+    std::process::exit(0);
+}
+
+/// A man page with the long `--help` as its description
+pub fn write_man_page<W: Write>(app: &mut App, bin: &str, out: &mut W) -> std::io::Result<()> {
+    let mut help = vec![];
+    app.write_long_help(&mut help)
+        .map_err(|err| std::io::Error::other(err.to_string()))?;
+    let help = String::from_utf8_lossy(&help);
+
+    writeln!(out, ".TH {} 1", bin.to_uppercase())?;
+    writeln!(out, ".SH NAME")?;
+    writeln!(out, "{}", roff_escape(bin))?;
+    writeln!(out, ".SH DESCRIPTION")?;
+    writeln!(out, ".nf")?;
+    for line in help.lines() {
+        writeln!(out, "{}", roff_escape(line))?;
+    }
+    writeln!(out, ".fi")
+}
+
+fn roff_escape(line: &str) -> String {
+    let line = line.replace('\\', "\\e");
+    // Lines starting with these would be taken as requests
+    if line.starts_with('.') || line.starts_with('\'') {
+        format!("\\&{line}")
+    } else {
+        line
+    }
+}
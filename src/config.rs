@@ -0,0 +1,196 @@
+//! Settings of a tool read from a TOML file given with `--config`, for when there are too many
+//! flags to manage. Flags win over environment variables, which win over the file:
+//!
+//! ```toml
+//! log_level = "debug"
+//!
+//! # Tables only group settings, their keys are the long names of the flags
+//! [queue]
+//! state_db = "/var/lib/cis/state.db"
+//! max_queued_per_repo = 10
+//!
+//! [workers]
+//! workers = 4
+//! drain_deadline = 600
+//!
+//! # Except for the ones of specific repositories
+//! [repos."paritytech/substrate"]
+//! clone_strategy = "blobless" # --repo-clone-strategy paritytech/substrate=blobless
+//! skip_submodules = true      # --skip-submodules paritytech/substrate
+//! ```
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use structopt::clap::ErrorKind;
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read config file {0:?}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Invalid config file {0:?}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("Unknown setting {0:?} in the config file")]
+    UnknownSetting(String),
+    #[error("Invalid value of {0:?} in the config file, expected a string, number, boolean or array of them")]
+    InvalidValue(String),
+}
+
+/// Table of the settings of specific repositories, by `<owner>/<name>`
+const REPOS: &str = "repos";
+
+/// Environment variables set from the file, which don't take precedence when reading it again
+static FROM_FILE: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// The command line arguments to parse `T` from, with the settings of the `--config` file (or
+/// the one in `CONFIG`) added. Settings that have no flag on the command line and whose
+/// environment variable isn't set are set in the environment, except arrays, which are added to
+/// the arguments since not every repeatable flag splits its variable. Settings of repositories
+/// given on the command line are added to the file's, winning for the same repository. Can be
+/// called again to pick up changes to the file.
+pub fn args<T: StructOpt>() -> Result<Vec<OsString>, Error> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    match path(&args) {
+        Some(path) => layer::<T>(&path, args),
+        None => Ok(args),
+    }
+}
+
+fn path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os("CONFIG").map(PathBuf::from)
+}
+
+fn layer<T: StructOpt>(path: &Path, mut args: Vec<OsString>) -> Result<Vec<OsString>, Error> {
+    let file = std::fs::read_to_string(path).map_err(|e| Error::Read(path.to_owned(), e))?;
+    let table: toml::value::Table =
+        toml::from_str(&file).map_err(|e| Error::Parse(path.to_owned(), e))?;
+    let mut settings = vec![];
+    flatten(table, &mut settings)?;
+    let mut from_file = FROM_FILE.lock().unwrap_or_else(|e| e.into_inner());
+    for var in from_file.drain(..) {
+        std::env::remove_var(var);
+    }
+    let mut layered = vec![];
+    for (name, value) in settings {
+        if !has_flag::<T>(&name) {
+            return Err(Error::UnknownSetting(name));
+        }
+        let flag = format!("--{name}");
+        let on_command_line = args.iter().skip(1).any(|arg| {
+            let arg = arg.to_string_lossy();
+            arg == flag || arg.starts_with(&format!("{flag}="))
+        });
+        let var = name.replace('-', "_").to_uppercase();
+        if std::env::var_os(&var).is_some()
+            || on_command_line && !matches!(value, Value::Repositories(_))
+        {
+            continue;
+        }
+        match value {
+            Value::Single(value) => {
+                std::env::set_var(&var, value);
+                from_file.push(var);
+            }
+            Value::Multiple(values) | Value::Repositories(values) => layered.extend(
+                values
+                    .into_iter()
+                    .map(|value| format!("{flag}={value}").into()),
+            ),
+        }
+    }
+    // Before the arguments of the command line, so the repository settings given there win
+    args.splice(1..1, layered);
+    Ok(args)
+}
+
+/// A setting as given to clap
+enum Value {
+    /// Booleans are flags set by their variable being there, so only true ones are
+    Single(String),
+    /// Repeatable
+    Multiple(Vec<String>),
+    /// Repeatable, for specific repositories
+    Repositories(Vec<String>),
+}
+
+/// The settings of `table` by flag name, looking into the tables grouping them
+fn flatten(table: toml::value::Table, settings: &mut Vec<(String, Value)>) -> Result<(), Error> {
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+        match value {
+            toml::Value::Table(repos) if key == REPOS => {
+                for (repo, repo_settings) in repos {
+                    let repo_settings = match repo_settings {
+                        toml::Value::Table(table) => table,
+                        _ => return Err(Error::InvalidValue(format!("{REPOS}.{repo}"))),
+                    };
+                    for (key, value) in repo_settings {
+                        let name = key.replace('_', "-");
+                        match value {
+                            // Lists of repositories, like `--skip-submodules`
+                            toml::Value::Boolean(true) => {
+                                settings.push((name, Value::Repositories(vec![repo.clone()])))
+                            }
+                            toml::Value::Boolean(false) => {}
+                            value => {
+                                let values = scalars(&key, value)?
+                                    .into_iter()
+                                    .map(|value| format!("{repo}={value}"))
+                                    .collect();
+                                settings
+                                    .push((format!("repo-{name}"), Value::Repositories(values)));
+                            }
+                        }
+                    }
+                }
+            }
+            toml::Value::Table(table) => flatten(table, settings)?,
+            toml::Value::Boolean(false) => {}
+            toml::Value::Array(_) => settings.push((name, Value::Multiple(scalars(&key, value)?))),
+            value => settings.push((name, Value::Single(scalar(&key, value)?))),
+        }
+    }
+    Ok(())
+}
+
+fn scalars(key: &str, value: toml::Value) -> Result<Vec<String>, Error> {
+    match value {
+        toml::Value::Array(values) => values.into_iter().map(|value| scalar(key, value)).collect(),
+        value => Ok(vec![scalar(key, value)?]),
+    }
+}
+
+fn scalar(key: &str, value: toml::Value) -> Result<String, Error> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Datetime(d) => Ok(d.to_string()),
+        _ => Err(Error::InvalidValue(key.to_string())),
+    }
+}
+
+/// Whether `T` has the flag `--<name>`. clap has no public way to list them, but tells flags it
+/// doesn't know from ones missing their value.
+fn has_flag<T: StructOpt>(name: &str) -> bool {
+    let args = ["", &format!("--{name}")];
+    match T::clap().get_matches_from_safe(args) {
+        Err(err) => !matches!(
+            err.kind,
+            ErrorKind::UnknownArgument | ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed
+        ),
+        Ok(_) => true,
+    }
+}
@@ -0,0 +1,175 @@
+use crate::api::phases::format_duration;
+use crate::history::{History, Record, Status};
+use crate::Job;
+
+/// Render the dashboard showing the queue, the jobs awaiting approval (by approval ID), the
+/// running jobs (including the tail of the log) and the most recently completed jobs of `tenant`.
+pub fn render<'a, I, P>(
+    tenant: &str,
+    queued: I,
+    pending: P,
+    history: &History,
+    log_tail: &[String],
+) -> String
+where
+    I: IntoIterator<Item = &'a Job>,
+    P: IntoIterator<Item = (&'a String, &'a Job)>,
+{
+    let queued = queued.into_iter().collect::<Vec<_>>();
+    let queued_rows = queued
+        .iter()
+        .enumerate()
+        .map(|(pos, job)| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                pos + 1,
+                escape(&job.id),
+                escape(&job.command.join(" ")),
+                issue_link(job),
+            )
+        })
+        .collect::<String>();
+    let queued_table = if queued.is_empty() {
+        "<p>The queue is empty.</p>".to_string()
+    } else {
+        format!(
+            "<table><tr><th>#</th><th>Job</th><th>Command</th><th>Issue</th></tr>{queued_rows}</table>"
+        )
+    };
+
+    let pending_rows = pending
+        .into_iter()
+        .map(|(approval_id, job)| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(approval_id),
+                escape(&job.id),
+                escape(&job.command.join(" ")),
+                issue_link(job),
+            )
+        })
+        .collect::<String>();
+    let pending = if pending_rows.is_empty() {
+        "<p>No jobs are awaiting approval.</p>".to_string()
+    } else {
+        format!(
+            "<table><tr><th>Approval ID</th><th>Job</th><th>Command</th><th>Issue</th></tr>{pending_rows}</table>"
+        )
+    };
+
+    let running_rows = history
+        .running()
+        .iter()
+        .map(|record| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td><a href=\"{}\">issue</a></td><td>{}</td></tr>",
+                escape(&record.id),
+                escape(&record.repository),
+                escape(&record.command),
+                record.issue_url,
+                format_duration(record.started_at.elapsed().unwrap_or_default()),
+            )
+        })
+        .collect::<String>();
+    let running = if running_rows.is_empty() {
+        "<p>No job is running.</p>".to_string()
+    } else {
+        format!(
+            "<table><tr><th>Job</th><th>Repository</th><th>Command</th><th>Issue</th><th>Running for</th></tr>{running_rows}</table>\
+             <pre>{}</pre>",
+            escape(&log_tail.join("\n")),
+        )
+    };
+
+    let completed_rows = history.completed().map(completed_row).collect::<String>();
+    let completed = if completed_rows.is_empty() {
+        "<p>No jobs have completed yet.</p>".to_string()
+    } else {
+        format!(
+            "<table><tr><th>Job</th><th>Repository</th><th>Command</th><th>Issue</th>\
+             <th>Duration</th><th>Phases</th><th>Outcome</th><th>Result</th></tr>{completed_rows}</table>"
+        )
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <meta http-equiv="refresh" content="5">
+  <title>CI Script Dashboard - {tenant}</title>
+  <style>
+    body {{ font-family: sans-serif; margin: 2em; }}
+    table {{ border-collapse: collapse; margin-bottom: 2em; }}
+    th, td {{ border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }}
+    pre {{ background: #111; color: #ddd; padding: 1em; max-height: 30em; overflow: auto; }}
+    .succeeded {{ color: green; }}
+    .failed {{ color: darkred; }}
+  </style>
+</head>
+<body>
+  <h1>CI Script Dashboard - {tenant}</h1>
+  <h2>Queued ({queued_count})</h2>
+  {queued_table}
+  <h2>Awaiting approval</h2>
+  {pending}
+  <h2>Running</h2>
+  {running}
+  <h2>Recently completed</h2>
+  {completed}
+</body>
+</html>
+"#,
+        tenant = escape(tenant),
+        queued_count = queued.len(),
+    )
+}
+
+fn completed_row(record: &Record) -> String {
+    let class = match record.status {
+        Status::Succeeded => "succeeded",
+        _ => "failed",
+    };
+    let result = match &record.comment_url {
+        Some(url) => format!("<a href=\"{url}\">comment</a>"),
+        None => "".to_string(),
+    };
+    let phases = record
+        .phases
+        .iter()
+        .map(|phase| {
+            format!(
+                "{}: {} (cpu {})",
+                escape(&phase.name),
+                format_duration(phase.wall),
+                format_duration(phase.cpu)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("<br>");
+    format!(
+        "<tr><td>{}</td><td>{}</td><td>{}</td><td><a href=\"{}\">issue</a></td><td>{}</td>\
+         <td>{phases}</td><td class=\"{class}\">{}</td><td>{result}</td></tr>",
+        escape(&record.id),
+        escape(&record.repository),
+        escape(&record.command),
+        record.issue_url,
+        record.duration.map(format_duration).unwrap_or_default(),
+        escape(&record.status.to_string()),
+    )
+}
+
+/// Link to the issue of `job`, or the ref it runs on if it has none
+fn issue_link(job: &Job) -> String {
+    match &job.issue {
+        Some(issue) => format!("<a href=\"{}\">#{}</a>", issue.html_url, issue.number),
+        None => escape(job.branch.as_deref().unwrap_or_default()),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
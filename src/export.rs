@@ -0,0 +1,394 @@
+//! Flat exports of the job queue, the job history and the recorded results, for analysis in
+//! notebooks and the like.
+
+use crate::api::usage::Usage;
+use crate::history::{Record, Status};
+use crate::results::Recorded;
+use crate::Job;
+use serde::Serialize;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Unknown export format {0:?}, expected csv or jsonl")]
+    UnknownFormat(String),
+    #[error("Parquet isn't supported, export as csv or jsonl and convert that")]
+    ParquetUnsupported,
+    #[error("Invalid period {0:?}, expected a number followed by s, m, h, d or w (like 30d)")]
+    InvalidPeriod(String),
+    #[error("Failed to write export: {0}")]
+    Write(#[from] std::io::Error),
+    #[error("Failed to serialize export: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::JsonLines),
+            "parquet" => Err(Error::ParquetUnsupported),
+            _ => Err(Error::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// Parse a period like `90m`, `12h` or `30d`
+pub fn parse_period(s: &str) -> Result<Duration, Error> {
+    let invalid = || Error::InvalidPeriod(s.to_string());
+    let unit = s.chars().last().ok_or_else(invalid)?;
+    let amount: u64 = s[..s.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    Ok(Duration::from_secs(amount * seconds))
+}
+
+/// A queued or finished job
+#[derive(Debug, Serialize)]
+pub struct JobRow {
+    pub tenant: String,
+    pub id: String,
+    pub repository: String,
+    pub command: String,
+    pub issue_url: String,
+    /// Login of whoever requested the job
+    pub user: Option<String>,
+    /// `queued`, `running`, `succeeded` or `failed`
+    pub status: String,
+    pub error: Option<String>,
+    pub machine: Option<String>,
+    /// Unix timestamp
+    pub started_at: Option<i64>,
+    pub duration_secs: Option<f64>,
+    pub comment_url: Option<String>,
+    pub phases: Vec<PhaseRow>,
+    /// What the processes of the job used
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhaseRow {
+    pub name: String,
+    pub wall_secs: f64,
+    pub cpu_secs: f64,
+    pub warm_up: bool,
+}
+
+impl JobRow {
+    pub const COLUMNS: &'static [&'static str] = &[
+        "tenant",
+        "id",
+        "repository",
+        "command",
+        "issue_url",
+        "user",
+        "status",
+        "error",
+        "machine",
+        "started_at",
+        "duration_secs",
+        "comment_url",
+        "phases",
+        "usage",
+    ];
+
+    pub fn queued(tenant: &str, job: &Job) -> Self {
+        JobRow {
+            tenant: tenant.to_string(),
+            id: job.id.clone(),
+            repository: format!("{}/{}", job.repository.owner.login, job.repository.name),
+            command: job.command.join(" "),
+            issue_url: job.issue_url().to_string(),
+            user: job.user.clone(),
+            status: "queued".to_string(),
+            error: None,
+            machine: None,
+            started_at: None,
+            duration_secs: None,
+            comment_url: None,
+            phases: vec![],
+            usage: None,
+        }
+    }
+
+    pub fn finished(tenant: &str, record: &Record) -> Self {
+        let (status, error) = match &record.status {
+            Status::Running => ("running", None),
+            Status::Succeeded => ("succeeded", None),
+            Status::Failed(error) => ("failed", Some(error.clone())),
+        };
+        JobRow {
+            tenant: tenant.to_string(),
+            id: record.id.clone(),
+            repository: record.repository.clone(),
+            command: record.command.clone(),
+            issue_url: record.issue_url.to_string(),
+            user: record.user.clone(),
+            status: status.to_string(),
+            error,
+            machine: Some(record.machine.clone()),
+            started_at: Some(unix_time(record.started_at)),
+            duration_secs: record.duration.map(|d| d.as_secs_f64()),
+            comment_url: record.comment_url.as_ref().map(|url| url.to_string()),
+            phases: record
+                .phases
+                .iter()
+                .map(|phase| PhaseRow {
+                    name: phase.name.clone(),
+                    wall_secs: phase.wall.as_secs_f64(),
+                    cpu_secs: phase.cpu.as_secs_f64(),
+                    warm_up: phase.warm_up,
+                })
+                .collect(),
+            usage: record.usage,
+        }
+    }
+}
+
+/// A single recorded metric
+#[derive(Debug, Serialize)]
+pub struct ResultRow {
+    pub run_id: String,
+    pub repo: String,
+    pub branch: String,
+    pub commit: Option<String>,
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+    /// Unix timestamp
+    pub recorded_at: i64,
+}
+
+impl ResultRow {
+    pub const COLUMNS: &'static [&'static str] = &[
+        "run_id",
+        "repo",
+        "branch",
+        "commit",
+        "name",
+        "value",
+        "unit",
+        "recorded_at",
+    ];
+}
+
+impl From<Recorded> for ResultRow {
+    fn from(recorded: Recorded) -> Self {
+        ResultRow {
+            run_id: recorded.run_id,
+            repo: recorded.repo,
+            branch: recorded.branch,
+            commit: recorded.commit,
+            name: recorded.metric.name,
+            value: recorded.metric.value,
+            unit: recorded.metric.unit,
+            recorded_at: unix_time(recorded.recorded_at),
+        }
+    }
+}
+
+/// Write `rows` with the given columns. In CSV, nested values (like the phases of a job) end up
+/// as JSON in a single column.
+pub fn write<W: Write, T: Serialize>(
+    mut out: W,
+    format: Format,
+    columns: &[&str],
+    rows: &[T],
+) -> Result<(), Error> {
+    match format {
+        Format::JsonLines => {
+            for row in rows {
+                serde_json::to_writer(&mut out, row)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        Format::Csv => {
+            writeln!(out, "{}", columns.join(","))?;
+            for row in rows {
+                let row = serde_json::to_value(row)?;
+                let cells = columns
+                    .iter()
+                    .map(|column| match &row[*column] {
+                        serde_json::Value::Null => String::new(),
+                        serde_json::Value::String(s) => csv_escape(s),
+                        value @ (serde_json::Value::Array(_) | serde_json::Value::Object(_)) => {
+                            csv_escape(&value.to_string())
+                        }
+                        value => value.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(out, "{}", cells.join(","))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periods() {
+        assert_eq!(parse_period("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_period("90m").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse_period("12h").unwrap(), Duration::from_secs(12 * 3600));
+        assert_eq!(
+            parse_period("30d").unwrap(),
+            Duration::from_secs(30 * 86400)
+        );
+        assert_eq!(parse_period("2w").unwrap(), Duration::from_secs(14 * 86400));
+        assert_eq!(parse_period("0d").unwrap(), Duration::ZERO);
+        for invalid in ["", "d", "30", "30y", "-1d", "1.5d", " 1d", "1dd", "3€"] {
+            assert!(
+                matches!(parse_period(invalid), Err(Error::InvalidPeriod(_))),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!("csv".parse::<Format>().unwrap(), Format::Csv);
+        assert_eq!("jsonl".parse::<Format>().unwrap(), Format::JsonLines);
+        assert!(matches!(
+            "parquet".parse::<Format>(),
+            Err(Error::ParquetUnsupported)
+        ));
+        assert!(matches!(
+            "xml".parse::<Format>(),
+            Err(Error::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn csv_cells_are_quoted_when_needed() {
+        assert_eq!(csv_escape("plain text"), "plain text");
+        assert_eq!(csv_escape(""), "");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_escape("cr\r"), "\"cr\r\"");
+    }
+
+    fn job() -> JobRow {
+        JobRow {
+            tenant: "default".to_string(),
+            id: "job-1".to_string(),
+            repository: "paritytech/substrate".to_string(),
+            command: "bench pallet_balances steps=50".to_string(),
+            issue_url: "https://github.com/paritytech/substrate/pull/7".to_string(),
+            user: Some("alice".to_string()),
+            status: "failed".to_string(),
+            error: Some("exit code 1, \"oops\"".to_string()),
+            machine: Some("bench-1".to_string()),
+            started_at: Some(1_600_000_000),
+            duration_secs: Some(1.5),
+            comment_url: None,
+            phases: vec![PhaseRow {
+                name: "build".to_string(),
+                wall_secs: 1.25,
+                cpu_secs: 2.0,
+                warm_up: false,
+            }],
+            usage: Some(Usage {
+                user: Duration::from_secs(2),
+                system: Duration::from_millis(500),
+                max_rss: 1024,
+                read_bytes: 0,
+                written_bytes: 4096,
+            }),
+        }
+    }
+
+    #[test]
+    fn csv_has_nested_values_as_json() {
+        let mut out = vec![];
+        write(&mut out, Format::Csv, JobRow::COLUMNS, &[job()]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(lines.next().unwrap(), JobRow::COLUMNS.join(","));
+        assert_eq!(
+            lines.next().unwrap(),
+            "default,job-1,paritytech/substrate,bench pallet_balances steps=50,\
+             https://github.com/paritytech/substrate/pull/7,alice,failed,\
+             \"exit code 1, \"\"oops\"\"\",bench-1,1600000000,1.5,,\
+             \"[{\"\"cpu_secs\"\":2.0,\"\"name\"\":\"\"build\"\",\"\"wall_secs\"\":1.25,\
+             \"\"warm_up\"\":false}]\",\
+             \"{\"\"max_rss\"\":1024,\"\"read_bytes\"\":0,\"\"system_secs\"\":0.5,\
+             \"\"user_secs\"\":2.0,\"\"written_bytes\"\":4096}\""
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn csv_of_no_rows_has_the_header() {
+        let mut out = vec![];
+        write::<_, ResultRow>(&mut out, Format::Csv, ResultRow::COLUMNS, &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "run_id,repo,branch,commit,name,value,unit,recorded_at\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_keep_nested_values() {
+        let mut out = vec![];
+        write(
+            &mut out,
+            Format::JsonLines,
+            JobRow::COLUMNS,
+            &[job(), job()],
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        let row: serde_json::Value = serde_json::from_str(out.lines().next().unwrap()).unwrap();
+        assert_eq!(row["phases"][0]["name"], "build");
+        assert_eq!(row["usage"]["system_secs"], 0.5);
+        assert_eq!(row["comment_url"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,996 @@
+//! Code forges jobs come from. Scripts and the reactor comment, open pull requests and set
+//! commit statuses through a [`Forge`], so the same bot can serve repositories on Github and
+//! projects on GitLab.
+//!
+//! Jobs keep describing their repository and issue with the Github models, GitLab projects and
+//! merge requests are translated into those when their webhooks arrive.
+
+use crate::api::pr::installation_client;
+use crate::job::Repository;
+use octocrab::models::issues::Issue;
+use octocrab::models::StatusState;
+use octocrab::Octocrab;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Error talking to Github: {0}")]
+    Github(#[from] crate::api::Error),
+    #[error("Error talking to GitLab: {0}")]
+    Gitlab(String),
+    #[error("Invalid GitLab webhook payload: {0}")]
+    Payload(#[from] serde_json::Error),
+    #[error("Can't run on the checkout: {0}")]
+    DryRun(String),
+}
+
+impl From<surf::Error> for Error {
+    fn from(err: surf::Error) -> Self {
+        Error::Gitlab(err.to_string())
+    }
+}
+
+/// The forge a job came from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Github,
+    Gitlab,
+}
+
+pub trait Forge: std::fmt::Debug + Send + Sync {
+    /// Comment on the issue or pull request (the merge request on GitLab) numbered `number`,
+    /// returning the URL of the comment
+    fn create_comment(&self, number: u64, body: &str) -> Result<url::Url, Error>;
+
+    /// Open a pull request (a merge request on GitLab) merging `head` into `base`, or update the
+    /// title and body of the one that's already open, like from an earlier run of the script
+    fn create_pr(&self, title: &str, body: &str, head: &str, base: &str) -> Result<(), Error>;
+
+    /// Delete the branches starting with `prefix` whose last commit is older than `max_age` and
+    /// that no pull request is open from, returning their names
+    fn delete_stale_branches(&self, prefix: &str, max_age: Duration) -> Result<Vec<String>, Error>;
+
+    /// Set the status named `context` of the commit `sha`
+    fn set_status(&self, sha: &str, context: &str, status: &CommitStatus) -> Result<(), Error>;
+
+    /// Add `label` to the issue or pull request numbered `number`
+    fn add_label(&self, number: u64, label: &str) -> Result<(), Error>;
+
+    /// Remove `label` from the issue or pull request numbered `number`, if it has it
+    fn remove_label(&self, number: u64, label: &str) -> Result<(), Error>;
+
+    /// Ask the team `team` (its slug, optionally after the organization like `org/team`) to
+    /// review pull request `number`
+    fn request_review(&self, number: u64, team: &str) -> Result<(), Error>;
+
+    /// Assign `user` to the issue or pull request numbered `number`, next to who's already
+    /// assigned
+    fn assign(&self, number: u64, user: &str) -> Result<(), Error>;
+
+    /// Longest comment the forge takes, in characters
+    fn max_comment_chars(&self) -> usize;
+
+    /// Put a report too long for a comment where a comment can link to it, returning its URL.
+    /// None if the forge has nowhere to put it.
+    fn upload_report(&self, body: &str) -> Result<Option<url::Url>, Error>;
+}
+
+/// Github rejects longer comments
+const GITHUB_MAX_COMMENT_CHARS: usize = 65536;
+
+/// State of a commit status, with what to tell about it
+#[derive(Clone, Debug)]
+pub struct CommitStatus {
+    pub state: StatusState,
+    /// Shown next to the status, forges cut it off after a while
+    pub description: Option<String>,
+    /// Where the status links to
+    pub target_url: Option<String>,
+}
+
+/// A Github repository, reached through the installation of the Github App
+#[derive(Clone, Debug)]
+pub struct Github {
+    client: Arc<Mutex<Octocrab>>,
+    repository: Repository,
+    /// Personal access token (with the `gist` scope) to upload long reports as gists with, which
+    /// Github Apps can't create
+    gist_token: Option<String>,
+    /// Review comment whose thread comments go to instead of the conversation
+    review_thread: Option<u64>,
+}
+
+impl Github {
+    pub fn new(client: Arc<Mutex<Octocrab>>, repository: Repository) -> Self {
+        Github {
+            client,
+            repository,
+            gist_token: None,
+            review_thread: None,
+        }
+    }
+
+    /// Upload reports too long for a comment as secret gists of the owner of `token`
+    pub fn with_gist_token(mut self, token: Option<String>) -> Self {
+        self.gist_token = token;
+        self
+    }
+
+    /// Reply in the thread of the review comment `comment` instead of commenting on the
+    /// conversation of pull requests
+    pub fn with_review_thread(mut self, comment: Option<u64>) -> Self {
+        self.review_thread = comment;
+        self
+    }
+
+    /// Run `f` with a client of the installation
+    fn with_installation<T, F, Fut>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(Octocrab, Repository) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, octocrab::Error>>,
+    {
+        let client = self.client.clone();
+        let repository = self.repository.clone();
+        let span = tracing::debug_span!("github", repository = %repository.name);
+        // Run on a separate thread so this also works when called from within a tokio runtime
+        std::thread::spawn(move || {
+            let _span = span.entered();
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| crate::api::Error::GithubApiError(format!("{e}")))?;
+            rt.block_on(async {
+                let client = {
+                    let client = client
+                        .lock()
+                        .map_err(|_| crate::api::Error::ExclusiveLock)?
+                        .clone();
+                    installation_client(&client, &repository).await?
+                };
+                Ok(f(client, repository)
+                    .await
+                    .map_err(crate::api::Error::from)?)
+            })
+        })
+        .join()
+        .map_err(|_| crate::api::Error::GithubApiError("Talking to Github panicked".into()))?
+    }
+}
+
+impl Forge for Github {
+    fn create_comment(&self, number: u64, body: &str) -> Result<url::Url, Error> {
+        #[derive(Deserialize)]
+        struct Reply {
+            html_url: url::Url,
+        }
+
+        let body = body.to_string();
+        let thread = self.review_thread;
+        self.with_installation(move |client, repository| async move {
+            if let Some(thread) = thread {
+                let route = format!(
+                    "repos/{}/{}/pulls/{number}/comments/{thread}/replies",
+                    repository.owner.login, repository.name
+                );
+                let reply: Reply = client
+                    .post(route, Some(&serde_json::json!({ "body": body })))
+                    .await?;
+                return Ok(reply.html_url);
+            }
+            let comment = client
+                .issues(&repository.owner.login, &repository.name)
+                .create_comment(number, body)
+                .await?;
+            Ok(comment.html_url)
+        })
+    }
+
+    fn create_pr(&self, title: &str, body: &str, head: &str, base: &str) -> Result<(), Error> {
+        let (title, body) = (title.to_string(), body.to_string());
+        let (head, base) = (head.to_string(), base.to_string());
+        self.with_installation(move |client, repository| async move {
+            let (owner, name) = (&repository.owner.login, &repository.name);
+            let open = client
+                .pulls(owner, name)
+                .list()
+                .state(octocrab::params::State::Open)
+                .head(format!("{owner}:{head}"))
+                .base(&base)
+                .send()
+                .await?;
+            match open.items.first() {
+                Some(pr) => {
+                    let route = format!("repos/{owner}/{name}/pulls/{}", pr.number);
+                    let update = serde_json::json!({ "title": title, "body": body });
+                    client
+                        .patch::<serde_json::Value, _, _>(route, Some(&update))
+                        .await?;
+                }
+                None => {
+                    client
+                        .pulls(owner, name)
+                        .create(title, head, base)
+                        .body(body)
+                        .send()
+                        .await?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_stale_branches(&self, prefix: &str, max_age: Duration) -> Result<Vec<String>, Error> {
+        #[derive(Deserialize)]
+        struct Ref {
+            #[serde(rename = "ref")]
+            name: String,
+            object: Object,
+        }
+        #[derive(Deserialize)]
+        struct Object {
+            sha: String,
+        }
+        #[derive(Deserialize)]
+        struct Commit {
+            commit: CommitDetails,
+        }
+        #[derive(Deserialize)]
+        struct CommitDetails {
+            committer: Committer,
+        }
+        #[derive(Deserialize)]
+        struct Committer {
+            date: chrono::DateTime<chrono::Utc>,
+        }
+
+        let prefix = prefix.to_string();
+        self.with_installation(move |client, repository| async move {
+            let (owner, name) = (&repository.owner.login, &repository.name);
+            let refs: Vec<Ref> = client
+                .get(
+                    format!("repos/{owner}/{name}/git/matching-refs/heads/{prefix}"),
+                    None::<&()>,
+                )
+                .await?;
+            let mut deleted = vec![];
+            for branch in refs {
+                let commit: Commit = client
+                    .get(
+                        format!("repos/{owner}/{name}/commits/{}", branch.object.sha),
+                        None::<&()>,
+                    )
+                    .await?;
+                let age = (chrono::Utc::now() - commit.commit.committer.date)
+                    .to_std()
+                    .unwrap_or_default();
+                let branch = branch.name.trim_start_matches("refs/heads/").to_string();
+                if age <= max_age {
+                    continue;
+                }
+                let open = client
+                    .pulls(owner, name)
+                    .list()
+                    .state(octocrab::params::State::Open)
+                    .head(format!("{owner}:{branch}"))
+                    .send()
+                    .await?;
+                if !open.items.is_empty() {
+                    continue;
+                }
+                let url =
+                    client.absolute_url(format!("repos/{owner}/{name}/git/refs/heads/{branch}"))?;
+                octocrab::map_github_error(client._delete(url, None::<&()>).await?).await?;
+                deleted.push(branch);
+            }
+            Ok(deleted)
+        })
+    }
+
+    fn set_status(&self, sha: &str, context: &str, status: &CommitStatus) -> Result<(), Error> {
+        let (sha, context, status) = (sha.to_string(), context.to_string(), status.clone());
+        self.with_installation(move |client, repository| async move {
+            let repo = client.repos(&repository.owner.login, &repository.name);
+            let mut request = repo.create_status(sha, status.state).context(context);
+            if let Some(description) = status.description {
+                request = request.description(description);
+            }
+            if let Some(target_url) = status.target_url {
+                request = request.target(target_url);
+            }
+            request.send().await?;
+            Ok(())
+        })
+    }
+
+    fn add_label(&self, number: u64, label: &str) -> Result<(), Error> {
+        let label = label.to_string();
+        self.with_installation(move |client, repository| async move {
+            let route = format!(
+                "repos/{}/{}/issues/{number}/labels",
+                repository.owner.login, repository.name
+            );
+            let body = serde_json::json!({ "labels": [label] });
+            client
+                .post::<_, serde_json::Value>(route, Some(&body))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn remove_label(&self, number: u64, label: &str) -> Result<(), Error> {
+        // In the path, where spaces aren't `+`
+        let label = encode(label).replace('+', "%20");
+        self.with_installation(move |client, repository| async move {
+            let url = client.absolute_url(format!(
+                "repos/{}/{}/issues/{number}/labels/{label}",
+                repository.owner.login, repository.name
+            ))?;
+            let response = client._delete(url, None::<&()>).await?;
+            // Github answers 404 if the issue doesn't have the label
+            if response.status() != 404 {
+                octocrab::map_github_error(response).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn request_review(&self, number: u64, team: &str) -> Result<(), Error> {
+        let team = team.rsplit('/').next().unwrap_or(team).to_string();
+        self.with_installation(move |client, repository| async move {
+            let route = format!(
+                "repos/{}/{}/pulls/{number}/requested_reviewers",
+                repository.owner.login, repository.name
+            );
+            let body = serde_json::json!({ "team_reviewers": [team] });
+            client
+                .post::<_, serde_json::Value>(route, Some(&body))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn assign(&self, number: u64, user: &str) -> Result<(), Error> {
+        let user = user.to_string();
+        self.with_installation(move |client, repository| async move {
+            let route = format!(
+                "repos/{}/{}/issues/{number}/assignees",
+                repository.owner.login, repository.name
+            );
+            let body = serde_json::json!({ "assignees": [user] });
+            client
+                .post::<_, serde_json::Value>(route, Some(&body))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn max_comment_chars(&self) -> usize {
+        GITHUB_MAX_COMMENT_CHARS
+    }
+
+    fn upload_report(&self, body: &str) -> Result<Option<url::Url>, Error> {
+        let token = match &self.gist_token {
+            Some(token) => token.clone(),
+            None => return Ok(None),
+        };
+        let body = body.to_string();
+        self.with_installation(move |client, repository| async move {
+            let description = format!("Report for {}/{}", repository.owner.login, repository.name);
+            let gist = Octocrab::builder()
+                .personal_token(token)
+                .base_url(client.base_url.clone())?
+                .build()?
+                .gists()
+                .create()
+                .description(description)
+                .public(false)
+                .file("report.md", body)
+                .send()
+                .await?;
+            Ok(Some(gist.html_url))
+        })
+    }
+}
+
+/// A GitLab instance, reached with an access token of the bot's user
+#[derive(Clone)]
+pub struct Gitlab {
+    url: url::Url,
+    token: String,
+}
+
+impl std::fmt::Debug for Gitlab {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Gitlab({})", self.url)
+    }
+}
+
+impl Gitlab {
+    pub fn new(mut url: url::Url, token: String) -> Self {
+        // So the API is found below it, for instances that aren't at the root of their host
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Gitlab { url, token }
+    }
+
+    /// To clone and fetch with, like the installation token on Github
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// The project of a job that came from this instance
+    pub fn project(&self, repository: &Repository) -> GitlabProject {
+        GitlabProject {
+            gitlab: self.clone(),
+            id: *repository.id.as_ref(),
+            web_url: repository.url.clone(),
+        }
+    }
+
+    fn api_url(&self, route: &str) -> Result<url::Url, Error> {
+        self.url
+            .join(&format!("api/v4/{route}"))
+            .map_err(|e| Error::Gitlab(format!("Invalid URL: {e}")))
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        req: surf::RequestBuilder,
+    ) -> Result<T, Error> {
+        let mut res = req.header("PRIVATE-TOKEN", self.token.as_str()).await?;
+        if !res.status().is_success() {
+            let body = res.body_string().await.unwrap_or_default();
+            return Err(Error::Gitlab(format!("{}: {body}", res.status())));
+        }
+        Ok(res.body_json().await?)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, route: &str) -> Result<T, Error> {
+        self.send(surf::get(self.api_url(route)?)).await
+    }
+
+    async fn post<T: serde::de::DeserializeOwned>(
+        &self,
+        route: &str,
+        body: serde_json::Value,
+    ) -> Result<T, Error> {
+        self.send(surf::post(self.api_url(route)?).body_json(&body)?)
+            .await
+    }
+
+    async fn put<T: serde::de::DeserializeOwned>(
+        &self,
+        route: &str,
+        body: serde_json::Value,
+    ) -> Result<T, Error> {
+        self.send(surf::put(self.api_url(route)?).body_json(&body)?)
+            .await
+    }
+
+    async fn delete(&self, route: &str) -> Result<(), Error> {
+        let mut res = surf::delete(self.api_url(route)?)
+            .header("PRIVATE-TOKEN", self.token.as_str())
+            .await?;
+        if !res.status().is_success() {
+            let body = res.body_string().await.unwrap_or_default();
+            return Err(Error::Gitlab(format!("{}: {body}", res.status())));
+        }
+        Ok(())
+    }
+}
+
+/// `value` escaped for the path or query of an API URL, where even `/` has to be escaped
+fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// A project on a GitLab instance
+#[derive(Clone, Debug)]
+pub struct GitlabProject {
+    gitlab: Gitlab,
+    id: u64,
+    web_url: url::Url,
+}
+
+/// Access level of GitLab's Developer role, the first that can push to a project
+const DEVELOPER_ACCESS: u32 = 30;
+
+impl GitlabProject {
+    pub async fn comment(&self, iid: u64, body: &str) -> Result<url::Url, Error> {
+        #[derive(Deserialize)]
+        struct Note {
+            id: u64,
+        }
+
+        let route = format!("projects/{}/merge_requests/{iid}/notes", self.id);
+        let note: Note = self
+            .gitlab
+            .post(&route, serde_json::json!({ "body": body }))
+            .await?;
+        let mut url = self
+            .web_url
+            .join(&format!("{}/-/merge_requests/{iid}", self.web_url.path()))
+            .map_err(|e| Error::Gitlab(format!("Invalid URL: {e}")))?;
+        url.set_fragment(Some(&format!("note_{}", note.id)));
+        Ok(url)
+    }
+
+    pub async fn create_merge_request(
+        &self,
+        title: &str,
+        body: &str,
+        head: &str,
+        base: &str,
+    ) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct MergeRequest {
+            iid: u64,
+        }
+
+        let route = format!("projects/{}/merge_requests", self.id);
+        let open: Vec<MergeRequest> = self
+            .gitlab
+            .get(&format!(
+                "{route}?state=opened&source_branch={}&target_branch={}",
+                encode(head),
+                encode(base)
+            ))
+            .await?;
+        match open.first() {
+            Some(merge_request) => {
+                let body = serde_json::json!({ "title": title, "description": body });
+                self.gitlab
+                    .put::<serde_json::Value>(&format!("{route}/{}", merge_request.iid), body)
+                    .await?;
+            }
+            None => {
+                let body = serde_json::json!({
+                    "title": title,
+                    "description": body,
+                    "source_branch": head,
+                    "target_branch": base,
+                });
+                self.gitlab.post::<serde_json::Value>(&route, body).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn delete_stale_branches(
+        &self,
+        prefix: &str,
+        max_age: Duration,
+    ) -> Result<Vec<String>, Error> {
+        #[derive(Deserialize)]
+        struct Branch {
+            name: String,
+            commit: Commit,
+        }
+        #[derive(Deserialize)]
+        struct Commit {
+            committed_date: chrono::DateTime<chrono::Utc>,
+        }
+
+        let route = format!("projects/{}/repository/branches", self.id);
+        let branches: Vec<Branch> = self
+            .gitlab
+            .get(&format!("{route}?per_page=100&search=^{}", encode(prefix)))
+            .await?;
+        let mut deleted = vec![];
+        for branch in branches {
+            let age = (chrono::Utc::now() - branch.commit.committed_date)
+                .to_std()
+                .unwrap_or_default();
+            if !branch.name.starts_with(prefix) || age <= max_age {
+                continue;
+            }
+            let open: Vec<serde_json::Value> = self
+                .gitlab
+                .get(&format!(
+                    "projects/{}/merge_requests?state=opened&source_branch={}",
+                    self.id,
+                    encode(&branch.name)
+                ))
+                .await?;
+            if !open.is_empty() {
+                continue;
+            }
+            self.gitlab
+                .delete(&format!("{route}/{}", encode(&branch.name)))
+                .await?;
+            deleted.push(branch.name);
+        }
+        Ok(deleted)
+    }
+
+    pub async fn set_commit_status(
+        &self,
+        sha: &str,
+        context: &str,
+        status: &CommitStatus,
+    ) -> Result<(), Error> {
+        let state = match status.state {
+            StatusState::Pending => "pending",
+            StatusState::Success => "success",
+            _ => "failed",
+        };
+        let route = format!("projects/{}/statuses/{sha}", self.id);
+        let body = serde_json::json!({
+            "state": state,
+            "name": context,
+            "description": status.description,
+            "target_url": status.target_url,
+        });
+        self.gitlab.post::<serde_json::Value>(&route, body).await?;
+        Ok(())
+    }
+
+    /// Add or remove (`remove` being true) `label` on merge request `iid`
+    pub async fn update_label(&self, iid: u64, label: &str, remove: bool) -> Result<(), Error> {
+        let field = if remove {
+            "remove_labels"
+        } else {
+            "add_labels"
+        };
+        let route = format!("projects/{}/merge_requests/{iid}", self.id);
+        self.gitlab
+            .put::<serde_json::Value>(&route, serde_json::json!({ field: label }))
+            .await?;
+        Ok(())
+    }
+
+    /// Add `username` to the assignees of merge request `iid`
+    pub async fn assign_merge_request(&self, iid: u64, username: &str) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct User {
+            id: u64,
+        }
+        #[derive(Deserialize)]
+        struct MergeRequest {
+            #[serde(default)]
+            assignees: Vec<User>,
+        }
+
+        let users: Vec<User> = self
+            .gitlab
+            .get(&format!("users?username={}", encode(username)))
+            .await?;
+        let user = users
+            .first()
+            .ok_or_else(|| Error::Gitlab(format!("No user {username}")))?;
+        let route = format!("projects/{}/merge_requests/{iid}", self.id);
+        let merge_request: MergeRequest = self.gitlab.get(&route).await?;
+        let mut ids: Vec<u64> = merge_request.assignees.iter().map(|user| user.id).collect();
+        if !ids.contains(&user.id) {
+            ids.push(user.id);
+        }
+        self.gitlab
+            .put::<serde_json::Value>(&route, serde_json::json!({ "assignee_ids": ids }))
+            .await?;
+        Ok(())
+    }
+
+    /// Whether `username` is a member of the project that can push to it
+    pub async fn has_write_access(&self, username: &str) -> Result<bool, Error> {
+        #[derive(Deserialize)]
+        struct Member {
+            username: String,
+            access_level: u32,
+        }
+
+        let route = format!("projects/{}/members/all?query={username}", self.id);
+        let members: Vec<Member> = self.gitlab.get(&route).await?;
+        Ok(members
+            .iter()
+            .any(|member| member.username == username && member.access_level >= DEVELOPER_ACCESS))
+    }
+}
+
+impl Forge for GitlabProject {
+    fn create_comment(&self, number: u64, body: &str) -> Result<url::Url, Error> {
+        async_std::task::block_on(self.comment(number, body))
+    }
+
+    fn create_pr(&self, title: &str, body: &str, head: &str, base: &str) -> Result<(), Error> {
+        async_std::task::block_on(self.create_merge_request(title, body, head, base))
+    }
+
+    fn delete_stale_branches(&self, prefix: &str, max_age: Duration) -> Result<Vec<String>, Error> {
+        async_std::task::block_on(GitlabProject::delete_stale_branches(self, prefix, max_age))
+    }
+
+    fn set_status(&self, sha: &str, context: &str, status: &CommitStatus) -> Result<(), Error> {
+        async_std::task::block_on(self.set_commit_status(sha, context, status))
+    }
+
+    fn add_label(&self, number: u64, label: &str) -> Result<(), Error> {
+        async_std::task::block_on(self.update_label(number, label, false))
+    }
+
+    fn remove_label(&self, number: u64, label: &str) -> Result<(), Error> {
+        async_std::task::block_on(self.update_label(number, label, true))
+    }
+
+    /// GitLab has no teams to request reviews from
+    fn request_review(&self, _number: u64, team: &str) -> Result<(), Error> {
+        Err(Error::Gitlab(format!(
+            "Can't request a review of team {team}, GitLab has no teams"
+        )))
+    }
+
+    fn assign(&self, number: u64, user: &str) -> Result<(), Error> {
+        async_std::task::block_on(self.assign_merge_request(number, user))
+    }
+
+    /// The limit of notes on GitLab
+    fn max_comment_chars(&self) -> usize {
+        1_000_000
+    }
+
+    fn upload_report(&self, _body: &str) -> Result<Option<url::Url>, Error> {
+        Ok(None)
+    }
+}
+
+/// A local checkout standing in for a forge, for trying out scripts without one. What would be
+/// posted is printed instead.
+#[derive(Clone, Debug)]
+pub struct DryRun {
+    repository: Repository,
+    url: url::Url,
+}
+
+impl DryRun {
+    pub fn new(dir: &Path) -> Result<Self, Error> {
+        let url = url::Url::from_directory_path(dir)
+            .map_err(|_| Error::DryRun(format!("{dir:?} isn't an absolute path")))?;
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // The branch checked out stands in for the default branch
+        let default_branch = git2::Repository::open(dir).ok().and_then(|repo| {
+            repo.head()
+                .ok()
+                .and_then(|head| head.shorthand().map(String::from))
+        });
+        let repository = serde_json::from_value(serde_json::json!({
+            "id": 0,
+            "name": name,
+            "url": url,
+            "owner": user("local", &url),
+            "clone_url": url,
+            "default_branch": default_branch,
+            "visibility": "private",
+        }))?;
+        Ok(DryRun { repository, url })
+    }
+
+    /// The checkout, in the shape of a Github repository
+    pub fn repository(&self) -> &Repository {
+        &self.repository
+    }
+
+    /// An issue `number` of the checkout, for scripts to comment on
+    pub fn issue(&self, number: i64) -> Result<Issue, Error> {
+        issue(
+            number,
+            "Dry run",
+            "open",
+            &self.url,
+            &self.url,
+            user("local", &self.url),
+        )
+    }
+}
+
+impl Forge for DryRun {
+    fn create_comment(&self, number: u64, body: &str) -> Result<url::Url, Error> {
+        println!("--- Comment on #{number} ---\n{body}\n---");
+        Ok(self.url.clone())
+    }
+
+    fn create_pr(&self, title: &str, body: &str, head: &str, base: &str) -> Result<(), Error> {
+        println!("--- Pull request {head} -> {base}: {title} ---\n{body}\n---");
+        Ok(())
+    }
+
+    fn delete_stale_branches(&self, prefix: &str, max_age: Duration) -> Result<Vec<String>, Error> {
+        println!("--- Delete branches {prefix}* older than {max_age:?} ---");
+        Ok(vec![])
+    }
+
+    fn set_status(&self, sha: &str, context: &str, status: &CommitStatus) -> Result<(), Error> {
+        println!(
+            "--- Status {context} of {sha}: {:?} {} ---",
+            status.state,
+            status.description.as_deref().unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    fn add_label(&self, number: u64, label: &str) -> Result<(), Error> {
+        println!("--- Label #{number} with {label} ---");
+        Ok(())
+    }
+
+    fn remove_label(&self, number: u64, label: &str) -> Result<(), Error> {
+        println!("--- Remove label {label} from #{number} ---");
+        Ok(())
+    }
+
+    fn request_review(&self, number: u64, team: &str) -> Result<(), Error> {
+        println!("--- Request review of #{number} from {team} ---");
+        Ok(())
+    }
+
+    fn assign(&self, number: u64, user: &str) -> Result<(), Error> {
+        println!("--- Assign {user} to #{number} ---");
+        Ok(())
+    }
+
+    /// Like on Github, to see how reports would be split there
+    fn max_comment_chars(&self) -> usize {
+        GITHUB_MAX_COMMENT_CHARS
+    }
+
+    fn upload_report(&self, _body: &str) -> Result<Option<url::Url>, Error> {
+        Ok(None)
+    }
+}
+
+/// A comment, as delivered by GitLab's `Note Hook`
+#[derive(Debug, Deserialize)]
+pub struct NoteEvent {
+    pub user: NoteUser,
+    pub project: NoteProject,
+    pub object_attributes: NoteAttributes,
+    /// Only set for comments on merge requests
+    pub merge_request: Option<NoteMergeRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NoteUser {
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NoteProject {
+    pub id: u64,
+    pub path_with_namespace: String,
+    pub web_url: url::Url,
+    pub git_http_url: url::Url,
+    pub default_branch: Option<String>,
+    /// 0 for private, 10 for internal and 20 for public projects
+    pub visibility_level: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NoteAttributes {
+    pub note: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NoteMergeRequest {
+    pub iid: i64,
+    pub title: String,
+    pub state: String,
+    pub url: url::Url,
+}
+
+impl NoteEvent {
+    /// The project and merge request the comment was made on, in the shape of a Github
+    /// repository and pull request, if it was made on a merge request
+    pub fn merge_request(&self) -> Result<Option<(Repository, Issue)>, Error> {
+        let merge_request = match &self.merge_request {
+            Some(merge_request) => merge_request,
+            None => return Ok(None),
+        };
+        let (namespace, name) = self
+            .project
+            .path_with_namespace
+            .rsplit_once('/')
+            .unwrap_or(("", &self.project.path_with_namespace));
+        let owner = self.gitlab_user(namespace)?;
+        let repository = serde_json::from_value(serde_json::json!({
+            "id": self.project.id,
+            "name": name,
+            "url": self.project.web_url,
+            "owner": owner,
+            "clone_url": self.project.git_http_url,
+            "default_branch": self.project.default_branch,
+            "visibility": self.project.visibility_level.map(|level| match level {
+                0 => "private",
+                10 => "internal",
+                _ => "public",
+            }),
+        }))?;
+        let issue = issue(
+            merge_request.iid,
+            &merge_request.title,
+            &merge_request.state,
+            &merge_request.url,
+            &self.project.web_url,
+            self.gitlab_user(&self.user.username)?,
+        )?;
+        Ok(Some((repository, issue)))
+    }
+
+    /// A Github user for the GitLab user or group at `path`
+    fn gitlab_user(&self, path: &str) -> Result<serde_json::Value, Error> {
+        let url = self
+            .project
+            .web_url
+            .join(&format!("/{path}"))
+            .map_err(|e| Error::Gitlab(format!("Invalid URL: {e}")))?;
+        Ok(user(path, &url))
+    }
+}
+
+/// An issue in the shape of a Github one, at `url`. Left without `pull_request`, as there's no
+/// Github pull request to fetch.
+fn issue(
+    number: i64,
+    title: &str,
+    state: &str,
+    url: &url::Url,
+    repository_url: &url::Url,
+    user: serde_json::Value,
+) -> Result<Issue, Error> {
+    let now = chrono::Utc::now();
+    Ok(serde_json::from_value(serde_json::json!({
+        "id": number,
+        "node_id": "",
+        "url": url,
+        "repository_url": repository_url,
+        "labels_url": url,
+        "comments_url": url,
+        "events_url": url,
+        "html_url": url,
+        "number": number,
+        "state": state,
+        "title": title,
+        "body": null,
+        "user": user,
+        "labels": [],
+        "assignees": [],
+        "author_association": "NONE",
+        "locked": false,
+        "comments": 0,
+        "created_at": now,
+        "updated_at": now,
+    }))?)
+}
+
+/// A Github user called `login`, at `url`
+pub(crate) fn user(login: &str, url: &url::Url) -> serde_json::Value {
+    serde_json::json!({
+        "login": login,
+        "id": 0,
+        "node_id": "",
+        "avatar_url": url,
+        "gravatar_id": "",
+        "url": url,
+        "html_url": url,
+        "followers_url": url,
+        "following_url": url,
+        "gists_url": url,
+        "starred_url": url,
+        "subscriptions_url": url,
+        "organizations_url": url,
+        "repos_url": url,
+        "events_url": url,
+        "received_events_url": url,
+        "type": "User",
+        "site_admin": false,
+    })
+}
@@ -0,0 +1,113 @@
+//! Handing over from one reactor process to the next, so upgrades don't lose webhook deliveries.
+//!
+//! The listening socket is either passed in by systemd (socket activation) or bound with
+//! `SO_REUSEPORT`, so a new process can accept deliveries while the old one drains: it stops
+//! listening once asked to stop, finishes the jobs it's running and exits. Processes sharing a
+//! state database take turns running jobs through a [`ProcessLock`].
+
+use futures_lite::StreamExt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+
+/// First file descriptor passed by systemd
+const LISTEN_FDS_START: i32 = 3;
+
+/// The socket passed in by systemd, if the process was started through socket activation
+#[cfg(unix)]
+pub fn inherited_listener() -> Option<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || fds < 1 {
+        return None;
+    }
+    // They're meant for this process only, not the ones it starts
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    if fds > 1 {
+        tracing::warn!("Got {fds} sockets from systemd, only listening on the first");
+    }
+    // Safe as systemd hands over ownership of the descriptors it passed
+    Some(unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+/// There's no systemd to pass in sockets off Unix
+#[cfg(not(unix))]
+pub fn inherited_listener() -> Option<TcpListener> {
+    None
+}
+
+/// Listen on `address`, allowing other processes to listen on it as well if `reuse_port`
+pub fn bind(address: SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(address),
+        socket2::Type::STREAM,
+        None,
+    )?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    #[cfg(not(unix))]
+    if reuse_port {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Only Unix lets processes listen on the same port",
+        ));
+    }
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+/// Resolves once the process is asked to stop (`SIGTERM` or `SIGINT`, Ctrl+C on Windows). Asking
+/// again stops it right away.
+pub async fn stop_requested() -> io::Result<()> {
+    #[cfg(unix)]
+    let stop = [async_signal::Signal::Term, async_signal::Signal::Int];
+    #[cfg(not(unix))]
+    let stop = [async_signal::Signal::Int];
+    let mut signals = async_signal::Signals::new(stop)?;
+    signals.next().await.transpose()?;
+    async_std::task::spawn(async move {
+        if let Some(Ok(signal)) = signals.next().await {
+            tracing::warn!("Got {signal:?} while draining, stopping right away");
+            std::process::exit(1);
+        }
+    });
+    Ok(())
+}
+
+/// Exclusive lock on a file, held until dropped
+pub struct ProcessLock {
+    _file: File,
+}
+
+impl ProcessLock {
+    /// Wait for the lock on `path` (created if needed) to be released by any other process
+    /// holding it. Blocks, so better called through `spawn_blocking`.
+    pub fn acquire<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.as_ref())?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(std::fs::TryLockError::WouldBlock) => {
+                tracing::info!(
+                    "Waiting for the process holding {:?} to finish its jobs",
+                    path.as_ref()
+                );
+                file.lock()?;
+            }
+            Err(std::fs::TryLockError::Error(err)) => return Err(err),
+        }
+        Ok(ProcessLock { _file: file })
+    }
+}
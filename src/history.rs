@@ -0,0 +1,377 @@
+use crate::api::phases::Phase;
+use crate::api::usage::Usage;
+use crate::secrets::Redactor;
+use crate::Job;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Debug)]
+pub enum Status {
+    Running,
+    Succeeded,
+    Failed(String),
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Running => write!(f, "running"),
+            Status::Succeeded => write!(f, "succeeded"),
+            Status::Failed(err) => write!(f, "failed: {err}"),
+        }
+    }
+}
+
+/// A job that is running or ran on this node
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub id: String,
+    pub repository: String,
+    pub command: String,
+    pub issue_url: url::Url,
+    /// Login of whoever requested the job
+    pub user: Option<String>,
+    /// Host name of the node the job ran on
+    pub machine: String,
+    pub started_at: SystemTime,
+    pub duration: Option<Duration>,
+    pub status: Status,
+    /// URL of the comment the job's result was posted in, if any
+    pub comment_url: Option<url::Url>,
+    pub phases: Vec<Phase>,
+    /// What the processes of the job used, if it ran any
+    pub usage: Option<Usage>,
+}
+
+impl Record {
+    fn new(job: &Job) -> Self {
+        Record {
+            id: job.id.clone(),
+            repository: format!("{}/{}", job.repository.owner.login, job.repository.name),
+            command: job.command.join(" "),
+            issue_url: job.issue_url(),
+            user: job.user.clone(),
+            machine: crate::platform::hostname(),
+            started_at: SystemTime::now(),
+            duration: None,
+            status: Status::Running,
+            comment_url: None,
+            phases: vec![],
+            usage: None,
+        }
+    }
+}
+
+/// Weight of the latest duration in the rolling averages of [`History::estimate`]
+const WEIGHT: f64 = 0.3;
+
+/// What the durations of jobs are averaged by: their repository and scripts, not arguments
+fn duration_key(repository: &str, command: &str) -> String {
+    let mut words = command.split_whitespace();
+    let mut scripts: Vec<&str> = words.next().into_iter().collect();
+    while let Some(word) = words.next() {
+        if word == crate::job::STEP_SEPARATOR {
+            scripts.extend(words.next());
+        }
+    }
+    format!("{repository}:{}", scripts.join(" "))
+}
+
+/// The currently running jobs and a bounded list of the most recently completed ones
+#[derive(Debug)]
+pub struct History {
+    running: Vec<Record>,
+    completed: VecDeque<Record>,
+    capacity: usize,
+    /// Rolling averages of how long jobs that succeeded took, by [`duration_key`]
+    durations: HashMap<String, Duration>,
+}
+
+pub type SharedHistory = Arc<Mutex<History>>;
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            running: vec![],
+            completed: VecDeque::with_capacity(capacity),
+            capacity,
+            durations: HashMap::new(),
+        }
+    }
+
+    pub fn start(&mut self, job: &Job) {
+        if let Some(pos) = self.running.iter().position(|record| record.id == job.id) {
+            tracing::warn!("Job {} was never marked as finished", job.id);
+            self.running.remove(pos);
+        }
+        self.running.push(Record::new(job));
+    }
+
+    /// Mark the running job `id` as completed, returning its final record
+    pub fn finish(
+        &mut self,
+        id: &str,
+        status: Status,
+        comment_url: Option<url::Url>,
+        phases: Vec<Phase>,
+        usage: Option<Usage>,
+    ) -> Option<Record> {
+        let pos = self.running.iter().position(|record| record.id == id)?;
+        let mut record = self.running.remove(pos);
+        record.duration = record.started_at.elapsed().ok();
+        record.status = status;
+        record.comment_url = comment_url;
+        record.phases = phases;
+        record.usage = usage;
+        self.learn(&record);
+        if self.completed.len() == self.capacity {
+            self.completed.pop_back();
+        }
+        self.completed.push_front(record.clone());
+        Some(record)
+    }
+
+    /// Running jobs, in the order they were started
+    pub fn running(&self) -> &[Record] {
+        &self.running
+    }
+
+    /// Completed jobs, most recent first
+    pub fn completed(&self) -> impl Iterator<Item = &Record> {
+        self.completed.iter()
+    }
+
+    /// Take how long a finished job took into account for [`History::estimate`], like the
+    /// ones archived before a restart
+    pub fn learn(&mut self, record: &Record) {
+        let duration = match (&record.status, record.duration) {
+            (Status::Succeeded, Some(duration)) => duration,
+            _ => return,
+        };
+        let key = duration_key(&record.repository, &record.command);
+        let average = self.durations.entry(key).or_insert(duration);
+        *average = average.mul_f64(1.0 - WEIGHT) + duration.mul_f64(WEIGHT);
+    }
+
+    /// How long a job of `repository` running `command` is expected to take, from how long
+    /// the ones running the same scripts took. Unknown scripts are expected to take as long as
+    /// the average job.
+    pub fn estimate(&self, repository: &str, command: &str) -> Option<Duration> {
+        if let Some(duration) = self.durations.get(&duration_key(repository, command)) {
+            return Some(*duration);
+        }
+        let count = self.durations.len() as u32;
+        (count > 0).then(|| self.durations.values().sum::<Duration>() / count)
+    }
+
+    /// When the `queued` jobs are expected to start from now, in order, once `workers` are
+    /// done with the running jobs and the ones queued before them. Unknown if no job ran yet.
+    pub fn starts(&self, queued: &[&Job], workers: usize) -> Vec<Option<Duration>> {
+        let estimate = |repository: &str, command: &str| self.estimate(repository, command);
+        // When each worker is free again
+        let mut free: Vec<Option<Duration>> = self
+            .running
+            .iter()
+            .map(|record| {
+                let elapsed = record.started_at.elapsed().unwrap_or_default();
+                Some(estimate(&record.repository, &record.command)?.saturating_sub(elapsed))
+            })
+            .collect();
+        free.resize(workers.max(free.len()).max(1), Some(Duration::ZERO));
+        queued
+            .iter()
+            .map(|job| {
+                let next = free
+                    .iter_mut()
+                    .min_by_key(|free| free.unwrap_or(Duration::MAX))?;
+                let start = *next;
+                let repository = format!("{}/{}", job.repository.owner.login, job.repository.name);
+                *next = start
+                    .zip(estimate(&repository, &job.command.join(" ")))
+                    .map(|(start, duration)| start + duration);
+                start
+            })
+            .collect()
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(50)
+    }
+}
+
+/// The last lines logged by this process, so they can be followed from the dashboard
+#[derive(Clone, Debug)]
+pub struct LogTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+    /// Only keeps the lines logged within a span of this tenant, see [`LogTail::of_tenant`]
+    tenant: Option<String>,
+}
+
+impl LogTail {
+    pub fn new(capacity: usize) -> Self {
+        LogTail {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            tenant: None,
+        }
+    }
+
+    /// Only keep the lines logged within a span whose `tenant` field is `tenant`, so tenants
+    /// don't see each other's logs
+    pub fn of_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    fn keeps(&self, tenant: Option<&str>) -> bool {
+        self.tenant.is_none() || self.tenant.as_deref() == tenant
+    }
+
+    fn push(&self, line: String) {
+        if let Ok(mut lines) = self.lines.lock() {
+            if lines.len() == self.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line);
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut lines) = self.lines.lock() {
+            lines.clear();
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines
+            .lock()
+            .map(|lines| lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Keeps a copy of every event in the `LogTail`s that keep it, with any secrets redacted
+pub struct TailLayer {
+    tails: Vec<LogTail>,
+    redactor: Redactor,
+}
+
+impl TailLayer {
+    pub fn new(tails: Vec<LogTail>) -> Self {
+        TailLayer {
+            tails,
+            redactor: Redactor::new(),
+        }
+    }
+
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for TailLayer
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut tenant = TenantField::default();
+        attrs.record(&mut tenant);
+        if let (Some(tenant), Some(span)) = (tenant.0, ctx.span(id)) {
+            span.extensions_mut().replace(tenant);
+        }
+    }
+
+    // Like the tenant of a job span, which is only known once a worker of the tenant runs it
+    fn on_record(
+        &self,
+        id: &tracing::span::Id,
+        values: &tracing::span::Record<'_>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut tenant = TenantField::default();
+        values.record(&mut tenant);
+        if let (Some(tenant), Some(span)) = (tenant.0, ctx.span(id)) {
+            span.extensions_mut().replace(tenant);
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        use tracing_log::NormalizeEvent;
+
+        // Of the innermost span that has one
+        let tenant = ctx.event_scope(event).and_then(|mut scope| {
+            scope.find_map(|span| span.extensions().get::<SpanTenant>().map(|t| t.0.clone()))
+        });
+        let tails: Vec<&LogTail> = self
+            .tails
+            .iter()
+            .filter(|tail| tail.keeps(tenant.as_deref()))
+            .collect();
+        if tails.is_empty() {
+            return;
+        }
+        // Records of dependencies logging through `log` have their target in a field
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut message = Message::default();
+        event.record(&mut message);
+        let line = format!(
+            "{:<5} {} > {}",
+            metadata.level(),
+            metadata.target(),
+            self.redactor.redact(&message.0)
+        );
+        for tail in tails {
+            tail.push(line.clone());
+        }
+    }
+}
+
+/// The tenant a span is about, from its `tenant` field
+struct SpanTenant(String);
+
+#[derive(Default)]
+struct TenantField(Option<SpanTenant>);
+
+impl tracing::field::Visit for TenantField {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "tenant" {
+            self.0 = Some(SpanTenant(value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        // `%tenant` fields are recorded as debug, which formats them like `Display`
+        if field.name() == "tenant" {
+            self.0 = Some(SpanTenant(format!("{value:?}")));
+        }
+    }
+}
+
+/// The message of an event followed by its other fields, as `key=value`
+#[derive(Default)]
+struct Message(String);
+
+impl tracing::field::Visit for Message {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        use std::fmt::Write;
+
+        match field.name() {
+            "message" => self.0.insert_str(0, &format!("{value:?}")),
+            name if name.starts_with("log.") => {}
+            name => {
+                let _ = write!(self.0, " {name}={value:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Acting on a repository as the installation of the Github App on it. An App has an installation
+//! per account it's installed on, and each tenant is an App of its own, so the installation is
+//! looked up by the repository instead of taking whichever one Github lists first.
+
+use octocrab::models::RepositoryId;
+use octocrab::params::apps::CreateInstallationAccessToken;
+use octocrab::Octocrab;
+
+/// Access token of the installation of the App of `app_client` on `owner/name`, only valid for
+/// the repositories `repository_ids` if any are given
+#[tracing::instrument(level = "debug", skip(app_client))]
+pub async fn token(
+    app_client: &Octocrab,
+    owner: &str,
+    name: &str,
+    repository_ids: &[RepositoryId],
+) -> octocrab::Result<String> {
+    let installation = app_client
+        .apps()
+        .get_repository_installation(owner, name)
+        .await?;
+    let mut access_token_req = CreateInstallationAccessToken::default();
+    access_token_req.repository_ids = repository_ids.to_vec();
+    // Relative to the base URL of the client, like every other call
+    let access: octocrab::models::InstallationToken = app_client
+        .post(
+            format!("app/installations/{}/access_tokens", installation.id),
+            Some(&access_token_req),
+        )
+        .await?;
+    Ok(access.token)
+}
+
+/// Client acting as the installation of the App of `app_client` on `owner/name`, see [`token`]
+pub async fn client(
+    app_client: &Octocrab,
+    owner: &str,
+    name: &str,
+    repository_ids: &[RepositoryId],
+) -> octocrab::Result<Octocrab> {
+    let token = token(app_client, owner, name, repository_ids).await?;
+    octocrab::OctocrabBuilder::new()
+        .base_url(app_client.base_url.clone())?
+        .personal_token(token)
+        .build()
+}
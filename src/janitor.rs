@@ -0,0 +1,247 @@
+//! Keeps the repositories root of a node from filling up its disk.
+//!
+//! The root holds the bare clones jobs are checked out from (`<id>_<owner>_<name>.git`), the
+//! target directories shared by the jobs of a repository (`<id>_<owner>_<name>.target`), the
+//! worktrees of running jobs (`worktrees/<job>`) and the repositories scripts clone through
+//! `GIT` (`https:__github.com_<owner>_<name>`). Anything else in it, like the roots of other
+//! tenants, is left alone.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid size {0:?}, expected a number of bytes optionally followed by K, M, G or T")]
+    InvalidSize(String),
+    #[error("Failed to clean up {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Failed to open clone: {0}")]
+    Git(#[from] git2::Error),
+    #[error("Failed to remove worktree: {0}")]
+    Worktree(#[from] crate::job::Error),
+}
+
+/// Parse a size like `500M` or `200G`
+pub fn parse_size(s: &str) -> Result<u64, Error> {
+    let invalid = || Error::InvalidSize(s.to_string());
+    let (amount, shift) = match s.chars().last().ok_or_else(invalid)? {
+        'K' | 'k' => (&s[..s.len() - 1], 10),
+        'M' | 'm' => (&s[..s.len() - 1], 20),
+        'G' | 'g' => (&s[..s.len() - 1], 30),
+        'T' | 't' => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    amount.checked_mul(1 << shift).ok_or_else(invalid)
+}
+
+/// What a collection removed
+#[derive(Debug, Default, Serialize)]
+pub struct Collected {
+    /// Size of the managed part of the root before collecting, in bytes
+    pub size_before: u64,
+    pub size_after: u64,
+    pub removed: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Janitor {
+    root: PathBuf,
+    max_size: Option<u64>,
+    busy: Arc<Mutex<()>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    path: PathBuf,
+    kind: Kind,
+    last_used: SystemTime,
+    size: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Kind {
+    /// Bare clone the jobs are checked out from
+    Mirror,
+    /// Target directory shared by the jobs of a repository
+    Target,
+    /// Clone made by a script
+    Clone,
+}
+
+impl Janitor {
+    pub fn new<P: AsRef<Path>>(root: P, max_size: Option<u64>) -> Self {
+        Janitor {
+            root: root.as_ref().into(),
+            max_size,
+            busy: Default::default(),
+        }
+    }
+
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    /// Hold while a job uses the root, collecting waits until it's released
+    pub fn lock(&self) -> MutexGuard<'_, ()> {
+        self.busy
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Remove the worktrees left behind by jobs that didn't finish, then, while the root is
+    /// larger than the maximum size, the shared target directories and those of the clones and
+    /// finally the clones themselves, least recently used first.
+    pub fn collect(&self) -> Result<Collected, Error> {
+        let _busy = self.lock();
+        let mut collected = Collected {
+            removed: self.remove_worktrees()?,
+            ..Default::default()
+        };
+
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|entry| entry.last_used);
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        collected.size_before = size;
+        let max_size = self.max_size.unwrap_or(u64::MAX);
+
+        for entry in entries
+            .iter_mut()
+            .filter(|entry| entry.kind != Kind::Mirror)
+        {
+            if size <= max_size {
+                break;
+            }
+            let target = match entry.kind {
+                Kind::Target => entry.path.clone(),
+                _ => entry.path.join("target"),
+            };
+            if target.is_dir() {
+                let freed = dir_size(&target)?;
+                tracing::info!("Removing {:?} to free {} bytes", target, freed);
+                remove_dir(&target)?;
+                size -= freed;
+                entry.size -= freed;
+                collected.removed.push(target);
+            }
+        }
+        for entry in entries.into_iter().filter(|entry| entry.path.exists()) {
+            if size <= max_size {
+                break;
+            }
+            tracing::info!("Removing {:?} to free {} bytes", entry.path, entry.size);
+            remove_dir(&entry.path)?;
+            size -= entry.size;
+            collected.removed.push(entry.path);
+        }
+
+        collected.size_after = size;
+        Ok(collected)
+    }
+
+    /// Worktrees only exist while their job runs, so any there are now belong to jobs that were
+    /// interrupted
+    fn remove_worktrees(&self) -> Result<Vec<PathBuf>, Error> {
+        let worktrees = self.root.join("worktrees");
+        let mut removed = vec![];
+        for entry in
+            self.entries_named(|name| name.ends_with(".git") && !name.starts_with("https:"))?
+        {
+            let _lock = crate::job::CheckoutLock::acquire(&entry);
+            let mirror = git2::Repository::open_bare(&entry)?;
+            for name in mirror.worktrees()?.iter().flatten() {
+                let dir = worktrees.join(name);
+                tracing::info!("Removing left over worktree {:?}", dir);
+                crate::job::remove_worktree(&mirror, name, &dir)?;
+                removed.push(dir);
+            }
+        }
+        // Those that didn't get as far as being added to their clone
+        if let Ok(entries) = std::fs::read_dir(&worktrees) {
+            let dirs = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir());
+            for dir in dirs {
+                tracing::info!("Removing left over worktree {:?}", dir);
+                remove_dir(&dir)?;
+                removed.push(dir);
+            }
+        }
+        Ok(removed)
+    }
+
+    fn entries(&self) -> Result<Vec<Entry>, Error> {
+        self.entries_named(|name| {
+            name.ends_with(".git") || name.ends_with(".target") || name.starts_with("https:")
+        })?
+        .into_iter()
+        .map(|path| {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let kind = if name.starts_with("https:") {
+                Kind::Clone
+            } else if name.ends_with(".git") {
+                Kind::Mirror
+            } else {
+                Kind::Target
+            };
+            let git_dir = match kind {
+                Kind::Clone => path.join(".git"),
+                _ => path.clone(),
+            };
+            // Fetching and checking out touches one of these, checking out touches shared target
+            // directories themselves
+            let last_used = [&path, &git_dir.join("FETCH_HEAD"), &git_dir.join("index")]
+                .iter()
+                .filter_map(|path| path.metadata().and_then(|m| m.modified()).ok())
+                .max()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            Ok(Entry {
+                size: dir_size(&path)?,
+                path,
+                kind,
+                last_used,
+            })
+        })
+        .collect()
+    }
+
+    /// Directories in the root with a name matching `filter`
+    fn entries_named<F: Fn(&str) -> bool>(&self, filter: F) -> Result<Vec<PathBuf>, Error> {
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(Error::Io(self.root.clone(), err)),
+        };
+        Ok(entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .filter(|entry| entry.file_name().to_str().is_some_and(&filter))
+            .map(|entry| entry.path())
+            .collect())
+    }
+}
+
+fn dir_size(dir: &Path) -> Result<u64, Error> {
+    let mut size = 0;
+    for entry in walkdir::WalkDir::new(dir) {
+        let entry = entry.map_err(|err| Error::Io(dir.into(), err.into()))?;
+        if entry.file_type().is_file() {
+            size += entry
+                .metadata()
+                .map_err(|err| Error::Io(entry.path().into(), err.into()))?
+                .len();
+        }
+    }
+    Ok(size)
+}
+
+fn remove_dir(dir: &Path) -> Result<(), Error> {
+    std::fs::remove_dir_all(dir).map_err(|err| Error::Io(dir.into(), err))
+}
@@ -1,11 +1,16 @@
 use crate::api;
-use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::build::RepoBuilder;
+use git2::{WorktreeAddOptions, WorktreePruneOptions};
 use octocrab::models::issues::Issue;
+use rhai::exported_module;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
 use thiserror::Error;
-use rhai::exported_module;
+
+/// Separates the scripts of a job running several in a row, like `bench.rhai && report.rhai`
+pub const STEP_SEPARATOR: &str = "&&";
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -30,6 +35,191 @@ pub enum Error {
     CargoCmdParse,
     #[error("Failed to parse Repository: missing field \"{0}\"")]
     MissingRepositoryField(String),
+    #[error("{0}")]
+    Pipeline(#[from] crate::pipeline::Error),
+    #[error("Failed to fetch pull request: {0}")]
+    PullRequest(#[from] api::Error),
+    #[error("Failed to prepare worktree: {0}")]
+    Worktree(std::io::Error),
+    #[error(
+        "Invalid clone strategy {0:?}, expected full, shallow[:<depth>], blobless or treeless"
+    )]
+    CloneStrategy(String),
+    #[error("{0}")]
+    Git(#[from] api::git::Error),
+    #[error("Invalid build cache {0:?}, expected job, shared or sccache")]
+    BuildCache(String),
+    #[error("{0}")]
+    Suites(#[from] crate::suites::Error),
+    #[error(
+        "Cargo.lock is out of date with Cargo.toml, run `cargo update --workspace` and commit it \
+         so results aren't measured against another dependency tree:\n{0}"
+    )]
+    StaleLockfile(String),
+    #[error("{0}")]
+    Toolchain(#[from] api::toolchain::Error),
+    #[error("{0}")]
+    Quota(#[from] api::quota::Error),
+    #[error(
+        "Resource limit exceeded: the job went over its {0} and was stopped so it wouldn't take \
+         down the runner"
+    )]
+    ResourceLimit(api::quota::Exceeded),
+    #[error("Step {step} failed: {source}\n\n{}", render_steps(.steps))]
+    Step {
+        step: String,
+        source: Box<Error>,
+        /// All the steps of the job, up to the failed one and the ones not run after it
+        steps: Vec<StepOutcome>,
+    },
+}
+
+impl Error {
+    /// Whether running the job again may succeed, like when the network or Github failed while
+    /// checking it out. Failures of the script itself don't count.
+    pub fn is_retryable(&self) -> bool {
+        fn is_transient(err: &git2::Error) -> bool {
+            matches!(
+                err.class(),
+                git2::ErrorClass::Net | git2::ErrorClass::Http | git2::ErrorClass::Ssh
+            )
+        }
+
+        match self {
+            Error::Clone { source } => is_transient(source),
+            Error::Git(api::git::Error::Checkout { source }) => is_transient(source),
+            Error::Git(api::git::Error::GitCommand { .. })
+            | Error::Git(api::git::Error::NoAccessToken(_))
+            | Error::Git(api::git::Error::GithubApiError { .. })
+            | Error::PullRequest(_)
+            | Error::Toolchain(api::toolchain::Error::Rustup { .. }) => true,
+            Error::Step { source, .. } => source.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+/// How much of a repository to fetch for a job. Anything but a full clone goes through the `git`
+/// command line, since libgit2 doesn't support shallow or partial clones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CloneStrategy {
+    #[default]
+    Full,
+    /// Only the last `n` commits (`--depth n`)
+    Shallow(u32),
+    /// All commits and trees, blobs are fetched when needed (`--filter=blob:none`)
+    Blobless,
+    /// All commits, trees and blobs are fetched when needed (`--filter=tree:0`)
+    Treeless,
+}
+
+impl std::str::FromStr for CloneStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "full" => Ok(CloneStrategy::Full),
+            None if s == "shallow" => Ok(CloneStrategy::Shallow(1)),
+            None if s == "blobless" => Ok(CloneStrategy::Blobless),
+            None if s == "treeless" => Ok(CloneStrategy::Treeless),
+            Some(("shallow", depth)) => match depth.parse() {
+                Ok(depth) if depth > 0 => Ok(CloneStrategy::Shallow(depth)),
+                _ => Err(Error::CloneStrategy(s.to_string())),
+            },
+            _ => Err(Error::CloneStrategy(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for CloneStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloneStrategy::Full => write!(f, "full"),
+            CloneStrategy::Shallow(depth) => write!(f, "shallow:{}", depth),
+            CloneStrategy::Blobless => write!(f, "blobless"),
+            CloneStrategy::Treeless => write!(f, "treeless"),
+        }
+    }
+}
+
+/// Where cargo keeps the build artifacts of a job
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildCache {
+    /// In the checkout, so every job starts from scratch
+    #[default]
+    Job,
+    /// One target directory per repository, kept between jobs. Fast, but a job can be affected
+    /// by what earlier jobs left behind.
+    Shared,
+    /// In the checkout, with rustc wrapped by `sccache`
+    Sccache,
+}
+
+impl std::str::FromStr for BuildCache {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "job" => Ok(BuildCache::Job),
+            "shared" => Ok(BuildCache::Shared),
+            "sccache" => Ok(BuildCache::Sccache),
+            _ => Err(Error::BuildCache(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for BuildCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildCache::Job => write!(f, "job"),
+            BuildCache::Shared => write!(f, "shared"),
+            BuildCache::Sccache => write!(f, "sccache"),
+        }
+    }
+}
+
+/// How `Job::checkout_with` checks out the repository
+#[derive(Clone, Debug)]
+pub struct CheckoutOptions {
+    pub clone_strategy: CloneStrategy,
+    /// Initialize and update the submodules, recursively
+    pub submodules: bool,
+    /// To clone and fetch private repositories with, also passed on to the script
+    pub credentials: api::git::Credentials,
+    pub build_cache: BuildCache,
+    /// Where sccache keeps the cache, with `BuildCache::Sccache`
+    pub sccache: api::cargo::sccache::Config,
+    /// Keep the worktree of the job if it's still there, like one handed off while running with
+    /// what it built so far, instead of starting over with a fresh one
+    pub reuse_checkout: bool,
+    /// Read-only mirror to clone from anonymously instead of the repository itself, like a cache
+    /// close by. Refs are still fetched from the repository.
+    pub clone_mirror: Option<url::Url>,
+}
+
+impl Default for CheckoutOptions {
+    fn default() -> Self {
+        CheckoutOptions {
+            clone_strategy: CloneStrategy::Full,
+            submodules: true,
+            credentials: api::git::Credentials::None,
+            build_cache: BuildCache::Job,
+            sccache: Default::default(),
+            reuse_checkout: false,
+            clone_mirror: None,
+        }
+    }
+}
+
+impl CloneStrategy {
+    fn clone_args(&self) -> Vec<String> {
+        match self {
+            CloneStrategy::Full => vec![],
+            CloneStrategy::Shallow(depth) => vec![format!("--depth={}", depth)],
+            CloneStrategy::Blobless => vec!["--filter=blob:none".into()],
+            CloneStrategy::Treeless => vec!["--filter=tree:0".into()],
+        }
+    }
 }
 
 // We use our own `Repository` definition instead of `octocrab::models::Repository` so we can make
@@ -42,6 +232,14 @@ pub struct Repository {
     pub url: url::Url,
     pub owner: octocrab::models::User,
     clone_url: url::Url,
+    /// Branch pull requests go into unless they ask otherwise, like `master` or `main`
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    /// `public`, `private` or `internal`
+    #[serde(default)]
+    pub visibility: Option<String>,
+    #[serde(default)]
+    pub topics: Vec<String>,
 }
 
 impl std::convert::TryFrom<octocrab::models::Repository> for Repository {
@@ -54,105 +252,494 @@ impl std::convert::TryFrom<octocrab::models::Repository> for Repository {
         let clone_url = repo
             .clone_url
             .ok_or_else(|| Error::MissingRepositoryField("clone_url".into()))?;
+        // Older payloads only tell whether the repository is private
+        let private = repo.private;
+        let visibility = repo.visibility.or_else(|| {
+            private.map(|private| if private { "private" } else { "public" }.to_string())
+        });
         Ok(Repository {
             id: repo.id,
             name: repo.name,
             url: repo.url,
             owner,
             clone_url,
+            default_branch: repo.default_branch,
+            visibility,
+            topics: repo.topics.unwrap_or_default(),
         })
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Job {
+    pub id: String,
     pub command: Vec<String>,
-    //pub user: octocrab::models::User,
+    /// Login of whoever requested the job, mentioned in its result
+    #[serde(default)]
+    pub user: Option<String>,
+    /// The command as it was given, like `/benchbot bench runtime`, quoted in the result
+    #[serde(default)]
+    pub request: Option<String>,
     pub repository: Repository,
-    pub issue: Issue,
+    /// Issue or pull request the job was requested on, none for jobs submitted through the API
+    pub issue: Option<Issue>,
+    /// Number of times this job was queued again after being interrupted, by a restart of the
+    /// reactor or its runner going silent, up to `--max-attempts`. Unlike `attempts`, these
+    /// aren't failures of the job itself.
+    #[serde(default)]
+    pub retries: u32,
+    /// Branch (or full ref, like `refs/tags/v1.0`) to run on instead of the head of the pull
+    /// request, like when refreshing the results of a base branch
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// Part of the job this one runs, if it's split into shards
+    #[serde(default)]
+    pub shard: Option<crate::shards::Shard>,
+    /// Number of times this job was queued again after failing in a way that may not happen
+    /// again, up to `--max-job-retries`. Interruptions are counted in `retries` instead.
+    #[serde(default)]
+    pub attempts: u32,
+    /// Why the previous attempt failed
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Where the repository and issue are, GitLab projects and merge requests are stored like
+    /// their Github counterparts
+    #[serde(default)]
+    pub forge: crate::forge::Kind,
+    /// Set when a reactor that was shutting down handed this job off while running it
+    #[serde(default)]
+    pub handoff: Option<Handoff>,
+    /// Pool of workers to run on, like the ones on reference hardware, the ordinary ones if none
+    #[serde(default)]
+    pub runner: Option<String>,
+    /// OS of the workers to run on, like `macos`, see `runners::route`
+    #[serde(default)]
+    pub runner_os: Option<String>,
+    /// Run even if the same scripts already ran successfully on the same commit, instead of
+    /// reporting their cached result
+    #[serde(default)]
+    pub force: bool,
+    /// Named baseline to compare the results of a pull request against, instead of the history
+    /// of its base branch
+    #[serde(default)]
+    pub baseline: Option<String>,
+    /// Label of the pull request that queued the job, which cancels it when removed, see
+    /// `crate::labels`
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Review comment the job was requested in, whose thread gets the replies, see
+    /// `crate::review_comments`
+    #[serde(default)]
+    pub review_comment: Option<u64>,
+}
+
+impl crate::Routed for Job {
+    fn route(&self) -> Option<std::borrow::Cow<'_, str>> {
+        crate::runners::route(self.runner.as_deref(), self.runner_os.as_deref())
+    }
+}
+
+/// State of a job that was still running when its reactor had to stop, for the worker picking
+/// it up next
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Handoff {
+    /// Worktree the job was running in, reused if the next worker shares it
+    pub checkout: PathBuf,
+    /// Phase the script was in
+    pub phase: Option<String>,
+    /// How long the job had been running
+    pub running_for: std::time::Duration,
 }
 
 impl Job {
+    /// Jobs running the same scripts with the same arguments on the same issue (or branch), and
+    /// on the same kind of worker, share a deduplication key
+    pub fn dedup_key(&self) -> String {
+        let mut script = self.command.join(" ");
+        if let Some(shard) = &self.shard {
+            script = format!("{}[{}]", script, shard);
+        }
+        if let Some(route) = crate::Routed::route(self) {
+            script = format!("{script} on {route}");
+        }
+        match (&self.branch, &self.issue) {
+            (Some(branch), _) => format!(
+                "{}/{}@{}:{}",
+                self.repository.owner.login, self.repository.name, branch, script
+            ),
+            (None, Some(issue)) => format!(
+                "{}/{}#{}:{}",
+                self.repository.owner.login, self.repository.name, issue.number, script
+            ),
+            (None, None) => format!(
+                "{}/{}:{}",
+                self.repository.owner.login, self.repository.name, script
+            ),
+        }
+    }
+
+    /// Web page of the issue the job was requested on, or of the repository without one
+    pub fn issue_url(&self) -> url::Url {
+        match &self.issue {
+            Some(issue) => issue.html_url.clone(),
+            None => self.repository.url.clone(),
+        }
+    }
+
+    /// The jobs of the shards of this job, if `shard` asks for more than one, each with their own
+    /// ID and all in the group of this job's ID. Just the job itself otherwise.
+    pub fn split(mut self) -> Vec<Job> {
+        let count = match self.shard.take() {
+            Some(shard) if shard.count > 1 && shard.group == self.id => shard.count,
+            // A part of a job split already, like one imported from another reactor
+            Some(shard) if shard.group != self.id => {
+                self.shard = Some(shard);
+                return vec![self];
+            }
+            _ => return vec![self],
+        };
+        (0..count)
+            .map(|index| {
+                let mut job = self.clone();
+                job.id = format!("{}_shard{}", self.id, index + 1);
+                job.shard = Some(crate::shards::Shard {
+                    group: self.id.clone(),
+                    index,
+                    count,
+                });
+                job
+            })
+            .collect()
+    }
+
+    /// Ref to fetch and check out
     fn pr_branch(&self) -> String {
-        format!("pull/{}/head", self.issue.number)
-    }
-
-    // This function assumes at most one Job::checkout() run at any time. This requirement is
-    // because of FS mutation, which unfortunately the type checker can't help us with. Currently
-    // this is guaranteed by spawning only one thread that synchronously runs jobs.
-    pub fn checkout<R: AsRef<Path> + Copy>(&self, root: R) -> Result<CheckedoutJob, Error>
-    where
-        PathBuf: From<R>,
-    {
-        let dir = self.repo_dir(root);
-        let branch = self.pr_branch();
-        let repo = match std::fs::metadata(&dir) {
-            Ok(metadata) if metadata.is_dir() => git2::Repository::open(&dir)?,
+        match (&self.branch, &self.issue, self.forge) {
+            (Some(branch), _, _) if branch.starts_with("refs/") => branch.clone(),
+            (Some(branch), _, _) => format!("refs/heads/{}", branch),
+            (None, Some(issue), crate::forge::Kind::Github) => {
+                format!("refs/pull/{}/head", issue.number)
+            }
+            (None, Some(issue), crate::forge::Kind::Gitlab) => {
+                format!("refs/merge-requests/{}/head", issue.number)
+            }
+            (None, None, _) => "HEAD".to_string(),
+        }
+    }
+
+    pub fn checkout<R: AsRef<Path>>(&self, root: R) -> Result<CheckedoutJob, Error> {
+        self.checkout_with(root, &CheckoutOptions::default())
+    }
+
+    // Checkouts are worktrees of a bare clone of the repository, so jobs on the same repository
+    // can be checked out at the same time. Cloning and fetching into the shared clone take turns
+    // through a `CheckoutLock` on it.
+    #[tracing::instrument(name = "checkout", skip_all, fields(job = %self.id))]
+    pub fn checkout_with<R: AsRef<Path>>(
+        &self,
+        root: R,
+        options: &CheckoutOptions,
+    ) -> Result<CheckedoutJob, Error> {
+        let strategy = options.clone_strategy;
+        let credentials = &options.credentials;
+        let root = root.as_ref();
+        std::fs::create_dir_all(root.join("worktrees")).map_err(Error::Worktree)?;
+        let mirror_dir = self.mirror_dir(root);
+        let lock = CheckoutLock::acquire(&mirror_dir);
+        let mirror = match std::fs::metadata(&mirror_dir) {
+            Ok(metadata) if metadata.is_dir() => git2::Repository::open_bare(&mirror_dir)?,
             Err(_) => {
                 // Path doesn't exist
-                let url = self.repository.clone_url.as_ref();
-
-                let mut checkout = CheckoutBuilder::new();
-                checkout.remove_untracked(true).remove_ignored(true).force();
-                log::info!("Cloning {} to {:?}", &self.repository.clone_url, &dir);
-                RepoBuilder::new()
-                    .with_checkout(checkout)
-                    .clone(url.as_ref(), &dir)?
+                let url = credentials.url(self.repository.clone_url.as_ref());
+                let clone = |url: &str, credentials: &api::git::Credentials| {
+                    tracing::info!("Cloning {} to {:?} ({})", url, &mirror_dir, strategy);
+                    if strategy == CloneStrategy::Full {
+                        Ok(RepoBuilder::new()
+                            .bare(true)
+                            .fetch_options(credentials.fetch_options())
+                            .clone(url, &mirror_dir)?)
+                    } else {
+                        let mut args = vec!["clone".to_string(), "--bare".into()];
+                        args.extend(strategy.clone_args());
+                        args.push(url.to_string());
+                        args.push(mirror_dir.to_string_lossy().into());
+                        api::git::run_git(root, &args, credentials)?;
+                        Ok::<_, Error>(git2::Repository::open_bare(&mirror_dir)?)
+                    }
+                };
+                match &options.clone_mirror {
+                    Some(clone_mirror) => {
+                        match clone(clone_mirror.as_str(), &api::git::Credentials::None) {
+                            Ok(repo) => {
+                                // Pull requests and what the mirror is behind on come from the
+                                // repository itself
+                                repo.remote_set_url("origin", &url)?;
+                                repo
+                            }
+                            Err(err) => {
+                                tracing::warn!("Failed to clone from {clone_mirror}: {err}");
+                                if mirror_dir.exists() {
+                                    std::fs::remove_dir_all(&mirror_dir)
+                                        .map_err(Error::Worktree)?;
+                                }
+                                clone(&url, credentials)?
+                            }
+                        }
+                    }
+                    None => clone(&url, credentials)?,
+                }
             }
             Ok(_) => {
-                log::warn!("Path {:?} exists but is not a directory", dir);
-                return Err(Error::NoDirectory(dir));
+                tracing::warn!("Path {:?} exists but is not a directory", mirror_dir);
+                return Err(Error::NoDirectory(mirror_dir));
             }
         };
 
-        log::info!("Fetching {} in {:?}", branch, dir);
-        repo.find_remote("origin")?.fetch(
-            &[&format!("refs/{}:refs/heads/{}", branch, branch)],
-            None,
-            None,
-        )?;
-
-        let rev = repo.revparse_single("FETCH_HEAD")?;
-        repo.reset(
-            &rev,
-            git2::ResetType::Hard,
-            Some(
-                CheckoutBuilder::new()
-                    .remove_untracked(true)
-                    .remove_ignored(true)
-                    .force(),
-            ),
-        )?;
+        let name = self.worktree_name();
+        let dir = root.join("worktrees").join(&name);
+        if options.reuse_checkout && dir.is_dir() && mirror.find_worktree(&name).is_ok() {
+            // Back to the checked out commit, keeping ignored files like the build artifacts
+            tracing::info!("Reusing checkout in {:?}", dir);
+            api::git::run_git(&dir, &["reset", "--hard", "HEAD"], credentials)?;
+            api::git::run_git(&dir, &["clean", "-fd"], credentials)?;
+        } else {
+            self.add_worktree(&mirror, &mirror_dir, &name, &dir, options)?;
+        }
+        let checkout_wait = lock.waited;
+        drop(lock);
+
+        // libgit2 can't update submodules of worktrees
+        if options.submodules && dir.join(".gitmodules").exists() {
+            tracing::info!("Updating submodules in {:?}", dir);
+            api::git::run_git(
+                &dir,
+                &["submodule", "update", "--init", "--recursive"],
+                credentials,
+            )?;
+        }
+
+        let cargo_env = match options.build_cache {
+            BuildCache::Job => vec![],
+            BuildCache::Shared => {
+                let target_dir = mirror_dir.with_extension("target");
+                std::fs::create_dir_all(&target_dir).map_err(Error::Worktree)?;
+                // For the janitor to tell which target directories are still in use
+                std::fs::File::open(&target_dir)
+                    .and_then(|dir| dir.set_modified(std::time::SystemTime::now()))
+                    .map_err(Error::Worktree)?;
+                vec![(
+                    "CARGO_TARGET_DIR".to_string(),
+                    target_dir.to_string_lossy().into_owned(),
+                )]
+            }
+            // Set by `sccache` below
+            BuildCache::Sccache => vec![],
+        };
+        let sccache = match options.build_cache {
+            BuildCache::Sccache => api::cargo::sccache::Sccache::new(options.sccache.clone()),
+            _ => Default::default(),
+        };
 
         let job = CheckedoutJob {
             //job: self.clone(),
             command: self.command.clone(),
             dir,
-            clone_dir: PathBuf::from(root),
+            clone_dir: root.into(),
             gh_repo: self.repository.clone(),
-            gh_issue: Some(self.issue.clone()),
+            gh_issue: self.issue.clone(),
+            http_allowlist: vec![],
+            credentials: credentials.clone(),
+            cargo_env,
+            cargo_output: api::cargo::Output {
+                log_dir: Some(self.logs_dir(root)),
+                limit: None,
+            },
+            env: Default::default(),
+            warm_up: false,
+            artifacts: None,
+            redactor: Default::default(),
+            shard: self.shard.clone(),
+            forge: None,
+            phases: Default::default(),
+            script_library: None,
+            pinning: Default::default(),
+            pr_policy: Default::default(),
+            commits: Default::default(),
+            toolchain: Default::default(),
+            sccache,
+            accounting: Default::default(),
+            quota: Default::default(),
+            results_history: None,
+            profiler: Default::default(),
+            checkout_wait,
+            triggered_by: self.user.clone(),
         };
         Ok(job)
     }
 
-    fn repo_dir<R: AsRef<Path>>(&self, root: R) -> PathBuf
-    where
-        PathBuf: From<R>,
-    {
-        let mut full_path = PathBuf::from(root);
-        let dir_name = format!(
-            "{}_{}_{}_{}_{}",
-            self.repository.id,
-            self.issue.number,
-            self.issue.user.login,
-            &self.repository.owner.login,
-            &self.repository.name
-        );
-        full_path.set_file_name(dir_name);
-        full_path
+    /// Fetch the branch of this job into `mirror` and check it out in a new worktree at `dir`
+    fn add_worktree(
+        &self,
+        mirror: &git2::Repository,
+        mirror_dir: &Path,
+        name: &str,
+        dir: &Path,
+        options: &CheckoutOptions,
+    ) -> Result<(), Error> {
+        let strategy = options.clone_strategy;
+        let credentials = &options.credentials;
+        // A retried job gets the same worktree, start over with a clean one
+        remove_worktree(mirror, name, dir)?;
+
+        let remote_ref = self.pr_branch();
+        let refspec = format!("+{}:refs/heads/{}", remote_ref, name);
+        // A clone made with another strategy before stays shallow or partial, so libgit2 can't
+        // work with it either
+        let use_git_cli = strategy != CloneStrategy::Full || api::git::is_reduced(mirror);
+        tracing::info!("Fetching {} in {:?}", remote_ref, mirror_dir);
+        if use_git_cli {
+            let mut args = vec!["fetch".to_string()];
+            match strategy {
+                CloneStrategy::Shallow(depth) => args.push(format!("--depth={}", depth)),
+                CloneStrategy::Full if mirror.is_shallow() => args.push("--unshallow".into()),
+                _ => {}
+            }
+            args.push("origin".into());
+            args.push(refspec);
+            api::git::run_git(mirror_dir, &args, credentials)?;
+
+            tracing::info!("Checking out {} in {:?}", remote_ref, dir);
+            api::git::run_git(
+                mirror_dir,
+                &["worktree", "add", &dir.to_string_lossy(), name],
+                credentials,
+            )?;
+        } else {
+            mirror.find_remote("origin")?.fetch(
+                &[&refspec],
+                Some(&mut credentials.fetch_options()),
+                None,
+            )?;
+
+            tracing::info!("Checking out {} in {:?}", remote_ref, dir);
+            let branch = mirror.find_branch(name, git2::BranchType::Local)?;
+            mirror.worktree(
+                name,
+                dir,
+                Some(WorktreeAddOptions::new().reference(Some(branch.get()))),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Remove the worktree checked out for this job, keeping the clone around for later jobs
+    pub fn remove_checkout<R: AsRef<Path>>(&self, root: R) -> Result<(), Error> {
+        let root = root.as_ref();
+        let _lock = CheckoutLock::acquire(&self.mirror_dir(root));
+        let mirror = git2::Repository::open_bare(self.mirror_dir(root))?;
+        let name = self.worktree_name();
+        let logs_dir = self.logs_dir(root);
+        if logs_dir.exists() {
+            std::fs::remove_dir_all(logs_dir).map_err(Error::Worktree)?;
+        }
+        remove_worktree(&mirror, &name, &root.join("worktrees").join(&name))
+    }
+
+    /// Where `checkout_with` checks out this job below `root`
+    pub fn checkout_dir<R: AsRef<Path>>(&self, root: R) -> PathBuf {
+        root.as_ref().join("worktrees").join(self.worktree_name())
+    }
+
+    /// Where the output of cargo goes, next to the checkout so scripts committing everything
+    /// don't pick it up
+    fn logs_dir(&self, root: &Path) -> PathBuf {
+        self.checkout_dir(root).with_extension("logs")
+    }
+
+    fn mirror_dir(&self, root: &Path) -> PathBuf {
+        root.join(format!(
+            "{}_{}_{}.git",
+            self.repository.id, self.repository.owner.login, self.repository.name
+        ))
+    }
+
+    /// Name of the worktree and the local branch it has checked out
+    fn worktree_name(&self) -> String {
+        self.id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+}
+
+/// Clones being changed right now, by the checkouts of this process
+fn busy_clones() -> &'static (Mutex<HashSet<PathBuf>>, Condvar) {
+    static BUSY: OnceLock<(Mutex<HashSet<PathBuf>>, Condvar)> = OnceLock::new();
+    BUSY.get_or_init(Default::default)
+}
+
+/// Exclusive use of the clone of a repository, to fetch into it and add or remove worktrees,
+/// until dropped. Jobs of the same repository would corrupt it otherwise.
+pub struct CheckoutLock {
+    dir: PathBuf,
+    /// For the clone to be released by the checkout before
+    pub waited: std::time::Duration,
+}
+
+impl CheckoutLock {
+    /// Wait for the clone at `dir` to be free, and take it
+    pub fn acquire(dir: &Path) -> Self {
+        let started = std::time::Instant::now();
+        let (busy, released) = busy_clones();
+        let mut busy = busy.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if busy.contains(dir) {
+            tracing::info!("Waiting for another checkout of {:?}", dir);
+        }
+        while busy.contains(dir) {
+            busy = released
+                .wait(busy)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        busy.insert(dir.to_owned());
+        CheckoutLock {
+            dir: dir.to_owned(),
+            waited: started.elapsed(),
+        }
+    }
+}
+
+impl Drop for CheckoutLock {
+    fn drop(&mut self) {
+        let (busy, released) = busy_clones();
+        let mut busy = busy.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        busy.remove(&self.dir);
+        released.notify_all();
     }
 }
 
+pub(crate) fn remove_worktree(
+    mirror: &git2::Repository,
+    name: &str,
+    dir: &Path,
+) -> Result<(), Error> {
+    if dir.exists() {
+        std::fs::remove_dir_all(dir).map_err(Error::Worktree)?;
+    }
+    if let Ok(worktree) = mirror.find_worktree(name) {
+        worktree.prune(Some(WorktreePruneOptions::new().valid(true).locked(true)))?;
+    }
+    if let Ok(mut branch) = mirror.find_branch(name, git2::BranchType::Local) {
+        branch.delete()?;
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct CheckedoutJob {
     //job: Job,
@@ -161,32 +748,277 @@ pub struct CheckedoutJob {
     pub clone_dir: PathBuf,
     pub gh_repo: Repository,
     pub gh_issue: Option<Issue>,
+    /// Hosts scripts are allowed to reach through `http_get` and `http_post`
+    pub http_allowlist: Vec<String>,
+    /// Used by the script to clone and fetch repositories
+    pub credentials: api::git::Credentials,
+    /// Environment of cargo, like where to put build artifacts
+    pub cargo_env: Vec<(String, String)>,
+    /// Where the output of cargo goes and how much of it scripts get
+    pub cargo_output: api::cargo::Output,
+    /// Variables and secrets the script can read through `env::get` and cargo is run with
+    pub env: crate::secrets::JobEnv,
+    /// Warm up before measuring, exposed to scripts as `WARM_UP` and the default for pipelines
+    pub warm_up: bool,
+    /// Where `upload_artifact` stores files, scripts can't upload any without it
+    pub artifacts: Option<api::artifacts::Artifacts>,
+    /// Masks secrets in what the script prints, gets back from cargo and comments. The secrets of
+    /// `env` and the token in `credentials` are added to it.
+    pub redactor: crate::secrets::Redactor,
+    /// Part of the job to run, exposed to scripts as `SHARD`. Only its share of the suites is
+    /// selected.
+    pub shard: Option<crate::shards::Shard>,
+    /// Where `ISSUE.comment` and `REPO.create_pr` go, the Github repository through the client
+    /// passed to `prepare_script` if not set
+    pub forge: Option<Arc<dyn crate::forge::Forge>>,
+    /// Timed by the script with `phase`, shared so others can tell which one is running
+    pub phases: api::phases::Phases,
+    /// Checkout of the modules scripts import as `lib:<name>`, see [`crate::library`]
+    pub script_library: Option<PathBuf>,
+    /// Cores cargo runs on, which scripts can change with `with_pinned_cores`
+    pub pinning: api::pinning::Pinning,
+    /// What pull requests `REPO.create_pr` may open
+    pub pr_policy: api::git::PrPolicy,
+    /// Who the commits scripts make are by and how they're signed
+    pub commits: api::git::CommitConfig,
+    /// Toolchain cargo runs with, which scripts can change through `TOOLCHAIN`
+    pub toolchain: api::toolchain::Toolchain,
+    /// Whether cargo builds through sccache, counting its hits and misses
+    pub sccache: api::cargo::sccache::Sccache,
+    /// Adds up the resources used by the cargo runs of the job
+    pub accounting: api::usage::Accounting,
+    /// Memory and disk the processes of the job may use, set up before the script runs
+    pub quota: api::quota::Quota,
+    /// Results of earlier runs, for scripts to chart through `RESULTS.history`
+    pub results_history: Option<api::results::History>,
+    /// Whether scripts may take flamegraphs with `flamegraph`
+    pub profiler: api::profile::Profiler,
+    /// How long the checkout waited for another one of the same repository
+    pub checkout_wait: std::time::Duration,
+    /// Login of whoever requested the job, exposed to scripts as `TRIGGERED_BY`
+    pub triggered_by: Option<String>,
 }
 
 impl CheckedoutJob {
-    fn prepare_engine(&self) -> Result<rhai::Engine, Error> {
+    fn prepare_engine(
+        &self,
+        git: &api::git::Git,
+        warnings: &api::warnings::Warnings,
+        phases: &api::phases::Phases,
+        results: &api::results::Results,
+        http: &api::http::Http,
+        report: &api::report::Report,
+    ) -> Result<rhai::Engine, Error> {
         let mut engine = rhai::Engine::new();
+        engine.set_module_resolver(crate::library::Resolver::new(
+            self.script_library.as_deref(),
+        ));
+
+        let print_redactor = self.redactor.clone();
+        let debug_redactor = self.redactor.clone();
+        engine
+            .on_print(move |text| println!("{}", print_redactor.redact(text)))
+            .on_debug(move |text, source, pos| {
+                let text = debug_redactor.redact(text);
+                match source {
+                    Some(source) => println!("{source} @ {pos:?} | {text}"),
+                    None if pos.is_none() => println!("{text}"),
+                    None => println!("{pos:?} | {text}"),
+                }
+            });
+
+        let deprecation_warnings = warnings.clone();
+        #[allow(deprecated)]
+        engine.on_var(move |name, _index, _context| {
+            if let Some((old, replacement)) = api::warnings::DEPRECATED_CONSTANTS
+                .iter()
+                .find(|(old, _)| *old == name)
+            {
+                deprecation_warnings.deprecated(old, replacement);
+            }
+            Ok(None)
+        });
 
         engine
             .register_type::<api::cargo::CargoResult>()
             .register_fn("is_ok", api::cargo::CargoResult::is_ok)
+            .register_get("success", api::cargo::CargoResult::is_ok)
+            .register_get("exit_code", api::cargo::CargoResult::get_exit_code)
+            .register_get("signal", api::cargo::CargoResult::get_signal)
+            .register_get("duration_ms", api::cargo::CargoResult::get_duration_ms)
             .register_get("stdout", api::cargo::CargoResult::get_stdout)
-            .register_get("stderr", api::cargo::CargoResult::get_stderr);
-
-        let cargo_dir = self.dir.clone();
-        engine.register_custom_syntax(&["cargo", "$expr$"], false, move |context, inputs| {
-            let expr = &inputs[0];
-            let value = context
-                .eval_expression_tree(expr)?
-                .try_cast::<String>()
-                .ok_or("Failed to parse `cargo` arguments into a string")?;
-
-            let value =
-                shell_words::split(&value).map_err(|_| "Failed to parse `cargo` arguments")?;
-            let cargo = api::cargo::Run::new(value, &cargo_dir);
-            let result = cargo.run();
-            Ok(rhai::Dynamic::from(result))
-        })?;
+            .register_get("stderr", api::cargo::CargoResult::get_stderr)
+            .register_get("stdout_path", api::cargo::CargoResult::get_stdout_path)
+            .register_get("stderr_path", api::cargo::CargoResult::get_stderr_path)
+            .register_get("truncated", api::cargo::CargoResult::get_truncated)
+            .register_get("artifacts", api::cargo::CargoResult::get_artifacts)
+            .register_get("diagnostics", api::cargo::CargoResult::get_diagnostics)
+            .register_get("timings", api::cargo::CargoResult::get_timings)
+            .register_get("sccache", api::cargo::CargoResult::get_sccache)
+            .register_get("usage", api::cargo::CargoResult::get_usage);
+
+        // `cargo_json` parses the messages of cargo into `artifacts` and `diagnostics`
+        for (keyword, json) in [("cargo", false), ("cargo_json", true)] {
+            let cargo_dir = self.dir.clone();
+            let cargo_env = self.all_cargo_env();
+            let cargo_output = self.cargo_output.clone();
+            let cargo_pinning = self.pinning.clone();
+            let cargo_toolchain = self.toolchain.clone();
+            let cargo_sccache = self.sccache.clone();
+            let cargo_accounting = self.accounting.clone();
+            let cargo_quota = self.quota.clone();
+            let cargo_redactor = self.redactor.clone();
+            engine.register_custom_syntax(
+                [keyword, "$expr$"],
+                false,
+                move |context, inputs| {
+                    let expr = &inputs[0];
+                    let value = context
+                        .eval_expression_tree(expr)?
+                        .try_cast::<String>()
+                        .ok_or("Failed to parse `cargo` arguments into a string")?;
+
+                    let value = shell_words::split(&value)
+                        .map_err(|_| "Failed to parse `cargo` arguments")?;
+                    let cargo = api::cargo::Run::new(value, &cargo_dir)
+                        .envs(&cargo_env)
+                        .output(&cargo_output)
+                        .pinning(&cargo_pinning)
+                        .toolchain(&cargo_toolchain)
+                        .sccache(&cargo_sccache)
+                        .accounting(&cargo_accounting)
+                        .quota(&cargo_quota)
+                        .redactor(&cargo_redactor);
+                    let result = if json { cargo.run_json() } else { cargo.run() };
+                    // Carrying on would only make the job fail later, for a less obvious reason
+                    if let Some(exceeded) = cargo_quota.exceeded() {
+                        return Err(format!("Resource limit exceeded: {exceeded}").into());
+                    }
+                    Ok(rhai::Dynamic::from(result))
+                },
+            )?;
+        }
+
+        // Profiles cargo like the `cargo` keyword runs it, into a flamegraph uploaded as an artifact
+        let profile_dir = self.dir.clone();
+        let profile_env = self.all_cargo_env();
+        let profile_output = self.cargo_output.clone();
+        let profile_pinning = self.pinning.clone();
+        let profile_toolchain = self.toolchain.clone();
+        let profile_accounting = self.accounting.clone();
+        let profile_quota = self.quota.clone();
+        let profile_redactor = self.redactor.clone();
+        let profiler = self.profiler.clone();
+        let profile_artifacts = self.artifacts.clone();
+        let flamegraph = move |args: &str, name: &str| {
+            profiler.check().map_err(|e| format!("{e}"))?;
+            let artifacts = profile_artifacts
+                .as_ref()
+                .ok_or("Artifacts aren't enabled on this runner")?;
+            let args =
+                shell_words::split(args).map_err(|_| "Failed to parse `flamegraph` arguments")?;
+            // Written outside the checkout so it doesn't end up in commits of the script
+            let path = std::env::temp_dir()
+                .join(format!("ci-script-flamegraph-{}.svg", uuid::Uuid::new_v4()));
+            let mut result =
+                api::cargo::Run::new(api::profile::Profiler::args(&args, &path), &profile_dir)
+                    .envs(&profile_env)
+                    .output(&profile_output)
+                    .pinning(&profile_pinning)
+                    .toolchain(&profile_toolchain)
+                    .accounting(&profile_accounting)
+                    .quota(&profile_quota)
+                    .redactor(&profile_redactor)
+                    .run();
+            let _ = std::fs::remove_file(api::profile::Profiler::samples(&profile_dir));
+            let uploaded = if result.is_ok() {
+                artifacts.store(&path, name).map_err(|e| format!("{e}"))
+            } else {
+                // What went wrong is at the end, after whatever cargo built
+                let stderr: Vec<&str> = result.stderr.lines().rev().take(20).collect();
+                let stderr: Vec<&str> = stderr.into_iter().rev().collect();
+                Err(format!(
+                    "Failed to take a flamegraph:\n{}",
+                    stderr.join("\n")
+                ))
+            };
+            let _ = std::fs::remove_file(&path);
+            if let Some(exceeded) = profile_quota.exceeded() {
+                return Err(format!("Resource limit exceeded: {exceeded}").into());
+            }
+            Ok::<_, Box<rhai::EvalAltResult>>(uploaded?.url)
+        };
+        let flamegraph_named = flamegraph.clone();
+        engine
+            .register_result_fn("flamegraph", move |args: &str| {
+                flamegraph(args, "flamegraph.svg")
+            })
+            .register_result_fn("flamegraph", move |args: &str, name: &str| {
+                flamegraph_named(args, name)
+            });
+
+        // Relative to the checkout, recorded to flag when it grows
+        let size_dir = self.dir.clone();
+        let size_results = results.clone();
+        engine.register_result_fn("binary_size", move |path: &str| {
+            size_results.clone().track_size(&size_dir.join(path))
+        });
+
+        engine
+            .register_type_with_name::<api::cargo::runtime::RuntimeBuild>("RuntimeBuild")
+            .register_get("success", api::cargo::runtime::RuntimeBuild::get_success)
+            .register_get("result", api::cargo::runtime::RuntimeBuild::get_result)
+            .register_get("path", api::cargo::runtime::RuntimeBuild::get_path)
+            .register_get("size", api::cargo::runtime::RuntimeBuild::get_size)
+            .register_get("hash", api::cargo::runtime::RuntimeBuild::get_hash);
+
+        // Builds a runtime like the `cargo` keyword runs cargo, with deterministic settings
+        let runtime_dir = self.dir.clone();
+        let runtime_env = self.all_cargo_env();
+        let runtime_output = self.cargo_output.clone();
+        let runtime_pinning = self.pinning.clone();
+        let runtime_toolchain = self.toolchain.clone();
+        let runtime_sccache = self.sccache.clone();
+        let runtime_accounting = self.accounting.clone();
+        let runtime_quota = self.quota.clone();
+        let runtime_redactor = self.redactor.clone();
+        let runtime_results = results.clone();
+        let build_runtime = move |package: &str, profile: &str| {
+            let target = runtime_env
+                .iter()
+                .rev()
+                .find(|(name, _)| name == "CARGO_TARGET_DIR")
+                .map(|(_, dir)| runtime_dir.join(dir))
+                .unwrap_or_else(|| runtime_dir.join("target"));
+            let result =
+                api::cargo::Run::new(api::cargo::runtime::args(package, profile), &runtime_dir)
+                    .envs(&runtime_env)
+                    .envs(&api::cargo::runtime::envs(&runtime_dir))
+                    .output(&runtime_output)
+                    .pinning(&runtime_pinning)
+                    .toolchain(&runtime_toolchain)
+                    .sccache(&runtime_sccache)
+                    .accounting(&runtime_accounting)
+                    .quota(&runtime_quota)
+                    .redactor(&runtime_redactor)
+                    .run();
+            if let Some(exceeded) = runtime_quota.exceeded() {
+                return Err(format!("Resource limit exceeded: {exceeded}").into());
+            }
+            let build = api::cargo::runtime::RuntimeBuild::new(result, &target, package, profile)?;
+            if let Some(wasm) = &build.wasm {
+                runtime_results.clone().track_size(wasm)?;
+            }
+            Ok::<_, Box<rhai::EvalAltResult>>(build)
+        };
+        let build_runtime_in = build_runtime.clone();
+        engine
+            .register_result_fn("build_runtime", move |package: &str| {
+                build_runtime(package, api::cargo::runtime::DEFAULT_PROFILE)
+            })
+            .register_result_fn("build_runtime", move |package: &str, profile: &str| {
+                build_runtime_in(package, profile)
+            });
 
         engine
             .register_type::<api::Issue>()
@@ -195,13 +1027,50 @@ impl CheckedoutJob {
             .register_result_fn(
                 "comment",
                 api::Issue::create_comment::<rhai::ImmutableString>,
-            );
+            )
+            .register_result_fn("publish_report", api::Issue::publish_report::<String>)
+            .register_result_fn(
+                "publish_report",
+                api::Issue::publish_report::<rhai::ImmutableString>,
+            )
+            .register_result_fn("add_label", api::Issue::add_label)
+            .register_result_fn("remove_label", api::Issue::remove_label)
+            .register_result_fn("request_review", api::Issue::request_review)
+            .register_result_fn("assign", api::Issue::assign);
+
+        engine
+            .register_type_with_name::<api::pr::PullRequest>("PullRequest")
+            .register_get("number", api::pr::PullRequest::get_number)
+            .register_get("title", api::pr::PullRequest::get_title)
+            .register_get("author", api::pr::PullRequest::get_author)
+            .register_get("labels", api::pr::PullRequest::get_labels)
+            .register_get("head_ref", api::pr::PullRequest::get_head_ref)
+            .register_get("head_sha", api::pr::PullRequest::get_head_sha)
+            .register_get("base_ref", api::pr::PullRequest::get_base_ref)
+            .register_get("base_sha", api::pr::PullRequest::get_base_sha)
+            .register_get("changed_files", api::pr::PullRequest::get_changed_files);
+
+        engine
+            .register_type_with_name::<api::toolchain::Toolchain>("Toolchain")
+            .register_result_fn("select", api::toolchain::Toolchain::select)
+            .register_result_fn("select", api::toolchain::Toolchain::select_with_components)
+            .register_fn("reset", api::toolchain::Toolchain::reset)
+            .register_get("name", api::toolchain::Toolchain::get_name);
+
+        engine
+            .register_type_with_name::<api::statuses::Statuses>("Statuses")
+            .register_result_fn("set", api::statuses::Statuses::set)
+            .register_result_fn("set", api::statuses::Statuses::set_without_url)
+            .register_result_fn("set", api::statuses::Statuses::set_with_description);
 
         engine
             .register_type::<api::git::Git>()
             .register_result_fn("clone", api::git::Git::clone::<String>)
             .register_result_fn("clone", api::git::Git::clone::<&str>)
-            .register_result_fn("clone", api::git::Git::clone::<rhai::ImmutableString>);
+            .register_result_fn("clone", api::git::Git::clone::<rhai::ImmutableString>)
+            .register_result_fn("open", api::git::Git::open::<String>)
+            .register_result_fn("open", api::git::Git::open::<&str>)
+            .register_result_fn("open", api::git::Git::open::<rhai::ImmutableString>);
 
         engine
             .register_type::<api::git::LocalRepo>()
@@ -213,7 +1082,6 @@ impl CheckedoutJob {
             .register_result_fn("read", api::git::LocalRepo::read_file::<&Path>)
             .register_result_fn("read", api::git::LocalRepo::read_file::<String>)
             .register_result_fn("read", api::git::LocalRepo::read_file::<&str>)
-
             .register_result_fn("write", api::git::LocalRepo::write_file::<PathBuf>)
             .register_result_fn(
                 "write",
@@ -222,7 +1090,6 @@ impl CheckedoutJob {
             .register_result_fn("write", api::git::LocalRepo::write_file::<&Path>)
             .register_result_fn("write", api::git::LocalRepo::write_file::<String>)
             .register_result_fn("write", api::git::LocalRepo::write_file::<&str>)
-
             .register_result_fn("ls", api::git::LocalRepo::list_files)
             .register_result_fn("ls", api::git::LocalRepo::list_files_in_dir::<PathBuf>)
             .register_result_fn("ls", api::git::LocalRepo::list_files_in_dir::<&Path>)
@@ -244,14 +1111,22 @@ impl CheckedoutJob {
                 api::git::LocalRepo::pub_branch::<rhai::ImmutableString>,
             )
             .register_result_fn("current_branch", api::git::LocalRepo::pub_current_branch)
+            .register_get("default_branch", api::git::LocalRepo::get_default_branch)
+            .register_get("visibility", api::git::LocalRepo::get_visibility)
+            .register_get("topics", api::git::LocalRepo::get_topics)
             .register_result_fn("push", api::git::LocalRepo::pub_push::<String, String>)
             .register_result_fn("push", api::git::LocalRepo::pub_push::<&str, &str>)
             .register_result_fn(
                 "push",
                 api::git::LocalRepo::pub_push::<rhai::ImmutableString, rhai::ImmutableString>,
             )
+            .register_result_fn("push", api::git::LocalRepo::pub_push_mode)
             .register_result_fn("create_pr", api::git::LocalRepo::pub_create_pr)
-            .register_result_fn("url", api::git::LocalRepo::pub_url);
+            .register_result_fn("pr_branch", api::git::LocalRepo::pub_pr_branch)
+            .register_result_fn("url", api::git::LocalRepo::pub_url)
+            .register_result_fn("unshallow", api::git::LocalRepo::pub_unshallow)
+            .register_result_fn("diff", api::git::LocalRepo::pub_diff)
+            .register_result_fn("apply_patch", api::git::LocalRepo::pub_apply_patch);
 
         engine
             .register_type::<api::git::DirEntry>()
@@ -283,12 +1158,220 @@ impl CheckedoutJob {
                 api::git::DirEntryPath::strip_prefix::<String>,
             )
             .register_fn("strip_prefix", api::git::DirEntryPath::strip_prefix::<&str>)
-            .register_fn("==",
-                |item1: &mut api::git::DirEntryPath, item2: rhai::ImmutableString| item1.to_string() == item2
+            .register_fn(
+                "==",
+                |item1: &mut api::git::DirEntryPath, item2: rhai::ImmutableString| {
+                    item1.to_string() == item2
+                },
+            );
+
+        engine
+            .register_type::<api::results::Results>()
+            .register_result_fn("record", api::results::Results::record::<String, String>)
+            .register_result_fn("record", api::results::Results::record::<&str, &str>)
+            .register_result_fn(
+                "record",
+                api::results::Results::record::<rhai::ImmutableString, rhai::ImmutableString>,
+            )
+            .register_result_fn(
+                "record",
+                api::results::Results::record_int::<String, String>,
+            )
+            .register_result_fn("record", api::results::Results::record_int::<&str, &str>)
+            .register_result_fn(
+                "record",
+                api::results::Results::record_int::<rhai::ImmutableString, rhai::ImmutableString>,
+            )
+            .register_result_fn("record", api::results::Results::record_quantity::<&str>)
+            .register_result_fn(
+                "record",
+                api::results::Results::record_quantity::<rhai::ImmutableString>,
+            )
+            .register_result_fn("history", api::results::Results::history::<&str, &str>)
+            .register_result_fn(
+                "history",
+                api::results::Results::history::<rhai::ImmutableString, rhai::ImmutableString>,
+            );
+
+        engine
+            .register_type_with_name::<api::baselines::Baselines>("Baselines")
+            .register_result_fn("names", api::baselines::Baselines::names)
+            .register_result_fn("get", api::baselines::Baselines::get)
+            .register_result_fn("commit", api::baselines::Baselines::commit)
+            .register_result_fn("save", api::baselines::Baselines::save);
+
+        engine
+            .register_type_with_name::<api::weights::Weights>("Weights")
+            .register_result_fn("update", api::weights::Weights::update)
+            .register_fn("summary", api::weights::Weights::summary);
+
+        engine
+            .register_type_with_name::<api::weights::Runtime>("Runtime")
+            .register_result_fn("pallets", api::weights::Runtime::pallets);
+
+        engine
+            .register_type_with_name::<api::outputs::Outputs>("Outputs")
+            .register_result_fn("set", api::outputs::Outputs::set)
+            .register_result_fn("get", api::outputs::Outputs::get);
+
+        engine
+            .register_type_with_name::<api::args::Args>("Args")
+            .register_indexer_get(api::args::Args::get_index)
+            .register_indexer_get(api::args::Args::get_named)
+            .register_fn("contains", api::args::Args::contains)
+            .register_fn("len", api::args::Args::len)
+            .register_fn("is_empty", api::args::Args::is_empty)
+            .register_get("positional", api::args::Args::get_positional)
+            .register_get("named", api::args::Args::get_named_map)
+            .register_iterator::<api::args::Args>();
+
+        engine.register_type_with_name::<api::units::Quantity>("Quantity");
+        engine.register_type_with_name::<api::table::Table>("Table");
+        engine.register_type_with_name::<api::table::Change>("Change");
+        engine.register_type_with_name::<api::chart::Chart>("Chart");
+        engine.register_type_with_name::<api::bench::stats::Stats>("Stats");
+
+        engine
+            .register_type_with_name::<api::report::Report>("Report")
+            .register_result_fn("render", api::report::Report::render)
+            .register_result_fn("render", |report: &mut api::report::Report, name: &str| {
+                report.render(name, rhai::Map::new().into())
+            })
+            .register_fn("bench", api::report::Report::bench);
+
+        engine
+            .register_type_with_name::<api::report::BenchReport>("BenchReport")
+            .register_fn("metric", api::report::BenchReport::metric)
+            .register_fn("metric", api::report::BenchReport::metric_int)
+            .register_fn("metric", api::report::BenchReport::metric_quantity)
+            .register_fn("section", api::report::BenchReport::section)
+            .register_fn("fail", api::report::BenchReport::fail);
+
+        let script_phases = phases.clone();
+        engine.register_result_fn(
+            "phase",
+            move |context: rhai::NativeCallContext, name: &str, f: rhai::FnPtr| {
+                script_phases.time(name, || f.call_raw(&context, None, []))
+            },
+        );
+        let warm_up_phases = phases.clone();
+        let warm_up_results = results.clone();
+        engine.register_result_fn(
+            "warm_up",
+            move |context: rhai::NativeCallContext, name: &str, f: rhai::FnPtr| {
+                let recorded = warm_up_results.len();
+                let result = warm_up_phases.warm_up(name, || f.call_raw(&context, None, []));
+                // Whatever the warm-up recorded would skew the results
+                warm_up_results.truncate(recorded);
+                result
+            },
+        );
+
+        let list_pinning = self.pinning.clone();
+        let range_pinning = self.pinning.clone();
+        engine
+            .register_result_fn(
+                "with_pinned_cores",
+                move |context: rhai::NativeCallContext, cores: rhai::Array, f: rhai::FnPtr| {
+                    let cores = cores
+                        .into_iter()
+                        .map(|core| {
+                            core.as_int()
+                                .ok()
+                                .filter(|core| {
+                                    (0..api::pinning::MAX_CORES as rhai::INT).contains(core)
+                                })
+                                .map(|core| core as usize)
+                                .ok_or("Cores to pin to must be numbers of cores")
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    list_pinning.with_cores(cores, || f.call_raw(&context, None, []))
+                },
+            )
+            .register_result_fn(
+                "with_pinned_cores",
+                move |context: rhai::NativeCallContext, cores: &str, f: rhai::FnPtr| {
+                    let cores = api::pinning::parse_cores(cores).map_err(|e| e.to_string())?;
+                    range_pinning.with_cores(cores, || f.call_raw(&context, None, []))
+                },
             );
 
-        engine.register_static_module("env", exported_module!(api::rhai::env).into());
+        let get = http.clone();
+        let post = http.clone();
+        let post_without_headers = http.clone();
+        engine
+            .register_result_fn("http_get", move |url: &str| get.get(url))
+            .register_result_fn(
+                "http_post",
+                move |url: &str, body: &str, headers: rhai::Map| post.post(url, body, headers),
+            )
+            .register_result_fn("http_post", move |url: &str, body: &str| {
+                post_without_headers.post(url, body, rhai::Map::new())
+            });
+
+        let artifacts = self.artifacts.clone();
+        let artifacts_dir = self.dir.clone();
+        let upload_artifact = move |path: &str, name: Option<&str>| {
+            let artifacts = artifacts
+                .as_ref()
+                .ok_or("Artifacts aren't enabled on this runner")?;
+            let artifact = artifacts
+                .upload(&artifacts_dir, path, name)
+                .map_err(|e| format!("{e}"))?;
+            Ok::<_, Box<rhai::EvalAltResult>>(artifact.url)
+        };
+        let upload_named_artifact = upload_artifact.clone();
+        let chart_artifacts = self.artifacts.clone();
+        let upload_chart = move |chart: api::chart::Chart, name: &str| {
+            let artifacts = chart_artifacts
+                .as_ref()
+                .ok_or("Artifacts aren't enabled on this runner")?;
+            api::chart::Format::of(name).map_err(|e| format!("{e}"))?;
+            // Rendered outside the checkout so it doesn't end up in commits of the script
+            let path = std::env::temp_dir().join(format!(
+                "ci-script-chart-{}-{}",
+                uuid::Uuid::new_v4(),
+                name.replace('/', "_")
+            ));
+            let rendered = chart
+                .render(&path)
+                .map_err(|e| format!("{e}"))
+                .and_then(|()| artifacts.store(&path, name).map_err(|e| format!("{e}")));
+            let _ = std::fs::remove_file(&path);
+            Ok::<_, Box<rhai::EvalAltResult>>(rendered?.url)
+        };
+        engine
+            .register_result_fn("upload_artifact", move |path: &str| {
+                upload_artifact(path, None)
+            })
+            .register_result_fn("upload_artifact", move |path: &str, name: &str| {
+                upload_named_artifact(path, Some(name))
+            })
+            .register_result_fn("upload_chart", upload_chart);
+
+        engine.register_static_module("git", api::git::module(git.clone()).into());
+        let mut report_module = rhai::Module::new();
+        let headline = report.clone();
+        report_module.set_native_fn("headline", move |text: &str| {
+            headline.set_headline(text);
+            Ok(())
+        });
+        engine.register_static_module("report", report_module.into());
+        let mut env = exported_module!(api::rhai::env);
+        let job_env = self.env.clone();
+        env.set_native_fn("get", move |name: &str| {
+            Ok(match job_env.get(name) {
+                Some(value) => value.to_string(),
+                None => std::env::var(name).unwrap_or_default(),
+            })
+        });
+        engine.register_static_module("env", env.into());
         engine.register_static_module("cargo_toml", exported_module!(api::rhai::toml).into());
+        engine.register_global_module(exported_module!(api::rhai::data).into());
+        engine.register_global_module(exported_module!(api::units::functions).into());
+        engine.register_global_module(exported_module!(api::table::functions).into());
+        engine.register_global_module(exported_module!(api::chart::functions).into());
+        engine.register_global_module(exported_module!(api::bench::stats::functions).into());
         /*
         let module = exported_module!(api::rhai::env);
         engine.register_static_module("env", module.into());
@@ -297,80 +1380,661 @@ impl CheckedoutJob {
         Ok(engine)
     }
 
+    fn all_cargo_env(&self) -> Vec<(String, String)> {
+        let mut env = self.cargo_env.clone();
+        if let Some(home) = self.toolchain.rustup_home() {
+            env.push((
+                "RUSTUP_HOME".to_string(),
+                home.to_string_lossy().into_owned(),
+            ));
+        }
+        env.extend(self.env.vars());
+        env
+    }
+
+    /// Install the toolchain the checkout asks for in `rust-toolchain.toml`, so jobs don't race
+    /// rustup installing it on first use
+    pub fn install_toolchain(&self) -> Result<(), Error> {
+        Ok(self.toolchain.prepare(&self.dir)?)
+    }
+
+    /// Fail if `Cargo.lock` doesn't match the manifests, which cargo would silently update before
+    /// building. Checkouts without a lockfile have nothing to check.
+    pub fn check_lockfile(&self) -> Result<(), Error> {
+        let manifest = self.dir.join("Cargo.toml");
+        if !manifest.exists() || !self.dir.join("Cargo.lock").exists() {
+            return Ok(());
+        }
+        tracing::info!("Checking that Cargo.lock is up to date in {:?}", self.dir);
+        let args = [
+            "update".to_string(),
+            "--workspace".to_string(),
+            "--locked".to_string(),
+            "--manifest-path".to_string(),
+            manifest.to_string_lossy().into_owned(),
+        ];
+        let mut result = api::cargo::Run::new(args, &self.dir)
+            .envs(&self.all_cargo_env())
+            .redactor(&self.redactor)
+            .run();
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(Error::StaleLockfile(result.stderr))
+        }
+    }
+
     pub fn prepare_script(
         self,
         github_client: octocrab::Octocrab,
     ) -> Result<RunnableJob<'static>, Error> {
-        log::debug!("Preparing script");
+        tracing::debug!("Preparing script");
+        // Shorter waits are the usual back and forth of checkouts
+        if self.checkout_wait >= std::time::Duration::from_secs(1) {
+            self.phases
+                .record("Waiting for the checkout", self.checkout_wait);
+        }
+        self.quota.start(&self.dir)?;
         //let script_path = self.script_path()?;
-        let script_path = PathBuf::from(self.command.get(0).ok_or(Error::NoCmd)?);
+        let mut steps = vec![];
+        for step in self.command.split(|arg| arg == STEP_SEPARATOR) {
+            // Relative to the checkout, not to wherever we were started from
+            let script_path = self.dir.join(step.first().ok_or(Error::NoCmd)?);
+            let args = api::args::Args::parse(&step[1..]);
+            // Repositories that don't need a script can use a declarative pipeline instead
+            let pipeline = if script_path.exists() {
+                None
+            } else {
+                ["yml", "yaml"]
+                    .iter()
+                    .map(|ext| script_path.with_extension(ext))
+                    .find(|path| path.exists())
+            };
+            match pipeline {
+                Some(path) => {
+                    let pipeline = crate::pipeline::Pipeline::from_file(&path)?;
+                    let dir = path.parent().unwrap_or(&self.dir).to_owned();
+                    let scripts = pipeline.scripts.iter().map(|script| dir.join(script));
+                    let scripts: Vec<_> = scripts
+                        .map(|script| Step::Script(script, args.clone()))
+                        .collect();
+                    steps.push(Step::Pipeline(path, pipeline));
+                    steps.extend(scripts);
+                }
+                None => {
+                    let builtin = script_path
+                        .file_stem()
+                        .filter(|_| !script_path.exists())
+                        .and_then(|name| crate::builtin::script(&name.to_string_lossy()));
+                    match builtin {
+                        Some(source) => steps.push(Step::Builtin {
+                            path: script_path,
+                            source,
+                            args,
+                        }),
+                        None => steps.push(Step::Script(script_path, args)),
+                    }
+                }
+            }
+        }
 
-        let engine = self.prepare_engine()?;
+        for secret in self.env.secret_values() {
+            self.redactor.add(secret);
+        }
+        if let api::git::Credentials::Token(token) = &self.credentials {
+            self.redactor.add(token.as_str());
+        }
 
         let client = Arc::new(Mutex::new(github_client));
+        let forge = self.forge.clone().unwrap_or_else(|| {
+            Arc::new(crate::forge::Github::new(
+                client.clone(),
+                self.gh_repo.clone(),
+            ))
+        });
+        let git = api::git::Git::new(
+            &self.dir,
+            &self.clone_dir,
+            client.clone(),
+            self.credentials.clone(),
+        )
+        .with_commits(self.commits.clone());
+
+        let warnings = api::warnings::Warnings::new();
+
+        let phases = self.phases.clone();
+
+        let http = api::http::Http::new(self.http_allowlist.clone());
+
+        let results = api::results::Results::new().with_history(self.results_history.clone());
+        let baselines = api::baselines::Baselines::new(self.results_history.clone());
+        let outputs = api::outputs::Outputs::new();
+        let mut report = api::report::Report::new();
+        if let Some(dir) = steps.first().and_then(|step| step.path().parent()) {
+            report = report.with_templates(dir.join(crate::templates::TEMPLATES_DIR));
+        }
+        let cargo_env = self.all_cargo_env();
+        let machine = crate::machine::Machine::gather();
+
+        // Runs the bencher like the `cargo` keyword runs cargo
+        let bencher_dir = self.dir.clone();
+        let bencher_env = cargo_env.clone();
+        let bencher_output = self.cargo_output.clone();
+        let bencher_pinning = self.pinning.clone();
+        let bencher_toolchain = self.toolchain.clone();
+        let bencher_accounting = self.accounting.clone();
+        let bencher_quota = self.quota.clone();
+        let bencher_redactor = self.redactor.clone();
+        let bencher: api::weights::Runner = Arc::new(move |program: &str, args: &[String]| {
+            api::cargo::Run::new(args, &bencher_dir)
+                .program(program)
+                .envs(&bencher_env)
+                .output(&bencher_output)
+                .pinning(&bencher_pinning)
+                .toolchain(&bencher_toolchain)
+                .accounting(&bencher_accounting)
+                .quota(&bencher_quota)
+                .redactor(&bencher_redactor)
+                .run()
+        });
+        let scripts_dir = steps
+            .first()
+            .and_then(|step| step.path().parent())
+            .unwrap_or(&self.dir)
+            .to_owned();
+        let runtime = api::weights::Runtime::new(&scripts_dir, bencher.clone());
+        let weights = api::weights::Weights::new(&self.dir, &scripts_dir, bencher);
+
+        let mut engine = self.prepare_engine(&git, &warnings, &phases, &results, &http, &report)?;
+        // Only known for pull requests
+        let mut changed_files = None;
 
         let scope = {
             let mut scope = rhai::Scope::new();
             let repo_name = self.gh_repo.name.clone();
             let repo_owner = self.gh_repo.owner.login.clone();
+            let issue_nr = self.gh_issue.as_ref().map(|issue| issue.number as u64);
+            tracing::debug!("local repo dir: {:?}", &self.dir);
+            let local_repo = git2::Repository::open(&self.dir)?;
+            // Statuses go on the head of the pull request, or on what's checked out otherwise
+            let mut status_sha = local_repo
+                .head()
+                .ok()
+                .and_then(|head| head.target())
+                .map(|oid| oid.to_string())
+                .unwrap_or_default();
             if let Some(gh_issue) = self.gh_issue {
-                let issue = api::Issue::new(client.clone(), self.gh_repo, gh_issue);
+                if gh_issue.pull_request.is_some() {
+                    let number = gh_issue.number as u64;
+                    let pr =
+                        api::pr::PullRequest::fetch(client.clone(), self.gh_repo.clone(), number)?;
+                    changed_files = Some(pr.changed_files.clone());
+                    status_sha = pr.head_sha.clone();
+                    scope.push_constant("PR", pr);
+                }
+                let issue =
+                    api::Issue::new(forge.clone(), gh_issue).with_redactor(self.redactor.clone());
                 scope.push_constant("ISSUE", issue);
             }
-            log::debug!("local repo dir: {:?}", &self.dir);
-            let local_repo = git2::Repository::open(&self.dir)?;
+            scope.push_constant(
+                "STATUS",
+                api::statuses::Statuses::new(forge.clone(), status_sha, self.redactor.clone()),
+            );
             let repo = api::git::LocalRepo::new(
                 &self.dir,
                 repo_owner,
                 repo_name,
                 local_repo,
                 client.clone(),
-            );
+            )
+            .with_credentials(self.credentials.clone())
+            .with_forge(forge)
+            .with_metadata(&self.gh_repo)
+            .with_pr_policy(self.pr_policy.clone(), issue_nr)
+            .with_commits(self.commits.clone());
             scope.push_constant("REPO", repo);
-            // TODO: replace with proper module export
-            let git = api::git::Git {
-                path: self.dir.clone(),
-                root: self.clone_dir,
-                github_client: client,
-            };
+            // Deprecated alias of the `git` module, kept for existing scripts
             scope.push_constant("Git", git);
+            scope.push_constant("RESULTS", results.clone());
+            scope.push_constant("BASELINES", baselines);
+            scope.push_constant("OUTPUT", outputs.clone());
+            scope.push_constant("REPORT", report.clone());
+            scope.push_constant("TOOLCHAIN", self.toolchain.clone());
+            scope.push_constant("WEIGHTS", weights);
+            scope.push_constant("RUNTIME", runtime);
+            scope.push_constant("WARM_UP", self.warm_up);
+            let shard = match &self.shard {
+                Some(shard) => {
+                    let mut map = rhai::Map::new();
+                    map.insert("index".into(), (shard.index as rhai::INT).into());
+                    map.insert("count".into(), (shard.count as rhai::INT).into());
+                    rhai::Dynamic::from_map(map)
+                }
+                None => rhai::Dynamic::UNIT,
+            };
+            scope.push_constant("SHARD", shard);
+            scope.push_constant("MACHINE", machine.to_dynamic());
+            let triggered_by = match &self.triggered_by {
+                Some(user) => user.as_str().into(),
+                None => rhai::Dynamic::UNIT,
+            };
+            scope.push_constant("TRIGGERED_BY", triggered_by);
             Box::new(scope)
         };
 
+        let suites_dir = self.dir.clone();
+        let suites_changed_files = changed_files.clone();
+        let suites_shard = self.shard.clone();
+        engine.register_result_fn("selected_suites", move || {
+            let suites = crate::suites::Suites::load(&suites_dir).map_err(|e| format!("{e}"))?;
+            let mut selected = suites
+                .map(|suites| suites.selected(suites_changed_files.as_deref()))
+                .unwrap_or_default();
+            if let Some(shard) = &suites_shard {
+                selected = shard.share(selected);
+            }
+            Ok(selected
+                .into_iter()
+                .map(rhai::Dynamic::from)
+                .collect::<rhai::Array>())
+        });
+
+        let workspace_dir = self.dir.clone();
+        let workspace_env = cargo_env.clone();
+        let affected_crates = move |changed_files: Option<&[String]>| {
+            let workspace = api::cargo::workspace::Workspace::load(&workspace_dir, &workspace_env)
+                .map_err(|e| format!("{e}"))?;
+            Ok::<_, Box<rhai::EvalAltResult>>(
+                workspace
+                    .affected(changed_files)
+                    .into_iter()
+                    .map(rhai::Dynamic::from)
+                    .collect::<rhai::Array>(),
+            )
+        };
+        let pr_affected_crates = affected_crates.clone();
+        let pr_changed_files = changed_files.clone();
+        engine
+            .register_result_fn("affected_crates", move || {
+                pr_affected_crates(pr_changed_files.as_deref())
+            })
+            // The paths, or the files `REPO.diff` returns
+            .register_result_fn("affected_crates", move |files: rhai::Array| {
+                let mut paths = vec![];
+                for file in files {
+                    if file.is::<rhai::Map>() {
+                        let map = file.cast::<rhai::Map>();
+                        paths.extend(
+                            ["path", "old_path"]
+                                .iter()
+                                .filter_map(|key| map.get(*key))
+                                .map(|path| path.to_string()),
+                        );
+                    } else if let Ok(path) = file.into_string() {
+                        paths.push(path);
+                    } else {
+                        return Err("Changed files must be paths or maps with a path".into());
+                    }
+                }
+                affected_crates(Some(&paths))
+            });
+
         Ok(RunnableJob {
             //job: self.job,
             dir: self.dir,
-            script_path,
+            credentials: self.credentials,
+            warm_up: self.warm_up,
+            cargo_env,
+            cargo_output: self.cargo_output,
+            changed_files,
+            artifacts: self.artifacts,
+            shard: self.shard,
+            machine,
+            pinning: self.pinning,
+            sccache: self.sccache,
+            accounting: self.accounting,
+            quota: self.quota,
+            steps,
             engine,
             scope,
+            results,
+            outputs,
+            warnings,
+            phases,
+            report,
+        })
+    }
+}
+
+/// What a successfully executed script left behind for the caller to report on
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Outcome {
+    /// Metrics recorded through `RESULTS.record(name, value, unit)`
+    pub metrics: Vec<crate::results::Metric>,
+    /// Deprecation and other warnings about the script itself
+    pub warnings: Vec<String>,
+    /// Timings of the parts of the script wrapped in `phase("name", || ...)`
+    pub phases: Vec<api::phases::Phase>,
+    /// Markdown sections to include in the result comment
+    pub report: Vec<String>,
+    /// Commit the job was run on
+    pub commit: Option<String>,
+    /// Files uploaded through `upload_artifact`
+    pub artifacts: Vec<api::artifacts::Artifact>,
+    /// One line summary of the results set through `report::headline`
+    pub headline: Option<String>,
+    /// Why the results are considered failed, through `BenchReport.fail`
+    #[serde(default)]
+    pub failure: Option<String>,
+    /// How long each of the scripts run took, in order
+    #[serde(default)]
+    pub steps: Vec<StepOutcome>,
+    /// What the machine the job ran on was like when it started
+    #[serde(default)]
+    pub machine: Option<crate::machine::Machine>,
+    /// Values set through `OUTPUT.set(key, value)`
+    #[serde(default)]
+    pub outputs: std::collections::BTreeMap<String, serde_json::Value>,
+    /// Hits and misses of sccache over all the builds of the job, if it built through it
+    #[serde(default)]
+    pub sccache: Option<api::cargo::sccache::CacheStats>,
+    /// Resources used by the cargo runs of the job, if it ran any
+    #[serde(default)]
+    pub usage: Option<api::usage::Usage>,
+}
+
+/// A script (or pipeline) run by a job, which may run several separated by [`STEP_SEPARATOR`]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StepOutcome {
+    /// Path of the script, relative to the checkout
+    pub script: String,
+    pub wall: std::time::Duration,
+    pub status: StepStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    /// An earlier step failed
+    NotRun,
+}
+
+/// Render the steps of a job as a short footer for a result comment
+pub fn render_steps(steps: &[StepOutcome]) -> String {
+    let steps = steps
+        .iter()
+        .map(|step| match step.status {
+            StepStatus::Succeeded => format!(
+                "{}: {}",
+                step.script,
+                api::phases::format_duration(step.wall)
+            ),
+            StepStatus::Failed => format!(
+                "{}: failed after {}",
+                step.script,
+                api::phases::format_duration(step.wall)
+            ),
+            StepStatus::NotRun => format!("{}: not run", step.script),
         })
+        .collect::<Vec<_>>()
+        .join(" · ");
+    format!("<sub>Steps: {steps}</sub>")
+}
+
+enum Step {
+    /// With the arguments after it in the command, or after the pipeline it's listed in
+    Script(PathBuf, api::args::Args),
+    /// A declarative pipeline, the scripts it lists are steps of their own after it
+    Pipeline(PathBuf, crate::pipeline::Pipeline),
+    /// A script of the bot's, see [`crate::builtin`], in place of the one at `path`
+    Builtin {
+        path: PathBuf,
+        source: &'static str,
+        args: api::args::Args,
+    },
+}
+
+impl Step {
+    fn path(&self) -> &Path {
+        match self {
+            Step::Script(path, _) | Step::Pipeline(path, _) | Step::Builtin { path, .. } => path,
+        }
+    }
+}
+
+/// Path of the script at `path` relative to the checkout `dir`, separated by `/` on any OS
+fn script_name(dir: &Path, path: &Path) -> String {
+    match path.strip_prefix(dir) {
+        Ok(relative) => relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => path.to_string_lossy().into_owned(),
     }
 }
 
 pub struct RunnableJob<'a> {
     dir: PathBuf,
-    script_path: PathBuf,
+    credentials: api::git::Credentials,
+    warm_up: bool,
+    cargo_env: Vec<(String, String)>,
+    cargo_output: api::cargo::Output,
+    /// Files changed by the pull request the job runs on
+    changed_files: Option<Vec<String>>,
+    artifacts: Option<api::artifacts::Artifacts>,
+    shard: Option<crate::shards::Shard>,
+    machine: crate::machine::Machine,
+    pinning: api::pinning::Pinning,
+    sccache: api::cargo::sccache::Sccache,
+    accounting: api::usage::Accounting,
+    quota: api::quota::Quota,
+    /// Run in order with the same engine and scope, so later scripts see the variables and
+    /// functions of earlier ones
+    steps: Vec<Step>,
     engine: rhai::Engine,
     scope: Box<rhai::Scope<'a>>,
+    results: api::results::Results,
+    outputs: api::outputs::Outputs,
+    warnings: api::warnings::Warnings,
+    phases: api::phases::Phases,
+    report: api::report::Report,
 }
 
 impl RunnableJob<'_> {
-    pub fn run(mut self) -> Result<(), Error> {
-        log::info!(
-            "Executing {} in {:?}",
-            self.script_path.to_string_lossy(),
-            self.dir
+    /// What the result of the job is cached by when run on `repo` with the arguments `args`, by
+    /// the workers of `runner` on `runner_os`. Scripts they import aren't part of it. None if the
+    /// commit checked out can't be read.
+    pub fn cache_key(
+        &self,
+        repo: &str,
+        args: &[String],
+        runner: Option<&str>,
+        runner_os: Option<&str>,
+    ) -> Option<crate::results::CacheKey> {
+        let commit = git2::Repository::open(&self.dir)
+            .and_then(|repo| Ok(repo.head()?.peel_to_commit()?.id().to_string()))
+            .ok()?;
+        let mut scripts = vec![];
+        let mut hasher = openssl::sha::Sha256::new();
+        for step in &self.steps {
+            scripts.push(script_name(&self.dir, step.path()));
+            match step {
+                Step::Builtin { source, .. } => hasher.update(source.as_bytes()),
+                Step::Script(path, _) | Step::Pipeline(path, _) => {
+                    hasher.update(&std::fs::read(path).ok()?)
+                }
+            }
+        }
+        Some(crate::results::CacheKey {
+            repo: repo.to_string(),
+            commit,
+            script: scripts.join(&format!(" {STEP_SEPARATOR} ")),
+            script_hash: hasher
+                .finish()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+            args: args.join(" "),
+            runner: runner.unwrap_or_default().to_string(),
+            runner_os: runner_os.unwrap_or_default().to_string(),
+        })
+    }
+
+    /// Run the steps until one fails, which fails the whole job
+    pub fn run(mut self) -> Result<Outcome, Error> {
+        // Before the script gets a chance to move HEAD
+        let commit = git2::Repository::open(&self.dir)
+            .and_then(|repo| Ok(repo.head()?.peel_to_commit()?.id().to_string()))
+            .ok();
+
+        let names: Vec<String> = self
+            .steps
+            .iter()
+            .map(|step| script_name(&self.dir, step.path()))
+            .collect();
+        let mut steps = vec![];
+        let mut report = vec![];
+        // Restored once all steps are done
+        let _governors = self.pinning.governor();
+        // Defined by the scripts run so far
+        let mut functions = rhai::AST::empty();
+        for (index, step) in std::mem::take(&mut self.steps).into_iter().enumerate() {
+            let span = tracing::info_span!("script", script = %step.path().display());
+            let _entered = span.enter();
+            tracing::info!(
+                "Executing {} in {:?}",
+                step.path().to_string_lossy(),
+                self.dir
+            );
+            let started = std::time::Instant::now();
+            let result = match &step {
+                Step::Pipeline(_, pipeline) => self
+                    .run_pipeline(pipeline)
+                    .map(|sections| report.extend(sections)),
+                Step::Script(path, args) => {
+                    self.scope.push_constant("ARGS", args.clone());
+                    self.run_script(path, &mut functions)
+                }
+                Step::Builtin { source, args, .. } => {
+                    self.scope.push_constant("ARGS", args.clone());
+                    self.run_source(source, &mut functions)
+                }
+            };
+            // However the script dealt with it, going over a limit fails the job
+            let result = match self.quota.exceeded() {
+                Some(exceeded) => Err(Error::ResourceLimit(exceeded)),
+                None => result,
+            };
+            steps.push(StepOutcome {
+                script: names[index].clone(),
+                wall: started.elapsed(),
+                status: match result {
+                    Ok(()) => StepStatus::Succeeded,
+                    Err(_) => StepStatus::Failed,
+                },
+            });
+            let err = match result {
+                Ok(()) => continue,
+                Err(err) if names.len() == 1 => return Err(err),
+                Err(err) => err,
+            };
+            steps.extend(names[index + 1..].iter().map(|script| StepOutcome {
+                script: script.clone(),
+                wall: std::time::Duration::ZERO,
+                status: StepStatus::NotRun,
+            }));
+            return Err(Error::Step {
+                step: format!("{} ({}/{})", names[index], index + 1, names.len()),
+                source: Box::new(err),
+                steps,
+            });
+        }
+
+        let bench_reports = self.report.bench_reports();
+        let mut metrics = self.results.metrics();
+        metrics.extend(
+            bench_reports
+                .iter()
+                .flat_map(api::report::BenchReport::metrics),
         );
+        let failures: Vec<_> = bench_reports
+            .iter()
+            .flat_map(api::report::BenchReport::failures)
+            .collect();
+        report.extend(bench_reports.iter().map(api::report::BenchReport::render));
+        Ok(Outcome {
+            metrics,
+            warnings: self.warnings.warnings(),
+            phases: self.phases.phases(),
+            report,
+            commit,
+            artifacts: self
+                .artifacts
+                .as_ref()
+                .map(api::artifacts::Artifacts::uploaded)
+                .unwrap_or_default(),
+            headline: self.report.headline().or_else(|| failures.first().cloned()),
+            failure: Some(failures.join("\n")).filter(|failure| !failure.is_empty()),
+            steps,
+            machine: Some(self.machine),
+            outputs: self.outputs.values(),
+            sccache: self.sccache.total(),
+            usage: self.accounting.total(),
+        })
+    }
 
-        // We don't want to leak any internal fs details
-        //let ast = self.engine.compile_file(self.dir.join(self.script_path.clone()))
+    /// Run the steps of `pipeline`, returning the sections it reports
+    fn run_pipeline(&self, pipeline: &crate::pipeline::Pipeline) -> Result<Vec<String>, Error> {
+        if pipeline.full_history {
+            api::git::unshallow(&self.dir, &self.credentials)?;
+        }
+        let suites = crate::suites::Suites::load(&self.dir)?
+            .map(|suites| suites.selected(self.changed_files.as_deref()))
+            .map(|selected| match &self.shard {
+                Some(shard) => shard.share(selected),
+                None => selected,
+            });
+        Ok(pipeline.run(
+            &self.dir,
+            &self.results,
+            &self.phases,
+            &self.cargo_env,
+            &self.cargo_output,
+            &self.pinning,
+            &self.sccache,
+            &self.accounting,
+            &self.quota,
+            self.warm_up,
+            suites.as_deref(),
+        )?)
+    }
+
+    /// Run the script at `path` with the `functions` of earlier ones, adding its own to them
+    fn run_script(&mut self, path: &Path, functions: &mut rhai::AST) -> Result<(), Error> {
         let ast = self
             .engine
-            .compile_file(self.script_path.clone())
+            .compile_file(path.to_owned())
             // Don't leak in the internal path
             .map_err(|e| Error::ScriptExecution(format!("{e}").into()))?;
+        self.run_ast(ast, functions)
+    }
+
+    fn run_source(&mut self, source: &str, functions: &mut rhai::AST) -> Result<(), Error> {
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|e| Error::ScriptExecution(format!("{e}").into()))?;
+        self.run_ast(ast, functions)
+    }
 
+    fn run_ast(&mut self, ast: rhai::AST, functions: &mut rhai::AST) -> Result<(), Error> {
+        // Functions of the script replace earlier ones of the same name
+        let ast = functions.merge(&ast);
         self.engine.run_ast_with_scope(&mut self.scope, &ast)?;
+        *functions = ast.clone_functions_only();
         Ok(())
     }
 }
@@ -0,0 +1,592 @@
+use crate::history::{Record, Status};
+use rusqlite::{params, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Journal database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Failed to (de)serialize journal entry: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Failed to gain exclusive lock on the journal")]
+    ExclusiveLock,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS queued (
+    seq INTEGER PRIMARY KEY,
+    queue TEXT NOT NULL,
+    id TEXT NOT NULL,
+    key TEXT NOT NULL,
+    item TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS queued_by_queue ON queued (queue, seq);
+CREATE TABLE IF NOT EXISTS pending (
+    queue TEXT NOT NULL,
+    id TEXT NOT NULL,
+    item TEXT NOT NULL,
+    PRIMARY KEY (queue, id)
+);
+CREATE TABLE IF NOT EXISTS in_flight (
+    queue TEXT PRIMARY KEY,
+    id TEXT NOT NULL,
+    item TEXT NOT NULL,
+    started_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS finished (
+    seq INTEGER PRIMARY KEY,
+    queue TEXT NOT NULL,
+    id TEXT NOT NULL,
+    repository TEXT NOT NULL,
+    command TEXT NOT NULL,
+    issue_url TEXT NOT NULL,
+    machine TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    duration REAL,
+    status TEXT NOT NULL,
+    error TEXT,
+    comment_url TEXT,
+    phases TEXT NOT NULL,
+    usage TEXT,
+    user TEXT
+);
+CREATE INDEX IF NOT EXISTS finished_by_time ON finished (started_at);
+CREATE TABLE IF NOT EXISTS deliveries (
+    seq INTEGER PRIMARY KEY,
+    queue TEXT NOT NULL,
+    id TEXT NOT NULL,
+    received_at INTEGER NOT NULL,
+    UNIQUE (queue, id)
+);
+CREATE TABLE IF NOT EXISTS shards (
+    queue TEXT NOT NULL,
+    grp TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    result TEXT NOT NULL,
+    PRIMARY KEY (queue, grp, idx)
+);
+CREATE TABLE IF NOT EXISTS results (
+    seq INTEGER PRIMARY KEY,
+    queue TEXT NOT NULL,
+    id TEXT NOT NULL,
+    result TEXT NOT NULL,
+    UNIQUE (queue, id)
+);
+CREATE TABLE IF NOT EXISTS paused (
+    queue TEXT PRIMARY KEY,
+    paused_at INTEGER NOT NULL
+);
+";
+
+/// Number of webhook deliveries remembered per queue
+const MAX_DELIVERIES: i64 = 10_000;
+
+/// Number of results of submitted jobs kept per queue
+const MAX_RESULTS: i64 = 10_000;
+
+/// Durable record of the queued jobs, the jobs held for approval and the job currently being run,
+/// so they survive a restart, and of the jobs that finished.
+///
+/// A single database can hold the state of multiple queues, see [`Journal::scoped`], each worked
+/// on by one or more workers, see [`Journal::worker`].
+#[derive(Clone)]
+pub struct Journal {
+    conn: Arc<Mutex<rusqlite::Connection>>,
+    queue: String,
+    worker: usize,
+}
+
+impl Journal {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::init(rusqlite::Connection::open(path)?)
+    }
+
+    /// A journal that doesn't outlive the process
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::init(rusqlite::Connection::open_in_memory()?)
+    }
+
+    fn init(conn: rusqlite::Connection) -> Result<Self, Error> {
+        conn.execute_batch(SCHEMA)?;
+        // Journals created before the usage of jobs and who requested them were recorded
+        for column in ["usage", "user"] {
+            let exists = conn
+                .prepare("SELECT 1 FROM pragma_table_info('finished') WHERE name = ?1")?
+                .exists([column])?;
+            if !exists {
+                conn.execute_batch(&format!("ALTER TABLE finished ADD COLUMN {column} TEXT"))?;
+            }
+        }
+        Ok(Journal {
+            conn: Arc::new(Mutex::new(conn)),
+            queue: String::new(),
+            worker: 0,
+        })
+    }
+
+    /// The journal of the queue called `name`, sharing the same database
+    pub fn scoped<S: Into<String>>(&self, name: S) -> Self {
+        Journal {
+            conn: self.conn.clone(),
+            queue: name.into(),
+            worker: 0,
+        }
+    }
+
+    /// The journal of worker `index` of the same queue, which has its own item in flight
+    pub fn worker(&self, index: usize) -> Self {
+        Journal {
+            conn: self.conn.clone(),
+            queue: self.queue.clone(),
+            worker: index,
+        }
+    }
+
+    /// Key of the item in flight of this worker, the first one uses the name of the queue like
+    /// before there were multiple workers
+    fn in_flight_key(&self) -> String {
+        match self.worker {
+            0 => self.queue.clone(),
+            worker => format!("{}#{worker}", self.queue),
+        }
+    }
+
+    pub fn enqueue<T: Serialize>(&self, id: &str, key: &str, item: &T) -> Result<(), Error> {
+        let item = serde_json::to_string(item)?;
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "INSERT INTO queued (queue, id, key, item) VALUES (?1, ?2, ?3, ?4)",
+            params![self.queue, id, key, item],
+        )?;
+        Ok(())
+    }
+
+    /// Forget the queued items with the given key
+    pub fn dequeue_key(&self, key: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "DELETE FROM queued WHERE queue = ?1 AND key = ?2",
+            params![self.queue, key],
+        )?;
+        Ok(())
+    }
+
+    /// Forget the queued item `id`
+    pub fn dequeue(&self, id: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "DELETE FROM queued WHERE queue = ?1 AND id = ?2",
+            params![self.queue, id],
+        )?;
+        Ok(())
+    }
+
+    /// All queued items as `(id, key, item)`, in the order they were queued
+    pub fn queued<T: DeserializeOwned>(&self) -> Result<Vec<(String, String, T)>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let mut stmt =
+            conn.prepare("SELECT id, key, item FROM queued WHERE queue = ?1 ORDER BY seq")?;
+        let rows = stmt
+            .query_map(params![self.queue], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+            })?
+            .collect::<Result<Vec<(String, String, String)>, _>>()?;
+        rows.into_iter()
+            .map(|(id, key, item)| Ok((id, key, serde_json::from_str(&item)?)))
+            .collect()
+    }
+
+    /// Whether the item `id` is still queued
+    pub fn is_queued(&self, id: &str) -> Result<bool, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let queued = conn
+            .query_row(
+                "SELECT 1 FROM queued WHERE queue = ?1 AND id = ?2",
+                params![self.queue, id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        Ok(queued)
+    }
+
+    /// Hold `item` back under `id` until it's approved, see `release`
+    pub fn hold<T: Serialize>(&self, id: &str, item: &T) -> Result<(), Error> {
+        let item = serde_json::to_string(item)?;
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO pending (queue, id, item) VALUES (?1, ?2, ?3)",
+            params![self.queue, id, item],
+        )?;
+        Ok(())
+    }
+
+    /// Forget the held item `id`, whether it was approved or cancelled
+    pub fn release(&self, id: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "DELETE FROM pending WHERE queue = ?1 AND id = ?2",
+            params![self.queue, id],
+        )?;
+        Ok(())
+    }
+
+    /// All held items as `(id, item)`
+    pub fn held<T: DeserializeOwned>(&self) -> Result<Vec<(String, T)>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let mut stmt = conn.prepare("SELECT id, item FROM pending WHERE queue = ?1")?;
+        let rows = stmt
+            .query_map(params![self.queue], |row| {
+                Ok((row.get(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+        rows.into_iter()
+            .map(|(id, item)| Ok((id, serde_json::from_str(&item)?)))
+            .collect()
+    }
+
+    /// Remember that the queue is paused, until `resume`
+    pub fn pause(&self) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "INSERT OR IGNORE INTO paused (queue, paused_at) VALUES (?1, ?2)",
+            params![self.queue, unix_time(SystemTime::now())],
+        )?;
+        Ok(())
+    }
+
+    pub fn resume(&self) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute("DELETE FROM paused WHERE queue = ?1", params![self.queue])?;
+        Ok(())
+    }
+
+    /// When the queue was paused, if it still is
+    pub fn paused_since(&self) -> Result<Option<SystemTime>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let paused_at = conn
+            .query_row(
+                "SELECT paused_at FROM paused WHERE queue = ?1",
+                params![self.queue],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?;
+        Ok(paused_at.map(|secs| UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)))
+    }
+
+    /// Fails unless the queue can be read, like when the database is gone or locked
+    pub fn check(&self) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.query_row(
+            "SELECT COUNT(*) FROM queued WHERE queue = ?1",
+            params![self.queue],
+            |_| Ok(()),
+        )?;
+        Ok(())
+    }
+
+    /// Mark the queued item `id` as being worked on. There's at most one item in flight per
+    /// worker.
+    pub fn start<T: Serialize>(&self, id: &str, item: &T) -> Result<(), Error> {
+        let item = serde_json::to_string(item)?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let mut conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM queued WHERE queue = ?1 AND id = ?2",
+            params![self.queue, id],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO in_flight (queue, id, item, started_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![self.in_flight_key(), id, item, now],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "DELETE FROM in_flight WHERE queue = ?1",
+            params![self.in_flight_key()],
+        )?;
+        Ok(())
+    }
+
+    /// Stop working on the item in flight and queue `item` in its place, ahead of all others, so
+    /// whoever works on the queue next picks it up first
+    pub fn hand_off<T: Serialize>(&self, id: &str, key: &str, item: &T) -> Result<(), Error> {
+        let item = serde_json::to_string(item)?;
+        let mut conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM in_flight WHERE queue = ?1",
+            params![self.in_flight_key()],
+        )?;
+        tx.execute(
+            "INSERT INTO queued (seq, queue, id, key, item)
+             SELECT COALESCE(MIN(seq), 1) - 1, ?1, ?2, ?3, ?4 FROM queued",
+            params![self.queue, id, key, item],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The item that was being worked on, if it never finished
+    pub fn in_flight<T: DeserializeOwned>(&self) -> Result<Option<T>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let item: Option<String> = conn
+            .query_row(
+                "SELECT item FROM in_flight WHERE queue = ?1",
+                params![self.in_flight_key()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(match item {
+            Some(item) => Some(serde_json::from_str(&item)?),
+            None => None,
+        })
+    }
+
+    /// Remember the webhook delivery `id`, unless it already is. Returns whether it was new.
+    pub fn claim_delivery(&self, id: &str) -> Result<bool, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let claimed = conn.execute(
+            "INSERT OR IGNORE INTO deliveries (queue, id, received_at) VALUES (?1, ?2, ?3)",
+            params![self.queue, id, unix_time(SystemTime::now())],
+        )? == 1;
+        if claimed {
+            conn.execute(
+                "DELETE FROM deliveries WHERE queue = ?1 AND seq NOT IN (
+                     SELECT seq FROM deliveries WHERE queue = ?1 ORDER BY seq DESC LIMIT ?2
+                 )",
+                params![self.queue, MAX_DELIVERIES],
+            )?;
+        }
+        Ok(claimed)
+    }
+
+    /// Forget the webhook delivery `id`, so it's processed if it's delivered again
+    pub fn release_delivery(&self, id: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "DELETE FROM deliveries WHERE queue = ?1 AND id = ?2",
+            params![self.queue, id],
+        )?;
+        Ok(())
+    }
+
+    /// Whether the webhook delivery `id` is remembered
+    pub fn knows_delivery(&self, id: &str) -> Result<bool, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let known = conn
+            .query_row(
+                "SELECT 1 FROM deliveries WHERE queue = ?1 AND id = ?2",
+                params![self.queue, id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        Ok(known)
+    }
+
+    /// Keep the result of shard `index` of `group`. Returns the number of shards of the group
+    /// that reported so far.
+    pub fn record_shard<T: Serialize>(
+        &self,
+        group: &str,
+        index: usize,
+        result: &T,
+    ) -> Result<usize, Error> {
+        let result = serde_json::to_string(result)?;
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO shards (queue, grp, idx, result) VALUES (?1, ?2, ?3, ?4)",
+            params![self.queue, group, index as i64, result],
+        )?;
+        let reported: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM shards WHERE queue = ?1 AND grp = ?2",
+            params![self.queue, group],
+            |row| row.get(0),
+        )?;
+        Ok(reported as usize)
+    }
+
+    /// The results of the shards of `group` that reported, in order
+    pub fn shard_results<T: DeserializeOwned>(&self, group: &str) -> Result<Vec<T>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let mut stmt =
+            conn.prepare("SELECT result FROM shards WHERE queue = ?1 AND grp = ?2 ORDER BY idx")?;
+        let rows = stmt
+            .query_map(params![self.queue, group], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.iter()
+            .map(|result| Ok(serde_json::from_str(result)?))
+            .collect()
+    }
+
+    pub fn forget_shards(&self, group: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "DELETE FROM shards WHERE queue = ?1 AND grp = ?2",
+            params![self.queue, group],
+        )?;
+        Ok(())
+    }
+
+    /// Keep the result of the job `id` for whoever submitted it to fetch
+    pub fn record_result<T: Serialize>(&self, id: &str, result: &T) -> Result<(), Error> {
+        let result = serde_json::to_string(result)?;
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO results (queue, id, result) VALUES (?1, ?2, ?3)",
+            params![self.queue, id, result],
+        )?;
+        conn.execute(
+            "DELETE FROM results WHERE queue = ?1 AND seq NOT IN (
+                 SELECT seq FROM results WHERE queue = ?1 ORDER BY seq DESC LIMIT ?2
+             )",
+            params![self.queue, MAX_RESULTS],
+        )?;
+        Ok(())
+    }
+
+    /// The result of the job `id`, if it finished
+    pub fn result<T: DeserializeOwned>(&self, id: &str) -> Result<Option<T>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let result: Option<String> = conn
+            .query_row(
+                "SELECT result FROM results WHERE queue = ?1 AND id = ?2",
+                params![self.queue, id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(match result {
+            Some(result) => Some(serde_json::from_str(&result)?),
+            None => None,
+        })
+    }
+
+    /// Keep the record of a finished job for later analysis
+    pub fn archive(&self, record: &Record) -> Result<(), Error> {
+        let (status, error) = match &record.status {
+            Status::Running => ("running", None),
+            Status::Succeeded => ("succeeded", None),
+            Status::Failed(error) => ("failed", Some(error.as_str())),
+        };
+        let phases = serde_json::to_string(&record.phases)?;
+        let usage = record
+            .usage
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        conn.execute(
+            "INSERT INTO finished (queue, id, repository, command, issue_url, machine, started_at,
+                                   duration, status, error, comment_url, phases, usage, user)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                self.queue,
+                record.id,
+                record.repository,
+                record.command,
+                record.issue_url.as_str(),
+                record.machine,
+                unix_time(record.started_at),
+                record.duration.map(|d| d.as_secs_f64()),
+                status,
+                error,
+                record.comment_url.as_ref().map(url::Url::as_str),
+                phases,
+                usage,
+                record.user
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The finished jobs of all queues that started after `since`, as `(queue, record)`
+    pub fn archived(&self, since: SystemTime) -> Result<Vec<(String, Record)>, Error> {
+        fn invalid<E: std::error::Error + Send + Sync + 'static>(
+            column: usize,
+        ) -> impl FnOnce(E) -> rusqlite::Error {
+            move |e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    column,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            }
+        }
+
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let mut stmt = conn.prepare(
+            "SELECT queue, id, repository, command, issue_url, machine, started_at, duration,
+                    status, error, comment_url, phases, usage, user
+             FROM finished WHERE started_at >= ?1 ORDER BY started_at, seq",
+        )?;
+        let records = stmt
+            .query_map(params![unix_time(since)], |row| {
+                let status = match row.get::<_, String>(8)?.as_str() {
+                    "running" => Status::Running,
+                    "succeeded" => Status::Succeeded,
+                    _ => Status::Failed(row.get::<_, Option<String>>(9)?.unwrap_or_default()),
+                };
+                let record = Record {
+                    id: row.get(1)?,
+                    repository: row.get(2)?,
+                    command: row.get(3)?,
+                    issue_url: row.get::<_, String>(4)?.parse().map_err(invalid(4))?,
+                    machine: row.get(5)?,
+                    started_at: UNIX_EPOCH
+                        + Duration::from_secs(row.get::<_, i64>(6)?.max(0) as u64),
+                    duration: row.get::<_, Option<f64>>(7)?.map(Duration::from_secs_f64),
+                    status,
+                    comment_url: row
+                        .get::<_, Option<String>>(10)?
+                        .map(|url| url.parse())
+                        .transpose()
+                        .map_err(invalid(10))?,
+                    phases: serde_json::from_str(&row.get::<_, String>(11)?)
+                        .map_err(invalid(11))?,
+                    usage: row
+                        .get::<_, Option<String>>(12)?
+                        .map(|usage| serde_json::from_str(&usage))
+                        .transpose()
+                        .map_err(invalid(12))?,
+                    user: row.get(13)?,
+                };
+                Ok((row.get(0)?, record))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(records)
+    }
+
+    /// The queued items of all queues as `(queue, id, item)`, in the order they were queued
+    pub fn all_queued<T: DeserializeOwned>(&self) -> Result<Vec<(String, String, T)>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let mut stmt = conn.prepare("SELECT queue, id, item FROM queued ORDER BY seq")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+            })?
+            .collect::<Result<Vec<(String, String, String)>, _>>()?;
+        rows.into_iter()
+            .map(|(queue, id, item)| Ok((queue, id, serde_json::from_str(&item)?)))
+            .collect()
+    }
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
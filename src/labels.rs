@@ -0,0 +1,84 @@
+//! Commands queued by adding a label to a pull request, like the extrinsics benchmarks when
+//! `bench:extrinsics` is added, instead of commenting them. Each repository has its own labels
+//! (see `--repo-label-trigger`). Removing the label again cancels the jobs it queued that haven't
+//! started yet.
+
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Expected <label>=<command>, got {0:?}")]
+    Format(String),
+    #[error("Failed to split the command of label {0:?}: {1}")]
+    Command(String, shell_words::ParseError),
+}
+
+/// A label and the command it queues, given as `<label>=<command>` where the command is what
+/// follows the command prefix in a comment, like `bench:extrinsics=bench extrinsics --all`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelTrigger {
+    pub label: String,
+    pub command: Vec<String>,
+}
+
+impl std::str::FromStr for LabelTrigger {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, command) = s
+            .split_once('=')
+            .filter(|(label, command)| !label.is_empty() && !command.trim().is_empty())
+            .ok_or_else(|| Error::Format(s.to_string()))?;
+        let command =
+            shell_words::split(command).map_err(|e| Error::Command(label.to_string(), e))?;
+        Ok(LabelTrigger {
+            label: label.to_string(),
+            command,
+        })
+    }
+}
+
+/// Payload of the `pull_request` webhook, as far as labels are concerned
+#[derive(Debug, Deserialize)]
+pub struct PullRequestEvent {
+    /// `labeled` and `unlabeled` are the ones about labels, there are many others
+    pub action: String,
+    /// Of the pull request
+    pub number: u64,
+    /// The label added or removed
+    #[serde(default)]
+    pub label: Option<Label>,
+    pub repository: octocrab::models::Repository,
+    /// Who added or removed the label
+    pub sender: Sender,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Label {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Sender {
+    pub login: String,
+}
+
+impl PullRequestEvent {
+    /// The label added, if one was
+    pub fn labeled(&self) -> Option<&str> {
+        self.label_of("labeled")
+    }
+
+    /// The label removed, if one was
+    pub fn unlabeled(&self) -> Option<&str> {
+        self.label_of("unlabeled")
+    }
+
+    fn label_of(&self, action: &str) -> Option<&str> {
+        match &self.label {
+            Some(label) if self.action == action => Some(&label.name),
+            _ => None,
+        }
+    }
+}
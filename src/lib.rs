@@ -1,19 +1,75 @@
 pub mod api;
+pub mod auth;
+pub mod auto_merge;
+pub mod branches;
+pub mod builtin;
+pub mod cli;
+pub mod config;
+pub mod dashboard;
+pub mod export;
+pub mod forge;
+pub mod handover;
+pub mod history;
+pub mod installation;
+pub mod janitor;
 pub mod job;
+pub mod journal;
+pub mod labels;
+pub mod library;
 mod local_queue;
+pub mod logging;
+pub mod machine;
+pub mod metrics;
+#[cfg(feature = "mock-github")]
+pub mod mock_github;
+mod persistent_queue;
+pub mod pipeline;
+pub mod platform;
+pub mod rate_limit;
+pub mod results;
+pub mod review_comments;
+pub mod runners;
+pub mod secrets;
+pub mod shards;
+pub mod suites;
+pub mod templates;
+pub mod webhook;
 
 pub use job::Job;
-pub use local_queue::LocalQueue;
+pub use local_queue::{Fair, LocalQueue};
+pub use persistent_queue::PersistentQueue;
+
+/// Items only some of the consumers of a queue take, like jobs that need specific machines
+pub trait Routed {
+    /// Consumers asking for this route take the item, `None` being the ordinary ones
+    fn route(&self) -> Option<std::borrow::Cow<'_, str>>;
+}
 
 pub trait Queue {
     type Err;
     type Id;
+    /// Items with the same key are considered duplicates of each other
+    type Key;
     type Item;
 
-    fn add(&mut self, id: Self::Id, item: Self::Item);
-    fn remove(&mut self) -> Option<Self::Item>;
+    fn add(&mut self, id: Self::Id, key: Self::Key, item: Self::Item);
+    /// Add an item, replacing a queued item with the same key. Returns the replaced item.
+    fn supersede(&mut self, id: Self::Id, key: Self::Key, item: Self::Item) -> Option<Self::Item>;
+    /// Remove the next item routed to `route`, see [`Routed`]
+    fn remove_routed(&mut self, route: Option<&str>) -> Option<Self::Item>;
+    /// Remove the queued item `id` without handing it out, like when it's cancelled
+    fn cancel(&mut self, id: &Self::Id) -> Option<Self::Item>;
     fn len(&self) -> usize;
     fn pos(&self, id: Self::Id) -> Option<usize>;
+    /// Position of the queued item with the given key
+    fn pos_by_key(&self, key: &Self::Key) -> Option<usize>;
+    /// All queued items, in the order they will be removed
+    fn items(&self) -> Vec<&Self::Item>;
+
+    /// Remove the next item that isn't routed anywhere in particular
+    fn remove(&mut self) -> Option<Self::Item> {
+        self.remove_routed(None)
+    }
 
     fn is_empty(&self) -> bool {
         self.len() == 0
@@ -0,0 +1,95 @@
+//! Rhai modules shared by the scripts of all repositories, so the code comparing and reporting
+//! on results isn't copied into each of them. They come from a central repository of bot
+//! scripts, which the reactor checks out at a pinned revision (see `--script-library`), and are
+//! imported with a `lib:` prefix:
+//!
+//! ```rhai
+//! import "lib:compare" as compare;   // compare.rhai at the root of the library
+//! import "lib:report/table" as table;
+//! ```
+//!
+//! Other imports are resolved like rhai does by default.
+
+use crate::api::git::{self, Credentials};
+use rhai::module_resolvers::FileModuleResolver;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to check out the script library: {0}")]
+    Git(#[from] git::Error),
+    #[error("Failed to check out the script library: {0}")]
+    Repository(#[from] git2::Error),
+    #[error("Failed to check out the script library: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Prefix of the modules imported from the library
+pub const PREFIX: &str = "lib:";
+
+/// Check out `rev` (a commit, tag or branch) of the repository at `url` into `dir`, cloning it
+/// first if it isn't there yet. Returns the commit checked out.
+pub fn sync(url: &str, rev: &str, dir: &Path, credentials: &Credentials) -> Result<String, Error> {
+    if !dir.join(".git").exists() {
+        let parent = dir.parent().unwrap_or(dir);
+        std::fs::create_dir_all(parent)?;
+        tracing::info!("Cloning script library {url} to {dir:?}");
+        git::run_git(
+            parent,
+            &["clone", "--no-checkout", url, &dir.to_string_lossy()],
+            credentials,
+        )?;
+    }
+    git::run_git(dir, &["remote", "set-url", "origin", url], credentials)?;
+    git::run_git(dir, &["fetch", "--tags", "origin", rev], credentials)?;
+    git::run_git(
+        dir,
+        &["checkout", "--force", "--detach", "FETCH_HEAD"],
+        credentials,
+    )?;
+    let commit = git2::Repository::open(dir)?
+        .head()?
+        .peel_to_commit()?
+        .id()
+        .to_string();
+    Ok(commit)
+}
+
+/// Resolves `lib:<name>` to `<name>.rhai` in the library, if there is one
+pub struct Resolver {
+    library: Option<FileModuleResolver>,
+    files: FileModuleResolver,
+}
+
+impl Resolver {
+    pub fn new(library: Option<&Path>) -> Self {
+        Resolver {
+            library: library.map(FileModuleResolver::new_with_path),
+            files: FileModuleResolver::new(),
+        }
+    }
+}
+
+impl rhai::ModuleResolver for Resolver {
+    fn resolve(
+        &self,
+        engine: &rhai::Engine,
+        source: Option<&str>,
+        path: &str,
+        pos: rhai::Position,
+    ) -> Result<rhai::Shared<rhai::Module>, Box<rhai::EvalAltResult>> {
+        let name = match path.strip_prefix(PREFIX) {
+            Some(name) => name,
+            None => return self.files.resolve(engine, source, path, pos),
+        };
+        // Only what's in the library itself
+        let contained = PathBuf::from(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        match &self.library {
+            Some(library) if contained => library.resolve(engine, None, name, pos),
+            _ => Err(rhai::EvalAltResult::ErrorModuleNotFound(path.to_string(), pos).into()),
+        }
+    }
+}
@@ -1,52 +1,209 @@
-use crate::Queue;
+use crate::{Queue, Routed};
+use async_std::channel::{Receiver, Sender};
 use indexmap::IndexMap;
+use std::collections::{HashMap, VecDeque};
 use std::hash::Hash;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {}
 
+/// Orders items so that every group of them (like the jobs of a repository) gets its turn,
+/// rather than the one that added a lot of items first holding up all others. Items of a group
+/// with weight 2 get twice as many turns as those of a group with weight 1, the default.
 #[derive(Debug)]
-pub struct LocalQueue<Id, Item> {
-    queue: IndexMap<Id, Item>,
-    watchers: Vec<async_std::channel::Sender<Item>>,
+pub struct Fair<Item> {
+    group: fn(&Item) -> String,
+    weights: HashMap<String, u32>,
 }
 
-impl<Id, Item> LocalQueue<Id, Item> {
+impl<Item> Fair<Item> {
+    pub fn new(group: fn(&Item) -> String, weights: HashMap<String, u32>) -> Self {
+        Fair { group, weights }
+    }
+
+    fn weight(&self, group: &str) -> f64 {
+        self.weights.get(group).copied().unwrap_or(1).max(1) as f64
+    }
+}
+
+#[derive(Debug)]
+pub struct LocalQueue<Id, Key, Item> {
+    queue: IndexMap<Id, (Key, Item)>,
+    /// Waiting for an item of their route while there was none, longest waiting first
+    watchers: VecDeque<(Option<String>, Sender<Item>)>,
+    /// Items are still added, but not handed out
+    paused: bool,
+    /// Queued in the order they're added if not given
+    fair: Option<Fair<Item>>,
+}
+
+impl<Id, Key, Item> LocalQueue<Id, Key, Item> {
     pub fn new() -> Self {
         let queue = IndexMap::new();
-        let watchers = vec![];
-        Self { queue, watchers }
+        let watchers = VecDeque::new();
+        Self {
+            queue,
+            watchers,
+            paused: false,
+            fair: None,
+        }
+    }
+
+    /// Wait for the next added item. Every item goes to a single watcher, the one waiting the
+    /// longest. Watchers that went away (dropped their receiver) are skipped, so the item goes to
+    /// the next one or the queue instead.
+    pub fn watch(&mut self) -> Receiver<Item> {
+        self.watch_routed(None)
+    }
+
+    /// Like `watch`, for the next added item routed to `route`, see [`Routed`]
+    pub fn watch_routed(&mut self, route: Option<String>) -> Receiver<Item> {
+        self.watchers
+            .retain(|(_route, watcher)| !watcher.is_closed());
+        let (sender, receiver) = async_std::channel::bounded(1);
+        self.watchers.push_back((route, sender));
+        receiver
+    }
+
+    /// Remove all queued items, keeping the watchers
+    pub fn clear(&mut self) {
+        self.queue.clear();
     }
 
-    pub fn register_watcher(&mut self, sender: async_std::channel::Sender<Item>) {
-        self.watchers.push(sender);
+    /// Stop handing out items until `resume`, neither to watchers nor through `remove`
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
     }
 }
 
-impl<Id, Item> Queue for LocalQueue<Id, Item>
+impl<Id, Key, Item: Routed> LocalQueue<Id, Key, Item> {
+    /// Hand `item` to the longest waiting watcher of its route that's still there, or give it
+    /// back if there is none
+    fn notify(&mut self, mut item: Item) -> Option<Item> {
+        let mut index = 0;
+        while index < self.watchers.len() {
+            if self.watchers[index].0.as_deref() != item.route().as_deref() {
+                index += 1;
+                continue;
+            }
+            let (_route, watcher) = self.watchers.remove(index)?;
+            // Every watcher gets a single item, so its channel can't be full
+            match watcher.try_send(item) {
+                Ok(()) => return None,
+                Err(err) => item = err.into_inner(),
+            }
+        }
+        Some(item)
+    }
+}
+
+impl<Id: Hash + Eq, Key, Item: Routed> LocalQueue<Id, Key, Item> {
+    /// Take turns between the groups of items from now on, reordering the queued ones
+    pub fn set_fair(&mut self, fair: Fair<Item>) {
+        self.fair = Some(fair);
+        for (id, (key, item)) in std::mem::take(&mut self.queue) {
+            self.insert(id, key, item);
+        }
+    }
+
+    /// Queue `item` where it's its group's turn, behind the items of its own group
+    fn insert(&mut self, id: Id, key: Key, item: Item) {
+        let position = self.position(&item);
+        let (index, _) = self.queue.insert_full(id, (key, item));
+        self.queue.move_index(index, position.min(index));
+    }
+
+    /// Where `item` is queued: the `n`th item of a group with weight `w` has its turn at
+    /// `(n - 1) / w`, and goes behind the items whose turns are earlier or at the same time
+    fn position(&self, item: &Item) -> usize {
+        let fair = match &self.fair {
+            Some(fair) => fair,
+            None => return self.queue.len(),
+        };
+        let turn = |group: &str, n: usize| (n - 1) as f64 / fair.weight(group);
+        let group = (fair.group)(item);
+        let own = self
+            .queue
+            .values()
+            .filter(|(_key, queued)| (fair.group)(queued) == group)
+            .count();
+        let mine = turn(&group, own + 1);
+        let mut counts = HashMap::new();
+        let mut position = 0;
+        for (index, (_key, queued)) in self.queue.values().enumerate() {
+            let group = (fair.group)(queued);
+            let n = counts.entry(group.clone()).or_insert(0);
+            *n += 1;
+            if turn(&group, *n) <= mine {
+                position = index + 1;
+            }
+        }
+        position
+    }
+
+    /// Hand out items again, the ones queued while paused going to the waiting watchers first
+    pub fn resume(&mut self) {
+        self.paused = false;
+        for (id, (key, item)) in std::mem::take(&mut self.queue) {
+            if let Some(item) = self.notify(item) {
+                self.queue.insert(id, (key, item));
+            }
+        }
+    }
+}
+
+impl<Id, Key, Item> Queue for LocalQueue<Id, Key, Item>
 where
-    Id: Hash + Eq,
-    Item: Send + 'static,
+    Id: Hash + Eq + Clone,
+    Key: Eq,
+    Item: Routed + Send + 'static,
 {
     type Err = Error;
     type Id = Id;
+    type Key = Key;
     type Item = Item;
 
-    fn add(&mut self, id: Self::Id, item: Self::Item) {
-        if !self.watchers.is_empty() {
-            let watcher = self.watchers.remove(0);
-            async_std::task::spawn(async move { watcher.send(item).await });
-        } else {
-            self.queue.insert_full(id, item);
+    fn add(&mut self, id: Self::Id, key: Self::Key, item: Self::Item) {
+        let item = match self.paused {
+            true => Some(item),
+            false => self.notify(item),
+        };
+        if let Some(item) = item {
+            self.insert(id, key, item);
         }
     }
 
-    fn remove(&mut self) -> Option<Self::Item> {
-        if !self.queue.is_empty() {
-            self.queue.shift_remove_index(0).map(|(_k, v)| v)
-        } else {
-            None
+    fn supersede(&mut self, id: Self::Id, key: Self::Key, item: Self::Item) -> Option<Self::Item> {
+        let superseded = self
+            .queue
+            .iter()
+            .find(|(_id, (k, _item))| *k == key)
+            .map(|(id, _)| id.clone())
+            .and_then(|id| self.queue.shift_remove(&id))
+            .map(|(_key, item)| item);
+        self.add(id, key, item);
+        superseded
+    }
+
+    fn remove_routed(&mut self, route: Option<&str>) -> Option<Self::Item> {
+        if self.paused {
+            return None;
         }
+        let index = self
+            .queue
+            .values()
+            .position(|(_key, item)| item.route().as_deref() == route)?;
+        self.queue
+            .shift_remove_index(index)
+            .map(|(_id, (_key, item))| item)
+    }
+
+    fn cancel(&mut self, id: &Self::Id) -> Option<Self::Item> {
+        self.queue.shift_remove(id).map(|(_key, item)| item)
     }
 
     fn len(&self) -> usize {
@@ -56,10 +213,198 @@ where
     fn pos(&self, id: Self::Id) -> Option<usize> {
         self.queue.get_index_of(&id)
     }
+
+    fn pos_by_key(&self, key: &Self::Key) -> Option<usize> {
+        self.queue.values().position(|(k, _item)| k == key)
+    }
+
+    fn items(&self) -> Vec<&Self::Item> {
+        self.queue.values().map(|(_key, item)| item).collect()
+    }
 }
 
-impl<Id, Item> Default for LocalQueue<Id, Item> {
+impl<Id, Key, Item> Default for LocalQueue<Id, Key, Item> {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::sync::{Arc, Mutex};
+    use std::collections::HashSet;
+
+    type TestQueue = LocalQueue<usize, usize, usize>;
+
+    /// From 1000 on to the big machines
+    impl Routed for usize {
+        fn route(&self) -> Option<std::borrow::Cow<'_, str>> {
+            (*self >= 1000).then_some("big".into())
+        }
+    }
+
+    #[test]
+    fn every_watcher_gets_one_item() {
+        let mut queue = TestQueue::new();
+        let watchers: Vec<_> = (0..3).map(|_| queue.watch()).collect();
+        for i in 0..5 {
+            queue.add(i, i, i);
+        }
+        // Longest waiting first, the rest is queued
+        for (i, watcher) in watchers.iter().enumerate() {
+            assert_eq!(watcher.try_recv().ok(), Some(i));
+            assert!(watcher.try_recv().is_err());
+        }
+        assert_eq!(queue.items(), vec![&3, &4]);
+    }
+
+    #[test]
+    fn gone_watchers_are_skipped() {
+        let mut queue = TestQueue::new();
+        let gone = queue.watch();
+        let waiting = queue.watch();
+        drop(gone);
+        queue.add(0, 0, 0);
+        assert_eq!(waiting.try_recv().ok(), Some(0));
+
+        drop(queue.watch());
+        queue.add(1, 1, 1);
+        assert_eq!(queue.remove(), Some(1));
+    }
+
+    #[test]
+    fn paused_queue_hands_out_nothing_until_resumed() {
+        let mut queue = TestQueue::new();
+        let watcher = queue.watch();
+        queue.pause();
+        for i in 0..3 {
+            queue.add(i, i, i);
+        }
+        assert!(watcher.try_recv().is_err());
+        assert_eq!(queue.remove(), None);
+        assert_eq!(queue.len(), 3);
+
+        // In order, the first to the waiting watcher
+        queue.resume();
+        assert_eq!(watcher.try_recv().ok(), Some(0));
+        assert_eq!(queue.items(), vec![&1, &2]);
+        assert_eq!(queue.remove(), Some(1));
+    }
+
+    #[test]
+    fn groups_take_turns_by_weight() {
+        // Grouped by the tens
+        let weights = vec![("1".to_string(), 2)].into_iter().collect();
+        let mut queue = TestQueue::new();
+        queue.set_fair(Fair::new(|item| (item / 10).to_string(), weights));
+        for i in [0, 1, 2, 3, 10, 11, 12, 13, 20] {
+            queue.add(i, i, i);
+        }
+        assert_eq!(queue.items(), vec![&0, &10, &20, &11, &1, &12, &13, &2, &3]);
+        assert_eq!(queue.remove(), Some(0));
+        queue.add(21, 21, 21);
+        assert_eq!(queue.pos(21), Some(7));
+    }
+
+    #[test]
+    fn routed_items_go_to_their_route_only() {
+        let mut queue = TestQueue::new();
+        let ordinary = queue.watch();
+        let big = queue.watch_routed(Some("big".to_string()));
+        queue.add(1000, 1000, 1000);
+        assert!(ordinary.try_recv().is_err());
+        assert_eq!(big.try_recv().ok(), Some(1000));
+
+        queue.add(1001, 1001, 1001);
+        queue.add(0, 0, 0);
+        assert_eq!(ordinary.try_recv().ok(), Some(0));
+        assert_eq!(queue.remove(), None);
+        assert_eq!(queue.remove_routed(Some("big")), Some(1001));
+    }
+
+    #[test]
+    fn cancelled_items_are_not_handed_out() {
+        let mut queue = TestQueue::new();
+        queue.pause();
+        for i in 0..3 {
+            queue.add(i, i, i);
+        }
+        assert_eq!(queue.cancel(&1), Some(1));
+        assert_eq!(queue.cancel(&1), None);
+        queue.resume();
+        assert_eq!(queue.remove(), Some(0));
+        assert_eq!(queue.remove(), Some(2));
+        assert_eq!(queue.remove(), None);
+    }
+
+    #[test]
+    fn gone_watchers_are_forgotten() {
+        let mut queue = TestQueue::new();
+        for _ in 0..100 {
+            drop(queue.watch());
+        }
+        assert_eq!(queue.watchers.len(), 1);
+    }
+
+    #[async_std::test]
+    async fn contending_consumers_get_every_item_once() {
+        const CONSUMERS: usize = 8;
+        const ITEMS: usize = 200;
+
+        let queue = Arc::new(Mutex::new(TestQueue::new()));
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let queue = queue.clone();
+                async_std::task::spawn(async move {
+                    let mut received = vec![];
+                    loop {
+                        // Like a worker long-polling the queue
+                        let watcher = {
+                            let mut queue = queue.lock().await;
+                            match queue.remove() {
+                                Some(item) => Ok(item),
+                                None => Err(queue.watch()),
+                            }
+                        };
+                        let item = match watcher {
+                            Ok(item) => item,
+                            Err(watcher) => watcher.recv().await.unwrap(),
+                        };
+                        // Items past `ITEMS` tell consumers to stop
+                        if item >= ITEMS {
+                            return received;
+                        }
+                        received.push(item);
+                    }
+                })
+            })
+            .collect();
+        let producers: Vec<_> = (0..4)
+            .map(|producer| {
+                let queue = queue.clone();
+                async_std::task::spawn(async move {
+                    for i in (producer..ITEMS).step_by(4) {
+                        queue.lock().await.add(i, i, i);
+                        async_std::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.await;
+        }
+        for i in ITEMS..ITEMS + CONSUMERS {
+            queue.lock().await.add(i, i, i);
+        }
+
+        let mut received = HashSet::new();
+        for consumer in consumers {
+            for item in consumer.await {
+                assert!(received.insert(item), "item {} was received twice", item);
+            }
+        }
+        assert_eq!(received.len(), ITEMS);
+        assert!(queue.lock().await.is_empty());
+    }
+}
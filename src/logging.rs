@@ -0,0 +1,272 @@
+//! How the tools write their logs: human readable, or as JSON lines carrying the job they're
+//! about, for log aggregators like Loki or Elasticsearch to correlate. Logs are `tracing`
+//! events, within spans for handling webhooks, queueing, checking out, running scripts and
+//! talking to Github, which can also be exported over OTLP (with the `otlp` feature).
+
+use crate::history::{LogTail, TailLayer};
+use crate::job::Job;
+use crate::secrets::Redactor;
+use std::io::IsTerminal;
+use thiserror::Error;
+use tracing_log::AsTrace;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Layer;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid log format {0:?}, expected pretty or json")]
+    Format(String),
+    #[error("Failed to set up logging: {0}")]
+    Subscriber(#[from] tracing::subscriber::SetGlobalDefaultError),
+    #[error("Failed to set up logging: {0}")]
+    Log(#[from] log::SetLoggerError),
+    #[cfg(feature = "otlp")]
+    #[error("Failed to set up exporting traces: {0}")]
+    Otlp(#[from] opentelemetry_otlp::ExporterBuildError),
+    #[error("Can't export traces to {0}, built without the `otlp` feature")]
+    NoOtlp(url::Url),
+    #[error("Failed to change the log level: {0}")]
+    Level(#[from] tracing_subscriber::reload::Error),
+}
+
+/// Format of the log lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Timestamped and colored, for people
+    Pretty,
+    /// A JSON object per line, with the id, repository and command of the job being run
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::Format(s.to_string())),
+        }
+    }
+}
+
+/// Where and how to log, installed as the global `tracing` subscriber with [`Logging::init`].
+/// Records of dependencies logging through `log` end up there too.
+pub struct Logging {
+    format: LogFormat,
+    level: log::LevelFilter,
+    redactor: Redactor,
+    tails: Vec<LogTail>,
+    otlp_endpoint: Option<url::Url>,
+    service: String,
+}
+
+impl Logging {
+    pub fn new(format: LogFormat, level: log::LevelFilter) -> Self {
+        Logging {
+            format,
+            level,
+            redactor: Redactor::new(),
+            tails: vec![],
+            otlp_endpoint: None,
+            service: env!("CARGO_PKG_NAME").to_string(),
+        }
+    }
+
+    /// Mask secrets in everything that's logged
+    pub fn redactor(mut self, redactor: &Redactor) -> Self {
+        self.redactor = redactor.clone();
+        self
+    }
+
+    /// Keep a copy of what's logged in `tail`, like for the dashboard. Can be given several
+    /// times, like for the dashboard of each tenant.
+    pub fn tail(mut self, tail: &LogTail) -> Self {
+        self.tails.push(tail.clone());
+        self
+    }
+
+    /// Export spans over OTLP/HTTP to `endpoint` as `service`, like
+    /// `http://localhost:4318/v1/traces`
+    pub fn otlp(mut self, endpoint: Option<url::Url>, service: &str) -> Self {
+        self.otlp_endpoint = endpoint;
+        self.service = service.to_string();
+        self
+    }
+
+    /// Start logging. Exported spans are flushed when the returned guard is dropped.
+    pub fn init(self) -> Result<Guard, Error> {
+        let (level, handle) = tracing_subscriber::reload::Layer::new(self.level.as_trace());
+        let writer = Redacting(self.redactor.clone());
+        // Spans report how long they took when they close, the ones at debug level (like calls
+        // to Github) only when debugging
+        let fmt = tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_span_events(FmtSpan::CLOSE);
+        let fmt = match self.format {
+            LogFormat::Pretty => fmt.boxed(),
+            LogFormat::Json => fmt
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+                .boxed(),
+        };
+        let tail = Some(self.tails.clone())
+            .filter(|tails| !tails.is_empty())
+            .map(|tails| TailLayer::new(tails).with_redactor(self.redactor.clone()));
+        let (otlp, guard) = self.otlp_layer(LevelHandle(handle))?;
+        let subscriber = tracing_subscriber::registry()
+            .with(level)
+            .with(fmt)
+            .with(tail)
+            .with(otlp);
+        tracing::subscriber::set_global_default(subscriber)?;
+        tracing_log::LogTracer::init_with_filter(self.level)?;
+        Ok(guard)
+    }
+
+    #[cfg(feature = "otlp")]
+    fn otlp_layer<S>(&self, level: LevelHandle) -> Result<(Option<impl Layer<S>>, Guard), Error>
+    where
+        S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    {
+        use opentelemetry::trace::TracerProvider;
+        use opentelemetry_otlp::WithExportConfig;
+
+        let endpoint = match &self.otlp_endpoint {
+            Some(endpoint) => endpoint,
+            None => {
+                let provider = None;
+                return Ok((None, Guard { level, provider }));
+            }
+        };
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint.as_str())
+            .build()?;
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                opentelemetry_sdk::Resource::builder()
+                    .with_service_name(self.service.clone())
+                    .build(),
+            )
+            .build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("ci-script"));
+        Ok((
+            Some(layer),
+            Guard {
+                level,
+                provider: Some(provider),
+            },
+        ))
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    fn otlp_layer(
+        &self,
+        level: LevelHandle,
+    ) -> Result<(Option<tracing_subscriber::layer::Identity>, Guard), Error> {
+        match &self.otlp_endpoint {
+            Some(endpoint) => Err(Error::NoOtlp(endpoint.clone())),
+            None => Ok((None, Guard { level })),
+        }
+    }
+}
+
+/// Flushes exported spans when dropped
+#[must_use]
+pub struct Guard {
+    level: LevelHandle,
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Guard {
+    /// To change the level of the logs while running
+    pub fn level(&self) -> LevelHandle {
+        self.level.clone()
+    }
+}
+
+/// Changes the level of the logs, of dependencies logging through `log` too
+#[derive(Clone)]
+pub struct LevelHandle(
+    tracing_subscriber::reload::Handle<
+        tracing_subscriber::filter::LevelFilter,
+        tracing_subscriber::Registry,
+    >,
+);
+
+impl LevelHandle {
+    pub fn set(&self, level: log::LevelFilter) -> Result<(), Error> {
+        self.0.reload(level.as_trace())?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("Failed to export the last traces: {err}");
+            }
+        }
+    }
+}
+
+/// Writes log lines to stderr with secrets masked
+struct Redacting(Redactor);
+
+impl<'a> MakeWriter<'a> for Redacting {
+    type Writer = RedactedLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactedLine {
+            line: vec![],
+            redactor: self.0.clone(),
+        }
+    }
+}
+
+/// A line, written to stderr once complete so it's redacted as a whole
+struct RedactedLine {
+    line: Vec<u8>,
+    redactor: Redactor,
+}
+
+impl std::io::Write for RedactedLine {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RedactedLine {
+    fn drop(&mut self) {
+        let line = self.redactor.redact(&String::from_utf8_lossy(&self.line));
+        let _ = std::io::Write::write_all(&mut std::io::stderr(), line.as_bytes());
+    }
+}
+
+/// Span to run `job` in, whose fields JSON logs add to every line logged within it
+pub fn job_span(job: &Job) -> tracing::Span {
+    tracing::info_span!(
+        "job",
+        id = %job.id,
+        repo = %format!("{}/{}", job.repository.owner.login, job.repository.name),
+        command = %job.command.join(" "),
+        user = job.user.as_deref().unwrap_or_default(),
+        // Recorded by whoever runs it for a tenant
+        tenant = tracing::field::Empty,
+    )
+}
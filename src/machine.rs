@@ -0,0 +1,93 @@
+//! What the machine running a job is like, since results of different machines (or of the same
+//! one under load) can't be compared. Gathered when a job starts, scripts see it as `MACHINE`:
+//!
+//! ```rhai
+//! print(`${MACHINE.cpu_model}, ${MACHINE.cores} cores, governor ${MACHINE.governor}`);
+//! ```
+//!
+//! What can't be found out (like the governor on machines without `cpufreq`) is left empty.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Machine {
+    pub cpu_model: Option<String>,
+    /// Logical cores available to the process
+    pub cores: Option<usize>,
+    /// Total memory in bytes
+    pub memory: Option<u64>,
+    pub kernel: Option<String>,
+    /// Scaling governor of the first core, like `performance` or `powersave`
+    pub governor: Option<String>,
+    /// Over the last 1, 5 and 15 minutes
+    pub load_average: Option<[f64; 3]>,
+}
+
+impl Machine {
+    /// Look at the machine we're running on
+    pub fn gather() -> Self {
+        let read = |path: &str| {
+            std::fs::read_to_string(Path::new(path))
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let cpuinfo = read("/proc/cpuinfo").unwrap_or_default();
+        let meminfo = read("/proc/meminfo").unwrap_or_default();
+        Machine {
+            cpu_model: field(&cpuinfo, "model name").map(str::to_string),
+            cores: std::thread::available_parallelism().map(usize::from).ok(),
+            memory: field(&meminfo, "MemTotal")
+                .and_then(|total| total.trim_end_matches("kB").trim().parse::<u64>().ok())
+                .map(|kb| kb * 1024),
+            kernel: read("/proc/sys/kernel/osrelease"),
+            governor: read("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+            load_average: read("/proc/loadavg").and_then(|loadavg| {
+                let mut loads = loadavg.split_whitespace().map(str::parse::<f64>);
+                Some([
+                    loads.next()?.ok()?,
+                    loads.next()?.ok()?,
+                    loads.next()?.ok()?,
+                ])
+            }),
+        }
+    }
+
+    /// For scripts, as a map with the same fields, `()` for the unknown ones
+    pub fn to_dynamic(&self) -> rhai::Dynamic {
+        rhai::serde::to_dynamic(self).unwrap_or(rhai::Dynamic::UNIT)
+    }
+
+    /// Render as a short footer for a result comment
+    pub fn render(&self) -> String {
+        let mut parts = vec![];
+        if let Some(cpu_model) = &self.cpu_model {
+            parts.push(cpu_model.clone());
+        }
+        if let Some(cores) = self.cores {
+            parts.push(format!("{cores} cores"));
+        }
+        if let Some(memory) = self.memory {
+            parts.push(format!("{:.1} GiB", memory as f64 / (1u64 << 30) as f64));
+        }
+        if let Some(kernel) = &self.kernel {
+            parts.push(format!("Linux {kernel}"));
+        }
+        if let Some(governor) = &self.governor {
+            parts.push(format!("governor {governor}"));
+        }
+        if let Some([one, five, fifteen]) = self.load_average {
+            parts.push(format!("load {one:.2} {five:.2} {fifteen:.2}"));
+        }
+        format!("<sub>Machine: {}</sub>", parts.join(", "))
+    }
+}
+
+/// Value of the first `name: value` line of a `/proc` file
+fn field<'a>(file: &'a str, name: &str) -> Option<&'a str> {
+    file.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == name).then(|| value.trim())
+    })
+}
@@ -0,0 +1,242 @@
+//! Metrics of the jobs of a tenant in the Prometheus text format, served at `/metrics`.
+
+use crate::api::usage::Usage;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// Clones share the metrics
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    tenant: String,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The latest numeric outputs, by repository, script and key
+    outputs: BTreeMap<(String, String, String), f64>,
+    /// What the finished jobs used, by repository and script
+    usage: BTreeMap<(String, String), Accounted>,
+}
+
+#[derive(Debug, Default)]
+struct Accounted {
+    /// Summed up over the jobs
+    total: Usage,
+    /// Of the latest job
+    max_rss: u64,
+}
+
+impl Metrics {
+    pub fn new(tenant: &str) -> Self {
+        Metrics {
+            tenant: tenant.to_string(),
+            inner: Default::default(),
+        }
+    }
+
+    /// Remember the numbers among the `outputs` of a job running `script` (its path in the
+    /// repository) on `repository`, replacing what they were set to by earlier jobs. Other
+    /// values, like strings and maps, aren't metrics.
+    pub fn record_outputs(
+        &self,
+        repository: &str,
+        script: &str,
+        outputs: &BTreeMap<String, serde_json::Value>,
+    ) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+        for (key, value) in outputs {
+            if let Some(value) = value.as_f64() {
+                inner.outputs.insert(
+                    (repository.to_string(), script.to_string(), key.clone()),
+                    value,
+                );
+            }
+        }
+    }
+
+    /// Add what a job running `script` on `repository` used to the counters
+    pub fn record_usage(&self, repository: &str, script: &str, usage: &Usage) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+        let accounted = inner
+            .usage
+            .entry((repository.to_string(), script.to_string()))
+            .or_default();
+        accounted.total.user += usage.user;
+        accounted.total.system += usage.system;
+        accounted.total.read_bytes += usage.read_bytes;
+        accounted.total.written_bytes += usage.written_bytes;
+        accounted.max_rss = usage.max_rss;
+    }
+
+    /// All metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return String::new(),
+        };
+        let mut out = String::new();
+        if !inner.outputs.is_empty() {
+            out.push_str("# HELP cis_output The latest number a script set with OUTPUT.set\n");
+            out.push_str("# TYPE cis_output gauge\n");
+            for ((repository, script, key), value) in &inner.outputs {
+                let labels =
+                    self.labels(&[("repository", repository), ("script", script), ("key", key)]);
+                let _ = writeln!(out, "cis_output{{{labels}}} {value}");
+            }
+        }
+        if !inner.usage.is_empty() {
+            type Value = fn(&Accounted) -> Vec<(Option<&'static str>, f64)>;
+            let families: [(&str, &str, &str, Value); 4] = [
+                (
+                    "cis_job_cpu_seconds_total",
+                    "counter",
+                    "CPU time the processes of the jobs spent, by mode",
+                    |accounted| {
+                        vec![
+                            (Some("user"), accounted.total.user.as_secs_f64()),
+                            (Some("system"), accounted.total.system.as_secs_f64()),
+                        ]
+                    },
+                ),
+                (
+                    "cis_job_read_bytes_total",
+                    "counter",
+                    "Bytes the processes of the jobs read from disk",
+                    |accounted| vec![(None, accounted.total.read_bytes as f64)],
+                ),
+                (
+                    "cis_job_written_bytes_total",
+                    "counter",
+                    "Bytes the processes of the jobs wrote to disk",
+                    |accounted| vec![(None, accounted.total.written_bytes as f64)],
+                ),
+                (
+                    "cis_job_max_rss_bytes",
+                    "gauge",
+                    "Peak resident set size of the largest process of the latest job",
+                    |accounted| vec![(None, accounted.max_rss as f64)],
+                ),
+            ];
+            for (name, kind, help, value) in families {
+                let _ = writeln!(out, "# HELP {name} {help}");
+                let _ = writeln!(out, "# TYPE {name} {kind}");
+                for ((repository, script), accounted) in &inner.usage {
+                    for (mode, value) in value(accounted) {
+                        let mut labels =
+                            vec![("repository", repository.as_str()), ("script", script)];
+                        labels.extend(mode.map(|mode| ("mode", mode)));
+                        let _ = writeln!(out, "{name}{{{}}} {value}", self.labels(&labels));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// `name="value"` pairs, starting with the tenant
+    fn labels(&self, labels: &[(&str, &str)]) -> String {
+        std::iter::once(("tenant", self.tenant.as_str()))
+            .chain(labels.iter().copied())
+            .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn numeric_outputs_are_gauges() {
+        let metrics = Metrics::new("default");
+        assert_eq!(metrics.render(), "");
+
+        let outputs = [
+            ("runtime_version", json!(9430)),
+            ("size", json!(1.5)),
+            ("name", json!("kusama")),
+            ("wasm", json!({ "size": 1 })),
+        ];
+        metrics.record_outputs(
+            "paritytech/polkadot",
+            ".github/benchbot/bench.rhai",
+            &outputs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        );
+        // Replaced by later jobs
+        metrics.record_outputs(
+            "paritytech/polkadot",
+            ".github/benchbot/bench.rhai",
+            &vec![("size".to_string(), json!(2.25))]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(
+            metrics.render(),
+            "# HELP cis_output The latest number a script set with OUTPUT.set\n\
+             # TYPE cis_output gauge\n\
+             cis_output{tenant=\"default\",repository=\"paritytech/polkadot\",script=\".github/benchbot/bench.rhai\",\
+             key=\"runtime_version\"} 9430\n\
+             cis_output{tenant=\"default\",repository=\"paritytech/polkadot\",script=\".github/benchbot/bench.rhai\",\
+             key=\"size\"} 2.25\n"
+        );
+    }
+
+    #[test]
+    fn usage_is_summed_up() {
+        let metrics = Metrics::new("default");
+        let usage = |secs, max_rss| Usage {
+            user: Duration::from_secs(secs),
+            system: Duration::from_millis(500),
+            max_rss,
+            read_bytes: 1024,
+            written_bytes: 0,
+        };
+        metrics.record_usage("acme/widgets", "bench.rhai", &usage(2, 4096));
+        metrics.record_usage("acme/widgets", "bench.rhai", &usage(3, 1024));
+        let labels = r#"tenant="default",repository="acme/widgets",script="bench.rhai""#;
+        let expected = format!(
+            "# HELP cis_job_cpu_seconds_total CPU time the processes of the jobs spent, by mode\n\
+             # TYPE cis_job_cpu_seconds_total counter\n\
+             cis_job_cpu_seconds_total{{{labels},mode=\"user\"}} 5\n\
+             cis_job_cpu_seconds_total{{{labels},mode=\"system\"}} 1\n\
+             # HELP cis_job_read_bytes_total Bytes the processes of the jobs read from disk\n\
+             # TYPE cis_job_read_bytes_total counter\n\
+             cis_job_read_bytes_total{{{labels}}} 2048\n\
+             # HELP cis_job_written_bytes_total Bytes the processes of the jobs wrote to disk\n\
+             # TYPE cis_job_written_bytes_total counter\n\
+             cis_job_written_bytes_total{{{labels}}} 0\n\
+             # HELP cis_job_max_rss_bytes Peak resident set size of the largest process of the \
+             latest job\n\
+             # TYPE cis_job_max_rss_bytes gauge\n\
+             cis_job_max_rss_bytes{{{labels}}} 1024\n"
+        );
+        assert_eq!(metrics.render(), expected);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        assert_eq!(escape("two\nlines"), "two\\nlines");
+    }
+}
@@ -0,0 +1,981 @@
+//! A stand-in for the parts of the Github API the reactor and `cis` talk to, so the whole
+//! pipeline (webhook, queue, checkout, script, comment) can be exercised in tests without Github
+//! credentials. Point `--github-api-base` at [`MockGithub::url`], deliver webhooks made with
+//! [`MockGithub::issue_comment`] and check what was posted with [`MockGithub::comments`] and
+//! friends.
+//!
+//! Any token and app key are accepted. Repositories are cloned from the `clone_url` they were
+//! added with, like a local path.
+
+use crate::forge::user;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tide::listener::Listener;
+
+/// A comment posted on an issue or pull request
+#[derive(Clone, Debug)]
+pub struct Comment {
+    /// `owner/name`
+    pub repository: String,
+    pub issue: u64,
+    pub body: String,
+    /// The review comment replied to, none for comments on the conversation
+    pub review_thread: Option<u64>,
+}
+
+/// A commit status that was set
+#[derive(Clone, Debug)]
+pub struct Status {
+    pub repository: String,
+    pub sha: String,
+    pub state: String,
+    pub context: Option<String>,
+    pub description: Option<String>,
+    pub target_url: Option<String>,
+}
+
+/// A pull request that was opened
+#[derive(Clone, Debug)]
+pub struct PullRequest {
+    pub repository: String,
+    pub title: String,
+    pub body: Option<String>,
+    pub head: String,
+    pub base: String,
+    /// Closed with [`MockGithub::close_pull_request`]
+    pub closed: bool,
+}
+
+/// A review of a pull request
+#[derive(Clone, Debug)]
+pub struct Review {
+    pub repository: String,
+    pub number: u64,
+    /// `APPROVE`, `REQUEST_CHANGES` or `COMMENT`
+    pub event: String,
+    pub body: Option<String>,
+}
+
+/// Slug of the app the mock serves, whose pull requests are opened by `<slug>[bot]`
+pub const APP_SLUG: &str = "mock-bot";
+
+#[derive(Default)]
+struct State {
+    /// By `owner/name`
+    repositories: HashMap<String, serde_json::Value>,
+    pulls: HashMap<(String, u64), serde_json::Value>,
+    /// Of users on every repository, `write` if not set
+    permissions: HashMap<String, String>,
+    comments: Vec<Comment>,
+    statuses: Vec<Status>,
+    pull_requests: Vec<PullRequest>,
+    /// By `owner/name`
+    branches: HashMap<String, Vec<String>>,
+    reviews: Vec<Review>,
+    /// GraphQL IDs of the pull requests auto-merge was enabled on, with the merge method
+    auto_merges: Vec<(String, String)>,
+    /// Of issues and pull requests, by `owner/name` and number
+    labels: HashMap<(String, u64), Vec<String>>,
+    assignees: HashMap<(String, u64), Vec<String>>,
+    /// Slugs of the teams asked to review pull requests, by `owner/name` and number
+    team_reviewers: HashMap<(String, u64), Vec<String>>,
+    /// Paths starting with these are answered with an error
+    failing: Vec<String>,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+/// A mock Github API listening on a local port, see the [module docs](self)
+#[derive(Clone)]
+pub struct MockGithub {
+    url: url::Url,
+    state: Shared,
+}
+
+impl MockGithub {
+    /// Listen on a free port of localhost, in the background
+    pub async fn start() -> std::io::Result<Self> {
+        let state = Shared::default();
+        let mut app = tide::with_state(state.clone());
+        app.with(fail_requests);
+        app.at("/app").get(app_json);
+        app.at("/app/installations").get(installations);
+        app.at("/app/installations/:id/access_tokens")
+            .post(access_token);
+        app.at("/installation/repositories").get(repositories);
+        app.at("/app/hook/deliveries")
+            .get(|_| async { json(serde_json::json!([])) });
+        app.at("/repos/:owner/:name").get(repository);
+        app.at("/repos/:owner/:name/installation").get(installation);
+        app.at("/repos/:owner/:name/collaborators/:user/permission")
+            .get(permission);
+        app.at("/repos/:owner/:name/compare/*range")
+            .get(|_| async { json(serde_json::json!({ "ahead_by": 0 })) });
+        app.at("/repos/:owner/:name/pulls")
+            .get(list_pulls)
+            .post(create_pull);
+        app.at("/repos/:owner/:name/pulls/:number")
+            .get(pull)
+            .patch(update_pull);
+        app.at("/repos/:owner/:name/pulls/:number/reviews")
+            .post(create_review);
+        app.at("/repos/:owner/:name/pulls/:number/requested_reviewers")
+            .post(request_reviewers);
+        app.at("/graphql").post(graphql);
+        app.at("/repos/:owner/:name/pulls/:number/comments/:id/replies")
+            .post(create_comment);
+        app.at("/repos/:owner/:name/pulls/:number/files")
+            .get(|_| async { json(serde_json::json!([])) });
+        app.at("/repos/:owner/:name/issues/:number").get(get_issue);
+        app.at("/repos/:owner/:name/issues/:number/comments")
+            .post(create_comment);
+        app.at("/repos/:owner/:name/issues/:number/labels")
+            .post(add_labels);
+        app.at("/repos/:owner/:name/issues/:number/labels/:label")
+            .delete(remove_label);
+        app.at("/repos/:owner/:name/issues/:number/assignees")
+            .post(add_assignees);
+        app.at("/repos/:owner/:name/statuses/:sha")
+            .post(create_status);
+        app.at("/repos/:owner/:name/git/matching-refs/heads/*prefix")
+            .get(matching_refs);
+        app.at("/repos/:owner/:name/git/refs/heads/*branch")
+            .delete(delete_branch);
+
+        let mut listener = app.bind("127.0.0.1:0").await?;
+        let url = listener
+            .info()
+            .first()
+            .and_then(|info| url::Url::parse(&format!("{}/", info.connection())).ok())
+            .ok_or_else(|| std::io::Error::other("Mock Github isn't listening"))?;
+        async_std::task::spawn(async move {
+            if let Err(err) = listener.accept().await {
+                tracing::warn!("Mock Github stopped: {err}");
+            }
+        });
+        Ok(MockGithub { url, state })
+    }
+
+    /// Base URL of the API, for `--github-api-base`
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    /// Serve the repository `owner/name`, cloned from `clone_url`. Returns it as Github would.
+    pub fn add_repository(&self, repository: &str, clone_url: &url::Url) -> serde_json::Value {
+        let (owner, name) = repository.split_once('/').unwrap_or(("", repository));
+        let mut state = self.lock();
+        let url = self.api_url(&format!("repos/{repository}"));
+        let value = serde_json::json!({
+            "id": state.repositories.len() + 1,
+            "name": name,
+            "full_name": repository,
+            "owner": user(owner, &self.api_url(&format!("users/{owner}"))),
+            "private": false,
+            "visibility": "public",
+            "url": url,
+            "html_url": url,
+            "clone_url": clone_url,
+            "default_branch": "master",
+            "topics": [],
+        });
+        state
+            .repositories
+            .insert(repository.to_string(), value.clone());
+        value
+    }
+
+    /// Serve pull request `number` of `repository` (`owner/name`), which has to be added first.
+    /// Its GraphQL ID is `PR_<number>`.
+    pub fn add_pull_request(
+        &self,
+        repository: &str,
+        number: u64,
+        head_ref: &str,
+        head_sha: &str,
+        base_ref: &str,
+        base_sha: &str,
+    ) {
+        let url = self.api_url(&format!("repos/{repository}/pulls/{number}"));
+        let value = serde_json::json!({
+            "url": url,
+            "id": number,
+            "node_id": format!("PR_{number}"),
+            "number": number,
+            "title": format!("Pull request #{number}"),
+            "user": user("author", &self.api_url("users/author")),
+            "labels": [],
+            "head": { "ref": head_ref, "sha": head_sha },
+            "base": { "ref": base_ref, "sha": base_sha },
+        });
+        self.lock()
+            .pulls
+            .insert((repository.to_string(), number), value);
+    }
+
+    /// Have pull request `number` of `repository` be opened by `login`, like `mock-bot[bot]` for
+    /// the app itself
+    pub fn set_pull_request_author(&self, repository: &str, number: u64, login: &str) {
+        let author = user(login, &self.api_url(&format!("users/{login}")));
+        if let Some(pull) = self.lock().pulls.get_mut(&(repository.to_string(), number)) {
+            pull["user"] = author;
+        }
+    }
+
+    /// Add the branch `branch` to `repository` (`owner/name`)
+    pub fn add_branch(&self, repository: &str, branch: &str) {
+        self.lock()
+            .branches
+            .entry(repository.to_string())
+            .or_default()
+            .push(branch.to_string());
+    }
+
+    /// Branches of `repository` that weren't deleted
+    pub fn branches(&self, repository: &str) -> Vec<String> {
+        self.lock()
+            .branches
+            .get(repository)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Answer the requests whose path starts with `prefix`, like `/repos/acme/widgets/`, with a
+    /// server error from now on
+    pub fn fail_requests(&self, prefix: &str) {
+        self.lock().failing.push(prefix.to_string());
+    }
+
+    /// Close the opened pull requests from `head` of `repository`, like when they're merged
+    pub fn close_pull_request(&self, repository: &str, head: &str) {
+        for pull in self.lock().pull_requests.iter_mut() {
+            if pull.repository == repository && pull.head == head {
+                pull.closed = true;
+            }
+        }
+    }
+
+    /// Give `user` a `permission` (`admin`, `write`, `read`, ...) on all repositories
+    pub fn set_permission(&self, user: &str, permission: &str) {
+        self.lock()
+            .permissions
+            .insert(user.to_string(), permission.to_string());
+    }
+
+    /// The payload of an `issue_comment` webhook for a comment of `user` on issue or pull request
+    /// `number` of `repository` (`owner/name`), which has to be added first. Added pull requests
+    /// are commented on as such.
+    pub fn issue_comment(
+        &self,
+        repository: &str,
+        number: u64,
+        user_login: &str,
+        body: &str,
+    ) -> serde_json::Value {
+        let state = self.lock();
+        let repo = state
+            .repositories
+            .get(repository)
+            .cloned()
+            .unwrap_or_default();
+        let url = self.api_url(&format!("repos/{repository}/issues/{number}"));
+        let sender = user(user_login, &self.api_url(&format!("users/{user_login}")));
+        let now = chrono::Utc::now();
+        serde_json::json!({
+            "action": "created",
+            "sender": sender,
+            "repository": repo,
+            "issue": issue(&state, &self.url, repository, number, &sender),
+            "comment": {
+                "id": 1,
+                "node_id": "",
+                "url": url,
+                "html_url": url,
+                "body": body,
+                "user": sender,
+                "created_at": now,
+            },
+        })
+    }
+
+    /// The payload of a `pull_request` webhook for `user` adding (`action` being `labeled`) or
+    /// removing (`unlabeled`) `label` on pull request `number` of `repository` (`owner/name`),
+    /// which has to be added first
+    pub fn pull_request_labeled(
+        &self,
+        repository: &str,
+        number: u64,
+        user_login: &str,
+        action: &str,
+        label: &str,
+    ) -> serde_json::Value {
+        let state = self.lock();
+        let repo = state
+            .repositories
+            .get(repository)
+            .cloned()
+            .unwrap_or_default();
+        let pull = state
+            .pulls
+            .get(&(repository.to_string(), number))
+            .cloned()
+            .unwrap_or_default();
+        serde_json::json!({
+            "action": action,
+            "number": number,
+            "label": { "name": label },
+            "pull_request": pull,
+            "repository": repo,
+            "sender": user(user_login, &self.api_url(&format!("users/{user_login}"))),
+        })
+    }
+
+    /// The payload of a `pull_request_review_comment` webhook for a review comment of `user` on
+    /// pull request `number` of `repository` (`owner/name`), which has to be added first. The
+    /// comment has the ID `id` and replies to the one with `in_reply_to`, if given.
+    pub fn pull_request_review_comment(
+        &self,
+        repository: &str,
+        number: u64,
+        user_login: &str,
+        body: &str,
+        id: u64,
+        in_reply_to: Option<u64>,
+    ) -> serde_json::Value {
+        let state = self.lock();
+        let repo = state
+            .repositories
+            .get(repository)
+            .cloned()
+            .unwrap_or_default();
+        let pull = state
+            .pulls
+            .get(&(repository.to_string(), number))
+            .cloned()
+            .unwrap_or_default();
+        let url = self.api_url(&format!("repos/{repository}/pulls/comments/{id}"));
+        let sender = user(user_login, &self.api_url(&format!("users/{user_login}")));
+        serde_json::json!({
+            "action": "created",
+            "comment": {
+                "id": id,
+                "url": url,
+                "html_url": url,
+                "body": body,
+                "user": sender,
+                "in_reply_to_id": in_reply_to,
+            },
+            "pull_request": pull,
+            "repository": repo,
+            "sender": sender,
+        })
+    }
+
+    /// Deliver the webhook `payload` of `event` (like `issue_comment`) to `webhook_url`, signed
+    /// with `secret` like Github does
+    pub async fn deliver(
+        &self,
+        webhook_url: &url::Url,
+        secret: &str,
+        event: &str,
+        payload: &serde_json::Value,
+    ) -> surf::Result<surf::StatusCode> {
+        let body = serde_json::to_vec(payload)?;
+        let response = surf::post(webhook_url)
+            .header("X-Github-Event", event)
+            .header("X-Hub-Signature-256", sign(secret, &body)?)
+            .content_type(surf::http::mime::JSON)
+            .body(body)
+            .await?;
+        Ok(response.status())
+    }
+
+    /// Comments posted so far, oldest first
+    pub fn comments(&self) -> Vec<Comment> {
+        self.lock().comments.clone()
+    }
+
+    /// Commit statuses set so far, oldest first
+    pub fn statuses(&self) -> Vec<Status> {
+        self.lock().statuses.clone()
+    }
+
+    /// Reviews of pull requests so far, oldest first
+    pub fn reviews(&self) -> Vec<Review> {
+        self.lock().reviews.clone()
+    }
+
+    /// GraphQL IDs of the pull requests auto-merge was enabled on so far and their merge
+    /// methods, like `("PR_7", "SQUASH")`
+    pub fn auto_merges(&self) -> Vec<(String, String)> {
+        self.lock().auto_merges.clone()
+    }
+
+    /// Labels of issue or pull request `number` of `repository`, in the order they were added
+    pub fn labels(&self, repository: &str, number: u64) -> Vec<String> {
+        Self::of(&self.lock().labels, repository, number)
+    }
+
+    /// Users assigned to issue or pull request `number` of `repository`
+    pub fn assignees(&self, repository: &str, number: u64) -> Vec<String> {
+        Self::of(&self.lock().assignees, repository, number)
+    }
+
+    /// Slugs of the teams asked to review pull request `number` of `repository`
+    pub fn team_reviewers(&self, repository: &str, number: u64) -> Vec<String> {
+        Self::of(&self.lock().team_reviewers, repository, number)
+    }
+
+    fn of(map: &HashMap<(String, u64), Vec<String>>, repository: &str, number: u64) -> Vec<String> {
+        map.get(&(repository.to_string(), number))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Pull requests opened so far, oldest first
+    pub fn pull_requests(&self) -> Vec<PullRequest> {
+        self.lock().pull_requests.clone()
+    }
+
+    fn api_url(&self, path: &str) -> url::Url {
+        self.url.join(path).unwrap_or_else(|_| self.url.clone())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // A panicking request handler doesn't leave the state half updated
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Issue `number` of `repository` as Github serves it, as a pull request if one was added as
+/// such
+fn issue(
+    state: &State,
+    base: &url::Url,
+    repository: &str,
+    number: u64,
+    author: &serde_json::Value,
+) -> serde_json::Value {
+    let api_url = |path: &str| base.join(path).unwrap_or_else(|_| base.clone());
+    let url = api_url(&format!("repos/{repository}/issues/{number}"));
+    let pull_request = state
+        .pulls
+        .contains_key(&(repository.to_string(), number))
+        .then(|| {
+            let url = api_url(&format!("repos/{repository}/pulls/{number}"));
+            serde_json::json!({
+                "url": url,
+                "html_url": url,
+                "diff_url": url,
+                "patch_url": url,
+            })
+        });
+    let now = chrono::Utc::now();
+    serde_json::json!({
+        "id": number,
+        "node_id": "",
+        "url": url,
+        "repository_url": api_url(&format!("repos/{repository}")),
+        "labels_url": url,
+        "comments_url": url,
+        "events_url": url,
+        "html_url": url,
+        "number": number,
+        "state": "open",
+        "title": format!("Issue #{number}"),
+        "user": author,
+        "labels": [],
+        "assignees": [],
+        "author_association": "NONE",
+        "locked": false,
+        "comments": 0,
+        "pull_request": pull_request,
+        "created_at": now,
+        "updated_at": now,
+    })
+}
+
+/// The `X-Hub-Signature-256` of a webhook delivery of `body`
+pub fn sign(secret: &str, body: &[u8]) -> Result<String, openssl::error::ErrorStack> {
+    crate::webhook::sign(secret, body)
+}
+
+/// Fails the requests set up to with [`MockGithub::fail_requests`]
+fn fail_requests<'a>(
+    req: tide::Request<Shared>,
+    next: tide::Next<'a, Shared>,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = tide::Result> + Send + 'a>> {
+    let path = req.url().path().to_string();
+    let fail = state(&req)
+        .failing
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()));
+    Box::pin(async move {
+        if fail {
+            return Ok(tide::Response::builder(500)
+                .content_type(tide::http::mime::JSON)
+                .body(serde_json::json!({ "message": "Server Error" }))
+                .build());
+        }
+        Ok(next.run(req).await)
+    })
+}
+
+fn json(value: serde_json::Value) -> tide::Result {
+    Ok(tide::Response::builder(200)
+        .content_type(tide::http::mime::JSON)
+        .body(value)
+        .build())
+}
+
+fn not_found() -> tide::Result {
+    Ok(tide::Response::builder(404)
+        .content_type(tide::http::mime::JSON)
+        .body(serde_json::json!({ "message": "Not Found" }))
+        .build())
+}
+
+fn state(req: &tide::Request<Shared>) -> std::sync::MutexGuard<'_, State> {
+    req.state()
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// `owner/name` of the repository in the path
+fn full_name(req: &tide::Request<Shared>) -> tide::Result<String> {
+    Ok(format!("{}/{}", req.param("owner")?, req.param("name")?))
+}
+
+fn number(req: &tide::Request<Shared>) -> tide::Result<u64> {
+    req.param("number")?
+        .parse()
+        .map_err(|err| tide::Error::new(400, err))
+}
+
+async fn installations(req: tide::Request<Shared>) -> tide::Result {
+    json(serde_json::json!([installation_json(&req)?]))
+}
+
+async fn installation(req: tide::Request<Shared>) -> tide::Result {
+    if !state(&req).repositories.contains_key(&full_name(&req)?) {
+        return not_found();
+    }
+    json(installation_json(&req)?)
+}
+
+fn installation_json(req: &tide::Request<Shared>) -> tide::Result<serde_json::Value> {
+    let base = req.url().join("/")?;
+    Ok(serde_json::json!({
+        "id": 1,
+        "account": user("mock", &base),
+        "access_tokens_url": base.join("app/installations/1/access_tokens")?,
+        "permissions": {},
+        "events": [],
+    }))
+}
+
+async fn app_json(req: tide::Request<Shared>) -> tide::Result {
+    let base = req.url().join("/")?;
+    json(serde_json::json!({
+        "id": 1,
+        "slug": APP_SLUG,
+        "node_id": "",
+        "owner": user("mock", &base),
+        "name": "Mock bot",
+        "external_url": base,
+        "html_url": base,
+        "permissions": {},
+        "events": [],
+    }))
+}
+
+async fn access_token(_req: tide::Request<Shared>) -> tide::Result {
+    json(serde_json::json!({
+        "token": "mock-installation-token",
+        "permissions": {},
+    }))
+}
+
+/// All repositories, as if the app were installed on all of them
+async fn repositories(req: tide::Request<Shared>) -> tide::Result {
+    let repositories: Vec<_> = state(&req).repositories.values().cloned().collect();
+    json(serde_json::json!({
+        "total_count": repositories.len(),
+        "repositories": repositories,
+    }))
+}
+
+async fn repository(req: tide::Request<Shared>) -> tide::Result {
+    match state(&req).repositories.get(&full_name(&req)?) {
+        Some(repository) => json(repository.clone()),
+        None => not_found(),
+    }
+}
+
+async fn permission(req: tide::Request<Shared>) -> tide::Result {
+    let permission = state(&req)
+        .permissions
+        .get(req.param("user")?)
+        .cloned()
+        .unwrap_or_else(|| "write".to_string());
+    json(serde_json::json!({ "permission": permission }))
+}
+
+async fn pull(req: tide::Request<Shared>) -> tide::Result {
+    let key = (full_name(&req)?, number(&req)?);
+    match state(&req).pulls.get(&key) {
+        Some(pull) => json(pull.clone()),
+        None => not_found(),
+    }
+}
+
+async fn get_issue(req: tide::Request<Shared>) -> tide::Result {
+    let (repository, number) = (full_name(&req)?, number(&req)?);
+    let state = state(&req);
+    if !state.repositories.contains_key(&repository) {
+        return not_found();
+    }
+    let base = req.url().join("/").unwrap_or_else(|_| req.url().clone());
+    let author = user("author", &base);
+    json(issue(&state, &base, &repository, number, &author))
+}
+
+async fn create_pull(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Create {
+        title: String,
+        body: Option<String>,
+        head: String,
+        base: String,
+    }
+
+    let create: Create = req.body_json().await?;
+    let pull = PullRequest {
+        repository: full_name(&req)?,
+        title: create.title,
+        body: create.body,
+        head: create.head,
+        base: create.base,
+        closed: false,
+    };
+    let number = {
+        let mut state = state(&req);
+        state.pull_requests.push(pull.clone());
+        OPENED_PULLS + state.pull_requests.len() as u64
+    };
+    json(opened_pull(req.url(), number, &pull))
+}
+
+/// Opened pull requests are numbered from after this, so they don't clash with added ones
+const OPENED_PULLS: u64 = 1000;
+
+fn opened_pull(url: &url::Url, number: u64, pull: &PullRequest) -> serde_json::Value {
+    serde_json::json!({
+        "url": url,
+        "id": number,
+        "number": number,
+        "title": pull.title,
+        "body": pull.body,
+        "head": { "ref": pull.head, "sha": "" },
+        "base": { "ref": pull.base, "sha": "" },
+        "state": if pull.closed { "closed" } else { "open" },
+    })
+}
+
+/// The opened pull requests filtered by `head` (`owner:branch`), `base` and `state` (`open` if
+/// not given, `closed` or `all`)
+async fn list_pulls(req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Query {
+        head: Option<String>,
+        base: Option<String>,
+        state: Option<String>,
+    }
+
+    let query: Query = req.query()?;
+    let head = query
+        .head
+        .as_deref()
+        .map(|head| head.split_once(':').map_or(head, |(_, branch)| branch));
+    let repository = full_name(&req)?;
+    let pulls: Vec<_> = state(&req)
+        .pull_requests
+        .iter()
+        .zip(OPENED_PULLS + 1..)
+        .filter(|(pull, _)| pull.repository == repository)
+        .filter(|(pull, _)| head.is_none_or(|head| pull.head == head))
+        .filter(|(pull, _)| query.base.as_ref().is_none_or(|base| &pull.base == base))
+        .filter(|(pull, _)| match query.state.as_deref() {
+            Some("all") => true,
+            Some("closed") => pull.closed,
+            _ => !pull.closed,
+        })
+        .map(|(pull, number)| opened_pull(req.url(), number, pull))
+        .collect();
+    json(serde_json::Value::Array(pulls))
+}
+
+async fn update_pull(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Update {
+        title: Option<String>,
+        body: Option<String>,
+    }
+
+    let update: Update = req.body_json().await?;
+    let number = number(&req)?;
+    let mut state = state(&req);
+    let pull = match number
+        .checked_sub(OPENED_PULLS + 1)
+        .and_then(|index| state.pull_requests.get_mut(index as usize))
+    {
+        Some(pull) => pull,
+        None => return not_found(),
+    };
+    if let Some(title) = update.title {
+        pull.title = title;
+    }
+    if update.body.is_some() {
+        pull.body = update.body;
+    }
+    json(opened_pull(req.url(), number, pull))
+}
+
+async fn create_comment(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Create {
+        body: String,
+    }
+
+    let create: Create = req.body_json().await?;
+    let repository = full_name(&req)?;
+    let issue = number(&req)?;
+    let review_thread = req.param("id").ok().and_then(|id| id.parse().ok());
+    let id = {
+        let mut state = state(&req);
+        state.comments.push(Comment {
+            repository,
+            issue,
+            body: create.body.clone(),
+            review_thread,
+        });
+        state.comments.len()
+    };
+    let url = req.url().join(&format!("comments/{id}"))?;
+    json(serde_json::json!({
+        "id": id,
+        "node_id": "",
+        "url": url,
+        "html_url": url,
+        "body": create.body,
+        "user": user("mock[bot]", &url),
+        "created_at": chrono::Utc::now(),
+    }))
+}
+
+async fn create_status(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Create {
+        state: String,
+        context: Option<String>,
+        description: Option<String>,
+        target_url: Option<String>,
+    }
+
+    let create: Create = req.body_json().await?;
+    let status = Status {
+        repository: full_name(&req)?,
+        sha: req.param("sha")?.to_string(),
+        state: create.state.clone(),
+        context: create.context,
+        description: create.description,
+        target_url: create.target_url,
+    };
+    state(&req).statuses.push(status);
+    json(serde_json::json!({ "state": create.state }))
+}
+
+async fn create_review(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Create {
+        event: String,
+        body: Option<String>,
+    }
+
+    let create: Create = req.body_json().await?;
+    let review = Review {
+        repository: full_name(&req)?,
+        number: number(&req)?,
+        event: create.event.clone(),
+        body: create.body,
+    };
+    state(&req).reviews.push(review);
+    json(serde_json::json!({ "id": 1, "state": create.event }))
+}
+
+async fn request_reviewers(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Request {
+        #[serde(default)]
+        team_reviewers: Vec<String>,
+    }
+
+    let request: Request = req.body_json().await?;
+    let key = (full_name(&req)?, number(&req)?);
+    let mut state = state(&req);
+    let pull = match state.pulls.get(&key) {
+        Some(pull) => pull.clone(),
+        None => return not_found(),
+    };
+    add_new(
+        state.team_reviewers.entry(key).or_default(),
+        request.team_reviewers,
+    );
+    json(pull)
+}
+
+async fn add_labels(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Add {
+        labels: Vec<String>,
+    }
+
+    let add: Add = req.body_json().await?;
+    let key = (full_name(&req)?, number(&req)?);
+    let mut state = state(&req);
+    let labels = state.labels.entry(key).or_default();
+    add_new(labels, add.labels);
+    json(label_json(labels))
+}
+
+async fn remove_label(req: tide::Request<Shared>) -> tide::Result {
+    let key = (full_name(&req)?, number(&req)?);
+    let label = decode(req.param("label")?);
+    let mut state = state(&req);
+    let labels = state.labels.entry(key).or_default();
+    match labels.iter().position(|name| *name == label) {
+        Some(index) => {
+            labels.remove(index);
+            json(label_json(labels))
+        }
+        None => not_found(),
+    }
+}
+
+async fn add_assignees(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Add {
+        assignees: Vec<String>,
+    }
+
+    let add: Add = req.body_json().await?;
+    let (repository, number) = (full_name(&req)?, number(&req)?);
+    let mut state = state(&req);
+    add_new(
+        state
+            .assignees
+            .entry((repository.clone(), number))
+            .or_default(),
+        add.assignees,
+    );
+    let base = req.url().join("/").unwrap_or_else(|_| req.url().clone());
+    let author = user("author", &base);
+    json(issue(&state, &base, &repository, number, &author))
+}
+
+/// Append the `new` values that aren't in `values` yet
+fn add_new(values: &mut Vec<String>, new: Vec<String>) {
+    for value in new {
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+}
+
+/// The path segment `segment` with its escapes undone, as tide leaves them. `+` is only a space
+/// in queries, but escaped as `%2B` by the clients anyway.
+fn decode(segment: &str) -> String {
+    url::form_urlencoded::parse(segment.as_bytes())
+        .map(|(key, _)| key.into_owned())
+        .next()
+        .unwrap_or_default()
+}
+
+/// Labels as Github lists them
+fn label_json(labels: &[String]) -> serde_json::Value {
+    labels
+        .iter()
+        .map(|name| serde_json::json!({ "name": name }))
+        .collect()
+}
+
+/// Only knows the mutation enabling auto-merge
+async fn graphql(mut req: tide::Request<Shared>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Query {
+        query: String,
+        #[serde(default)]
+        variables: HashMap<String, serde_json::Value>,
+    }
+
+    let query: Query = req.body_json().await?;
+    if !query.query.contains("enablePullRequestAutoMerge") {
+        return json(serde_json::json!({ "errors": [{ "message": "Unknown query" }] }));
+    }
+    let variable = |name: &str| {
+        query
+            .variables
+            .get(name)
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let (id, method) = (variable("id"), variable("method"));
+    let mut state = state(&req);
+    if !state
+        .pulls
+        .values()
+        .any(|pull| pull["node_id"].as_str() == Some(id.as_str()))
+    {
+        let message = format!("Could not resolve to a node with the global id of '{id}'");
+        return json(serde_json::json!({ "errors": [{ "message": message }] }));
+    }
+    state.auto_merges.push((id, method));
+    json(serde_json::json!({
+        "data": { "enablePullRequestAutoMerge": { "clientMutationId": null } }
+    }))
+}
+
+async fn matching_refs(req: tide::Request<Shared>) -> tide::Result {
+    let prefix = req.param("prefix")?;
+    let refs: Vec<_> = state(&req)
+        .branches
+        .get(&full_name(&req)?)
+        .into_iter()
+        .flatten()
+        .filter(|branch| branch.starts_with(prefix))
+        .map(|branch| {
+            serde_json::json!({
+                "ref": format!("refs/heads/{branch}"),
+                "object": { "sha": "", "type": "commit" },
+            })
+        })
+        .collect();
+    json(serde_json::Value::Array(refs))
+}
+
+async fn delete_branch(req: tide::Request<Shared>) -> tide::Result {
+    let branch = req.param("branch")?.to_string();
+    let mut state = state(&req);
+    let branches = state.branches.entry(full_name(&req)?).or_default();
+    match branches.iter().position(|known| *known == branch) {
+        Some(index) => {
+            branches.remove(index);
+            Ok(tide::Response::builder(204).build())
+        }
+        None => not_found(),
+    }
+}
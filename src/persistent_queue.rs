@@ -0,0 +1,154 @@
+use crate::journal::{self, Journal};
+use crate::{Fair, LocalQueue, Queue, Routed};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A `LocalQueue` that writes through to a `Journal`, so its items survive a restart.
+///
+/// Removing an item doesn't remove it from the journal, that only happens once it's marked as
+/// started with `Journal::start`. Items handed out but never started are queued again after a
+/// restart.
+pub struct PersistentQueue<Item> {
+    queue: LocalQueue<String, String, Item>,
+    journal: Journal,
+}
+
+impl<Item> PersistentQueue<Item>
+where
+    Item: Serialize + DeserializeOwned + Routed + Send + 'static,
+{
+    /// Create the queue with the items that were still queued in `journal`
+    pub fn restore(journal: Journal) -> Result<Self, journal::Error> {
+        let mut queue = LocalQueue::new();
+        if journal.paused_since()?.is_some() {
+            queue.pause();
+        }
+        for (id, key, item) in journal.queued()? {
+            queue.add(id, key, item);
+        }
+        Ok(PersistentQueue { queue, journal })
+    }
+
+    /// See `LocalQueue::watch`
+    pub fn watch(&mut self) -> async_std::channel::Receiver<Item> {
+        self.queue.watch()
+    }
+
+    /// See `LocalQueue::watch_routed`
+    pub fn watch_routed(&mut self, route: Option<String>) -> async_std::channel::Receiver<Item> {
+        self.queue.watch_routed(route)
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Take turns between the groups of items, see `Fair`. The journal keeps the order they were
+    /// added in, so they're reordered the same way after a restart.
+    pub fn set_fair(&mut self, fair: Fair<Item>) {
+        self.queue.set_fair(fair);
+    }
+
+    /// Journal `item` without queuing it yet, like a job that is retried after a while. It's
+    /// queued once it's passed to `undefer`, or right away after a restart.
+    pub fn defer(&self, id: &str, key: &str, item: &Item) -> Result<(), journal::Error> {
+        self.journal.enqueue(id, key, item)
+    }
+
+    /// Queue an item passed to `defer` before, unless it's no longer in the journal (like when a
+    /// job with the same key superseded it). Returns whether it was queued.
+    pub fn undefer(&mut self, id: String, key: String, item: Item) -> Result<bool, journal::Error> {
+        if !self.journal.is_queued(&id)? {
+            return Ok(false);
+        }
+        self.queue.add(id, key, item);
+        Ok(true)
+    }
+
+    /// Replace the queued items with the ones in the journal, like after another process using
+    /// the same journal took some of them or paused it
+    pub fn reload(&mut self) -> Result<(), journal::Error> {
+        let queued = self.journal.queued()?;
+        let paused = self.journal.paused_since()?.is_some();
+        self.queue.clear();
+        self.queue.pause();
+        for (id, key, item) in queued {
+            self.queue.add(id, key, item);
+        }
+        if !paused {
+            self.queue.resume();
+        }
+        Ok(())
+    }
+
+    /// Keep accepting items, but don't hand any out until `resume`, also after a restart
+    pub fn pause(&mut self) -> Result<(), journal::Error> {
+        self.journal.pause()?;
+        self.queue.pause();
+        Ok(())
+    }
+
+    /// Hand out items again, first to the ones waiting for them
+    pub fn resume(&mut self) -> Result<(), journal::Error> {
+        self.journal.resume()?;
+        self.queue.resume();
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.queue.is_paused()
+    }
+}
+
+impl<Item> Queue for PersistentQueue<Item>
+where
+    Item: Serialize + DeserializeOwned + Routed + Send + 'static,
+{
+    type Err = journal::Error;
+    type Id = String;
+    type Key = String;
+    type Item = Item;
+
+    fn add(&mut self, id: Self::Id, key: Self::Key, item: Self::Item) {
+        if let Err(err) = self.journal.enqueue(&id, &key, &item) {
+            tracing::warn!("Failed to journal queued item {id}: {err}");
+        }
+        self.queue.add(id, key, item);
+    }
+
+    fn supersede(&mut self, id: Self::Id, key: Self::Key, item: Self::Item) -> Option<Self::Item> {
+        if let Err(err) = self.journal.dequeue_key(&key) {
+            tracing::warn!("Failed to remove superseded items from the journal: {err}");
+        }
+        if let Err(err) = self.journal.enqueue(&id, &key, &item) {
+            tracing::warn!("Failed to journal queued item {id}: {err}");
+        }
+        self.queue.supersede(id, key, item)
+    }
+
+    fn remove_routed(&mut self, route: Option<&str>) -> Option<Self::Item> {
+        self.queue.remove_routed(route)
+    }
+
+    fn cancel(&mut self, id: &Self::Id) -> Option<Self::Item> {
+        if let Err(err) = self.journal.dequeue(id) {
+            tracing::warn!("Failed to remove cancelled item {id} from the journal: {err}");
+        }
+        self.queue.cancel(id)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn pos(&self, id: Self::Id) -> Option<usize> {
+        self.queue.pos(id)
+    }
+
+    fn pos_by_key(&self, key: &Self::Key) -> Option<usize> {
+        self.queue.pos_by_key(key)
+    }
+
+    fn items(&self) -> Vec<&Self::Item> {
+        self.queue.items()
+    }
+}
@@ -0,0 +1,257 @@
+//! Declarative alternative to rhai scripts for repositories that just need to run some cargo
+//! commands and report on them:
+//!
+//! ```yaml
+//! steps:
+//!   - name: build
+//!     cargo: build --release
+//!     record: build-time
+//!   - name: bench
+//!     cargo: bench -p my-crate
+//! checks:
+//!   - metric: build-time
+//!     max: 600
+//! report:
+//!   - title: Benchmarks
+//!     step: bench
+//!   - title: Notes
+//!     text: Numbers are from a shared runner, expect some noise.
+//! ```
+//!
+//! Pipelines that need the history of the repository can set `full_history: true`. With
+//! `warm_up: true` (or `--warm-up`, unless the pipeline sets `warm_up: false`), every step is run
+//! once before the run that is measured, so cold caches don't skew the first sample.
+//!
+//! Every step is timed as a phase, `record` additionally records its wall time (in seconds) as a
+//! metric. A failing step stops the pipeline unless it has `allow_failure: true`.
+//!
+//! Steps with a `suite` only run if that suite is selected, see [`crate::suites`].
+//!
+//! Pipelines can hand over to rhai scripts for what they can't do, like reporting on the results
+//! in their own way. The `scripts` (relative to the pipeline) are run after the steps, one after
+//! the other, as if the command had listed them with `&&`:
+//!
+//! ```yaml
+//! steps:
+//!   - name: bench
+//!     cargo: bench -p my-crate
+//! scripts:
+//!   - compare.rhai
+//!   - report.rhai
+//! ```
+
+use crate::api;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read pipeline: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Failed to parse pipeline: {0}")]
+    Parse(#[from] serde_yaml::Error),
+    #[error("Failed to parse the cargo arguments of step {0}")]
+    CargoArgs(String),
+    #[error("Step {name} failed:\n{stderr}")]
+    StepFailed { name: String, stderr: String },
+    #[error("Report section {title} refers to unknown step {step}")]
+    UnknownStep { title: String, step: String },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    /// Fetch the full history first, in case the repository was cloned shallowly
+    #[serde(default)]
+    pub full_history: bool,
+    /// Run every step once before measuring it, instead of doing whatever the runner is
+    /// configured to do
+    pub warm_up: Option<bool>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub checks: Vec<Check>,
+    #[serde(default)]
+    pub report: Vec<Section>,
+    /// Scripts to run after the steps, relative to the pipeline
+    #[serde(default)]
+    pub scripts: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub name: String,
+    /// Arguments to cargo, split like a shell would
+    pub cargo: String,
+    #[serde(default)]
+    pub allow_failure: bool,
+    /// Name of the metric to record the wall time of this step as
+    pub record: Option<String>,
+    /// Benchmark suite in `.benchbot.toml` the step belongs to
+    pub suite: Option<String>,
+}
+
+/// Upper bound on a metric recorded by the pipeline
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Check {
+    pub metric: String,
+    pub max: f64,
+}
+
+/// Section of the result comment, with either the output of a step or some fixed text
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Section {
+    pub title: String,
+    pub step: Option<String>,
+    #[serde(default)]
+    pub stream: Stream,
+    pub text: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    #[default]
+    Stdout,
+    Stderr,
+}
+
+impl Pipeline {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(serde_yaml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Run the steps in `dir` and return the markdown sections to report. `warm_up` applies
+    /// unless the pipeline says otherwise. Steps of suites other than `suites` are skipped, if
+    /// given.
+    #[allow(clippy::too_many_arguments)]
+    pub fn run<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        results: &api::results::Results,
+        phases: &api::phases::Phases,
+        cargo_env: &[(String, String)],
+        cargo_output: &api::cargo::Output,
+        pinning: &api::pinning::Pinning,
+        sccache: &api::cargo::sccache::Sccache,
+        accounting: &api::usage::Accounting,
+        quota: &api::quota::Quota,
+        warm_up: bool,
+        suites: Option<&[String]>,
+    ) -> Result<Vec<String>, Error> {
+        let warm_up = self.warm_up.unwrap_or(warm_up);
+        let mut results = results.clone();
+        let mut outputs = HashMap::new();
+        let mut recorded = HashMap::new();
+        let mut skipped = vec![];
+        for step in &self.steps {
+            if let (Some(suite), Some(suites)) = (&step.suite, suites) {
+                if !suites.contains(suite) {
+                    tracing::info!("Skipping step {} of unaffected suite {suite}", step.name);
+                    skipped.push(step.name.as_str());
+                    continue;
+                }
+            }
+            let args =
+                shell_words::split(&step.cargo).map_err(|_| Error::CargoArgs(step.name.clone()))?;
+            if warm_up {
+                let mut result = phases.warm_up(&step.name, || {
+                    api::cargo::Run::new(args.clone(), dir.as_ref())
+                        .envs(cargo_env)
+                        .output(cargo_output)
+                        .pinning(pinning)
+                        .sccache(sccache)
+                        .accounting(accounting)
+                        .quota(quota)
+                        .run()
+                });
+                if !result.is_ok() {
+                    tracing::warn!("Warm-up of step {} failed:\n{}", step.name, result.stderr);
+                }
+            }
+            let start = std::time::Instant::now();
+            let mut result = phases.time(&step.name, || {
+                api::cargo::Run::new(args, dir.as_ref())
+                    .envs(cargo_env)
+                    .output(cargo_output)
+                    .pinning(pinning)
+                    .sccache(sccache)
+                    .accounting(accounting)
+                    .quota(quota)
+                    .run()
+            });
+            let elapsed = start.elapsed().as_secs_f64();
+            if let Some(metric) = &step.record {
+                // Recording only fails on a poisoned lock, which we can't do anything about
+                let _ = results.record(metric.as_str(), elapsed, "s");
+                recorded.insert(metric.as_str(), elapsed);
+            }
+            if !result.is_ok() && !step.allow_failure {
+                return Err(Error::StepFailed {
+                    name: step.name.clone(),
+                    stderr: result.stderr,
+                });
+            }
+            outputs.insert(step.name.as_str(), result);
+        }
+
+        let mut sections = vec![];
+        if !self.checks.is_empty() {
+            let mut table =
+                String::from("### Checks\n\n| Metric | Value | Max | |\n|---|---:|---:|---|\n");
+            for check in &self.checks {
+                let (value, passed) = match recorded.get(check.metric.as_str()) {
+                    Some(value) => (format!("{value:.2}"), *value <= check.max),
+                    None => ("-".to_string(), false),
+                };
+                let mark = if passed { ":white_check_mark:" } else { ":x:" };
+                table.push_str(&format!(
+                    "| {} | {value} | {} | {mark} |\n",
+                    check.metric, check.max
+                ));
+            }
+            sections.push(table);
+        }
+        for section in &self.report {
+            let mut body = format!("### {}\n\n", section.title);
+            if let Some(text) = &section.text {
+                body.push_str(text);
+                body.push('\n');
+            }
+            if let Some(step) = &section.step {
+                if skipped.contains(&step.as_str()) {
+                    continue;
+                }
+                let output = outputs
+                    .get(step.as_str())
+                    .ok_or_else(|| Error::UnknownStep {
+                        title: section.title.clone(),
+                        step: step.clone(),
+                    })?;
+                let output = match section.stream {
+                    Stream::Stdout => &output.stdout,
+                    Stream::Stderr => &output.stderr,
+                };
+                body.push_str(&format!("```\n{}\n```\n", output.trim_end()));
+            }
+            sections.push(body);
+        }
+        if !skipped.is_empty() {
+            sections.push(format!(
+                "<sub>Skipped steps of suites the changes don't affect: {}</sub>",
+                skipped.join(", ")
+            ));
+        }
+        if warm_up {
+            sections.push(
+                "<sub>Every step was run once to warm up before it was measured.</sub>".to_string(),
+            );
+        }
+        Ok(sections)
+    }
+}
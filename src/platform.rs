@@ -0,0 +1,132 @@
+//! What differs between the operating systems runners run on. Linux runners have every feature,
+//! macOS ones all but those only Linux has (cgroups, pinning to cores), and Windows ones run jobs
+//! without what Unix brings: process groups are job-less console groups, killed through
+//! `taskkill`, and what cargo used isn't accounted.
+
+use std::process::{Command, ExitStatus};
+use std::time::Duration;
+
+/// Name of the operating system, as `--runner-os` takes it, like `linux`, `macos` or `windows`
+pub fn os() -> &'static str {
+    std::env::consts::OS
+}
+
+/// Host name of this node, or an empty string if it can't be determined
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Host name of this node, or an empty string if it can't be determined
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// Make `command` start a process group of its own, to kill with [`kill_process_group`]
+#[cfg(unix)]
+pub(crate) fn own_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0);
+}
+
+/// Make `command` start a process group of its own, to kill with [`kill_process_group`]
+#[cfg(windows)]
+pub(crate) fn own_process_group(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+}
+
+/// Kill the process `pid` started with [`own_process_group`], and everything it started
+#[cfg(unix)]
+pub(crate) fn kill_process_group(pid: u32) {
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Kill the process `pid` started with [`own_process_group`], and everything it started
+#[cfg(windows)]
+pub(crate) fn kill_process_group(pid: u32) {
+    let killed = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+    if let Err(err) = killed {
+        tracing::warn!("Failed to kill process {pid}: {err}");
+    }
+}
+
+/// Signal the process was killed by, if it was
+#[cfg(unix)]
+pub(crate) fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+/// Signal the process was killed by, if it was
+#[cfg(not(unix))]
+pub(crate) fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Space the file of `metadata` takes up on disk, in bytes
+#[cfg(unix)]
+pub(crate) fn size_on_disk(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.blocks() * 512
+}
+
+/// Space the file of `metadata` takes up on disk, in bytes
+#[cfg(not(unix))]
+pub(crate) fn size_on_disk(metadata: &std::fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Whether this process runs as root, and may do what only root may
+#[cfg(unix)]
+pub(crate) fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Whether this process runs as root, and may do what only root may
+#[cfg(not(unix))]
+pub(crate) fn is_root() -> bool {
+    false
+}
+
+/// User and system time of this process and its waited-for children
+#[cfg(unix)]
+pub(crate) fn cpu_time() -> Duration {
+    fn usage(who: libc::c_int) -> Duration {
+        let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+        // SAFETY: `usage` is a valid pointer to a `rusage` for `getrusage` to fill in
+        if unsafe { libc::getrusage(who, usage.as_mut_ptr()) } != 0 {
+            return Duration::ZERO;
+        }
+        // SAFETY: `getrusage` succeeded so `usage` is initialized
+        let usage = unsafe { usage.assume_init() };
+        let timeval = |tv: libc::timeval| {
+            Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+        };
+        timeval(usage.ru_utime) + timeval(usage.ru_stime)
+    }
+    usage(libc::RUSAGE_SELF) + usage(libc::RUSAGE_CHILDREN)
+}
+
+/// User and system time of this process and its waited-for children, unknown here
+#[cfg(not(unix))]
+pub(crate) fn cpu_time() -> Duration {
+    Duration::ZERO
+}
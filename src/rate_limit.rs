@@ -0,0 +1,124 @@
+//! Keeping a single user from flooding the queue with commands.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid rate {0:?}, expected <count>/<duration> like 10/1h")]
+    InvalidRate(String),
+}
+
+/// At most `max` hits per `per`, given like `10/1h` (units `s`, `m`, `h` and `d`)
+#[derive(Clone, Copy, Debug)]
+pub struct Rate {
+    pub max: usize,
+    pub per: Duration,
+}
+
+impl std::str::FromStr for Rate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidRate(s.to_string());
+        let (max, per) = s.split_once('/').ok_or_else(invalid)?;
+        let max = max.trim().parse().map_err(|_| invalid())?;
+        let per = per.trim();
+        let split = per.find(|c: char| !c.is_ascii_digit()).unwrap_or(per.len());
+        let (count, unit) = per.split_at(split);
+        // `10/h` is `10/1h`
+        let count: u64 = if count.is_empty() {
+            1
+        } else {
+            count.parse().map_err(|_| invalid())?
+        };
+        if count == 0 {
+            return Err(invalid());
+        }
+        let unit = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        Ok(Rate {
+            max,
+            per: Duration::from_secs(count * unit),
+        })
+    }
+}
+
+impl std::fmt::Display for Rate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.per.as_secs();
+        match secs {
+            _ if secs.is_multiple_of(24 * 60 * 60) => {
+                write!(f, "{}/{}d", self.max, secs / (24 * 60 * 60))
+            }
+            _ if secs.is_multiple_of(60 * 60) => write!(f, "{}/{}h", self.max, secs / (60 * 60)),
+            _ if secs.is_multiple_of(60) => write!(f, "{}/{}m", self.max, secs / 60),
+            _ => write!(f, "{}/{secs}s", self.max),
+        }
+    }
+}
+
+/// Counts hits by key over a sliding window. Clones share the counts and the rate.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    /// Unlimited if not given
+    rate: Arc<RwLock<Option<Rate>>>,
+    hits: Arc<Mutex<HashMap<String, VecDeque<Instant>>>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: Option<Rate>) -> Self {
+        RateLimiter {
+            rate: Arc::new(RwLock::new(rate)),
+            hits: Default::default(),
+        }
+    }
+
+    pub fn rate(&self) -> Option<Rate> {
+        self.rate.read().ok().and_then(|rate| *rate)
+    }
+
+    /// Limit to `rate` from now on, counting the hits so far
+    pub fn set_rate(&self, rate: Option<Rate>) {
+        if let Ok(mut current) = self.rate.write() {
+            *current = rate;
+        }
+    }
+
+    /// Count a hit of `key`, unless that exceeds the rate. Returns whether it was allowed.
+    pub fn try_hit(&self, key: &str) -> bool {
+        let rate = match self.rate() {
+            Some(rate) => rate,
+            None => return true,
+        };
+        let mut hits = match self.hits.lock() {
+            Ok(hits) => hits,
+            // Better to let everything through than nothing
+            Err(_) => return true,
+        };
+        let now = Instant::now();
+        // Forget about what's out of the window, for all keys so the map doesn't keep growing
+        hits.retain(|_, times| {
+            while times
+                .front()
+                .is_some_and(|time| now.duration_since(*time) >= rate.per)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        let times = hits.entry(key.to_string()).or_default();
+        if times.len() >= rate.max {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
@@ -0,0 +1,760 @@
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Results database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Failed to gain exclusive lock on the results database")]
+    ExclusiveLock,
+    #[error("Failed to read a cached result: {0}")]
+    Cache(#[from] serde_json::Error),
+    #[error("`{id}` could be any of {count} runs, give more of the run ID")]
+    AmbiguousRun { id: String, count: usize },
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS results (
+    id INTEGER PRIMARY KEY,
+    run_id TEXT NOT NULL,
+    repo TEXT NOT NULL,
+    branch TEXT NOT NULL,
+    name TEXT NOT NULL,
+    value REAL NOT NULL,
+    unit TEXT NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    commit_sha TEXT
+);
+CREATE INDEX IF NOT EXISTS results_history ON results (repo, branch, name, recorded_at);
+CREATE TABLE IF NOT EXISTS outputs (
+    run_id TEXT NOT NULL,
+    repo TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    PRIMARY KEY (run_id, key)
+);
+CREATE TABLE IF NOT EXISTS cache (
+    repo TEXT NOT NULL,
+    commit_sha TEXT NOT NULL,
+    script TEXT NOT NULL,
+    script_hash TEXT NOT NULL,
+    args TEXT NOT NULL,
+    runner TEXT NOT NULL,
+    runner_os TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    result TEXT NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    PRIMARY KEY (repo, commit_sha, script, script_hash, args, runner, runner_os)
+);
+CREATE TABLE IF NOT EXISTS baselines (
+    repo TEXT NOT NULL,
+    name TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    commit_sha TEXT,
+    saved_at INTEGER NOT NULL,
+    PRIMARY KEY (repo, name)
+);
+";
+
+/// Prefix of the metrics tracking the size of a file, like `size/polkadot`, see `binary_size`
+pub const SIZE_PREFIX: &str = "size/";
+
+/// A named measurement recorded by a script through `RESULTS.record(name, value, unit)`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+}
+
+/// When to consider a metric a regression compared to the history of a branch.
+///
+/// Metrics are assumed to be "lower is better" (durations, sizes, weights), so only increases
+/// are flagged.
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    /// Number of most recent runs on the target branch to compare against
+    pub window: usize,
+    /// Minimum number of historical samples required before flagging anything
+    pub min_samples: usize,
+    /// Number of standard deviations above the historical mean a value needs to be
+    pub threshold: f64,
+    /// Percent a tracked size needs to have grown by since the latest run. Sizes only change
+    /// with the code, so they're compared to that run instead of the spread of the history.
+    pub size_threshold: f64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            window: 20,
+            min_samples: 5,
+            threshold: 3.0,
+            size_threshold: 1.0,
+        }
+    }
+}
+
+/// When the results of a branch are too out of date to compare against
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RefreshPolicy {
+    /// Maximum number of commits the branch may have advanced since its latest results
+    pub max_commits: Option<u64>,
+    /// Maximum age of the latest results
+    pub max_age: Option<Duration>,
+}
+
+impl RefreshPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_commits.is_some() || self.max_age.is_some()
+    }
+
+    /// Whether `baseline` needs to be refreshed, given the number of commits the branch advanced
+    /// since (if known)
+    pub fn is_stale(&self, baseline: &Baseline, commits_behind: Option<u64>) -> bool {
+        let too_old = self
+            .max_age
+            .is_some_and(|max_age| baseline.recorded_at.elapsed().unwrap_or_default() > max_age);
+        let too_far = matches!(
+            (self.max_commits, commits_behind),
+            (Some(max_commits), Some(behind)) if behind > max_commits
+        );
+        too_old || too_far
+    }
+}
+
+/// The most recent results recorded on a branch
+#[derive(Clone, Debug)]
+pub struct Baseline {
+    pub commit: Option<String>,
+    pub recorded_at: SystemTime,
+}
+
+/// All metrics recorded by a single run
+#[derive(Clone, Debug)]
+pub struct Run {
+    pub id: String,
+    pub repo: String,
+    pub branch: String,
+    pub commit: Option<String>,
+    pub metrics: Vec<Metric>,
+}
+
+/// A metric as it was stored
+#[derive(Clone, Debug)]
+pub struct Recorded {
+    pub run_id: String,
+    pub repo: String,
+    pub branch: String,
+    pub commit: Option<String>,
+    pub metric: Metric,
+    pub recorded_at: SystemTime,
+}
+
+/// A run saved under a name to compare against, like `release-1.9`, see `Store::save_baseline`
+#[derive(Clone, Debug)]
+pub struct NamedBaseline {
+    pub name: String,
+    pub run_id: String,
+    pub commit: Option<String>,
+    pub saved_at: SystemTime,
+}
+
+/// What the result of a successful run is cached by: running the same scripts with the same
+/// arguments on the same commit and the same kind of worker again is assumed to give the same
+/// result
+#[derive(Clone, Debug)]
+pub struct CacheKey {
+    pub repo: String,
+    pub commit: String,
+    /// Paths of the scripts run, relative to the checkout
+    pub script: String,
+    /// SHA-256 of the contents of the scripts run, in hex
+    pub script_hash: String,
+    pub args: String,
+    /// Pool of workers run on, empty for the ordinary ones
+    pub runner: String,
+    /// OS of the workers run on, empty for any
+    pub runner_os: String,
+}
+
+/// A result found in the cache
+#[derive(Clone, Debug)]
+pub struct Cached<T> {
+    /// Of the run the result is of
+    pub run_id: String,
+    pub result: T,
+    pub recorded_at: SystemTime,
+}
+
+#[derive(Clone, Debug)]
+pub struct Regression {
+    pub metric: Metric,
+    pub mean: f64,
+    pub stddev: f64,
+    pub samples: usize,
+}
+
+impl Regression {
+    /// Relative change compared to the historical mean, in percent
+    pub fn change(&self) -> f64 {
+        if self.mean == 0.0 {
+            return 0.0;
+        }
+        (self.metric.value - self.mean) / self.mean * 100.0
+    }
+}
+
+#[derive(Clone)]
+pub struct Store {
+    conn: Arc<Mutex<rusqlite::Connection>>,
+}
+
+impl Store {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        // Databases created before commits were recorded
+        let has_commit = conn
+            .prepare("SELECT 1 FROM pragma_table_info('results') WHERE name = 'commit_sha'")?
+            .exists([])?;
+        if !has_commit {
+            conn.execute_batch("ALTER TABLE results ADD COLUMN commit_sha TEXT")?;
+        }
+        // Caches created before results were cached by the workers they ran on, which can't
+        // tell what they ran on
+        let has_runner = conn
+            .prepare("SELECT 1 FROM pragma_table_info('cache') WHERE name = 'runner'")?
+            .exists([])?;
+        if !has_runner {
+            conn.execute_batch("DROP TABLE cache")?;
+            conn.execute_batch(SCHEMA)?;
+        }
+        Ok(Store {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn record<R: AsRef<str>, B: AsRef<str>>(
+        &self,
+        run_id: &str,
+        repo: R,
+        branch: B,
+        commit: Option<&str>,
+        metrics: &[Metric],
+    ) -> Result<(), Error> {
+        let mut conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let tx = conn.transaction()?;
+        for metric in metrics {
+            tx.execute(
+                "INSERT INTO results (run_id, repo, branch, name, value, unit, recorded_at, commit_sha)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    run_id,
+                    repo.as_ref(),
+                    branch.as_ref(),
+                    metric.name,
+                    metric.value,
+                    metric.unit,
+                    now,
+                    commit
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Keep what a run set with `OUTPUT.set`, the values as JSON
+    pub fn record_outputs<R: AsRef<str>>(
+        &self,
+        run_id: &str,
+        repo: R,
+        outputs: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<(), Error> {
+        let mut conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let tx = conn.transaction()?;
+        for (key, value) in outputs {
+            tx.execute(
+                "INSERT OR REPLACE INTO outputs (run_id, repo, key, value, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![run_id, repo.as_ref(), key, value.to_string(), now],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The most recent `limit` values of the named metric on the given branch, newest first
+    pub fn history<R: AsRef<str>, B: AsRef<str>>(
+        &self,
+        repo: R,
+        branch: B,
+        name: &str,
+        limit: usize,
+    ) -> Result<Vec<f64>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let mut stmt = conn.prepare(
+            "SELECT value FROM results WHERE repo = ?1 AND branch = ?2 AND name = ?3
+             ORDER BY recorded_at DESC, id DESC LIMIT ?4",
+        )?;
+        let values = stmt
+            .query_map(
+                params![repo.as_ref(), branch.as_ref(), name, limit as i64],
+                |row| row.get(0),
+            )?
+            .collect::<Result<Vec<f64>, _>>()?;
+        Ok(values)
+    }
+
+    /// The most recent results recorded on `branch`, if any
+    pub fn latest<R: AsRef<str>, B: AsRef<str>>(
+        &self,
+        repo: R,
+        branch: B,
+    ) -> Result<Option<Baseline>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let baseline = conn
+            .query_row(
+                "SELECT commit_sha, recorded_at FROM results WHERE repo = ?1 AND branch = ?2
+                 ORDER BY recorded_at DESC, id DESC LIMIT 1",
+                params![repo.as_ref(), branch.as_ref()],
+                |row| {
+                    let recorded_at: i64 = row.get(1)?;
+                    Ok(Baseline {
+                        commit: row.get(0)?,
+                        recorded_at: std::time::UNIX_EPOCH
+                            + Duration::from_secs(recorded_at.max(0) as u64),
+                    })
+                },
+            )
+            .optional()?;
+        Ok(baseline)
+    }
+
+    /// The run with the given ID. Since run IDs end in a UUID, that suffices to look it up, but
+    /// a part after the UUID like the `shard1` of a shard's ID doesn't, which fails if it's the
+    /// end of more than one run ID.
+    pub fn run(&self, id: &str) -> Result<Option<Run>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let pattern = id.replace('!', "!!").replace('%', "!%").replace('_', "!_");
+        let ids = conn
+            .prepare(
+                "SELECT DISTINCT run_id FROM results
+                 WHERE run_id = ?1 OR run_id LIKE '%!_' || ?2 ESCAPE '!'",
+            )?
+            .query_map(params![id, pattern], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let run_id = match ids.as_slice() {
+            [] => return Ok(None),
+            [run_id] => run_id.as_str(),
+            _ if ids.iter().any(|run_id| run_id == id) => id,
+            _ => {
+                return Err(Error::AmbiguousRun {
+                    id: id.to_string(),
+                    count: ids.len(),
+                })
+            }
+        };
+        let mut stmt = conn.prepare(
+            "SELECT run_id, repo, branch, commit_sha, name, value, unit FROM results
+             WHERE run_id = ?1 ORDER BY id",
+        )?;
+        let mut rows = stmt.query(params![run_id])?;
+        let mut run: Option<Run> = None;
+        while let Some(row) = rows.next()? {
+            let metric = Metric {
+                name: row.get(4)?,
+                value: row.get(5)?,
+                unit: row.get(6)?,
+            };
+            match &mut run {
+                Some(run) => run.metrics.push(metric),
+                None => {
+                    run = Some(Run {
+                        id: row.get(0)?,
+                        repo: row.get(1)?,
+                        branch: row.get(2)?,
+                        commit: row.get(3)?,
+                        metrics: vec![metric],
+                    })
+                }
+            }
+        }
+        Ok(run)
+    }
+
+    /// Everything recorded after `since`, oldest first
+    pub fn recorded_since(&self, since: SystemTime) -> Result<Vec<Recorded>, Error> {
+        let since = since
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let mut stmt = conn.prepare(
+            "SELECT run_id, repo, branch, commit_sha, name, value, unit, recorded_at FROM results
+             WHERE recorded_at >= ?1 ORDER BY recorded_at, id",
+        )?;
+        let recorded = stmt
+            .query_map(params![since], |row| {
+                Ok(Recorded {
+                    run_id: row.get(0)?,
+                    repo: row.get(1)?,
+                    branch: row.get(2)?,
+                    commit: row.get(3)?,
+                    metric: Metric {
+                        name: row.get(4)?,
+                        value: row.get(5)?,
+                        unit: row.get(6)?,
+                    },
+                    recorded_at: std::time::UNIX_EPOCH
+                        + Duration::from_secs(row.get::<_, i64>(7)?.max(0) as u64),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(recorded)
+    }
+
+    /// Compare the given metrics against the rolling history of `branch`
+    pub fn regressions<R: AsRef<str>, B: AsRef<str>>(
+        &self,
+        repo: R,
+        branch: B,
+        metrics: &[Metric],
+        policy: &Policy,
+    ) -> Result<Vec<Regression>, Error> {
+        let mut regressions = vec![];
+        for metric in metrics {
+            let history =
+                self.history(repo.as_ref(), branch.as_ref(), &metric.name, policy.window)?;
+            if let Some(regression) = detect_regression(metric, &history, policy) {
+                regressions.push(regression);
+            }
+        }
+        Ok(regressions)
+    }
+
+    /// Save the latest run on `commit` (or a commit it's a prefix of) as the baseline `name` of
+    /// `repo`, replacing the run saved under that name before. None if nothing was recorded on
+    /// the commit.
+    pub fn save_baseline<R: AsRef<str>>(
+        &self,
+        repo: R,
+        name: &str,
+        commit: &str,
+    ) -> Result<Option<NamedBaseline>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let run = conn
+            .query_row(
+                "SELECT run_id, commit_sha FROM results
+                 WHERE repo = ?1 AND substr(commit_sha, 1, length(?2)) = ?2
+                 ORDER BY recorded_at DESC, id DESC LIMIT 1",
+                params![repo.as_ref(), commit],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+            )
+            .optional()?;
+        let (run_id, commit) = match run {
+            Some(run) => run,
+            None => return Ok(None),
+        };
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        conn.execute(
+            "INSERT OR REPLACE INTO baselines (repo, name, run_id, commit_sha, saved_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![repo.as_ref(), name, run_id, commit, now],
+        )?;
+        Ok(Some(NamedBaseline {
+            name: name.to_string(),
+            run_id,
+            commit,
+            saved_at: std::time::UNIX_EPOCH + Duration::from_secs(now.max(0) as u64),
+        }))
+    }
+
+    /// The baselines saved for `repo`, by name
+    pub fn baselines<R: AsRef<str>>(&self, repo: R) -> Result<Vec<NamedBaseline>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let mut stmt = conn.prepare(
+            "SELECT name, run_id, commit_sha, saved_at FROM baselines WHERE repo = ?1
+             ORDER BY name",
+        )?;
+        let baselines = stmt
+            .query_map(params![repo.as_ref()], |row| {
+                Ok(NamedBaseline {
+                    name: row.get(0)?,
+                    run_id: row.get(1)?,
+                    commit: row.get(2)?,
+                    saved_at: std::time::UNIX_EPOCH
+                        + Duration::from_secs(row.get::<_, i64>(3)?.max(0) as u64),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(baselines)
+    }
+
+    /// The baseline `name` of `repo` and the run saved as it, if there is one
+    pub fn baseline<R: AsRef<str>>(
+        &self,
+        repo: R,
+        name: &str,
+    ) -> Result<Option<(NamedBaseline, Run)>, Error> {
+        let baseline = self
+            .baselines(repo)?
+            .into_iter()
+            .find(|baseline| baseline.name == name);
+        let baseline = match baseline {
+            Some(baseline) => baseline,
+            None => return Ok(None),
+        };
+        Ok(self.run(&baseline.run_id)?.map(|run| (baseline, run)))
+    }
+
+    /// Keep the result of the successful run `run_id`, replacing whatever was cached by `key`
+    pub fn cache<T: Serialize>(
+        &self,
+        key: &CacheKey,
+        run_id: &str,
+        result: &T,
+    ) -> Result<(), Error> {
+        let result = serde_json::to_string(result)?;
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        conn.execute(
+            "INSERT OR REPLACE INTO cache
+             (repo, commit_sha, script, script_hash, args, runner, runner_os, run_id, result,
+              recorded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                key.repo,
+                key.commit,
+                key.script,
+                key.script_hash,
+                key.args,
+                key.runner,
+                key.runner_os,
+                run_id,
+                result,
+                now
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The result cached by `key`, if any
+    pub fn cached<T: serde::de::DeserializeOwned>(
+        &self,
+        key: &CacheKey,
+    ) -> Result<Option<Cached<T>>, Error> {
+        let conn = self.conn.lock().map_err(|_| Error::ExclusiveLock)?;
+        let row = conn
+            .query_row(
+                "SELECT run_id, result, recorded_at FROM cache
+                 WHERE repo = ?1 AND commit_sha = ?2 AND script = ?3 AND script_hash = ?4
+                 AND args = ?5 AND runner = ?6 AND runner_os = ?7",
+                params![
+                    key.repo,
+                    key.commit,
+                    key.script,
+                    key.script_hash,
+                    key.args,
+                    key.runner,
+                    key.runner_os
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let (run_id, result, recorded_at) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        Ok(Some(Cached {
+            run_id,
+            result: serde_json::from_str(&result)?,
+            recorded_at: std::time::UNIX_EPOCH + Duration::from_secs(recorded_at.max(0) as u64),
+        }))
+    }
+
+    /// Compare the metrics of a run on `branch` against the history of `baseline` and record
+    /// them afterwards, so the run doesn't end up in its own baseline.
+    #[allow(clippy::too_many_arguments)]
+    pub fn compare_and_record(
+        &self,
+        run_id: &str,
+        repo: &str,
+        branch: &str,
+        commit: Option<&str>,
+        baseline: &str,
+        metrics: &[Metric],
+        policy: &Policy,
+    ) -> Result<Vec<Regression>, Error> {
+        let regressions = self.regressions(repo, baseline, metrics, policy)?;
+        self.record(run_id, repo, branch, commit, metrics)?;
+        Ok(regressions)
+    }
+}
+
+fn detect_regression(metric: &Metric, history: &[f64], policy: &Policy) -> Option<Regression> {
+    if metric.name.starts_with(SIZE_PREFIX) {
+        // The history is latest first
+        let latest = *history.first()?;
+        let regression = Regression {
+            metric: metric.clone(),
+            mean: latest,
+            stddev: 0.0,
+            samples: 1,
+        };
+        return (regression.change() > policy.size_threshold).then_some(regression);
+    }
+    if history.len() < policy.min_samples.max(2) {
+        return None;
+    }
+    let samples = history.len();
+    let mean = history.iter().sum::<f64>() / samples as f64;
+    let variance = history.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (samples - 1) as f64;
+    let stddev = variance.sqrt();
+
+    // Deterministic metrics (like binary sizes) have no spread, so any increase is significant
+    let significant = if stddev == 0.0 {
+        metric.value > mean
+    } else {
+        (metric.value - mean) / stddev > policy.threshold
+    };
+
+    if significant {
+        Some(Regression {
+            metric: metric.clone(),
+            mean,
+            stddev,
+            samples,
+        })
+    } else {
+        None
+    }
+}
+
+/// One line summary of the worst regression, like `ref-time +2.30%`
+pub fn headline(regressions: &[Regression]) -> Option<String> {
+    regressions
+        .iter()
+        .max_by(|a, b| a.change().total_cmp(&b.change()))
+        .map(|r| format!("{} {:+.2}%", r.metric.name, r.change()))
+}
+
+/// The changes of `metrics` compared to a single run, like a named baseline, for the metrics both
+/// have
+pub fn changes(baseline: &Run, metrics: &[Metric]) -> Vec<Regression> {
+    metrics
+        .iter()
+        .filter_map(|metric| {
+            let base = baseline
+                .metrics
+                .iter()
+                .rev()
+                .find(|base| base.name == metric.name && base.unit == metric.unit)?;
+            Some(Regression {
+                metric: metric.clone(),
+                mean: base.value,
+                stddev: 0.0,
+                samples: 1,
+            })
+        })
+        .collect()
+}
+
+/// Render the regressions as a markdown section suitable for a PR comment
+pub fn render_regressions<B: std::fmt::Display>(branch: B, regressions: &[Regression]) -> String {
+    let mut out = format!(
+        "### :warning: Possible regressions compared to `{branch}`\n\n\
+         | Metric | Value | Mean | Std. dev. | Change | Samples |\n\
+         |---|---:|---:|---:|---:|---:|\n"
+    );
+    for r in regressions {
+        out.push_str(&format!(
+            "| {} | {:.2} {unit} | {:.2} {unit} | {:.2} | {:+.2}% | {} |\n",
+            r.metric.name,
+            r.metric.value,
+            r.mean,
+            r.stddev,
+            r.change(),
+            r.samples,
+            unit = r.metric.unit,
+        ));
+    }
+    out
+}
+
+/// Render the metrics of two runs side by side as a markdown section suitable for a PR comment
+pub fn render_comparison(a: &Run, b: &Run) -> String {
+    fn describe(run: &Run) -> String {
+        match &run.commit {
+            Some(commit) => format!(
+                "`{}` ({}@{})",
+                run.id,
+                run.branch,
+                &commit[..commit.len().min(8)]
+            ),
+            None => format!("`{}` ({})", run.id, run.branch),
+        }
+    }
+
+    let mut out = format!(
+        "### Comparison of runs\n\n\
+         - **A**: {}\n- **B**: {}\n\n\
+         | Metric | A | B | Change |\n\
+         |---|---:|---:|---:|\n",
+        describe(a),
+        describe(b)
+    );
+    let mut names: Vec<&str> = vec![];
+    for metric in a.metrics.iter().chain(&b.metrics) {
+        if !names.contains(&metric.name.as_str()) {
+            names.push(&metric.name);
+        }
+    }
+    for name in names {
+        let value = |run: &Run| run.metrics.iter().rev().find(|m| m.name == name).cloned();
+        let (value_a, value_b) = (value(a), value(b));
+        let cell = |metric: &Option<Metric>| match metric {
+            Some(m) => format!("{:.2} {}", m.value, m.unit),
+            None => "-".to_string(),
+        };
+        let change = match (&value_a, &value_b) {
+            (Some(a), Some(b)) if a.value != 0.0 && a.unit == b.unit => {
+                format!("{:+.2}%", (b.value - a.value) / a.value * 100.0)
+            }
+            _ => "-".to_string(),
+        };
+        out.push_str(&format!(
+            "| {name} | {} | {} | {change} |\n",
+            cell(&value_a),
+            cell(&value_b)
+        ));
+    }
+    out
+}
@@ -0,0 +1,48 @@
+//! Commands in review comments on pull requests, like one on a line of a benchmark asking to run
+//! it, which are queued like the ones in ordinary comments. Replies go to the thread of the review
+//! comment instead of the conversation of the pull request.
+
+use serde::Deserialize;
+
+/// Payload of the `pull_request_review_comment` webhook
+#[derive(Debug, Deserialize)]
+pub struct ReviewCommentEvent {
+    /// `created`, `edited` or `deleted`
+    pub action: String,
+    pub comment: ReviewComment,
+    pub pull_request: PullRequest,
+    pub repository: octocrab::models::Repository,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewComment {
+    pub id: u64,
+    pub body: String,
+    pub user: User,
+    /// The first comment of the thread, if this one is a reply
+    #[serde(default)]
+    pub in_reply_to_id: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub login: String,
+}
+
+impl ReviewCommentEvent {
+    /// The body of the comment if it was just made and starts with `prefix`
+    pub fn command(&self, prefix: &str) -> Option<&str> {
+        Some(self.comment.body.as_str())
+            .filter(|body| self.action == "created" && body.starts_with(prefix))
+    }
+
+    /// The comment to reply to, the first of the thread as Github doesn't take replies to replies
+    pub fn thread(&self) -> u64 {
+        self.comment.in_reply_to_id.unwrap_or(self.comment.id)
+    }
+}
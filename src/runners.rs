@@ -0,0 +1,133 @@
+//! Liveness of the workers taking jobs off a queue, remote ones included. Workers that give a
+//! name when they poll the queue (`POST /queue/remove?name=<name>`) are remembered along with the
+//! job they took, and send a heartbeat (`POST /queue/heartbeat`) while running it. A worker that
+//! stops sending them is taken to be lost, and its job can be queued again.
+
+use crate::Job;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often workers send a heartbeat while running a job
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Route of the jobs run on the pool `runner` of workers on `os`, like `big-machine@macos`, see
+/// `Routed`. Jobs that don't ask for an OS are taken by workers that don't say theirs.
+pub fn route<'a>(runner: Option<&'a str>, os: Option<&str>) -> Option<Cow<'a, str>> {
+    match (runner, os) {
+        (runner, None) => runner.map(Cow::Borrowed),
+        (runner, Some(os)) => Some(Cow::Owned(format!("{}@{os}", runner.unwrap_or_default()))),
+    }
+}
+
+/// Sent by a worker to say it's still there
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Heartbeat {
+    pub name: String,
+    pub version: Option<String>,
+    /// Pool of workers it belongs to, see `Routed`
+    pub runner: Option<String>,
+    /// OS it runs on, if it takes the jobs asking for one, see `route`
+    #[serde(default)]
+    pub os: Option<String>,
+    /// ID of the job it's running, none once it's done
+    pub job: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Runner {
+    pub name: String,
+    pub version: Option<String>,
+    pub runner: Option<String>,
+    pub os: Option<String>,
+    /// The job it took and didn't finish yet
+    pub job: Option<Job>,
+    pub last_seen: DateTime<Utc>,
+    /// Whether it was reported as silent since it was last seen
+    pub silent: bool,
+}
+
+impl Runner {
+    fn silent_for(&self, now: DateTime<Utc>) -> Duration {
+        (now - self.last_seen).to_std().unwrap_or_default()
+    }
+}
+
+/// The workers of a queue by name, shared by the handlers of the worker API
+#[derive(Clone, Default)]
+pub struct Runners(Arc<Mutex<HashMap<String, Runner>>>);
+
+impl Runners {
+    /// Remember that the worker was just heard from
+    pub fn seen(&self, heartbeat: Heartbeat) {
+        let mut runners = self.lock();
+        let runner = runners
+            .entry(heartbeat.name.clone())
+            .or_insert_with(|| Runner {
+                name: heartbeat.name.clone(),
+                version: None,
+                runner: None,
+                os: None,
+                job: None,
+                last_seen: Utc::now(),
+                silent: false,
+            });
+        runner.version = heartbeat.version;
+        runner.runner = heartbeat.runner;
+        runner.os = heartbeat.os;
+        runner.last_seen = Utc::now();
+        runner.silent = false;
+        let running = runner.job.as_ref().map(|job| job.id.as_str());
+        if heartbeat.job.is_none() || heartbeat.job.as_deref() != running {
+            runner.job = None;
+        }
+    }
+
+    /// Remember that the worker `name` took `job`
+    pub fn took(&self, name: &str, job: &Job) {
+        if let Some(runner) = self.lock().get_mut(name) {
+            runner.job = Some(job.clone());
+            runner.last_seen = Utc::now();
+        }
+    }
+
+    /// All known workers, by name
+    pub fn list(&self) -> Vec<Runner> {
+        let mut runners: Vec<_> = self.lock().values().cloned().collect();
+        runners.sort_by(|a, b| a.name.cmp(&b.name));
+        runners
+    }
+
+    /// The workers running a job that weren't heard from for longer than `timeout`, each only
+    /// once until they're heard from again
+    pub fn newly_silent(&self, timeout: Duration) -> Vec<Runner> {
+        let now = Utc::now();
+        self.lock()
+            .values_mut()
+            .filter(|runner| runner.job.is_some() && !runner.silent)
+            .filter(|runner| runner.silent_for(now) > timeout)
+            .map(|runner| {
+                runner.silent = true;
+                runner.clone()
+            })
+            .collect()
+    }
+
+    /// Take the jobs of the workers that weren't heard from for longer than `grace`, as
+    /// `(worker, job)`
+    pub fn take_lost(&self, grace: Duration) -> Vec<(String, Job)> {
+        let now = Utc::now();
+        self.lock()
+            .values_mut()
+            .filter(|runner| runner.silent_for(now) > grace)
+            .filter_map(|runner| Some((runner.name.clone(), runner.job.take()?)))
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Runner>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,143 @@
+//! Environment variables and secrets passed on to jobs, and keeping the secrets out of what the
+//! bot logs and posts.
+
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Expected KEY=VALUE, got {0:?}")]
+    InvalidVar(String),
+    #[error("Failed to read secrets: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Invalid line {0} in secrets file, expected KEY=VALUE")]
+    InvalidLine(usize),
+}
+
+/// Parse a variable given as `KEY=VALUE`
+pub fn parse_var(s: &str) -> Result<(String, String), Error> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(Error::InvalidVar(s.to_string())),
+    }
+}
+
+/// Variables set for every job, exposed to scripts through the `env` module and set for the
+/// commands they run
+#[derive(Clone, Default)]
+pub struct JobEnv {
+    vars: Vec<(String, String)>,
+    /// Like `vars`, but never shown
+    secrets: Vec<(String, String)>,
+}
+
+impl JobEnv {
+    pub fn new(vars: Vec<(String, String)>) -> Self {
+        JobEnv {
+            vars,
+            secrets: vec![],
+        }
+    }
+
+    /// Add the secrets in a file with a `KEY=VALUE` per line. Empty lines and lines starting with
+    /// `#` are ignored.
+    pub fn with_secrets_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, Error> {
+        for (n, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let secret = parse_var(line).map_err(|_| Error::InvalidLine(n + 1))?;
+            self.secrets.push(secret);
+        }
+        Ok(self)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        // Later ones win, like they would when setting them one after the other
+        self.vars
+            .iter()
+            .chain(&self.secrets)
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All variables, secrets included
+    pub fn vars(&self) -> Vec<(String, String)> {
+        self.vars.iter().chain(&self.secrets).cloned().collect()
+    }
+
+    /// The values to keep out of logs and comments
+    pub fn secret_values(&self) -> impl Iterator<Item = &str> {
+        self.secrets.iter().map(|(_, value)| value.as_str())
+    }
+}
+
+impl std::fmt::Debug for JobEnv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobEnv")
+            .field("vars", &self.vars)
+            .field(
+                "secrets",
+                &self.secrets.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+const REDACTED: &str = "***";
+
+/// Replaces secrets with `***`. Clones share the secrets, so ones added later (like tokens that
+/// are only known once a job runs) are redacted everywhere.
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+    secrets: Arc<RwLock<Vec<String>>>,
+}
+
+impl Redactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<S: Into<String>>(&self, secret: S) {
+        let secret = secret.into();
+        // Redacting short values would mangle everything, and they aren't much of a secret
+        if secret.len() < 4 {
+            return;
+        }
+        if let Ok(mut secrets) = self.secrets.write() {
+            if !secrets.contains(&secret) {
+                secrets.push(secret);
+                // Longest first, in case one secret contains another
+                secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+            }
+        }
+    }
+
+    /// Add a PEM encoded key, and each line of it in case only part of it leaks
+    pub fn add_pem(&self, pem: &str) {
+        self.add(pem.trim());
+        for line in pem.lines().filter(|line| !line.starts_with("-----")) {
+            self.add(line.trim());
+        }
+    }
+
+    /// Stop redacting a secret that can't be used anymore, like a token of a finished job
+    pub fn remove(&self, secret: &str) {
+        if let Ok(mut secrets) = self.secrets.write() {
+            secrets.retain(|known| known != secret);
+        }
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let secrets = match self.secrets.read() {
+            Ok(secrets) => secrets,
+            Err(_) => return text.to_string(),
+        };
+        secrets.iter().fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), REDACTED)
+        })
+    }
+}
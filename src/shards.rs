@@ -0,0 +1,131 @@
+//! Splitting jobs on huge benchmark suites (like all pallets) into shards that run in parallel on
+//! different workers, and putting their outcomes back together once all of them reported.
+//!
+//! Every shard runs the same script. Scripts see which shard they are as `SHARD`
+//! (`#{index, count}`), `selected_suites()` and pipelines only get the share of the suites of the
+//! shard: every `count`th suite, starting at `index`.
+
+use crate::job::Outcome;
+use crate::journal::{self, Journal};
+use serde::{Deserialize, Serialize};
+
+/// Part `index` of `count` of a job, all parts sharing the same `group`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Shard {
+    pub group: String,
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    /// The part of `items` this shard takes care of: every `count`th one, starting at `index`
+    pub fn share<T>(&self, items: Vec<T>) -> Vec<T> {
+        items
+            .into_iter()
+            .skip(self.index)
+            .step_by(self.count.max(1))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index + 1, self.count)
+    }
+}
+
+/// What a shard left behind, or why it failed
+pub type ShardResult = Result<Outcome, String>;
+
+/// Collects the results of shards until all shards of a job reported, in a journal so they
+/// survive a restart
+#[derive(Clone)]
+pub struct Coordinator {
+    journal: Journal,
+}
+
+impl Coordinator {
+    pub fn new(journal: Journal) -> Self {
+        Coordinator { journal }
+    }
+
+    /// Keep the result of `shard`. Returns the results of all shards of its job, in order, if it
+    /// was the last one to report.
+    pub fn report(
+        &self,
+        shard: &Shard,
+        result: &ShardResult,
+    ) -> Result<Option<Vec<ShardResult>>, journal::Error> {
+        let reported = self
+            .journal
+            .record_shard(&shard.group, shard.index, result)?;
+        if reported < shard.count {
+            return Ok(None);
+        }
+        let results = self.journal.shard_results(&shard.group)?;
+        self.journal.forget_shards(&shard.group)?;
+        Ok(Some(results))
+    }
+}
+
+/// Put the results of all shards of a job together, as if a single job recorded everything. The
+/// phases of each shard are suffixed with the shard. Returns the errors of the shards that
+/// failed alongside.
+pub fn merge(results: Vec<ShardResult>) -> (Outcome, Vec<String>) {
+    let count = results.len();
+    let mut merged = Outcome::default();
+    let mut errors = vec![];
+    for (index, result) in results.into_iter().enumerate() {
+        let outcome = match result {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                errors.push(format!("Shard {}/{count} failed: {err}", index + 1));
+                continue;
+            }
+        };
+        merged.metrics.extend(outcome.metrics);
+        for warning in outcome.warnings {
+            if !merged.warnings.contains(&warning) {
+                merged.warnings.push(warning);
+            }
+        }
+        merged
+            .phases
+            .extend(outcome.phases.into_iter().map(|mut phase| {
+                phase.name = format!("{} ({}/{count})", phase.name, index + 1);
+                phase
+            }));
+        merged.report.extend(outcome.report);
+        merged
+            .steps
+            .extend(outcome.steps.into_iter().map(|mut step| {
+                step.script = format!("{} ({}/{count})", step.script, index + 1);
+                step
+            }));
+        merged.commit = merged.commit.or(outcome.commit);
+        // Shards run on the same kind of machine
+        merged.machine = merged.machine.or(outcome.machine);
+        merged.artifacts.extend(outcome.artifacts);
+        merged.outputs.extend(outcome.outputs);
+        merged.sccache = match (merged.sccache, outcome.sccache) {
+            (Some(mut merged), Some(stats)) => {
+                merged.add(stats);
+                Some(merged)
+            }
+            (merged, stats) => merged.or(stats),
+        };
+        merged.usage = match (merged.usage, outcome.usage) {
+            (Some(mut merged), Some(usage)) => {
+                merged.add(usage);
+                Some(merged)
+            }
+            (merged, usage) => merged.or(usage),
+        };
+        merged.headline = merged.headline.or(outcome.headline);
+        merged.failure = match (merged.failure, outcome.failure) {
+            (Some(merged), Some(failure)) => Some(format!("{merged}\n{failure}")),
+            (merged, failure) => merged.or(failure),
+        };
+    }
+    (merged, errors)
+}
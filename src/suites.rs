@@ -0,0 +1,121 @@
+//! Selecting the benchmark suites affected by the changes of a pull request, as configured in the
+//! `.benchbot.toml` at the root of the repository:
+//!
+//! ```toml
+//! # Only run the suites affected by the changed files of a pull request
+//! only_affected = true
+//!
+//! [suites.balances]
+//! paths = ["frame/balances/**", "primitives/arithmetic/"]
+//!
+//! [suites.runtime]
+//! paths = ["runtime/**/*.rs"]
+//! ```
+//!
+//! A path ending in `/` matches everything below it, `*` matches within a path segment and `**`
+//! any number of segments.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use thiserror::Error;
+
+pub const CONFIG_FILE: &str = ".benchbot.toml";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read {CONFIG_FILE}: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Failed to parse {CONFIG_FILE}: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suites {
+    #[serde(default)]
+    pub only_affected: bool,
+    #[serde(default)]
+    pub suites: BTreeMap<String, Suite>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suite {
+    /// Patterns of the source paths the suite benchmarks
+    pub paths: Vec<String>,
+}
+
+impl Suites {
+    /// Read the configuration of the repository in `dir`, if it has any
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Option<Self>, Error> {
+        match std::fs::read_to_string(dir.as_ref().join(CONFIG_FILE)) {
+            Ok(config) => Ok(Some(toml::from_str(&config)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.suites.keys().cloned().collect()
+    }
+
+    /// Suites with a path matching any of the changed files
+    pub fn affected<S: AsRef<str>>(&self, changed_files: &[S]) -> Vec<String> {
+        self.suites
+            .iter()
+            .filter(|(_, suite)| {
+                changed_files.iter().any(|file| {
+                    suite
+                        .paths
+                        .iter()
+                        .any(|pattern| matches(pattern, file.as_ref()))
+                })
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The suites to run: only the affected ones if so configured and the changed files are
+    /// known (i.e. the job runs on a pull request), all of them otherwise
+    pub fn selected<S: AsRef<str>>(&self, changed_files: Option<&[S]>) -> Vec<String> {
+        match changed_files {
+            Some(changed_files) if self.only_affected => self.affected(changed_files),
+            _ => self.names(),
+        }
+    }
+}
+
+fn matches(pattern: &str, path: &str) -> bool {
+    if pattern.ends_with('/') {
+        return path.starts_with(pattern);
+    }
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    matches_segments(&pattern, &path)
+}
+
+fn matches_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| matches_segments(rest, &path[skip..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((first, path)) => {
+                matches_segment(segment.as_bytes(), first.as_bytes())
+                    && matches_segments(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Whether `name` matches `pattern`, in which `*` matches any characters
+pub(crate) fn matches_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| matches_segment(rest, &name[skip..])),
+        Some((c, rest)) => name
+            .split_first()
+            .is_some_and(|(first, name)| first == c && matches_segment(rest, name)),
+    }
+}
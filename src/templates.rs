@@ -0,0 +1,306 @@
+//! Markdown templates for result comments, so scripts don't need to build big strings
+//! themselves. Scripts render the templates in the `templates` directory next to them with
+//! `REPORT.render("success", data)`, which renders `templates/success.md`.
+//!
+//! Templates use a small subset of Handlebars:
+//!
+//! ```md
+//! ## {{title}}
+//!
+//! {{#if regressions}}
+//! | Benchmark | Change |
+//! |-----------|--------|
+//! {{#each regressions}}
+//! | {{name}} | {{change}} |
+//! {{/each}}
+//! {{else}}
+//! No regressions in {{suite.name}} :tada:
+//! {{/if}}
+//! ```
+//!
+//! Names are looked up in the item `#each` is at first, then in the blocks around it. `this` is
+//! the item itself and `@index` its position.
+
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub const TEMPLATES_DIR: &str = "templates";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid template name {0:?}")]
+    InvalidName(String),
+    #[error("Failed to read template {0:?}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Unclosed tag at line {0}")]
+    UnclosedTag(usize),
+    #[error("Unexpected {{{{{tag}}}}} at line {line}")]
+    UnexpectedTag { tag: String, line: usize },
+    #[error("Missing {{{{/{0}}}}}")]
+    UnclosedBlock(String),
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Value(String),
+    If {
+        path: String,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        path: String,
+        body: Vec<Node>,
+    },
+}
+
+/// A parsed template
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut tags = Tags { source, offset: 0 };
+        let (nodes, end) = parse_nodes(&mut tags)?;
+        match end {
+            None => Ok(Template { nodes }),
+            Some((tag, line)) => Err(Error::UnexpectedTag { tag, line }),
+        }
+    }
+
+    /// Load `<name>.md` from `dir`. The name can't point outside of it.
+    pub fn load<P: AsRef<Path>>(dir: P, name: &str) -> Result<Self, Error> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+        let path = dir.as_ref().join(format!("{name}.md"));
+        let source = std::fs::read_to_string(&path).map_err(|e| Error::Read(path, e))?;
+        Self::parse(&source)
+    }
+
+    pub fn render(&self, data: &Value) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, &[Scope::new(data)], &mut out);
+        out
+    }
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    /// The trimmed contents of a `{{...}}` tag, and the line it's on
+    Tag(&'a str, usize),
+}
+
+struct Tags<'a> {
+    source: &'a str,
+    offset: usize,
+}
+
+impl<'a> Tags<'a> {
+    fn next(&mut self) -> Result<Option<Token<'a>>, Error> {
+        let rest = &self.source[self.offset..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        let line = self.source[..self.offset].matches('\n').count() + 1;
+        match rest.find("{{") {
+            Some(0) => {
+                let end = rest.find("}}").ok_or(Error::UnclosedTag(line))?;
+                let tag = rest[2..end].trim();
+                let start = self.offset;
+                self.offset += end + 2;
+                if let Some(line_end) = self.standalone(start, tag) {
+                    self.offset = line_end;
+                }
+                Ok(Some(Token::Tag(tag, line)))
+            }
+            Some(start) => {
+                let mut text = &rest[..start];
+                self.offset += start;
+                let tag = rest[start + 2..]
+                    .split("}}")
+                    .next()
+                    .unwrap_or_default()
+                    .trim();
+                if self.standalone(self.offset, tag).is_some() {
+                    text = text.trim_end_matches([' ', '\t']);
+                }
+                Ok(Some(Token::Text(text)))
+            }
+            None => {
+                self.offset = self.source.len();
+                Ok(Some(Token::Text(rest)))
+            }
+        }
+    }
+
+    /// Where the line ends if the block tag (`#if`, `/each`, ...) starting at `start` is the only
+    /// thing on it, like Handlebars, so blocks don't leave empty lines behind
+    fn standalone(&self, start: usize, tag: &str) -> Option<usize> {
+        if !(tag.starts_with('#') || tag.starts_with('/') || tag == "else") {
+            return None;
+        }
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        if !self.source[line_start..start].trim().is_empty() {
+            return None;
+        }
+        let after = start + self.source[start..].find("}}")? + 2;
+        let line_end = self.source[after..]
+            .find('\n')
+            .map_or(self.source.len(), |i| after + i + 1);
+        if !self.source[after..line_end].trim().is_empty() {
+            return None;
+        }
+        Some(line_end)
+    }
+}
+
+/// A tag closing or splitting a block (`else`, `/if`, ...) and its line
+type BlockEnd = Option<(String, usize)>;
+
+/// Parse nodes up to the end of the template or the end of a block
+fn parse_nodes(tags: &mut Tags) -> Result<(Vec<Node>, BlockEnd), Error> {
+    let mut nodes = vec![];
+    while let Some(token) = tags.next()? {
+        let (tag, line) = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text.to_string()));
+                continue;
+            }
+            Token::Tag(tag, line) => (tag, line),
+        };
+        if tag == "else" || tag.starts_with('/') {
+            return Ok((nodes, Some((tag.to_string(), line))));
+        }
+        if let Some(path) = tag.strip_prefix("#if ") {
+            let (then, end) = parse_nodes(tags)?;
+            let otherwise = match end {
+                Some((end, _)) if end == "/if" => vec![],
+                Some((end, _)) if end == "else" => match parse_nodes(tags)? {
+                    (otherwise, Some((end, _))) if end == "/if" => otherwise,
+                    (_, Some((tag, line))) => return Err(Error::UnexpectedTag { tag, line }),
+                    (_, None) => return Err(Error::UnclosedBlock("if".into())),
+                },
+                Some((tag, line)) => return Err(Error::UnexpectedTag { tag, line }),
+                None => return Err(Error::UnclosedBlock("if".into())),
+            };
+            nodes.push(Node::If {
+                path: path.trim().to_string(),
+                then,
+                otherwise,
+            });
+        } else if let Some(path) = tag.strip_prefix("#each ") {
+            let body = match parse_nodes(tags)? {
+                (body, Some((end, _))) if end == "/each" => body,
+                (_, Some((tag, line))) => return Err(Error::UnexpectedTag { tag, line }),
+                (_, None) => return Err(Error::UnclosedBlock("each".into())),
+            };
+            nodes.push(Node::Each {
+                path: path.trim().to_string(),
+                body,
+            });
+        } else if tag.starts_with('#') {
+            return Err(Error::UnexpectedTag {
+                tag: tag.to_string(),
+                line,
+            });
+        } else {
+            nodes.push(Node::Value(tag.to_string()));
+        }
+    }
+    Ok((nodes, None))
+}
+
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    value: &'a Value,
+    index: Option<usize>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(value: &'a Value) -> Self {
+        Scope { value, index: None }
+    }
+}
+
+fn lookup(scopes: &[Scope], path: &str) -> Option<Value> {
+    if path == "@index" {
+        let index = scopes.iter().rev().find_map(|scope| scope.index)?;
+        return Some(Value::from(index));
+    }
+    let mut segments = path.split('.');
+    let first = segments.next()?;
+    let mut value = if first == "this" {
+        scopes.last()?.value
+    } else {
+        scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.value.get(first))?
+    };
+    for segment in segments {
+        value = match value {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => value.get(segment)?,
+        };
+    }
+    Some(value.clone())
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(value) => !value.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+fn render_nodes(nodes: &[Node], scopes: &[Scope], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value(path) => match lookup(scopes, path) {
+                None | Some(Value::Null) => {}
+                Some(Value::String(value)) => out.push_str(&value),
+                Some(value) => out.push_str(&value.to_string()),
+            },
+            Node::If {
+                path,
+                then,
+                otherwise,
+            } => {
+                let nodes = match lookup(scopes, path) {
+                    Some(value) if is_truthy(&value) => then,
+                    _ => otherwise,
+                };
+                render_nodes(nodes, scopes, out);
+            }
+            Node::Each { path, body } => {
+                let items = match lookup(scopes, path) {
+                    Some(Value::Array(items)) => items,
+                    Some(Value::Object(fields)) => fields.into_iter().map(|(_, v)| v).collect(),
+                    _ => continue,
+                };
+                for (index, item) in items.iter().enumerate() {
+                    let mut inner = scopes.to_vec();
+                    inner.push(Scope {
+                        value: item,
+                        index: Some(index),
+                    });
+                    render_nodes(body, &inner, out);
+                }
+            }
+        }
+    }
+}
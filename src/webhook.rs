@@ -0,0 +1,126 @@
+//! Webhooks sent when jobs finish, so dashboards and notification systems get the results without
+//! polling. The body is a [`Finished`] as JSON, signed like Github signs its webhooks if there's a
+//! secret: `X-Hub-Signature-256: sha256=<HMAC-SHA256 of the body in hex>`.
+
+use crate::api::usage::Usage;
+use crate::history::{Record, Status};
+use crate::results::Metric;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::UNIX_EPOCH;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to serialize webhook: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Failed to sign webhook: {0}")]
+    Signing(#[from] openssl::error::ErrorStack),
+    #[error("Failed to send webhook: {0}")]
+    Request(surf::Error),
+    #[error("Webhook receiver responded with {0}")]
+    Status(surf::StatusCode),
+}
+
+/// Body of the webhook of a finished job
+#[derive(Clone, Debug, Serialize)]
+pub struct Finished {
+    pub id: String,
+    /// `<owner>/<name>`
+    pub repository: String,
+    pub command: String,
+    /// Login of whoever requested the job
+    pub user: Option<String>,
+    /// `succeeded` or `failed`
+    pub status: &'static str,
+    pub error: Option<String>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    /// In seconds
+    pub duration: Option<f64>,
+    pub commit: Option<String>,
+    pub headline: Option<String>,
+    pub issue_url: String,
+    pub comment_url: Option<String>,
+    /// What the processes of the job used
+    pub usage: Option<Usage>,
+    pub metrics: Vec<Metric>,
+    /// What the script set with `OUTPUT.set`
+    pub outputs: BTreeMap<String, serde_json::Value>,
+}
+
+impl Finished {
+    pub fn new(
+        record: &Record,
+        commit: Option<String>,
+        headline: Option<String>,
+        metrics: Vec<Metric>,
+        outputs: BTreeMap<String, serde_json::Value>,
+    ) -> Self {
+        Finished {
+            id: record.id.clone(),
+            repository: record.repository.clone(),
+            command: record.command.clone(),
+            user: record.user.clone(),
+            status: match record.status {
+                Status::Failed(_) => "failed",
+                _ => "succeeded",
+            },
+            error: match &record.status {
+                Status::Failed(err) => Some(err.clone()),
+                _ => None,
+            },
+            started_at: record
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default(),
+            duration: record.duration.map(|duration| duration.as_secs_f64()),
+            commit,
+            headline,
+            issue_url: record.issue_url.to_string(),
+            comment_url: record.comment_url.as_ref().map(|url| url.to_string()),
+            usage: record.usage,
+            metrics,
+            outputs,
+        }
+    }
+}
+
+/// Where to send the webhooks of finished jobs
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    url: url::Url,
+    secret: Option<String>,
+}
+
+impl Webhook {
+    pub fn new(url: url::Url, secret: Option<String>) -> Self {
+        Webhook { url, secret }
+    }
+
+    pub async fn send(&self, finished: &Finished) -> Result<(), Error> {
+        let body = serde_json::to_vec(finished)?;
+        let mut request = surf::post(self.url.as_str())
+            .header("Content-Type", "application/json")
+            .header("X-CIS-Event", "job.finished");
+        if let Some(secret) = &self.secret {
+            request = request.header("X-Hub-Signature-256", sign(secret, &body)?);
+        }
+        let response = request.body(body).await.map_err(Error::Request)?;
+        if !response.status().is_success() {
+            return Err(Error::Status(response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// The `X-Hub-Signature-256` of `body`
+pub fn sign(secret: &str, body: &[u8]) -> Result<String, openssl::error::ErrorStack> {
+    let key = openssl::pkey::PKey::hmac(secret.as_bytes())?;
+    let mut signer = openssl::sign::Signer::new(openssl::hash::MessageDigest::sha256(), &key)?;
+    signer.update(body)?;
+    let signature = signer.sign_to_vec()?;
+    let hex: String = signature.iter().map(|byte| format!("{byte:02x}")).collect();
+    Ok(format!("sha256={hex}"))
+}
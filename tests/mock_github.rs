@@ -0,0 +1,621 @@
+#![cfg(feature = "mock-github")]
+
+use ci_script::mock_github::MockGithub;
+use std::convert::TryInto;
+
+#[tokio::test]
+async fn serves_what_the_reactor_asks_for() {
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    github.add_repository("acme/widgets", &clone_url);
+    github.add_pull_request("acme/widgets", 7, "feature", "abc", "master", "def");
+
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(github.url().as_str())
+        .unwrap()
+        .personal_token("token".to_string())
+        .build()
+        .unwrap();
+    let repository: ci_script::job::Repository = client
+        .repos("acme", "widgets")
+        .get()
+        .await
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert_eq!(repository.default_branch.as_deref(), Some("master"));
+    let pull = client.pulls("acme", "widgets").get(7).await.unwrap();
+    assert_eq!(pull.head.sha, "abc");
+    client
+        .issues("acme", "widgets")
+        .create_comment(7, "Done")
+        .await
+        .unwrap();
+
+    let comments = github.comments();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].repository, "acme/widgets");
+    assert_eq!(comments[0].issue, 7);
+    assert_eq!(comments[0].body, "Done");
+}
+
+#[tokio::test]
+async fn webhook_payloads_parse() {
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    github.add_repository("acme/widgets", &clone_url);
+    github.add_pull_request("acme/widgets", 7, "feature", "abc", "master", "def");
+
+    let payload = github.issue_comment("acme/widgets", 7, "alice", "/bench run");
+    let payload: tide_github::payload::Payload = serde_json::from_value(payload).unwrap();
+    let issue = payload.issue.unwrap();
+    assert!(issue.pull_request.is_some());
+    assert_eq!(payload.comment.unwrap().body.as_deref(), Some("/bench run"));
+}
+
+#[tokio::test]
+async fn label_payloads_parse() {
+    use ci_script::labels::{LabelTrigger, PullRequestEvent};
+
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    github.add_repository("acme/widgets", &clone_url);
+    github.add_pull_request("acme/widgets", 7, "feature", "abc", "master", "def");
+
+    let trigger: LabelTrigger = "bench:extrinsics=bench extrinsics --all".parse().unwrap();
+    assert_eq!(trigger.label, "bench:extrinsics");
+    assert_eq!(trigger.command, vec!["bench", "extrinsics", "--all"]);
+
+    let payload =
+        github.pull_request_labeled("acme/widgets", 7, "alice", "labeled", "bench:extrinsics");
+    let event: PullRequestEvent = serde_json::from_value(payload).unwrap();
+    assert_eq!(event.labeled(), Some("bench:extrinsics"));
+    assert_eq!(event.unlabeled(), None);
+    assert_eq!(event.sender.login, "alice");
+    let repository: ci_script::job::Repository = event.repository.try_into().unwrap();
+    assert_eq!(repository.name, "widgets");
+
+    // Jobs need the pull request as an issue
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(github.url().as_str())
+        .unwrap()
+        .personal_token("token".to_string())
+        .build()
+        .unwrap();
+    let issue = client.issues("acme", "widgets").get(7).await.unwrap();
+    assert!(issue.pull_request.is_some());
+}
+
+#[tokio::test]
+async fn jobs_differing_in_arguments_or_runner_are_not_duplicates() {
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    let repository = github.add_repository("acme/widgets", &clone_url);
+    github.add_pull_request("acme/widgets", 7, "feature", "abc", "master", "def");
+    let payload = github.issue_comment("acme/widgets", 7, "alice", "/bench w.rhai pallet=a");
+    let job = |id: &str, command: &[&str], runner_os: Option<&str>| -> ci_script::job::Job {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "command": command,
+            "repository": repository,
+            "issue": payload["issue"],
+            "runner_os": runner_os,
+        }))
+        .unwrap()
+    };
+
+    let a = job("1", &["w.rhai", "pallet=a"], None);
+    assert_eq!(
+        a.dedup_key(),
+        job("2", &["w.rhai", "pallet=a"], None).dedup_key()
+    );
+    assert_ne!(
+        a.dedup_key(),
+        job("3", &["w.rhai", "pallet=b"], None).dedup_key()
+    );
+    assert_ne!(
+        a.dedup_key(),
+        job("4", &["w.rhai", "pallet=a"], Some("macos")).dedup_key()
+    );
+}
+
+#[tokio::test]
+async fn pull_requests_of_earlier_runs_are_updated() {
+    use ci_script::forge::Forge;
+
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    let repository =
+        serde_json::from_value(github.add_repository("acme/widgets", &clone_url)).unwrap();
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(github.url().as_str())
+        .unwrap()
+        .personal_token("token".to_string())
+        .build()
+        .unwrap();
+    let forge = ci_script::forge::Github::new(
+        std::sync::Arc::new(std::sync::Mutex::new(client)),
+        repository,
+    );
+
+    forge
+        .create_pr("Weights", "First run", "bot/weights", "master")
+        .unwrap();
+    forge
+        .create_pr("Weights", "Second run", "bot/weights", "master")
+        .unwrap();
+    forge
+        .create_pr("Weights", "Other base", "bot/weights", "release")
+        .unwrap();
+
+    let pulls = github.pull_requests();
+    assert_eq!(pulls.len(), 2);
+    assert_eq!(pulls[0].body.as_deref(), Some("Second run"));
+    assert_eq!(pulls[1].base, "release");
+}
+
+#[tokio::test]
+async fn branches_of_done_pull_requests_are_cleaned_up() {
+    use ci_script::forge::Forge;
+
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    let repository =
+        serde_json::from_value(github.add_repository("acme/widgets", &clone_url)).unwrap();
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(github.url().as_str())
+        .unwrap()
+        .personal_token("token".to_string())
+        .build()
+        .unwrap();
+    let forge = ci_script::forge::Github::new(
+        std::sync::Arc::new(std::sync::Mutex::new(client.clone())),
+        repository,
+    );
+    for branch in ["bot/merged", "bot/open", "bot/unused", "feature"] {
+        github.add_branch("acme/widgets", branch);
+    }
+    for branch in ["bot/merged", "bot/open", "feature"] {
+        forge.create_pr(branch, "", branch, "master").unwrap();
+    }
+    github.close_pull_request("acme/widgets", "bot/merged");
+    github.close_pull_request("acme/widgets", "feature");
+
+    let cleaned = ci_script::branches::clean_up(&client, "bot/")
+        .await
+        .unwrap();
+    assert_eq!(cleaned.repositories, 1);
+    assert_eq!(cleaned.deleted, vec!["acme/widgets:bot/merged"]);
+    assert_eq!(
+        github.branches("acme/widgets"),
+        vec!["bot/open", "bot/unused", "feature"]
+    );
+    assert!(cleaned.failed.is_empty(), "{:?}", cleaned.failed);
+}
+
+#[tokio::test]
+async fn branches_of_other_repositories_are_cleaned_up_when_one_fails() {
+    use ci_script::forge::Forge;
+
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(github.url().as_str())
+        .unwrap()
+        .personal_token("token".to_string())
+        .build()
+        .unwrap();
+    for name in ["acme/broken", "acme/widgets"] {
+        let repository = serde_json::from_value(github.add_repository(name, &clone_url)).unwrap();
+        let forge = ci_script::forge::Github::new(
+            std::sync::Arc::new(std::sync::Mutex::new(client.clone())),
+            repository,
+        );
+        github.add_branch(name, "bot/merged");
+        forge
+            .create_pr("bot/merged", "", "bot/merged", "master")
+            .unwrap();
+        github.close_pull_request(name, "bot/merged");
+    }
+    github.fail_requests("/repos/acme/broken/git/");
+
+    let cleaned = ci_script::branches::clean_up(&client, "bot/")
+        .await
+        .unwrap();
+    assert_eq!(cleaned.repositories, 2);
+    assert_eq!(cleaned.deleted, vec!["acme/widgets:bot/merged"]);
+    assert_eq!(cleaned.failed.len(), 1);
+    assert_eq!(cleaned.failed[0].0, "acme/broken");
+    assert_eq!(github.branches("acme/broken"), vec!["bot/merged"]);
+    assert!(github.branches("acme/widgets").is_empty());
+}
+
+#[tokio::test]
+async fn pull_requests_of_the_bot_are_auto_merged() {
+    use ci_script::auto_merge::{self, Method};
+
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    github.add_repository("acme/widgets", &clone_url);
+    github.add_pull_request("acme/widgets", 7, "bot/weights", "abc", "master", "def");
+    let bot = format!("{}[bot]", ci_script::mock_github::APP_SLUG);
+    github.set_pull_request_author("acme/widgets", 7, &bot);
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(github.url().as_str())
+        .unwrap()
+        .personal_token("token".to_string())
+        .build()
+        .unwrap();
+
+    let app = client.current().app().await.unwrap();
+    let pull = client.pulls("acme", "widgets").get(7).await.unwrap();
+    assert_eq!(
+        pull.user.map(|user| user.login),
+        app.slug.map(|slug| format!("{slug}[bot]"))
+    );
+    auto_merge::approve(&client, "acme", "widgets", 7, "No regressions")
+        .await
+        .unwrap();
+    let node_id = pull.node_id.unwrap();
+    auto_merge::enable(&client, &node_id, "squash".parse().unwrap())
+        .await
+        .unwrap();
+    assert!(auto_merge::enable(&client, "PR_8", Method::Merge)
+        .await
+        .is_err());
+
+    let reviews = github.reviews();
+    assert_eq!(reviews.len(), 1);
+    assert_eq!(
+        (reviews[0].number, reviews[0].event.as_str()),
+        (7, "APPROVE")
+    );
+    assert_eq!(
+        github.auto_merges(),
+        vec![("PR_7".to_string(), "SQUASH".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn scripts_label_and_pull_in_reviewers() {
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    let repository =
+        serde_json::from_value(github.add_repository("acme/widgets", &clone_url)).unwrap();
+    github.add_pull_request("acme/widgets", 7, "feature", "abc", "master", "def");
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(github.url().as_str())
+        .unwrap()
+        .personal_token("token".to_string())
+        .build()
+        .unwrap();
+    let forge = ci_script::forge::Github::new(
+        std::sync::Arc::new(std::sync::Mutex::new(client)),
+        repository,
+    );
+    let gh_issue = github.issue_comment("acme/widgets", 7, "alice", "")["issue"].clone();
+    let mut issue = ci_script::api::Issue::new(
+        std::sync::Arc::new(forge),
+        serde_json::from_value(gh_issue).unwrap(),
+    );
+
+    issue.add_label("performance-regression").unwrap();
+    issue.add_label("needs review: runtime").unwrap();
+    issue.remove_label("needs review: runtime").unwrap();
+    issue.remove_label("never added").unwrap();
+    issue.request_review("acme/benchmarks").unwrap();
+    issue.assign("alice").unwrap();
+
+    assert_eq!(
+        github.labels("acme/widgets", 7),
+        vec!["performance-regression"]
+    );
+    assert_eq!(github.team_reviewers("acme/widgets", 7), vec!["benchmarks"]);
+    assert_eq!(github.assignees("acme/widgets", 7), vec!["alice"]);
+}
+
+#[tokio::test]
+async fn review_comments_are_replied_to_in_their_thread() {
+    use ci_script::forge::Forge;
+    use ci_script::review_comments::ReviewCommentEvent;
+
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::parse("file:///tmp/repo").unwrap();
+    let repository =
+        serde_json::from_value(github.add_repository("acme/widgets", &clone_url)).unwrap();
+    github.add_pull_request("acme/widgets", 7, "feature", "abc", "master", "def");
+
+    let payload = github.pull_request_review_comment(
+        "acme/widgets",
+        7,
+        "alice",
+        "/bench runtime",
+        12,
+        Some(10),
+    );
+    let event: ReviewCommentEvent = serde_json::from_value(payload).unwrap();
+    assert_eq!(event.command("/bench"), Some("/bench runtime"));
+    assert_eq!(event.command("/other"), None);
+    assert_eq!(event.pull_request.number, 7);
+    assert_eq!(event.comment.user.login, "alice");
+    // Replies to replies go to the first comment of the thread
+    assert_eq!(event.thread(), 10);
+
+    let client = octocrab::OctocrabBuilder::new()
+        .base_url(github.url().as_str())
+        .unwrap()
+        .personal_token("token".to_string())
+        .build()
+        .unwrap();
+    let forge = ci_script::forge::Github::new(
+        std::sync::Arc::new(std::sync::Mutex::new(client)),
+        repository,
+    )
+    .with_review_thread(Some(event.thread()));
+    forge.create_comment(7, "Queued").unwrap();
+
+    let comments = github.comments();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].issue, 7);
+    assert_eq!(comments[0].review_thread, Some(10));
+}
+
+/// A reactor running against a mock Github, in a directory of its own that's removed with it
+struct Reactor {
+    process: std::process::Child,
+    url: url::Url,
+    dir: std::path::PathBuf,
+    args: Vec<String>,
+}
+
+impl Reactor {
+    const WEBHOOK_SECRET: &'static str = "secret";
+
+    async fn start(github: &MockGithub, dir: &std::path::Path, args: &[&str]) -> Reactor {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let (process, url) = Self::spawn(github, dir, &args).await;
+        Reactor {
+            process,
+            url,
+            dir: dir.to_owned(),
+            args,
+        }
+    }
+
+    /// Stop the reactor and start it again with the same directory and arguments, on another
+    /// port
+    async fn restart(&mut self, github: &MockGithub) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let (process, url) = Self::spawn(github, &self.dir, &self.args).await;
+        self.process = process;
+        self.url = url;
+    }
+
+    async fn spawn(
+        github: &MockGithub,
+        dir: &std::path::Path,
+        args: &[String],
+    ) -> (std::process::Child, url::Url) {
+        let app_key = openssl::rsa::Rsa::generate(2048)
+            .unwrap()
+            .private_key_to_pem()
+            .unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut process = std::process::Command::new(env!("CARGO_BIN_EXE_cis-gh-reactor"))
+            .args(["--app-id", "1", "--webhook-secret", Self::WEBHOOK_SECRET])
+            .arg(format!("--app-key={}", String::from_utf8(app_key).unwrap()))
+            .arg(format!("--github-api-base={}", github.url()))
+            .args(["--address", "127.0.0.1", "--port", &port.to_string()])
+            .arg("--repos-root")
+            .arg(dir.join("repos"))
+            .args(args)
+            .spawn()
+            .unwrap();
+        let url = format!("http://127.0.0.1:{port}/").parse().unwrap();
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                return (process, url);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        let _ = process.kill();
+        let _ = process.wait();
+        panic!("The reactor didn't start listening");
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A repository at `dir` with the script `.github/benchbot/<name>` in its only commit, which the
+/// pull request refs point at too. Returns the commit.
+fn repository_with_script(dir: &std::path::Path, name: &str, script: &str) -> String {
+    let repo = git2::Repository::init(dir).unwrap();
+    let scripts = dir.join(".github/benchbot");
+    std::fs::create_dir_all(&scripts).unwrap();
+    std::fs::write(scripts.join(name), script).unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("alice", "alice@example.com").unwrap();
+    let commit = repo
+        .commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Add script",
+            &tree,
+            &[],
+        )
+        .unwrap();
+    repo.reference("refs/pull/7/head", commit, true, "pull request")
+        .unwrap();
+    commit.to_string()
+}
+
+/// Poll `github` until `found` is true of what it has, or a minute passed
+async fn eventually(github: &MockGithub, found: impl Fn(&MockGithub) -> bool) -> bool {
+    for _ in 0..600 {
+        if found(github) {
+            return true;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_are_run_and_their_result_commented() {
+    let dir = std::env::temp_dir().join(format!("cis-reactor-{}", uuid::Uuid::new_v4().simple()));
+    let origin = dir.join("origin");
+    let sha = repository_with_script(
+        &origin,
+        "hello.rhai",
+        r#"OUTPUT.set("greeting", `Hello ${ARGS[0]}`); OUTPUT.set("answer", 42);"#,
+    );
+
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::from_directory_path(&origin).unwrap();
+    github.add_repository("acme/widgets", &clone_url);
+    github.add_pull_request("acme/widgets", 7, "feature", &sha, "master", &sha);
+    github.set_permission("alice", "write");
+    let reactor = Reactor::start(&github, &dir, &[]).await;
+
+    let payload = github.issue_comment("acme/widgets", 7, "alice", "/benchbot hello world");
+    let status = github
+        .deliver(
+            &reactor.url,
+            Reactor::WEBHOOK_SECRET,
+            "issue_comment",
+            &payload,
+        )
+        .await
+        .unwrap();
+    assert!(status.is_success(), "{}", status);
+
+    let commented = eventually(&github, |github| {
+        github
+            .comments()
+            .iter()
+            .any(|comment| comment.body.contains("Hello world"))
+    })
+    .await;
+    assert!(commented, "{:#?}", github.comments());
+    let comments = github.comments();
+    let result = comments
+        .iter()
+        .find(|comment| comment.body.contains("Hello world"))
+        .unwrap();
+    assert_eq!(result.repository, "acme/widgets");
+    assert_eq!(result.issue, 7);
+    // Quoting the command and mentioning who asked for it
+    assert!(
+        result.body.contains("> /benchbot hello world"),
+        "{}",
+        result.body
+    );
+    assert!(result.body.contains("@alice"), "{}", result.body);
+
+    // Numeric outputs are metrics
+    let metrics = surf::get(reactor.url.join("metrics").unwrap())
+        .recv_string()
+        .await
+        .unwrap();
+    assert!(
+        metrics.contains(
+            r#"cis_output{tenant="default",repository="acme/widgets",script=".github/benchbot/hello.rhai",key="answer"} 42"#
+        ),
+        "{}",
+        metrics
+    );
+    assert!(!metrics.contains("greeting"), "{}", metrics);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_held_for_approval_survive_a_restart() {
+    let dir = std::env::temp_dir().join(format!("cis-reactor-{}", uuid::Uuid::new_v4().simple()));
+    let origin = dir.join("origin");
+    let sha = repository_with_script(&origin, "hello.rhai", r#"OUTPUT.set("greeting", "Hello");"#);
+
+    let github = MockGithub::start().await.unwrap();
+    let clone_url = url::Url::from_directory_path(&origin).unwrap();
+    github.add_repository("acme/widgets", &clone_url);
+    github.add_pull_request("acme/widgets", 7, "feature", &sha, "master", &sha);
+    github.set_permission("mallory", "read");
+    github.set_permission("alice", "write");
+    let state_db = dir.join("state.db");
+    let mut reactor = Reactor::start(
+        &github,
+        &dir,
+        &[
+            "--require-approval=true",
+            "--state-db",
+            state_db.to_str().unwrap(),
+        ],
+    )
+    .await;
+
+    let payload = github.issue_comment("acme/widgets", 7, "mallory", "/benchbot hello");
+    let status = github
+        .deliver(
+            &reactor.url,
+            Reactor::WEBHOOK_SECRET,
+            "issue_comment",
+            &payload,
+        )
+        .await
+        .unwrap();
+    assert!(status.is_success(), "{}", status);
+    let held = eventually(&github, |github| {
+        github
+            .comments()
+            .iter()
+            .any(|comment| comment.body.contains("needs to be approved"))
+    })
+    .await;
+    assert!(held, "{:#?}", github.comments());
+    let comments = github.comments();
+    let approval_id = comments
+        .iter()
+        .find_map(|comment| comment.body.split("approve ").nth(1))
+        .and_then(|rest| rest.split('`').next())
+        .unwrap()
+        .to_string();
+
+    reactor.restart(&github).await;
+    let command = format!("/benchbot approve {approval_id}");
+    let payload = github.issue_comment("acme/widgets", 7, "alice", &command);
+    let status = github
+        .deliver(
+            &reactor.url,
+            Reactor::WEBHOOK_SECRET,
+            "issue_comment",
+            &payload,
+        )
+        .await
+        .unwrap();
+    assert!(status.is_success(), "{}", status);
+    let ran = eventually(&github, |github| {
+        github
+            .comments()
+            .iter()
+            .any(|comment| comment.body.contains("Hello"))
+    })
+    .await;
+    assert!(ran, "{:#?}", github.comments());
+}